@@ -0,0 +1,249 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+Galleries saved as a single archive, see `--gallery-archive`.
+
+The items are downloaded into the gallery directory as usual, but every item
+is moved into the archive as soon as it is complete, so at most one item
+is on the disk twice. The directory is removed once it is empty.
+The archive is only created when the first item is added,
+so posts which turn out not to be galleries leave no empty archive behind.
+
+The items are stored without compression, because images and videos
+are already compressed.
+*/
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use tokio::task;
+use zip::{result::ZipError, write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::prelude::*;
+
+/// The archive formats of `--gallery-archive`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    /// A `.zip` file.
+    Zip,
+}
+
+impl<'a> From<&'a str> for ArchiveFormat {
+    fn from(s: &str) -> Self {
+        match s {
+            "zip" => ArchiveFormat::Zip,
+            _ => unreachable!(), // Guaranteed by clap's `possible_values`
+        }
+    }
+}
+
+/// Returns the archive file of a gallery directory,
+/// which is the directory name with `.zip` appended.
+pub fn path(directory: &Path) -> PathBuf {
+    let mut path = directory.as_os_str().to_owned();
+    path.push(".zip");
+
+    PathBuf::from(path)
+}
+
+/// The archive of a gallery.
+pub struct Archive {
+    /// The gallery directory the items are downloaded to.
+    directory: PathBuf,
+
+    /// Whether an existing archive is kept, see `--no-overwrite`.
+    no_overwrite: bool,
+
+    /// The archive, which is created when the first item is added.
+    writer: Arc<Mutex<Option<ZipWriter<File>>>>,
+}
+
+impl Archive {
+    /// Prepares the archive of a gallery directory.
+    pub fn new(directory: &Path, no_overwrite: bool) -> Archive {
+        Archive {
+            directory: directory.to_path_buf(),
+            no_overwrite,
+            writer: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Moves a downloaded item of the gallery directory into the archive.
+    ///
+    /// Copying the item blocks, so it runs on a blocking thread.
+    pub async fn add(&self, item: &Path) -> Result<()> {
+        trace!("Archive::add({:?})", item);
+
+        let name = entry_name(
+            item.strip_prefix(&self.directory)
+                .map_err(|_| Error::new(format!("{:?} is not part of the gallery", item)))?,
+        );
+        let item = item.to_path_buf();
+        let archive = path(&self.directory);
+        let no_overwrite = self.no_overwrite;
+        let writer = Arc::clone(&self.writer);
+
+        task::spawn_blocking(move || {
+            // The lock is only poisoned if another job panicked while writing
+            let mut writer = writer.lock().unwrap();
+            if writer.is_none() {
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(!no_overwrite)
+                    .create_new(no_overwrite)
+                    .open(archive)?;
+                *writer = Some(ZipWriter::new(file));
+            };
+            let writer = writer.as_mut().unwrap();
+
+            writer.start_file(
+                name,
+                FileOptions::default().compression_method(CompressionMethod::Stored),
+            )?;
+            io::copy(&mut File::open(&item)?, writer)?;
+            fs::remove_file(&item)?;
+
+            Ok::<_, ZipError>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    /// Completes the archive and removes the empty gallery directory.
+    /// Returns the path of the archive, if any item was added.
+    pub async fn finish(self) -> Result<Option<PathBuf>> {
+        trace!("Archive::finish({:?})", self.directory);
+
+        let Archive {
+            directory, writer, ..
+        } = self;
+
+        // All jobs adding items are done, so this is the only reference
+        let writer = Arc::try_unwrap(writer)
+            .map_err(|_| Error::new("The archive is still in use"))?
+            .into_inner()
+            .unwrap();
+
+        let archive = task::spawn_blocking(move || {
+            let _ = remove_empty(&directory);
+
+            match writer {
+                Some(mut writer) => {
+                    writer.finish()?;
+                    Ok::<_, ZipError>(Some(path(&directory)))
+                }
+                None => Ok(None),
+            }
+        })
+        .await??;
+
+        Ok(archive)
+    }
+}
+
+// `ZipWriter` does not implement `Debug`
+impl fmt::Debug for Archive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Archive")
+            .field("directory", &self.directory)
+            .field("no_overwrite", &self.no_overwrite)
+            .finish()
+    }
+}
+
+/// Returns the name of an item in the archive, which always uses `/` as separator.
+fn entry_name(relative: &Path) -> String {
+    relative
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Removes a directory and its subdirectories, unless they contain files.
+/// Files left behind by failed items, or by earlier runs, are never deleted.
+fn remove_empty(directory: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            let _ = remove_empty(&entry.path());
+        };
+    }
+
+    fs::remove_dir(directory)
+}
+
+#[tokio::test]
+#[allow(unused_must_use)]
+async fn archive_zip() {
+    let mut root = std::env::temp_dir();
+    root.push("redditrip_archive");
+    fs::remove_dir_all(&root);
+    let gallery = root.join("abc-Lorem ipsum");
+    fs::create_dir_all(gallery.join("def")).unwrap();
+
+    assert_eq!(root.join("abc-Lorem ipsum.zip"), path(&gallery));
+    assert_eq!(
+        "def/0.jpg",
+        entry_name(Path::new("def").join("0.jpg").as_path())
+    );
+
+    // Nothing was added, so no archive is created
+    Archive::new(&root.join("ghi"), false)
+        .finish()
+        .await
+        .unwrap();
+    assert!(!root.join("ghi.zip").exists());
+
+    let archive = Archive::new(&gallery, false);
+    fs::write(gallery.join("0.jpg"), "Lorem").unwrap();
+    archive.add(&gallery.join("0.jpg")).await.unwrap();
+    fs::write(gallery.join("def").join("0.png"), "ipsum").unwrap();
+    archive
+        .add(&gallery.join("def").join("0.png"))
+        .await
+        .unwrap();
+    assert!(archive.add(&root.join("other.jpg")).await.is_err());
+    assert_eq!(Some(path(&gallery)), archive.finish().await.unwrap());
+
+    assert!(!gallery.exists());
+    let data = fs::read(path(&gallery)).unwrap();
+    assert!(data.starts_with(b"PK\x03\x04"));
+    // Stored entries contain the plain content
+    let contains = |text: &[u8]| data.windows(text.len()).any(|window| window == text);
+    assert!(contains(b"0.jpgLorem"));
+    assert!(contains(b"def/0.pngipsum"));
+
+    // With `--no-overwrite`, an existing archive is kept
+    let archive = Archive::new(&gallery, true);
+    fs::create_dir_all(&gallery).unwrap();
+    fs::write(gallery.join("0.jpg"), "dolor").unwrap();
+    assert!(archive.add(&gallery.join("0.jpg")).await.is_err());
+    assert_eq!(data, fs::read(path(&gallery)).unwrap());
+
+    fs::remove_dir_all(&root);
+}
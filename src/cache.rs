@@ -0,0 +1,116 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+Caching of Pushshift listings, see `--cache-dir`.
+
+Every response is stored in its own file, named after the SHA-256 hash
+of the full request URL. An entry is stale once its file is older than
+`--cache-ttl`, and is then replaced by the next request. Media downloads
+are never cached.
+*/
+
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::checksum::{Digest, Sha256};
+use crate::prelude::*;
+
+/// A directory of cached responses.
+#[derive(Debug)]
+pub struct Cache {
+    directory: PathBuf,
+
+    /// How long an entry is used after it was written.
+    ttl: Duration,
+}
+
+impl Cache {
+    /// Opens the cache, creating the directory if it does not exist.
+    pub fn open(directory: &Path, ttl: Duration) -> Result<Cache> {
+        trace!("Cache::open({:?}, {:?})", directory, ttl);
+
+        fs::create_dir_all(directory)?;
+
+        Ok(Cache {
+            directory: directory.to_path_buf(),
+            ttl,
+        })
+    }
+
+    /// Returns the cached response of the URL, unless it is missing or stale.
+    pub fn get(&self, url: &str) -> Option<Vec<u8>> {
+        let path = self.path(url);
+
+        // Files modified in the future, for example after the clock changed, count as stale
+        let age = fs::metadata(&path).ok()?.modified().ok()?.elapsed().ok()?;
+        if age >= self.ttl {
+            debug!("The cached response of {:?} is stale", url);
+            return None;
+        };
+
+        fs::read(&path).ok()
+    }
+
+    /// Stores the response of the URL, replacing a stale entry.
+    /// Failures are only logged, the response is used either way.
+    pub fn insert(&self, url: &str, data: &[u8]) {
+        let path = self.path(url);
+
+        // Written next to the entry first, so a concurrent `get()` never reads a partial file
+        let temp = path.with_extension("tmp");
+        if let Err(e) = fs::write(&temp, data).and_then(|_| fs::rename(&temp, &path)) {
+            warn!("Failed to cache the response of {:?}: {}", url, e);
+        };
+    }
+
+    /// Returns the file of the URL.
+    fn path(&self, url: &str) -> PathBuf {
+        let mut name = String::with_capacity(69);
+        for i in Sha256::digest(url.as_bytes()).iter() {
+            let _ = write!(name, "{:02x}", i);
+        }
+        name.push_str(".json");
+
+        self.directory.join(name)
+    }
+}
+
+#[test]
+#[allow(unused_must_use)]
+fn cache_ttl() {
+    let mut root = std::env::temp_dir();
+    root.push("redditrip_cache");
+    fs::remove_dir_all(&root);
+
+    let url = "https://api.pushshift.io/reddit/search/submission?subreddit=test";
+    let cache = Cache::open(&root, Duration::from_secs(60)).unwrap();
+    assert_eq!(None, cache.get(url));
+    cache.insert(url, b"{\"data\":[]}");
+    assert_eq!(Some(b"{\"data\":[]}".to_vec()), cache.get(url));
+    assert_eq!(None, cache.get(&format!("{}&before=946684800", url)));
+    assert_eq!(1, fs::read_dir(&root).unwrap().count());
+
+    // Every entry is stale immediately
+    let cache = Cache::open(&root, Duration::from_secs(0)).unwrap();
+    assert_eq!(None, cache.get(url));
+
+    fs::remove_dir_all(&root);
+}
@@ -0,0 +1,114 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+Checksum files in the format of `sha256sum`, see `--checksum-file`.
+
+Every line contains the hex encoded SHA-256 hash of a saved file, two spaces
+and the path of the file relative to the directory of the checksum file,
+so the downloads can be verified by running `sha256sum -c` in that directory.
+*/
+
+use std::{
+    env,
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+pub use sha2::{Digest, Sha256};
+
+use crate::prelude::*;
+
+/// A checksum file opened for appending lines.
+///
+/// Lines are written under a lock, because many download jobs finish concurrently.
+#[derive(Debug)]
+pub struct Checksums {
+    file: Mutex<File>,
+
+    /// The absolute directory of the checksum file.
+    base: PathBuf,
+}
+
+impl Checksums {
+    /// Opens the checksum file, creating it if it does not exist.
+    pub fn open(path: &Path) -> Result<Checksums> {
+        trace!("Checksums::open({:?})", path);
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let base = absolute(path.parent().unwrap_or_else(|| Path::new("")))?;
+
+        Ok(Checksums {
+            file: Mutex::new(file),
+            base,
+        })
+    }
+
+    /// Appends the line of a saved file.
+    /// Failures are only logged, they do not affect the download.
+    pub fn record(&self, path: &Path, hasher: Sha256) {
+        let line = match absolute(path) {
+            Ok(path) => line(
+                &hasher.result(),
+                path.strip_prefix(&self.base).unwrap_or(&path),
+            ),
+            Err(e) => {
+                warn!("Failed to resolve the path of {:?}: {}", path, e);
+                return;
+            }
+        };
+
+        // The lock is only poisoned if another job panicked while writing
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            warn!("Failed to write to the checksum file: {}", e);
+        };
+    }
+}
+
+/// Formats a line of a checksum file.
+pub fn line(hash: &[u8], path: &Path) -> String {
+    let mut line = String::with_capacity(hash.len() * 2 + 3);
+    for i in hash {
+        let _ = write!(line, "{:02x}", i);
+    }
+    let _ = writeln!(line, "  {}", path.display());
+
+    line
+}
+
+/// Makes a path absolute, without resolving symbolic links.
+pub fn absolute(path: &Path) -> io::Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(path.to_path_buf())
+    } else {
+        Ok(env::current_dir()?.join(path))
+    }
+}
+
+#[test]
+fn checksum_line() {
+    let mut hasher = Sha256::new();
+    hasher.input(b"abc");
+
+    assert_eq!(
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  pics/abc.jpg\n",
+        line(&hasher.result(), Path::new("pics/abc.jpg"))
+    );
+}
@@ -0,0 +1,763 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+Support for configuration files.
+
+A configuration file is a TOML file containing default values for the
+command line arguments. The keys are the names of the long arguments
+without the leading `--`, for example:
+
+```toml
+output = "/home/user/reddit"
+title = "{id}-{author}-{title}"
+exclude = ["youtube.com", "youtu.be"]
+selfposts = true
+```
+
+Arguments passed on the command line always take precedence.
+Arguments which can not be combined are rejected after merging,
+also if one of them is only set in the configuration file.
+*/
+
+use std::{env, fs, io::ErrorKind, path::PathBuf};
+
+use serde::Deserialize;
+use structopt::clap::ArgMatches;
+
+use crate::archive::ArchiveFormat;
+use crate::exec::Exec;
+use crate::net::parse_size;
+use crate::sites::{
+    gfycat::{GfycatFallback, GfycatType},
+    reddit::VRedditMode,
+    selfpost::SelftextFormat,
+};
+use crate::subreddit::{Collision, FlairFilterMode, Flatten};
+use crate::title::{self, Title};
+use crate::{
+    parse_address, parse_alias, parse_domains, parse_field, parse_filter, Parameters, PAGE_SIZES,
+    QUEUE_SIZES,
+};
+
+/// The default values read from a configuration file.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    color: Option<String>,
+    log_file: Option<PathBuf>,
+    max_file_name_length: Option<usize>,
+    manifest: Option<PathBuf>,
+    posts_ndjson: Option<PathBuf>,
+    checksum_file: Option<PathBuf>,
+    dedupe_db: Option<PathBuf>,
+    cache_dir: Option<PathBuf>,
+    cache_ttl: Option<u64>,
+    output: Option<PathBuf>,
+    temp_dir: Option<PathBuf>,
+    force: Option<bool>,
+    skip_html_pages: Option<bool>,
+    update: Option<bool>,
+    skip_existing: Option<bool>,
+    skip_duplicate_urls: Option<bool>,
+    resume_queue: Option<bool>,
+    date_index: Option<bool>,
+    no_overwrite: Option<bool>,
+    rename_on_extension_mismatch: Option<bool>,
+    quiet_errors: Option<bool>,
+    fail_fast: Option<bool>,
+    fail_on_unsupported: Option<bool>,
+    no_parent: Option<bool>,
+    flatten: Option<String>,
+    mirror: Option<bool>,
+    group_by_domain: Option<bool>,
+    alias: Option<Vec<String>>,
+    collision_strategy: Option<String>,
+    queue_size: Option<usize>,
+    concurrency_adaptive: Option<bool>,
+    page_size: Option<usize>,
+    concurrent_subreddits: Option<usize>,
+    gallery_depth: Option<usize>,
+    pool_idle_per_host: Option<usize>,
+    pool_idle_timeout: Option<u64>,
+    max_redirects: Option<usize>,
+    ca_cert: Option<PathBuf>,
+    metrics_addr: Option<String>,
+    selfposts: Option<bool>,
+    only_nsfw: Option<bool>,
+    no_nsfw: Option<bool>,
+    exclude_stickied: Option<bool>,
+    exclude_pinned: Option<bool>,
+    include_removed: Option<bool>,
+    flatten_galleries: Option<bool>,
+    gallery_archive: Option<String>,
+    number: Option<bool>,
+    name_by_source: Option<bool>,
+    lowercase_filenames: Option<bool>,
+    no_normalize_unicode: Option<bool>,
+    truncate_at_word: Option<bool>,
+    ascii_filenames: Option<bool>,
+    filename_prefix: Option<String>,
+    filename_suffix: Option<String>,
+    zero_pad: Option<usize>,
+    allow: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    allow_file: Option<PathBuf>,
+    exclude_file: Option<PathBuf>,
+    record_only: Option<Vec<String>>,
+    filter: Option<Vec<String>>,
+    flair: Option<Vec<String>>,
+    print_fields: Option<Vec<String>>,
+    flair_filter_mode: Option<String>,
+    flair_case_sensitive: Option<bool>,
+    require_flair: Option<bool>,
+    gfycat_type: Option<String>,
+    gfycat_fallback: Option<String>,
+    precheck: Option<bool>,
+    insecure: Option<bool>,
+    prefer_mp4: Option<bool>,
+    thumbnails_only: Option<bool>,
+    max_resolution: Option<u64>,
+    min_comments: Option<u64>,
+    max_total_size: Option<String>,
+    interval: Option<u64>,
+    vreddit_mode: Option<String>,
+    selftext_format: Option<String>,
+    title: Option<String>,
+    exec: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    refresh_token: Option<String>,
+}
+
+impl Config {
+    /// Reads a configuration file.
+    pub fn load(path: &PathBuf) -> Result<Config, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+
+        toml::from_str(&data).map_err(|e| format!("Invalid config file {}: {}", path.display(), e))
+    }
+
+    /// Reads the configuration file at the default location,
+    /// which is `$XDG_CONFIG_HOME/redditrip/config.toml` or
+    /// `$HOME/.config/redditrip/config.toml`.
+    ///
+    /// A missing file is not an error.
+    pub fn load_default() -> Result<Config, String> {
+        let mut path = match env::var_os("XDG_CONFIG_HOME") {
+            Some(path) if !path.is_empty() => PathBuf::from(path),
+            _ => match env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".config"),
+                None => return Ok(Config::default()),
+            },
+        };
+        path.push("redditrip");
+        path.push("config.toml");
+
+        match fs::metadata(&path) {
+            Ok(_) => Config::load(&path),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!(
+                "Failed to read config file {}: {}",
+                path.display(),
+                e
+            )),
+        }
+    }
+
+    /// Applies the values to the parameters.
+    /// Values which were passed on the command line are left unchanged.
+    pub fn apply(self, parameters: &mut Parameters, matches: &ArgMatches) -> Result<(), String> {
+        let unset = |name: &str| matches.occurrences_of(name) == 0;
+
+        if let Some(color) = self.color {
+            if !["always", "auto", "never"].contains(&color.as_str()) {
+                return Err(format!(
+                    "Invalid value '{}' for 'color' in config file",
+                    color
+                ));
+            };
+            if unset("color") {
+                parameters.color = color;
+            };
+        };
+        if let (Some(value), true) = (self.log_file, unset("log-file")) {
+            parameters.log_file = Some(value);
+        };
+        if let (Some(value), true) = (self.max_file_name_length, unset("max-file-name-length")) {
+            parameters.max_file_name_length = value;
+        };
+        if let (Some(value), true) = (self.manifest, unset("manifest")) {
+            parameters.manifest = Some(value);
+        };
+        if let (Some(value), true) = (self.posts_ndjson, unset("posts-ndjson")) {
+            parameters.posts_ndjson = Some(value);
+        };
+        if let (Some(value), true) = (self.checksum_file, unset("checksum-file")) {
+            parameters.checksum_file = Some(value);
+        };
+        if let (Some(value), true) = (self.dedupe_db, unset("dedupe-db")) {
+            parameters.dedupe_db = Some(value);
+        };
+        if let (Some(value), true) = (self.cache_dir, unset("cache-dir")) {
+            parameters.cache_dir = Some(value);
+        };
+        if let (Some(value), true) = (self.cache_ttl, unset("cache-ttl")) {
+            parameters.cache_ttl = value;
+        };
+        if let (Some(value), true) = (self.output, unset("output")) {
+            parameters.output = value;
+        };
+        if let (Some(value), true) = (self.temp_dir, unset("temp-dir")) {
+            parameters.temp_dir = Some(value);
+        };
+        if let (Some(value), true) = (self.force, unset("force")) {
+            parameters.force = value;
+        };
+        if let (Some(value), true) = (self.skip_html_pages, unset("skip-html-pages")) {
+            parameters.skip_html_pages = value;
+        };
+        if let (Some(value), true) = (self.update, unset("update")) {
+            parameters.update = value;
+        };
+        if let (Some(value), true) = (self.skip_existing, unset("skip-existing")) {
+            parameters.skip_existing = value;
+        };
+        if let (Some(value), true) = (self.skip_duplicate_urls, unset("skip-duplicate-urls")) {
+            parameters.skip_duplicate_urls = value;
+        };
+        if let (Some(value), true) = (self.resume_queue, unset("resume-queue")) {
+            parameters.resume_queue = value;
+        };
+        if let (Some(value), true) = (self.date_index, unset("date-index")) {
+            parameters.date_index = value;
+        };
+        if let (Some(value), true) = (self.no_overwrite, unset("no-overwrite")) {
+            parameters.no_overwrite = value;
+        };
+        if let (Some(value), true) = (
+            self.rename_on_extension_mismatch,
+            unset("rename-on-extension-mismatch"),
+        ) {
+            parameters.rename_on_extension_mismatch = value;
+        };
+        if let (Some(value), true) = (self.quiet_errors, unset("quiet-errors")) {
+            parameters.quiet_errors = value;
+        };
+        if let (Some(value), true) = (self.fail_fast, unset("fail-fast")) {
+            parameters.fail_fast = value;
+        };
+        if let (Some(value), true) = (self.fail_on_unsupported, unset("fail-on-unsupported")) {
+            parameters.fail_on_unsupported = value;
+        };
+        if let (Some(value), true) = (self.no_parent, unset("no-parent")) {
+            parameters.no_parent = value;
+        };
+        if let Some(queue_size) = self.queue_size {
+            if !QUEUE_SIZES.contains(&queue_size) {
+                return Err(format!(
+                    "Invalid value '{}' for 'queue-size' in config file: must be between {} and {}",
+                    queue_size,
+                    QUEUE_SIZES.start(),
+                    QUEUE_SIZES.end()
+                ));
+            };
+            if unset("queue-size") {
+                parameters.queue_size = queue_size;
+            };
+        };
+        if let (Some(value), true) = (self.concurrency_adaptive, unset("concurrency-adaptive")) {
+            parameters.concurrency_adaptive = value;
+        };
+        if let Some(page_size) = self.page_size {
+            if !PAGE_SIZES.contains(&page_size) {
+                return Err(format!(
+                    "Invalid value '{}' for 'page-size' in config file: must be between {} and {}",
+                    page_size,
+                    PAGE_SIZES.start(),
+                    PAGE_SIZES.end()
+                ));
+            };
+            if unset("page-size") {
+                parameters.page_size = page_size;
+            };
+        };
+        if let Some(concurrent_subreddits) = self.concurrent_subreddits {
+            if concurrent_subreddits == 0 {
+                return Err(String::from(
+                    "Invalid value '0' for 'concurrent-subreddits' in config file: must be at least 1",
+                ));
+            };
+            if unset("concurrent-subreddits") {
+                parameters.concurrent_subreddits = concurrent_subreddits;
+            };
+        };
+        if let Some(gallery_depth) = self.gallery_depth {
+            if gallery_depth == 0 {
+                return Err(String::from(
+                    "Invalid value '0' for 'gallery-depth' in config file: must be at least 1",
+                ));
+            };
+            if unset("gallery-depth") {
+                parameters.gallery_depth = gallery_depth;
+            };
+        };
+        if let (Some(value), true) = (self.pool_idle_per_host, unset("pool-idle-per-host")) {
+            parameters.pool_idle_per_host = Some(value);
+        };
+        if let (Some(value), true) = (self.pool_idle_timeout, unset("pool-idle-timeout")) {
+            parameters.pool_idle_timeout = Some(value);
+        };
+        if let (Some(value), true) = (self.max_redirects, unset("max-redirects")) {
+            parameters.max_redirects = value;
+        };
+        if let (Some(value), true) = (self.ca_cert, unset("ca-cert")) {
+            parameters.ca_cert = Some(value);
+        };
+        if let Some(address) = self.metrics_addr {
+            let address = parse_address(&address).map_err(|e| {
+                format!(
+                    "Invalid value '{}' for 'metrics-addr' in config file: {}",
+                    address, e
+                )
+            })?;
+            if unset("metrics-addr") {
+                parameters.metrics_addr = Some(address);
+            };
+        };
+        if let (Some(value), true) = (self.selfposts, unset("selfposts")) {
+            parameters.selfposts = value;
+        };
+        // `--only-nsfw` and `--no-nsfw` conflict with each other
+        if unset("only-nsfw") && unset("no-nsfw") {
+            if let Some(value) = self.only_nsfw {
+                parameters.only_nsfw = value;
+            };
+            if let Some(value) = self.no_nsfw {
+                parameters.no_nsfw = value;
+            };
+        };
+        if let (Some(value), true) = (self.exclude_stickied, unset("exclude-stickied")) {
+            parameters.exclude_stickied = value;
+        };
+        if let (Some(value), true) = (self.exclude_pinned, unset("exclude-pinned")) {
+            parameters.exclude_pinned = value;
+        };
+        if let (Some(value), true) = (self.include_removed, unset("include-removed")) {
+            parameters.include_removed = value;
+        };
+        if let (Some(value), true) = (self.mirror, unset("mirror")) {
+            parameters.mirror = value;
+        };
+        if let (Some(value), true) = (self.group_by_domain, unset("group-by-domain")) {
+            parameters.group_by_domain = value;
+        };
+        if let Some(aliases) = self.alias {
+            let aliases = aliases
+                .iter()
+                .map(|alias| parse_alias(alias))
+                .collect::<Result<Vec<_>, _>>()?;
+            if unset("alias") {
+                parameters.aliases = aliases;
+            };
+        };
+        if let (Some(value), true) = (self.flatten_galleries, unset("flatten-galleries")) {
+            parameters.flatten_galleries = value;
+        };
+        if let (Some(value), true) = (self.number, unset("number")) {
+            parameters.number = value;
+        };
+        if let (Some(value), true) = (self.name_by_source, unset("name-by-source")) {
+            parameters.name_by_source = value;
+        };
+        if let (Some(value), true) = (self.lowercase_filenames, unset("lowercase-filenames")) {
+            parameters.lowercase_filenames = value;
+        };
+        if let (Some(value), true) = (self.no_normalize_unicode, unset("no-normalize-unicode")) {
+            parameters.no_normalize_unicode = value;
+        };
+        if let (Some(value), true) = (self.truncate_at_word, unset("truncate-at-word")) {
+            parameters.truncate_at_word = value;
+        };
+        if let (Some(value), true) = (self.ascii_filenames, unset("ascii-filenames")) {
+            parameters.ascii_filenames = value;
+        };
+        if let (Some(value), true) = (self.filename_prefix, unset("filename-prefix")) {
+            parameters.filename_prefix = Some(title::clean(&value));
+        };
+        if let (Some(value), true) = (self.filename_suffix, unset("filename-suffix")) {
+            parameters.filename_suffix = Some(title::clean(&value));
+        };
+        if let (Some(value), true) = (self.zero_pad, unset("zero-pad")) {
+            parameters.zero_pad = Some(value);
+        };
+        if let Some(domains) = self.allow {
+            let domains = parse_domain_list(domains)?;
+            if unset("allow") {
+                parameters.allow = Some(domains);
+            };
+        };
+        if let Some(domains) = self.exclude {
+            let domains = parse_domain_list(domains)?;
+            if unset("exclude") {
+                parameters.exclude = Some(domains);
+            };
+        };
+        if let (Some(value), true) = (self.allow_file, unset("allow-file")) {
+            parameters.allow_file = Some(value);
+        };
+        if let (Some(value), true) = (self.exclude_file, unset("exclude-file")) {
+            parameters.exclude_file = Some(value);
+        };
+        if let Some(domains) = self.record_only {
+            let domains = parse_domain_list(domains)?;
+            if unset("record-only") {
+                parameters.record_only = domains;
+            };
+        };
+        if let Some(filters) = self.filter {
+            let filters = filters
+                .iter()
+                .map(|filter| parse_filter(filter))
+                .collect::<Result<Vec<_>, _>>()?;
+            if unset("filter") {
+                parameters.filter = filters;
+            };
+        };
+        if let Some(fields) = self.print_fields {
+            let fields = fields
+                .iter()
+                .map(|field| parse_field(field))
+                .collect::<Result<Vec<_>, _>>()?;
+            if unset("print-fields") {
+                parameters.print_fields = fields;
+            };
+        };
+        if let (Some(value), true) = (self.flair, unset("flair")) {
+            parameters.flair = value;
+        };
+        if let Some(flair_filter_mode) = self.flair_filter_mode {
+            if !["include", "exclude"].contains(&flair_filter_mode.as_str()) {
+                return Err(format!(
+                    "Invalid value '{}' for 'flair-filter-mode' in config file",
+                    flair_filter_mode
+                ));
+            };
+            if unset("flair-filter-mode") {
+                parameters.flair_filter_mode = FlairFilterMode::from(flair_filter_mode.as_str());
+            };
+        };
+        if let (Some(value), true) = (self.flair_case_sensitive, unset("flair-case-sensitive")) {
+            parameters.flair_case_sensitive = value;
+        };
+        if let (Some(value), true) = (self.require_flair, unset("require-flair")) {
+            parameters.require_flair = value;
+        };
+        if let Some(gfycat_type) = self.gfycat_type {
+            if !["mp4", "webm"].contains(&gfycat_type.as_str()) {
+                return Err(format!(
+                    "Invalid value '{}' for 'gfycat-type' in config file",
+                    gfycat_type
+                ));
+            };
+            if unset("gfycat-type") {
+                parameters.gfycat_type = GfycatType::from(gfycat_type.as_str());
+            };
+        };
+        if let Some(gallery_archive) = self.gallery_archive {
+            if gallery_archive != "zip" {
+                return Err(format!(
+                    "Invalid value '{}' for 'gallery-archive' in config file",
+                    gallery_archive
+                ));
+            };
+            if unset("gallery-archive") {
+                parameters.gallery_archive = Some(ArchiveFormat::from(gallery_archive.as_str()));
+            };
+        };
+        if let Some(collision_strategy) = self.collision_strategy {
+            if !["overwrite", "skip", "rename"].contains(&collision_strategy.as_str()) {
+                return Err(format!(
+                    "Invalid value '{}' for 'collision-strategy' in config file",
+                    collision_strategy
+                ));
+            };
+            if unset("collision-strategy") {
+                parameters.collision_strategy = Collision::from(collision_strategy.as_str());
+            };
+        };
+        if let Some(flatten) = self.flatten {
+            if !["never", "always", "auto"].contains(&flatten.as_str()) {
+                return Err(format!(
+                    "Invalid value '{}' for 'flatten' in config file",
+                    flatten
+                ));
+            };
+            if unset("flatten") && unset("no-parent") {
+                parameters.flatten = Flatten::from(flatten.as_str());
+            };
+        };
+        if let Some(gfycat_fallback) = self.gfycat_fallback {
+            if !["none", "wayback"].contains(&gfycat_fallback.as_str()) {
+                return Err(format!(
+                    "Invalid value '{}' for 'gfycat-fallback' in config file",
+                    gfycat_fallback
+                ));
+            };
+            if unset("gfycat-fallback") {
+                parameters.gfycat_fallback = GfycatFallback::from(gfycat_fallback.as_str());
+            };
+        };
+        if let (Some(value), true) = (self.precheck, unset("precheck")) {
+            parameters.precheck = value;
+        };
+        if let (Some(value), true) = (self.insecure, unset("insecure")) {
+            parameters.insecure = value;
+        };
+        if let (Some(value), true) = (self.prefer_mp4, unset("prefer-mp4")) {
+            parameters.prefer_mp4 = value;
+        };
+        if let (Some(value), true) = (self.thumbnails_only, unset("thumbnails-only")) {
+            parameters.thumbnails_only = value;
+        };
+        if let (Some(value), true) = (self.max_resolution, unset("max-resolution")) {
+            parameters.max_resolution = Some(value);
+        };
+        if let (Some(value), true) = (self.min_comments, unset("min-comments")) {
+            parameters.min_comments = Some(value);
+        };
+        if let Some(max_total_size) = self.max_total_size {
+            let max_total_size = parse_size(&max_total_size)
+                .map_err(|e| format!("Invalid value for 'max-total-size' in config file: {}", e))?;
+            if unset("max-total-size") {
+                parameters.max_total_size = Some(max_total_size);
+            };
+        };
+        if let (Some(value), true) = (self.interval, unset("interval")) {
+            parameters.interval = Some(value);
+        };
+        if let (Some(value), true) = (self.vreddit_mode, unset("vreddit-mode")) {
+            parameters.vreddit_mode = VRedditMode::from(value.as_str());
+        };
+        if let Some(selftext_format) = self.selftext_format {
+            if !["raw", "md", "html"].contains(&selftext_format.as_str()) {
+                return Err(format!(
+                    "Invalid value '{}' for 'selftext-format' in config file",
+                    selftext_format
+                ));
+            };
+            if unset("selftext-format") {
+                parameters.selftext_format = SelftextFormat::from(selftext_format.as_str());
+            };
+        };
+        if let (Some(value), true) = (self.title, unset("title")) {
+            parameters.title = Title::parse(&value)
+                .map_err(|e| format!("Invalid value for 'title' in config file: {}", e))?;
+        };
+        if let (Some(value), true) = (self.exec, unset("exec")) {
+            parameters.exec = Some(Exec::new(&value));
+        };
+        if let (Some(value), true) = (self.client_id, unset("client-id")) {
+            parameters.client_id = Some(value);
+        };
+        if let (Some(value), true) = (self.client_secret, unset("client-secret")) {
+            parameters.client_secret = Some(value);
+        };
+        if let (Some(value), true) = (self.refresh_token, unset("refresh-token")) {
+            parameters.refresh_token = Some(value);
+        };
+
+        validate(parameters)
+    }
+}
+
+/// The arguments which can not be combined, like clap's `conflicts_with`.
+static CONFLICTS: &[(&str, &str)] = &[
+    ("fail-on-unsupported", "force"),
+    ("posts-ndjson", "reconcile"),
+    ("posts-ndjson", "retry-failed"),
+    ("rename-on-extension-mismatch", "checksum-file"),
+    ("flatten", "no-parent"),
+    ("mirror", "no-parent"),
+    ("only-nsfw", "no-nsfw"),
+    ("gallery-archive", "flatten-galleries"),
+    ("gallery-archive", "checksum-file"),
+    ("gallery-archive", "dedupe-db"),
+    ("print-fields", "list-only"),
+    ("print-fields", "stdout"),
+    ("stdout", "exec"),
+    ("stdout", "manifest"),
+    ("stdout", "checksum-file"),
+    ("thumbnails-only", "reconcile"),
+    ("thumbnails-only", "retry-failed"),
+];
+
+/// The arguments which need another one, like clap's `requires`.
+static REQUIREMENTS: &[(&str, &str)] = &[
+    ("client-id", "client-secret"),
+    ("client-id", "refresh-token"),
+    ("client-secret", "client-id"),
+    ("refresh-token", "client-id"),
+];
+
+/// Checks the merged parameters for arguments which can not be combined.
+///
+/// clap only validates the command line, so a value of the config file
+/// could otherwise conflict with an argument without being noticed.
+fn validate(parameters: &Parameters) -> Result<(), String> {
+    let set = |name: &str| match name {
+        "fail-on-unsupported" => parameters.fail_on_unsupported,
+        "force" => parameters.force,
+        "posts-ndjson" => parameters.posts_ndjson.is_some(),
+        "reconcile" => parameters.reconcile.is_some(),
+        "retry-failed" => parameters.retry_failed.is_some(),
+        "rename-on-extension-mismatch" => parameters.rename_on_extension_mismatch,
+        "checksum-file" => parameters.checksum_file.is_some(),
+        "flatten" => parameters.flatten != Flatten::Never,
+        "no-parent" => parameters.no_parent,
+        "mirror" => parameters.mirror,
+        "only-nsfw" => parameters.only_nsfw,
+        "no-nsfw" => parameters.no_nsfw,
+        "gallery-archive" => parameters.gallery_archive.is_some(),
+        "flatten-galleries" => parameters.flatten_galleries,
+        "dedupe-db" => parameters.dedupe_db.is_some(),
+        "print-fields" => !parameters.print_fields.is_empty(),
+        "list-only" => parameters.list_only,
+        "stdout" => parameters.stdout,
+        "exec" => parameters.exec.is_some(),
+        "manifest" => parameters.manifest.is_some(),
+        "client-id" => parameters.client_id.is_some(),
+        "client-secret" => parameters.client_secret.is_some(),
+        "refresh-token" => parameters.refresh_token.is_some(),
+        "thumbnails-only" => parameters.thumbnails_only,
+        _ => unreachable!(), // Every name of the tables is listed
+    };
+
+    for (a, b) in CONFLICTS {
+        if set(a) && set(b) {
+            return Err(format!(
+                "'{}' can not be combined with '{}' (after merging the config file)",
+                a, b
+            ));
+        };
+    }
+    for (a, b) in REQUIREMENTS {
+        if set(a) && !set(b) {
+            return Err(format!(
+                "'{}' requires '{}' (after merging the config file)",
+                a, b
+            ));
+        };
+    }
+
+    Ok(())
+}
+
+/// Parses every domain in the list.
+fn parse_domain_list(domains: Vec<String>) -> Result<Vec<String>, String> {
+    domains.iter().map(|domain| parse_domains(domain)).collect()
+}
+
+#[test]
+fn config_apply() {
+    use structopt::StructOpt;
+
+    let config: Config = toml::from_str(
+        r#"
+            output = "lorem"
+            queue-size = 4
+            exclude = ["i.redd.it"]
+            title = "{id}"
+            filename-prefix = "2020/01/01-"
+            flatten = "auto"
+            alias = ["u/someuser=someone"]
+        "#,
+    )
+    .unwrap();
+
+    let matches = Parameters::clap().get_matches_from(["test", "--queue-size", "8"]);
+    let mut parameters = Parameters::from_clap(&matches);
+    config.apply(&mut parameters, &matches).unwrap();
+
+    assert_eq!(PathBuf::from("lorem"), parameters.output);
+    assert_eq!(8, parameters.queue_size);
+    assert_eq!(250, parameters.page_size);
+    assert_eq!(Some(vec![String::from("i.redd.it")]), parameters.exclude);
+    assert_eq!(vec!["id"], parameters.title.iter().collect::<Vec<_>>());
+    assert_eq!(
+        Some(String::from("2020_01_01-")),
+        parameters.filename_prefix
+    );
+    assert_eq!(Flatten::Auto, parameters.flatten);
+    assert_eq!(
+        vec![(String::from("u_someuser"), String::from("someone"))],
+        parameters.aliases
+    );
+
+    assert!(toml::from_str::<Config>("unknown = 1").is_err());
+
+    let config: Config = toml::from_str(r#"filter = ["flair=OC"]"#).unwrap();
+    let matches = Parameters::clap().get_matches_from(["test"]);
+    let mut parameters = Parameters::from_clap(&matches);
+    assert!(config.apply(&mut parameters, &matches).is_err());
+
+    let config: Config = toml::from_str(r#"alias = ["pics=.."]"#).unwrap();
+    let matches = Parameters::clap().get_matches_from(["test"]);
+    let mut parameters = Parameters::from_clap(&matches);
+    assert!(config.apply(&mut parameters, &matches).is_err());
+
+    // Conflicts between the config file and the command line are rejected
+    let config: Config = toml::from_str(r#"dedupe-db = "dedupe.db""#).unwrap();
+    let matches = Parameters::clap().get_matches_from(["test", "--gallery-archive", "zip"]);
+    let mut parameters = Parameters::from_clap(&matches);
+    assert!(config.apply(&mut parameters, &matches).is_err());
+
+    for manifest in &["--reconcile", "--retry-failed"] {
+        let config: Config = toml::from_str("thumbnails-only = true").unwrap();
+        let matches = Parameters::clap().get_matches_from(["test", manifest, "manifest.jsonl"]);
+        let mut parameters = Parameters::from_clap(&matches);
+        assert!(config.apply(&mut parameters, &matches).is_err());
+    }
+
+    let config: Config = toml::from_str("only-nsfw = true\nno-nsfw = true").unwrap();
+    let matches = Parameters::clap().get_matches_from(["test"]);
+    let mut parameters = Parameters::from_clap(&matches);
+    assert!(config.apply(&mut parameters, &matches).is_err());
+
+    let config: Config = toml::from_str(r#"client-id = "abc""#).unwrap();
+    let matches = Parameters::clap().get_matches_from(["test"]);
+    let mut parameters = Parameters::from_clap(&matches);
+    assert!(config.apply(&mut parameters, &matches).is_err());
+
+    // The command line takes precedence, so the config value is not merged
+    let config: Config = toml::from_str("only-nsfw = true").unwrap();
+    let matches = Parameters::clap().get_matches_from(["test", "--no-nsfw"]);
+    let mut parameters = Parameters::from_clap(&matches);
+    config.apply(&mut parameters, &matches).unwrap();
+
+    let config: Config = toml::from_str("queue-size = 0").unwrap();
+    let matches = Parameters::clap().get_matches_from(["test"]);
+    let mut parameters = Parameters::from_clap(&matches);
+    assert!(config.apply(&mut parameters, &matches).is_err());
+
+    assert!(Parameters::clap()
+        .get_matches_from_safe(["test", "--queue-size", "0"])
+        .is_err());
+    assert!(Parameters::clap()
+        .get_matches_from_safe(["test", "--queue-size", "1001"])
+        .is_err());
+}
@@ -0,0 +1,233 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+Deduplication of downloads across runs, see `--dedupe-db`.
+
+The database maps the SHA-256 hash of every saved file to the first path
+it was saved to. It uses the format of checksum files, one hash and an
+absolute path per line, and is only ever appended to. If the same hash
+occurs multiple times, the last line wins.
+
+Downloads are hashed while they are written. If the content was saved
+before, the new file is replaced with a hard link to the first one,
+or a symbolic link if the files are on different file systems.
+
+Linked files share their content, so they must never be written in place.
+Downloads are moved over the output instead, see [`Storage::create_file()`],
+which replaces the link and leaves the other file untouched.
+
+[`Storage::create_file()`]: ../storage/struct.Storage.html#method.create_file
+*/
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::checksum::{absolute, line, Digest, Sha256};
+use crate::prelude::*;
+
+/// A deduplication database opened for appending lines.
+#[derive(Debug)]
+pub struct Dedupe {
+    /// The file and the known paths by hex encoded hash.
+    ///
+    /// Both are locked together, so identical files which finish
+    /// at the same time can not both be recorded as the first one.
+    inner: Mutex<(File, HashMap<String, PathBuf>)>,
+}
+
+impl Dedupe {
+    /// Opens the database, creating it if it does not exist.
+    pub fn open(path: &Path) -> Result<Dedupe> {
+        trace!("Dedupe::open({:?})", path);
+
+        let mut known = HashMap::new();
+        match File::open(path) {
+            Ok(file) => {
+                for i in BufReader::new(file).lines() {
+                    if let Some((hash, path)) = parse_line(&i?) {
+                        known.insert(hash.to_string(), PathBuf::from(path));
+                    };
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        };
+        debug!("Loaded {} known files from {:?}", known.len(), path);
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Dedupe {
+            inner: Mutex::new((file, known)),
+        })
+    }
+
+    /// Links a saved file to an earlier file with the same content,
+    /// or records it as the first file with this content.
+    /// Failures are only logged, they do not affect the download.
+    pub fn deduplicate(&self, path: &Path, hasher: Sha256) {
+        let path = match absolute(path) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("Failed to resolve the path of {:?}: {}", path, e);
+                return;
+            }
+        };
+        let line = line(&hasher.result(), &path);
+        let hash = &line[..64];
+
+        // The lock is only poisoned if another job panicked while writing
+        let mut inner = self.inner.lock().unwrap();
+        let (file, known) = &mut *inner;
+
+        // The first file may have been deleted or moved since
+        match known.get(hash) {
+            Some(first) if *first != path && first.exists() => {
+                match link(first, &path) {
+                    Ok(()) => debug!("Linked {:?} to the identical {:?}", path, first),
+                    Err(e) => warn!("Failed to link {:?} to {:?}: {}", path, first, e),
+                };
+                return;
+            }
+            Some(first) if *first == path => return,
+            _ => (),
+        };
+
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            warn!("Failed to write to the dedupe database: {}", e);
+        };
+        known.insert(hash.to_string(), path);
+    }
+}
+
+/// Parses a line of the database into the hash and the path.
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let (hash, path) = line.split_at(line.find("  ")?);
+    let path = &path[2..];
+
+    if hash.len() == 64 && !path.is_empty() {
+        Some((hash, path))
+    } else {
+        None
+    }
+}
+
+/// Replaces a file with a link to another file.
+/// A hard link is preferred, because it survives the other file being moved.
+fn link(original: &Path, path: &Path) -> io::Result<()> {
+    // The link is created next to the file first, so the file is not lost if linking fails
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".link");
+    let temp = PathBuf::from(temp);
+
+    if fs::hard_link(original, &temp).is_err() {
+        symlink(original, &temp)?;
+    };
+
+    fs::rename(&temp, path)
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
+
+#[test]
+#[allow(unused_must_use)]
+fn dedupe_link() {
+    let mut root = std::env::temp_dir();
+    root.push("redditrip_dedupe");
+    fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let database = root.join("dedupe.sha256");
+    let hash = |data: &[u8]| {
+        let mut hasher = Sha256::new();
+        hasher.input(data);
+        hasher
+    };
+
+    let first = root.join("abc-first.jpg");
+    fs::write(&first, "Lorem").unwrap();
+    Dedupe::open(&database)
+        .unwrap()
+        .deduplicate(&first, hash(b"Lorem"));
+
+    // A later run finds the first file
+    let dedupe = Dedupe::open(&database).unwrap();
+    let second = root.join("def-second.jpg");
+    fs::write(&second, "Lorem").unwrap();
+    dedupe.deduplicate(&second, hash(b"Lorem"));
+    let other = root.join("ghi-other.jpg");
+    fs::write(&other, "ipsum").unwrap();
+    dedupe.deduplicate(&other, hash(b"ipsum"));
+
+    fs::write(&first, "Lorem ipsum").unwrap();
+    assert_eq!("Lorem ipsum", fs::read_to_string(&second).unwrap());
+    assert_eq!("ipsum", fs::read_to_string(&other).unwrap());
+    assert_eq!(2, fs::read_to_string(&database).unwrap().lines().count());
+    assert!(!root.join("def-second.jpg.link").exists());
+
+    let hash = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    assert_eq!(
+        Some((hash, "/pics/a  b.jpg")),
+        parse_line(&format!("{}  /pics/a  b.jpg", hash))
+    );
+    assert_eq!(None, parse_line("ba7816bf  /pics/abc.jpg"));
+    assert_eq!(None, parse_line(hash));
+
+    fs::remove_dir_all(&root);
+}
+
+#[tokio::test]
+#[allow(unused_must_use)]
+async fn dedupe_rewrite() {
+    let mut root = std::env::temp_dir();
+    root.push("redditrip_dedupe_rewrite");
+    fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let hash = |data: &[u8]| {
+        let mut hasher = Sha256::new();
+        hasher.input(data);
+        hasher
+    };
+
+    let storage = Storage {
+        dedupe: Some(Dedupe::open(&root.join("dedupe.sha256")).unwrap()),
+        ..Storage::default()
+    };
+    let dedupe = storage.dedupe.as_ref().unwrap();
+    let first = root.join("abc-first.jpg");
+    fs::write(&first, "Lorem").unwrap();
+    dedupe.deduplicate(&first, hash(b"Lorem"));
+    let second = root.join("def-second.jpg");
+    fs::write(&second, "Lorem").unwrap();
+    dedupe.deduplicate(&second, hash(b"Lorem"));
+
+    // Downloading the duplicate again replaces the link
+    assert!(storage.write(&second, b"ipsum").await.unwrap());
+    assert_eq!("ipsum", fs::read_to_string(&second).unwrap());
+    assert_eq!("Lorem", fs::read_to_string(&first).unwrap());
+}
@@ -32,6 +32,9 @@ pub const HELP_JSON: &'static str = "This is likely caused by a broken backend a
 /// A helpful message if ffmpeg failed.
 pub const HELP_FFMPEG: &'static str = "This was an error with ffmpeg. Consider updating your local copy, or use a different '--vreddit-mode'.";
 
+/// A helpful message if the reddit authorization failed.
+pub const HELP_OAUTH: &'static str = "Make sure that '--client-id', '--client-secret' and '--refresh-token' belong to the same reddit app and that the token has the 'identity' and 'history' scopes.";
+
 /// A convenient type for fallible operations.
 pub type Result<T> = result::Result<T, Error>;
 
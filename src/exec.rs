@@ -0,0 +1,171 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+Commands run on every downloaded file, see `--exec`.
+
+The command is a template in which `{}` is replaced by the path of the file
+and the placeholders of `--title` by the values of the post. Every inserted
+value is quoted, so titles can not inject shell syntax.
+*/
+
+use std::{path::Path, process::Stdio};
+
+use serde_json::Value;
+use tokio::process::Command;
+
+use crate::title::{FIELDS, SYNTHETIC_FIELDS};
+
+/// A command template.
+#[derive(Debug)]
+pub struct Exec {
+    /// The template as passed on the command line.
+    template: String,
+
+    /// The fields that are present in the template.
+    fields: Vec<&'static str>,
+}
+
+impl Exec {
+    /// Creates a command template.
+    pub fn new(template: &str) -> Exec {
+        let fields = FIELDS
+            .iter()
+            .chain(SYNTHETIC_FIELDS)
+            .filter(|field| template.contains(&format!("{{{}}}", field)))
+            .copied()
+            .collect();
+
+        Exec {
+            template: template.to_string(),
+            fields,
+        }
+    }
+
+    /// Returns whether the placeholder of the field is in the template.
+    pub fn utilizes(&self, field: &str) -> bool {
+        self.fields.contains(&field)
+    }
+
+    /// Returns an iterator over the fields that are part of the post data.
+    /// Synthetic fields are left out.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.fields
+            .iter()
+            .filter(|item| !SYNTHETIC_FIELDS.contains(item))
+            .copied()
+    }
+
+    /// Extracts the values of the fields in the template from the post data.
+    pub fn values(&self, json: &Value) -> Value {
+        let values = self
+            .fields
+            .iter()
+            .map(|field| (field.to_string(), json[*field].clone()))
+            .collect();
+
+        Value::Object(values)
+    }
+
+    /// Creates the command for a post.
+    /// The `json` parameter contains the replacement values.
+    pub fn command(&self, json: &Value, output: &Path) -> String {
+        let mut result = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+
+        while let Some(start) = rest.find('{') {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let name = rest
+                .find('}')
+                .map(|end| &rest[1..end])
+                .filter(|name| name.is_empty() || self.utilizes(name));
+
+            match name {
+                Some("") => result.push_str(&quote(&output.to_string_lossy())),
+                Some(name) => result.push_str(&quote(&match &json[name] {
+                    Value::Null => String::new(),
+                    Value::String(value) => value.to_owned(),
+                    value => value.to_string(),
+                })),
+                None => {
+                    result.push('{');
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+
+            rest = &rest[name.unwrap().len() + 2..];
+        }
+
+        result.push_str(rest);
+        result
+    }
+}
+
+/// Runs a command using the shell.
+/// Failures are only logged, they do not affect the download.
+pub async fn run(command: &str) {
+    trace!("run({:?})", command);
+
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+
+    match shell
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .await
+    {
+        Ok(status) if status.success() => debug!("Ran `{}`", command),
+        Ok(status) => warn!("Command `{}` returned error status {}", command, status),
+        Err(e) => warn!("Failed to run command `{}`: {}", command, e),
+    };
+}
+
+/// Quotes a value for the shell.
+fn quote(value: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", value.replace('"', ""))
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+#[test]
+#[cfg(not(windows))]
+fn exec_command() {
+    use serde_json::json;
+
+    let exec = Exec::new("tag {} --title {title} {id}{nothing} {");
+    assert_eq!(vec!["id", "title"], exec.iter().collect::<Vec<_>>());
+
+    let values = exec.values(&json!({ "id": "abc", "title": "It's {id} $(rm)", "author": "a" }));
+    assert_eq!(json!({ "id": "abc", "title": "It's {id} $(rm)" }), values);
+    assert_eq!(
+        r#"tag '/tmp/a b.jpg' --title 'It'\''s {id} $(rm)' 'abc'{nothing} {"#,
+        exec.command(&values, Path::new("/tmp/a b.jpg"))
+    );
+}
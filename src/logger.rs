@@ -16,9 +16,18 @@
 
 /*!
 A logging implementation for this crate.
+
+With `--log-file`, every message is also appended to a file with a
+timestamp and without colors, independent of `--quiet`.
 */
 
-use std::{fmt::Display, process};
+use std::{
+    fmt::{self, Display},
+    fs::File,
+    io::Write,
+    process,
+    sync::Mutex,
+};
 
 use ansi_term::Color;
 use log::{Level, LevelFilter, Log, Metadata, Record};
@@ -30,6 +39,17 @@ struct Logger {
 
     /// Whether colors should be sent to stderr.
     stderr_colors: bool,
+
+    /// Whether every message is sent to stderr,
+    /// because stdout is used for the download, see `--stdout`.
+    stderr_only: bool,
+
+    /// The most verbose level printed to the terminal.
+    /// The file may receive more verbose messages.
+    level: LevelFilter,
+
+    /// The file every message is appended to, see `--log-file`.
+    file: Option<Mutex<File>>,
 }
 
 impl Logger {
@@ -42,6 +62,38 @@ impl Logger {
     pub fn supports_colors_stderr() -> bool {
         log::logger().enabled(&Metadata::builder().target("stderr").build())
     }
+
+    /// Prints a message which is usually sent to stdout.
+    fn print(&self, message: fmt::Arguments) {
+        if self.stderr_only {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+
+    /// Appends a message to the log file, if there is one.
+    fn write_file(&self, record: &Record) {
+        let file = match self.file {
+            Some(ref file) => file,
+            None => return,
+        };
+
+        let level = match record.level() {
+            Level::Trace => "[TRACE]  ",
+            Level::Debug => "[VERBOSE]",
+            Level::Info => "[INFO]   ",
+            Level::Warn => "[ERROR]  ",
+            Level::Error => "[FATAL]  ",
+        };
+        let time = time::strftime("%F %T", &time::now()).unwrap_or_default();
+        let message = strip_colors(&record.args().to_string());
+
+        // The lock is only poisoned if another thread panicked while writing,
+        // and failing to log can not be logged either
+        let mut file = file.lock().unwrap();
+        let _ = writeln!(file, "{} {} {}", time, level, message);
+    }
 }
 
 impl Log for Logger {
@@ -64,12 +116,21 @@ impl Log for Logger {
             return;
         };
 
+        self.write_file(record);
+        if record.level() > self.level {
+            return;
+        };
+
         match record.level() {
             Level::Trace => {
-                println!("[TRACE]   {}:{}", record.target(), record.args());
+                self.print(format_args!(
+                    "[TRACE]   {}:{}",
+                    record.target(),
+                    record.args()
+                ));
             }
             Level::Debug => {
-                println!("[VERBOSE] {}", record.args());
+                self.print(format_args!("[VERBOSE] {}", record.args()));
             }
             Level::Info => {
                 let text: Box<dyn Display> = if cfg!(not(windows)) && self.stdout_colors {
@@ -77,7 +138,7 @@ impl Log for Logger {
                 } else {
                     Box::new("[INFO]")
                 };
-                println!("{}    {}", text, record.args());
+                self.print(format_args!("{}    {}", text, record.args()));
             }
             Level::Warn => {
                 let text: Box<dyn Display> = if cfg!(not(windows)) && self.stderr_colors {
@@ -98,26 +159,49 @@ impl Log for Logger {
         };
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        if let Some(ref file) = self.file {
+            let _ = file.lock().unwrap().flush();
+        };
+    }
 }
 
 /// Initializes the logger.
-pub fn init(verbose: usize, stdout_colors: bool, stderr_colors: bool) {
+///
+/// If `stderr_only` is set, messages which are usually printed
+/// to stdout are printed to stderr instead.
+/// The `file` receives at least the informational messages.
+pub fn init(
+    verbose: usize,
+    stdout_colors: bool,
+    stderr_colors: bool,
+    stderr_only: bool,
+    file: Option<File>,
+) {
+    let level = match verbose {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        5 => LevelFilter::Trace,
+        _ => unreachable!(), // Guaranteed from `main()`
+    };
+    let max_level = if file.is_some() {
+        level.max(LevelFilter::Info)
+    } else {
+        level
+    };
     let logger = Logger {
         stdout_colors,
         stderr_colors,
+        stderr_only,
+        level,
+        file: file.map(Mutex::new),
     };
 
     match log::set_boxed_logger(Box::new(logger)) {
-        Ok(()) => match verbose {
-            0 => log::set_max_level(LevelFilter::Off),
-            1 => log::set_max_level(LevelFilter::Error),
-            2 => log::set_max_level(LevelFilter::Warn),
-            3 => log::set_max_level(LevelFilter::Info),
-            4 => log::set_max_level(LevelFilter::Debug),
-            5 => log::set_max_level(LevelFilter::Trace),
-            _ => unreachable!(), // Guaranteed from `main()`
-        },
+        Ok(()) => log::set_max_level(max_level),
         Err(e) => {
             let text: Box<dyn Display> = if cfg!(not(windows)) && stderr_colors {
                 Box::new(Color::Red.bold().italic().paint("[FATAL]   Fatal error"))
@@ -133,6 +217,27 @@ pub fn init(verbose: usize, stdout_colors: bool, stderr_colors: bool) {
     };
 }
 
+/// Removes the ANSI escape sequences of colors from a message.
+fn strip_colors(message: &str) -> String {
+    let mut result = String::with_capacity(message.len());
+    let mut chars = message.chars();
+
+    while let Some(i) = chars.next() {
+        if i == '\u{1b}' {
+            // Sequences like `ESC[1;36m` end with a letter
+            for i in chars.by_ref() {
+                if i.is_ascii_alphabetic() {
+                    break;
+                };
+            }
+        } else {
+            result.push(i);
+        };
+    }
+
+    result
+}
+
 /// Colors a string, respecting whether colors are enabled on the `stdout` stream.
 pub fn color_stdout(input: &impl Display) -> Box<dyn Display> {
     let input = format!("{}", input);
@@ -153,9 +258,21 @@ pub fn color_stderr(input: &impl Display) -> Box<dyn Display> {
     }
 }
 
+#[test]
+fn log_file_colors() {
+    assert_eq!(
+        "Downloading Lorem ipsum",
+        strip_colors(&format!(
+            "Downloading {}",
+            Color::Cyan.bold().paint("Lorem ipsum")
+        ))
+    );
+    assert_eq!("Lorem [ipsum]", strip_colors("Lorem [ipsum]"));
+}
+
 #[test]
 pub fn logger() {
-    init(1, false, true);
+    init(1, false, true, false, None);
 
     assert!(!Logger::supports_colors_stdout());
     assert!(Logger::supports_colors_stderr());
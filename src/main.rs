@@ -24,75 +24,314 @@ A versatile tool for downloading the linked contents of entire subreddits fast a
 ## Flags
 
 - `-q, --quiet`
- Disable output on stdout
+  Disable output on stdout
+
+- `--quiet-errors`
+  Log failed downloads only with '--verbose' instead of printing a warning for each of them. They are still counted in the summary at the end of every subreddit. This keeps the output readable when ripping old subreddits full of dead links.
+
+- `--fail-fast`
+  Stop the whole run if the directory of a subreddit can not be created. By default, the subreddit is skipped with an error and the remaining subreddits are still downloaded.
+
+- `--fail-on-unsupported`
+  Exit with a non-zero code if any post links to an unsupported domain, and list the unsupported domains with their number of posts at the end of the run. This shows which sites are missing the most. Can not be combined with '--force'.
 
 - `-v`, `--verbose`
- Enable verbose output
+  Enable verbose output
 
 - `--domains`
- Output a list of supported domains
+  Output a list of supported domains
+
+- `--domains-json`
+  Output the supported domains as a JSON array, for use in scripts. Every object has the fields 'domain', 'api', which is true if the media is resolved using an API or web page instead of being downloaded directly, and 'tools', a list of external programs used by some modes, like 'ffmpeg' for 'v.redd.it'.
 
 - `-f`, `--force`
- Whether to force the download from unsupported domains by simpling writing whatever is on the page to disk.
+  Whether to force the download from unsupported domains by simpling writing whatever is on the page to disk.
+
+- `--skip-html-pages`
+  With '--force', fail downloads which return a web page instead of the expected image or video, like the error pages of many hosts. By default, these pages are saved with the '.html' extension instead of the extension of the media, with a warning.
 
 - `--formatting-fields`
- Display the possible placeholders for the '--title' argument. Note that not all fields are set for every post.
+  Display the possible placeholders for the '--title' argument. Note that not all fields are set for every post.
+
+- `--formatting-fields-json`
+  Output the possible placeholders for the '--title' argument as a JSON array, for use in scripts. Every object has the fields 'name', 'type' and 'always_present', which is true if the field is set for every post, like 'id' and 'created_utc'.
+
+- `--rename-on-extension-mismatch`
+  Rename downloaded files whose content does not match their file extension, like videos linked as '.gif' or images without extension. The type is detected from the first bytes of the file or the 'Content-Type' header. Note that '--update' and '--skip-existing' only know the original file names. Can not be combined with '--checksum-file'.
+
+- `--no-overwrite`
+  Never overwrite a file that already exists, but continue with the remaining posts, unlike '--update'. In contrast to '--skip-existing', this is checked when a file is written, which also covers posts mapping to the same file name.
 
 - `--no-parent`
- Normally, a directory is created as a subdirectory of '--output'. This option causes the files to be placed directly within '--output'.
+  Normally, a directory is created as a subdirectory of '--output'. This option causes the files to be placed directly within '--output'.
+
+- `--mirror`
+  Lay out the files like the URLs on reddit, in 'r/<subreddit>/<year>/<month>' for subreddits and 'u/<user>/<year>/<month>' for profiles, using the creation date of the posts. Every directory, up to '--output', gets an 'index.html' file listing its contents, so the archive can be browsed. The index files are regenerated from the directory contents on every run, so they also list the files of earlier runs. This overrides '--flatten'.
+
+- `--group-by-domain`
+  Sort the files into subdirectories named after the domain of the post, like 'i.redd.it' or 'imgur.com'. The domain directory is placed within the subreddit directory, or directly within '--output' with '--no-parent'. With '--mirror', it is placed within the year and month directories.
+
+- `--flatten-galleries`
+  Normally, the items of galleries and albums are saved to a subdirectory named after the post. This option causes them to be placed directly next to the other files instead, named after the post with the index of the item appended.
+
+- `--gallery-archive <format>`
+  Save the items of galleries and albums to a single archive named after the post, like 'abc-Title.zip', instead of a subdirectory. Every item is moved into the archive as soon as it is downloaded, so the items do not take up the disk space twice. The items are stored without compression. Can not be combined with '--flatten-galleries', '--checksum-file' or '--dedupe-db'. [possible values: zip]
+
+- `--gallery-depth <n>`
+  The number of levels of Imgur albums which are downloaded. Albums linked in the descriptions of the images of an album are saved to a subdirectory named after their ID, up to this many levels deep. Every album is only downloaded once per post, so albums linking to each other do not loop. [default: 1]
+
+- `--no-normalize-unicode`
+  Keep the characters of file names as they are. By default, file names are converted to the Unicode normalization form C, so accented characters which can be written in multiple ways always result in the same file name, for example across macOS and Linux.
+
+- `--truncate-at-word`
+  Shorten file names which are longer than '--max-file-name-length' at the last whitespace before the limit, instead of in the middle of a word. If the first word alone is already too long, it is cut at the limit.
+
+- `--lowercase-filenames`
+  Convert file names to lowercase, so archives behave the same on case-insensitive file systems.
+
+- `--ascii-filenames`
+  Remove all characters which are not ASCII from file names, so they are portable to any file system. Accented letters like 'é' and common punctuation are replaced by their closest ASCII equivalent. If nothing remains of a file name, the post ID is used instead.
+
+- `--name-by-source`
+  Name the files after the ID of the media on its host instead of '--title', so the same media always gets the same file name, no matter which post linked it. This covers the hashes of Imgur images, the IDs of Gfycat and RedGifs videos and the files on 'v.redd.it' and 'i.redd.it'. Other posts are still named after '--title'. '--number', '--filename-prefix' and '--filename-suffix' are applied as usual.
+
+- `--number`
+  Prepend a zero-padded sequence number to every file name, for example `001-`. The numbers follow the order of the posts, newest first, and not the order in which the downloads complete.
+
+- `--precheck`
+  Before downloading a post, check with a HEAD request whether the link is dead, falling back to GET for hosts which do not support HEAD. Links returning 404 or 410 are counted as failed without being downloaded. This reduces the wasted connections on old subreddits full of dead links, at the cost of an additional request for every working link.
+
+- `--insecure`
+  Accept any TLS certificate and host name, for example to run behind an intercepting proxy. This makes every connection vulnerable to interception, prefer adding the certificate of the proxy with '--ca-cert'.
+
+- `--prefer-mp4`
+  Download the '.mp4' variant of animated '.gif' and '.gifv' files from Imgur, which is a lot smaller. If the variant is not available, the original file is downloaded. GIFs hosted on 'i.redd.it' are downloaded as the video reddit converted them to, if the post has one.
 
 - `-s`, `--selfposts`
- Download self posts as text files
+  Download self posts as text files
+
+- `--flair-case-sensitive`
+  Compare the flairs of '--flair' case-sensitively.
+
+- `--require-flair`, `--flair-required`
+  Skip posts without a flair, for subreddits which mark their content with flairs. This also applies with '--flair-filter-mode exclude'.
+
+- `--list-only`
+  Print the URL of every post to stdout, one per line, instead of downloading it, for example to pass them to another downloader. All filters and dates are applied, and unsupported domains are listed as well. Reddit galleries are listed as their images and 'v.redd.it' videos as the streams of '--vreddit-mode'. No directories or update files are created, and the informational output is disabled like with '--quiet'.
+
+- `--stdout`
+  Write the media of the newest matching post to stdout instead of a file, for example to pipe it into another program. Exactly one subreddit or user has to be given; '--before', '--after' and the filters select the post. Galleries and '--vreddit-mode ffmpeg' are not supported. The log output is sent to stderr.
+
+- `--no-nsfw`
+  Do not download posts marked as NSFW
+
+- `--exclude-stickied`, `--exclude-pinned`
+  Do not download posts which are stickied by the moderators of the subreddit or pinned to the profile of the author, like rules and megathreads. Posts without the information are downloaded.
+
+- `--include-removed`
+  Also download posts which were removed by the moderators, reddit or their author. By default, they are skipped, because their links are mostly dead already. This is useful if the media is still available on the host.
+
+- `--only-nsfw`
+  Only download posts marked as NSFW
+
+- `--resume-queue`
+  Save the position within the listing of every subreddit to the '.redditrip' file while downloading, and continue from there if the download was interrupted. The position lags one page behind, so a few posts may be downloaded twice. Private listings are not resumed.
+
+- `--date-index`
+  Record the months whose posts were all seen in the '.redditrip' file of every subreddit, and skip them in later runs instead of querying Pushshift for them again. This makes re-scans of large archives, for example with '--skip-existing', much faster. The current month is only recorded once it is over. The months are only valid for the same filters, and failed downloads within them are not retried. To scan a month again, remove its line from the file.
+
+- `--skip-existing`
+  Skip every post whose output file already exists, regardless of the '--update' marker. Gallery directories are only considered existing if they are not empty. This is useful for re-fetching files that were deleted from a local copy.
+
+- `--skip-duplicate-urls`
+  Skip posts linking to the same URL as an earlier post of the run, like reposts of the same video under another title. URLs are compared with a lowercase host and without tracking parameters like 'utm_source'. Self posts are never skipped. Unlike '--dedupe-db', the duplicates are not downloaded at all, but only identical links are found.
+
+- `--thumbnails-only`
+  Download the small thumbnail reddit shows next to every post instead of the linked media. Posts without a thumbnail, like most self posts, are skipped. This is useful for previewing a subreddit cheaply before downloading everything.
 
 - `-u`, `--update`
- Stop at the first already existing file for each subreddit. If this flag is not given, everything is overwritten if it exists.
+  Stop at the first already existing file for each subreddit. If this flag is not given, everything is overwritten if it exists.
 
 ## Options
 
 - `--after <date>`
- Only download posts after this date. The date should be formatted like 'YYYY-MM-DD', with an optionally appended time in the format 'HH:MM:SS', or a UNIX timestamp with second precision.
+  Only download posts after this date. The date should be formatted like 'YYYY-MM-DD', with an optionally appended time in the format 'HH:MM:SS', or a UNIX timestamp with second precision.
+
+- `--allow-file <file>`
+  Read domains for '--allow' from a file, one per line. Blank lines and lines starting with '#' are ignored. The domains are combined with the ones of '--allow'.
 
--- `allow <domain>`
- Only allows downloading from a domain. It is practical to use brace expansion syntax for this argument: `--allow={"i.redd.it","i.imgur.com"}`.
+- `--allow <domain>`
+  Only allows downloading from a domain. It is practical to use brace expansion syntax for this argument: `--allow={"i.redd.it","i.imgur.com"}`. Can be combined with '--exclude': a post is downloaded if its domain is allowed and not excluded, so '--exclude' takes precedence.
+
+- `--client-id <id>`, `--client-secret <secret>`, `--refresh-token <token>`
+  The credentials of a reddit app. They enable downloading private listings of the authenticated user by passing `u/me/saved` or `u/me/upvoted`.
 
 - `--before <date>`
- Only download posts before this date. The date should be formatted like 'YYYY-MM-DD', with an optionally appended time in the format 'HH:MM:SS', or a UNIX timestamp with second precision.
+  Only download posts before this date. The date should be formatted like 'YYYY-MM-DD', with an optionally appended time in the format 'HH:MM:SS', or a UNIX timestamp with second precision.
+
+- `--between <start..end>`
+  Only download posts created between two dates, for example '--between 2020-01-01..2020-02-01'. This is the same as '--after <start> --before <end>', the dates are formatted like there.
+
+- `--created-after-id <id>`
+  Only download posts created after the post with this ID, excluding the post itself. The creation time of the post is looked up on Pushshift once at the start, so the boundary stays the same across runs, unlike a date which has to be chosen by hand. Accepts the ID with or without the 't3_' prefix.
+
+- `--created-before-id <id>`
+  Only download posts created before the post with this ID, excluding the post itself. See '--created-after-id'.
+
+- `-b`, `--queue-size <size>`
+  A number between 1 and 1000 that specifies the number of simultaneous download jobs. A higher number eats more resources, but is faster. [default: 16]
+
+- `--concurrency-adaptive`
+  Adapt the number of simultaneous downloads to the failures, for example when a host starts throttling. The downloads are evaluated in groups as large as the current limit: if more than half of a group failed, the limit is halved, and if none failed, it grows by one again, up to '--queue-size'.
+
+- `--page-size <size>`
+  A number between 1 and 1000 that specifies the number of posts requested from Pushshift at once. Pushshift may return fewer posts than requested. This is independent of '--queue-size', which only limits the simultaneous downloads. [default: 250]
+
+- `--concurrent-subreddits <n>`
+  The number of subreddits which are downloaded at once, each with its own queries to Pushshift. The downloads of all of them together are still limited by '--queue-size'. If more than one subreddit is downloaded at once, the messages about files name their subreddit. '--interval' is waited before each subreddit after the first. [default: 1]
+
+- `--checksum-file <file>`
+  Append the SHA-256 hash of every saved file to a file in the format of `sha256sum`, so the downloads can later be verified with 'sha256sum -c <file>'. The paths are relative to the directory of the checksum file if the downloads are within it. Files are hashed while they are written.
+
+- `--dedupe-db <file>`
+  Remember the SHA-256 hash of every saved file in a database, which is created if it does not exist. If a later download, also of a later run, has the same content as a file in the database, it is replaced with a hard link to that file, or a symbolic link if the files are on different file systems. The file is still downloaded to compute the hash. The database uses the format of '--checksum-file' with absolute paths.
+
+- `--cache-dir <path>`
+  Store the responses of Pushshift in a directory, which is created if it does not exist, and read them from there instead of querying Pushshift again while they are younger than '--cache-ttl'. This speeds up running the same query repeatedly, for example to try out filters. Media downloads and listings from reddit are not cached.
+
+- `--cache-ttl <secs>`
+  The number of seconds a listing cached with '--cache-dir' is used. Older entries are requested from Pushshift again and replaced. [default: 3600]
 
- `-b`, `--queue-size <size>`
- A number between 1 and 1000 that specifies the number of simultaneous download jobs. A higher number eats more resources, but is faster. [default: 16]
+- `--config <file>`
+  Read default values for the options from a TOML file. The keys are the names of the long options, for example 'title = "{id}"'. Options passed on the command line take precedence. If this option is not given, '~/.config/redditrip/config.toml' is read if it exists.
+
+- `--collision-strategy <strategy>`
+  What happens if multiple posts of a run map to the same file name, for example because the title does not contain '{id}'. The value 'overwrite' saves the later post over the earlier one, 'skip' keeps the first file, and 'rename' appends ' (1)', ' (2)' and so on to the file name, respecting '--max-file-name-length'. Files of previous runs are not considered collisions. [default: overwrite]  [possible values: overwrite, skip, rename]
 
 - `-C`, `--color <'auto'|'always'|'never'>`
- Enable colored output [default: auto]  [possible values: always, auto, never]
+  Enable colored output [default: auto]  [possible values: always, auto, never]
 
 - `-e`, `--exclude <domain>`
- Prevents downloading from a domain. It is practical to use brace expansion syntax for this argument: `--exclude={"i.redd.it","i.imgur.com"}`.
+  Prevents downloading from a domain. It is practical to use brace expansion syntax for this argument: `--exclude={"i.redd.it","i.imgur.com"}`. Takes precedence over '--allow'.
+
+- `--exclude-file <file>`
+  Read domains for '--exclude' from a file, one per line, for example a long list of dead or spam hosts. Blank lines and lines starting with '#' are ignored. The domains are combined with the ones of '--exclude'.
+
+- `--record-only <domain>`
+  Record the posts linking to a domain in '--manifest' and '--posts-ndjson' without downloading them, for example for hosts without support where '--force' would only save a web page. They are counted as recorded in the summary. Can be given multiple times.
+
+- `--filter <field=value>`
+  Only download posts whose field has the value, for example '--filter link_flair_text=OC' or '--filter post_hint=image'. The filter is applied by Pushshift, and can be given multiple times. The value is URL-encoded. The field must be one of '--formatting-fields', so typos are rejected. Private listings and single posts are filtered locally, comparing text case-insensitively.
+
+- `--flair <text>`
+  Only download posts whose flair is one of the given texts, or skip them with '--flair-filter-mode exclude'. For example '--flair OC --flair "Original Content"'. The comparison ignores the case unless '--flair-case-sensitive' is given. Posts without a flair are skipped when including and downloaded when excluding.
+
+- `--flair-filter-mode <mode>`
+  Whether '--flair' selects the posts which are downloaded ('include') or the posts which are skipped ('exclude'). [default: include]  [possible values: include, exclude]
+
+- `--flatten <mode>`
+  Whether the files are placed directly within '--output' instead of a subdirectory named after the subreddit. The value 'always' is the same as '--no-parent'. The value 'auto' only flattens if exactly one subreddit is downloaded, so multiple subreddits can not collide. [default: never]  [possible values: never, always, auto]
+
+- `--alias <input=dirname>`
+  Save the posts of a subreddit or profile into a directory with the given name instead of its own name, for example '--alias u/someuser=someone'. The input is compared to the subreddits like their directory names, so 'u/someuser' and 'r/u_someuser' are the same. Characters which are not allowed in file names are replaced with '_'. Can be given multiple times. Has no effect with '--mirror'.
+
+- `--gfycat-fallback <mode>`
+  Gfycat is offline, so its videos usually fail to download. With the value 'wayback', an archived copy is looked up and downloaded from the Wayback Machine at archive.org instead. This is opt-in because it sends requests to archive.org. [default: none]  [possible values: none, wayback]
 
 - `--gfycat-type <type>`
- The media type of gfycat videos [default: mp4]  [possible values: mp4, webm]
+  The media type of gfycat videos [default: mp4]  [possible values: mp4, webm]
+
+- `--interval <secs>`
+  Wait this many seconds before starting the next subreddit, to avoid bursts of requests to reddit and Pushshift when downloading many subreddits. The waiting does not block the downloads which are still running.
+
+- `--log-file <file>`
+  Append every message to a file with a timestamp and without colors, for example for scheduled runs. The file also receives the informational messages with '--quiet', and the verbose ones with '--verbose'.
+
+- `--manifest <file>`
+  Append the outcome of every download to a manifest file, one JSON object per line. Besides the post ID, URL and output file, it contains a hash of every saved file, which is used by '--reconcile'.
+
+- `--posts-ndjson <file>`
+  Append the data of every post which passes the filters to a file, one JSON object per line, as received from Pushshift or reddit. Posts are recorded whether or not their download succeeds, so this can be combined with '--list-only' or '--print-fields' to only collect the data.
+
+- `--exec <command>`
+  Run a shell command after every successful download. The string '{}' is replaced by the path of the file, and the placeholders of '--title', like '{id}', by the values of the post. All inserted values are quoted. For example: '--exec "exiftool -Title={title} {}"'. A failing command is reported, but does not stop the download.
+
+- `--filename-prefix <text>`, `--filename-suffix <text>`
+  Prepend or append a text to every file name, independent of '--title', for example to tag the files with the date of the download. The suffix is inserted before the file extension. Characters which are not allowed in file names are replaced with '_'. The text counts towards '--max-file-name-length', and is shortened if it leaves no room for the post ID.
+
+- `--zero-pad <width>`
+  Pad the values of numeric placeholders of '--title', like '{created_utc}' or '{score}', with leading zeros to this width, so the file names sort in numeric order in file managers. '{index}' and the numbers of '--number' are padded to the number of digits of the post count, but at least to this width.
+
+- `--from-file <file>`
+  Read additional subreddits or profiles from a file, one per line, in the same format as on the command line. Blank lines and lines starting with '#' are ignored. Invalid lines are reported and skipped.
 
 - `--max-file-name-length <length>`
- Some systems impose restrictions to file names. If you run into a "File name too long" error, look up what the maximum allowed length on your system is and pass it with this parameter. The value of this argument is in bytes, not characters. [default: 255]
+  Some systems impose restrictions to file names. If you run into a "File name too long" error, look up what the maximum allowed length on your system is and pass it with this parameter. The value of this argument is in bytes, not characters. [default: 255]
+
+- `--max-resolution <height>`
+  Download the Vimeo video with the highest resolution not exceeding this height, for example '720'. If every available resolution is higher, the lowest one is downloaded. By default, the highest resolution is downloaded.
+
+- `--max-total-size <size>`
+  Stop starting new downloads once this many bytes were downloaded in total, across all subreddits. Downloads which are already running are finished, so the total can be slightly exceeded. The size can have a unit, for example '500MB' or '2GiB'.
+
+- `--min-comments <n>`
+  Only download posts with at least this many comments. The filter is applied by Pushshift, and checked again for private listings. Posts without a comment count are downloaded.
 
 - `-o, --output <directory>`
- The output directory [default: .]
+  The output directory [default: .]
+
+- `--print-fields <fields>`
+  Print the given fields of every post to stdout as tab-separated values instead of downloading it, for example '--print-fields id,score,author,url'. The fields are the ones of '--formatting-fields'. Fields which are not set for a post are empty. All filters and dates are applied. No directories or update files are created, and the informational output is disabled like with '--quiet'.
+
+- `--pool-idle-per-host <n>`
+  Keep connections open after a request and reuse them, keeping up to this many idle connections per host. By default, every connection is closed after its request. With a high '--queue-size', reusing connections saves the setup of a new connection for every download from the same host.
+
+- `--pool-idle-timeout <secs>`
+  Keep connections open after a request and reuse them, closing them after being idle for this many seconds. See '--pool-idle-per-host'. If only '--pool-idle-per-host' is given, idle connections are not closed.
+
+- `--max-redirects <n>`
+  The number of redirects which are followed by a download before it fails, for example of link shorteners. Redirects of Imgur images are treated as deleted images. [default: 5]
+
+- `--ca-cert <file>`
+  Trust the root certificate in this file in addition to the ones of the system, for example the certificate of an intercepting proxy. The file can be PEM or DER encoded.
+
+- `--metrics-addr <host:port>`
+  Serve counters of the run at 'http://<host:port>/metrics' in the Prometheus text format, for monitoring scheduled or long runs. The counters are the listed posts, the saved and failed downloads and the downloaded bytes. The server stops when the run is finished.
+
+- `--reconcile <manifest>`, `--hash-check <manifest>`
+  Verify a local copy against a manifest written with '--manifest' instead of downloading subreddits. Pushshift is not queried; instead, every saved entry is downloaded again. Files which are missing locally are restored, and files whose content differs from the hash in the manifest are replaced. Entries which fail to download, for example because of link rot, keep their local file. Galleries are only downloaded if they are missing. Self posts are not checked.
+
+- `--retry-failed <manifest>`
+  Download the entries of a manifest written with '--manifest' again whose last outcome is a failure, instead of downloading subreddits. Pushshift is not queried, so recovering from a run with network problems is fast. Pass the same file to '--manifest' to record the new outcomes, so a later retry only covers the remaining failures. Self posts and posts which need data of the listing, like reddit videos and galleries, can not be retried.
+
+- `--temp-dir <directory>`
+  The directory for temporary files, like the separate video and audio streams of '--vreddit-mode ffmpeg'. Every file name is unique, so multiple runs can share the directory. By default, the temporary directory of the system is used.
+
+- `--selftext-format <format>`
+  The file format self posts are saved in when using '--selfposts'. The value 'raw' saves the text as it is to a '.txt' file, 'md' does the same with a '.md' file. The value 'html' renders the Markdown to a standalone '.html' file, with the title of the post as heading. [default: raw]  [possible values: raw, md, html]
 
 - `-t`, `--title <title>`
- This argument takes a string containing placeholders which are replaced with the values of each respective post. All possible placeholders can be retrieved by running the program with '--formatting-fields'. The placeholders are enclosed in curly braces. For example: '--title "{author}_{title}-{created_utc}"'. Note that not all fields are set for every post. Unset placeholder values are replaced by an empty string. Also note that the formatted string is always followed by the file extension, if any. The file name length  is also limited on most file systems. The '--max-file-name-length' argument is used to truncate the generated name. It is moreover advised to include `{id}` in the title to prevent collisions. [default: {id}-{title}]
+  This argument takes a string containing placeholders which are replaced with the values of each respective post. All possible placeholders can be retrieved by running the program with '--formatting-fields'. The placeholders are enclosed in curly braces. For example: '--title "{author}_{title}-{created_utc}"'. Unknown placeholders are an error, literal braces are written as '{{' and '}}'. Note that not all fields are set for every post. Unset placeholder values are replaced by an empty string. Also note that the formatted string is always followed by the file extension, if any. Characters which are not allowed in file names, including '/', are replaced with '_', so the title can not create directories. The file name length  is also limited on most file systems. The '--max-file-name-length' argument is used to truncate the generated name. It is moreover advised to include `{id}` in the title to prevent collisions. [default: {id}-{title}]
 
 - `--vreddit-mode <mode>`
- This setting specifies how videos are downloaded from `v.redd.it`. The value 'no-audio' downloads videos without audio. The value 'ffmpeg' downloads video and audio separately and combines them using the `ffmpeg` command, which must be installed locally. Any other value must be a valid URL, in which the string `{}` is replaced by the video ID, that is the part after that comes after `v.redd.it/` in URLs. [default: no-audio]
+  This setting specifies how videos are downloaded from `v.redd.it`. The value 'no-audio' downloads videos without audio. The value 'ffmpeg' downloads video and audio separately and combines them using the `ffmpeg` command, which must be installed locally. The value 'mux' does the same without requiring any external program; videos without audio are saved as they are. The value 'audio-only' downloads only the audio into an '.m4a' file, videos without audio fail. Any other value must be a valid URL, in which the string `{}` is replaced by the video ID, that is the part after that comes after `v.redd.it/` in URLs. [default: no-audio]
 
 # Exit status
 
 - `0` if the program was able to run and download at least one post;
-      and only minor errors occurred during the execution
+  and only minor errors occurred during the execution
 
 - `1` if an initial error occurred and the program was not able to start
 
 - `2` if a crucial network error occurred
 
 - `3` if an unexpected error occurred which normally indicates
-      that one the APIs and services used is broken
+  that one the APIs and services used is broken
+
+- `4` if the program ran to the end, but at least one download failed;
+  the failures are counted in the summary of every subreddit
+
+- `5` if the disk became full and the run was stopped
 
 */
 
@@ -101,6 +340,7 @@ A versatile tool for downloading the linked contents of entire subreddits fast a
 extern crate aho_corasick;
 extern crate ansi_term; // already required by structopt
 extern crate atty; // already required by structopt
+extern crate base64;
 extern crate bytes; // already required by hyper
 extern crate futures_util; // already required by hyper
 extern crate http; // already required by hyper
@@ -108,17 +348,22 @@ extern crate hyper;
 extern crate hyper_tls; // already required by hyper
 #[macro_use]
 extern crate log;
+extern crate pulldown_cmark;
 extern crate serde; // already required by serde_json
 extern crate serde_json;
 extern crate structopt;
 extern crate time;
 extern crate tokio; // already required by hyper
+extern crate toml;
 
 use std::{
     fmt::Display,
-    io::{stdin, ErrorKind},
+    fs::{self, OpenOptions},
+    io::{self, stdin, ErrorKind},
     mem,
-    path::PathBuf,
+    net::{SocketAddr, ToSocketAddrs},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
     process::{self, Command, Stdio},
     str::FromStr,
 };
@@ -130,21 +375,43 @@ use structopt::StructOpt;
 use time::{strftime, strptime, Timespec};
 use tokio::runtime::Builder;
 
+use crate::archive::ArchiveFormat;
+use crate::config::Config;
 use crate::error::{HELP_JSON, HELP_NETWORK};
-use crate::sites::{gfycat::GfycatType, pushshift::Subreddit, reddit::VRedditMode};
+use crate::exec::Exec;
+use crate::net::parse_size;
+use crate::sites::{
+    gfycat::{GfycatFallback, GfycatType},
+    pushshift::Subreddit,
+    reddit::{self, VRedditMode},
+    selfpost::SelftextFormat,
+};
+use crate::subreddit::{Collision, Finished, FlairFilterMode, Flatten};
 use crate::title::Title;
 use logger::color_stdout;
 
+mod archive;
+mod cache;
+mod checksum;
+mod config;
+mod dedupe;
 mod error;
+mod exec;
 mod logger;
+mod manifest;
+mod metrics;
+mod mirror;
+mod mp4;
 mod net;
 mod sites;
+mod storage;
 mod subreddit;
 mod title;
 
 mod prelude {
     pub use crate::error::*;
     pub use crate::net::*;
+    pub use crate::storage::*;
     pub use crate::Parameters;
 }
 
@@ -164,151 +431,1162 @@ pub struct Parameters {
     #[structopt(short, long, conflicts_with("quiet"), help = "Enable verbose output")]
     verbose: bool,
 
-    #[structopt(short, long, help = "Disable output on stdout")]
-    quiet: bool,
+    #[structopt(short, long, help = "Disable output on stdout")]
+    quiet: bool,
+
+    #[structopt(
+        long,
+        help = "Do not print a warning for every failed download",
+        long_help = "\
+            Log failed downloads only with '--verbose' instead of printing a \
+            warning for each of them. They are still counted in the summary \
+            at the end of every subreddit. This keeps the output readable \
+            when ripping old subreddits full of dead links.\
+        "
+    )]
+    quiet_errors: bool,
+
+    #[structopt(
+        long,
+        help = "Stop if the directory of a subreddit can not be created",
+        long_help = "\
+            Stop the whole run if the directory of a subreddit can not be \
+            created. By default, the subreddit is skipped with an error and the \
+            remaining subreddits are still downloaded.\
+        "
+    )]
+    fail_fast: bool,
+
+    #[structopt(
+        long,
+        conflicts_with("force"),
+        help = "Fail the run if posts from unsupported domains are found",
+        long_help = "\
+            Exit with a non-zero code if any post links to an unsupported \
+            domain, and list the unsupported domains with their number of \
+            posts at the end of the run. This shows which sites are missing \
+            the most. Can not be combined with '--force'.\
+        "
+    )]
+    fail_on_unsupported: bool,
+
+    #[structopt(long, hidden = true, requires = "verbose", conflicts_with("quiet"))]
+    very_verbose: bool,
+
+    #[structopt(
+        short = "C", long, possible_values = &["always", "auto", "never"], default_value = "auto", value_name = "'auto'|'always'|'never'",
+        help = "Enable colored output"
+    )]
+    color: String,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "file",
+        help = "Append the log to a file",
+        long_help = "\
+            Append every message to a file with a timestamp and without colors, \
+            for example for scheduled runs. The file also receives the \
+            informational messages with '--quiet', and the verbose ones \
+            with '--verbose'.\
+        "
+    )]
+    log_file: Option<PathBuf>,
+
+    #[structopt(long, help = "Output a list of supported domains")]
+    domains: bool,
+
+    #[structopt(
+        long,
+        conflicts_with("domains"),
+        help = "Output the supported domains as JSON",
+        long_help = "\
+            Output the supported domains as a JSON array, for use in scripts. \
+            Every object has the fields 'domain', 'api', which is true if the \
+            media is resolved using an API or web page instead of being \
+            downloaded directly, and 'tools', a list of external programs \
+            used by some modes, like 'ffmpeg' for 'v.redd.it'.\
+        "
+    )]
+    domains_json: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "file",
+        help = "Read default options from a TOML file",
+        long_help = "\
+            Read default values for the options from a TOML file. The keys \
+            are the names of the long options, for example 'title = \"{id}\"'. \
+            Options passed on the command line take precedence. If this \
+            option is not given, '~/.config/redditrip/config.toml' is read \
+            if it exists.\
+        "
+    )]
+    config: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "file",
+        help = "Read subreddits from a file, one per line",
+        long_help = "\
+            Read additional subreddits or profiles from a file, one per line, \
+            in the same format as on the command line. Blank lines and lines \
+            starting with '#' are ignored. Invalid lines are reported and \
+            skipped.\
+        "
+    )]
+    from_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "file",
+        help = "Record the downloads in a manifest file",
+        long_help = "\
+            Append the outcome of every download to a manifest file, one JSON \
+            object per line. Besides the post ID, URL and output file, it contains \
+            a hash of every saved file, which is used by '--reconcile'.\
+        "
+    )]
+    manifest: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "file",
+        conflicts_with_all(&["reconcile", "retry-failed"]),
+        help = "Record the data of the posts in a file",
+        long_help = "\
+            Append the data of every post which passes the filters to a file, \
+            one JSON object per line, as received from Pushshift or reddit. \
+            Posts are recorded whether or not their download succeeds, so this \
+            can be combined with '--list-only' or '--print-fields' to only \
+            collect the data.\
+        "
+    )]
+    posts_ndjson: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "file",
+        help = "Record the SHA-256 hashes of the downloads",
+        long_help = "\
+            Append the SHA-256 hash of every saved file to a file in the format \
+            of `sha256sum`, so the downloads can later be verified with \
+            'sha256sum -c <file>'. The paths are relative to the directory of \
+            the checksum file if the downloads are within it. Files are hashed \
+            while they are written.\
+        "
+    )]
+    checksum_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "file",
+        help = "Link identical downloads across runs",
+        long_help = "\
+            Remember the SHA-256 hash of every saved file in a database, which \
+            is created if it does not exist. If a later download, also of a later \
+            run, has the same content as a file in the database, it is replaced \
+            with a hard link to that file, or a symbolic link if the files are on \
+            different file systems. The file is still downloaded to compute the \
+            hash. The database uses the format of '--checksum-file' with \
+            absolute paths.\
+        "
+    )]
+    dedupe_db: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "path",
+        help = "Cache the Pushshift listings in a directory",
+        long_help = "\
+            Store the responses of Pushshift in a directory, which is created if \
+            it does not exist, and read them from there instead of querying \
+            Pushshift again while they are younger than '--cache-ttl'. This \
+            speeds up running the same query repeatedly, for example to try out \
+            filters. Media downloads and listings from reddit are not cached.\
+        "
+    )]
+    cache_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        value_name = "secs",
+        default_value = "3600",
+        help = "How long cached listings are used",
+        long_help = "\
+            The number of seconds a listing cached with '--cache-dir' is used. \
+            Older entries are requested from Pushshift again and replaced.\
+        "
+    )]
+    cache_ttl: u64,
+
+    #[structopt(
+        long,
+        alias = "hash-check",
+        conflicts_with = "SUBREDDITS",
+        parse(from_os_str),
+        value_name = "manifest",
+        help = "Verify a local copy against a manifest",
+        long_help = "\
+            Verify a local copy against a manifest written with '--manifest' \
+            instead of downloading subreddits. Pushshift is not queried; instead, \
+            every saved entry is downloaded again. Files which are missing locally \
+            are restored, and files whose content differs from the hash in the \
+            manifest are replaced. Entries which fail to download, for example \
+            because of link rot, keep their local file. Galleries are only \
+            downloaded if they are missing. Self posts are not checked.\
+        "
+    )]
+    reconcile: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        conflicts_with_all(&["SUBREDDITS", "reconcile"]),
+        parse(from_os_str),
+        value_name = "manifest",
+        help = "Retry the failed downloads of a manifest",
+        long_help = "\
+            Download the entries of a manifest written with '--manifest' again \
+            whose last outcome is a failure, instead of downloading subreddits. \
+            Pushshift is not queried, so recovering from a run with network \
+            problems is fast. Pass the same file to '--manifest' to record the \
+            new outcomes, so a later retry only covers the remaining failures. \
+            Self posts and posts which need data of the listing, like reddit \
+            videos and galleries, can not be retried.\
+        "
+    )]
+    retry_failed: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        value_name = "length",
+        default_value = "255",
+        help = "The maximum file name length in bytes",
+        long_help = "\
+            Some systems impose restrictions to file names. If you run \
+            into a \"File name too long\" error, look up what the maximum \
+            allowed length on your system is and pass it with this parameter. \
+            The value of this argument is in bytes, not characters.\
+        "
+    )]
+    max_file_name_length: usize,
+
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        value_name = "directory",
+        default_value = ".",
+        help = "The output directory"
+    )]
+    output: PathBuf,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "directory",
+        help = "The directory for temporary files",
+        long_help = "\
+            The directory for temporary files, like the separate video and audio \
+            streams of '--vreddit-mode ffmpeg'. Every file name is unique, so \
+            multiple runs can share the directory. By default, the temporary \
+            directory of the system is used.\
+        "
+    )]
+    temp_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        value_name = "n",
+        help = "Keep up to this many idle connections per host",
+        long_help = "\
+            Keep connections open after a request and reuse them, keeping up to \
+            this many idle connections per host. By default, every connection is \
+            closed after its request. With a high '--queue-size', reusing \
+            connections saves the setup of a new connection for every download \
+            from the same host.\
+        "
+    )]
+    pool_idle_per_host: Option<usize>,
+
+    #[structopt(
+        long,
+        value_name = "secs",
+        help = "Close reused connections after being idle this long",
+        long_help = "\
+            Keep connections open after a request and reuse them, closing them \
+            after being idle for this many seconds. See '--pool-idle-per-host'. \
+            If only '--pool-idle-per-host' is given, idle connections are not \
+            closed.\
+        "
+    )]
+    pool_idle_timeout: Option<u64>,
+
+    #[structopt(
+        long,
+        default_value = "5",
+        value_name = "n",
+        help = "The number of redirects followed by a download",
+        long_help = "\
+            The number of redirects which are followed by a download before it \
+            fails, for example of link shorteners. Redirects of Imgur images \
+            are treated as deleted images.\
+        "
+    )]
+    max_redirects: usize,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "file",
+        help = "Trust an additional root certificate",
+        long_help = "\
+            Trust the root certificate in this file in addition to the ones of \
+            the system, for example the certificate of an intercepting proxy. \
+            The file can be PEM or DER encoded.\
+        "
+    )]
+    ca_cert: Option<PathBuf>,
+
+    #[structopt(
+        long, parse(try_from_str = parse_address), value_name = "host:port",
+        help = "Serve counters of the run for Prometheus",
+        long_help = "\
+            Serve counters of the run at 'http://<host:port>/metrics' in the \
+            Prometheus text format, for monitoring scheduled or long runs. The \
+            counters are the listed posts, the saved and failed downloads and \
+            the downloaded bytes. The server stops when the run is finished.\
+        "
+    )]
+    metrics_addr: Option<SocketAddr>,
+
+    #[structopt(
+        short,
+        long,
+        help = "Force downloads from unknown domains",
+        long_help = "\
+            Whether to force the download from unsupported domains \
+            by simpling writing whatever is on the page to disk.\
+        "
+    )]
+    force: bool,
+
+    #[structopt(
+        long,
+        help = "Do not save web pages instead of media with '--force'",
+        long_help = "\
+            With '--force', fail downloads which return a web page instead of \
+            the expected image or video, like the error pages of many hosts. By \
+            default, these pages are saved with the '.html' extension instead of \
+            the extension of the media, with a warning.\
+        "
+    )]
+    skip_html_pages: bool,
+
+    #[structopt(
+        short,
+        long,
+        help = "Update the local copy",
+        long_help = "\
+            Stop at the first already existing file for each subreddit. \
+            If this flag is not given, everything is overwritten if it exists.\
+        "
+    )]
+    update: bool,
+
+    #[structopt(
+        long,
+        help = "Skip posts whose files already exist",
+        long_help = "\
+            Skip every post whose output file already exists, regardless \
+            of the '--update' marker. Gallery directories are only considered \
+            existing if they are not empty. This is useful for re-fetching \
+            files that were deleted from a local copy.\
+        "
+    )]
+    skip_existing: bool,
+
+    #[structopt(
+        long,
+        help = "Skip posts linking to a URL which was already downloaded",
+        long_help = "\
+            Skip posts linking to the same URL as an earlier post of the run, \
+            like reposts of the same video under another title. URLs are \
+            compared with a lowercase host and without tracking parameters like \
+            'utm_source'. Self posts are never skipped. Unlike '--dedupe-db', \
+            the duplicates are not downloaded at all, but only identical links \
+            are found.\
+        "
+    )]
+    skip_duplicate_urls: bool,
+
+    #[structopt(
+        long,
+        help = "Resume interrupted downloads",
+        long_help = "\
+            Save the position within the listing of every subreddit to the \
+            '.redditrip' file while downloading, and continue from there if the \
+            download was interrupted. The position lags one page behind, so a few \
+            posts may be downloaded twice. Private listings are not resumed.\
+        "
+    )]
+    resume_queue: bool,
+
+    #[structopt(
+        long,
+        help = "Skip the months which were downloaded entirely",
+        long_help = "\
+            Record the months whose posts were all seen in the '.redditrip' file \
+            of every subreddit, and skip them in later runs instead of querying \
+            Pushshift for them again. This makes re-scans of large archives, for \
+            example with '--skip-existing', much faster. The current month is \
+            only recorded once it is over. The months are only valid for the same \
+            filters, and failed downloads within them are not retried. To scan a \
+            month again, remove its line from the file.\
+        "
+    )]
+    date_index: bool,
+
+    #[structopt(
+        long,
+        help = "Never overwrite existing files",
+        long_help = "\
+            Never overwrite a file that already exists, but continue with the \
+            remaining posts, unlike '--update'. In contrast to '--skip-existing', \
+            this is checked when a file is written, which also covers posts \
+            mapping to the same file name.\
+        "
+    )]
+    no_overwrite: bool,
+
+    #[structopt(
+        long,
+        conflicts_with("checksum-file"),
+        help = "Fix file extensions which do not match the content",
+        long_help = "\
+            Rename downloaded files whose content does not match their file \
+            extension, like videos linked as '.gif' or images without extension. \
+            The type is detected from the first bytes of the file or the \
+            'Content-Type' header. Note that '--update' and '--skip-existing' \
+            only know the original file names. Can not be combined with \
+            '--checksum-file'.\
+        "
+    )]
+    rename_on_extension_mismatch: bool,
+
+    #[structopt(
+        long,
+        help = "Do not create a subdirectory",
+        long_help = "\
+            Normally, a directory is created as a subdirectory of '--output'. \
+            This option causes the files to be placed directly within '--output'. \
+        "
+    )]
+    no_parent: bool,
+
+    #[structopt(
+        long, parse(from_str), possible_values = &["never", "always", "auto"], default_value = "never", value_name = "mode",
+        conflicts_with("no-parent"),
+        help = "When to omit the subdirectory",
+        long_help = "\
+            Whether the files are placed directly within '--output' instead of \
+            a subdirectory named after the subreddit. The value 'always' is the \
+            same as '--no-parent'. The value 'auto' only flattens if exactly one \
+            subreddit is downloaded, so multiple subreddits can not collide.\
+        "
+    )]
+    flatten: Flatten,
+
+    #[structopt(
+        name = "alias", long = "alias", alias = "subreddit-alias", parse(try_from_str = parse_alias), number_of_values = 1, value_name = "input=dirname",
+        help = "Use a custom directory name for a subreddit",
+        long_help = "\
+            Save the posts of a subreddit or profile into a directory with the \
+            given name instead of its own name, for example '--alias \
+            u/someuser=someone'. The input is compared to the subreddits like \
+            their directory names, so 'u/someuser' and 'r/u_someuser' are the \
+            same. Characters which are not allowed in file names are replaced \
+            with '_'. Can be given multiple times. Has no effect with '--mirror'.\
+        "
+    )]
+    aliases: Vec<(String, String)>,
+
+    #[structopt(
+        long,
+        conflicts_with("no-parent"),
+        help = "Lay out the files like reddit, with index files",
+        long_help = "\
+            Lay out the files like the URLs on reddit, in 'r/<subreddit>/<year>/<month>' \
+            for subreddits and 'u/<user>/<year>/<month>' for profiles, using the \
+            creation date of the posts. Every directory, up to '--output', gets \
+            an 'index.html' file listing its contents, so the archive can be \
+            browsed. The index files are regenerated from the directory contents \
+            on every run, so they also list the files of earlier runs. This \
+            overrides '--flatten'.\
+        "
+    )]
+    mirror: bool,
+
+    #[structopt(
+        long,
+        alias = "output-by-domain",
+        help = "Create a subdirectory for every domain",
+        long_help = "\
+            Sort the files into subdirectories named after the domain of the \
+            post, like 'i.redd.it' or 'imgur.com'. The domain directory is placed \
+            within the subreddit directory, or directly within '--output' with \
+            '--no-parent'. With '--mirror', it is placed within the year and \
+            month directories.\
+        "
+    )]
+    group_by_domain: bool,
+
+    #[structopt(
+        long, parse(from_str), possible_values = &["overwrite", "skip", "rename"], default_value = "overwrite", value_name = "strategy",
+        help = "How to handle posts with the same file name",
+        long_help = "\
+            What happens if multiple posts of a run map to the same file name, \
+            for example because the title does not contain '{id}'. The value \
+            'overwrite' saves the later post over the earlier one, 'skip' keeps \
+            the first file, and 'rename' appends ' (1)', ' (2)' and so on to the \
+            file name, respecting '--max-file-name-length'. Files of previous \
+            runs are not considered collisions.\
+        "
+    )]
+    collision_strategy: Collision,
+
+    #[structopt(
+        long, parse(try_from_str = parse_date), value_name = "date",
+        help = "Filter for posts after this date",
+        long_help = "\
+            Only download posts after this date. The date should be formatted like \
+            'YYYY-MM-DD', with an optionally appended time in the format 'HH:MM:SS', \
+            or a UNIX timestamp with second precision.\
+        "
+    )]
+    after: Option<u64>,
+
+    #[structopt(
+        long, parse(try_from_str = parse_date), value_name = "date",
+        help = "Filter for posts before this date",
+        long_help = "\
+            Only download posts before this date. The date should be formatted like \
+            'YYYY-MM-DD', with an optionally appended time in the format 'HH:MM:SS', \
+            or a UNIX timestamp with second precision.\
+        "
+    )]
+    before: Option<u64>,
+
+    #[structopt(
+        long, parse(try_from_str = parse_between), value_name = "start..end",
+        conflicts_with_all = &["after", "before", "created-after-id", "created-before-id"],
+        help = "Filter for posts between two dates",
+        long_help = "\
+            Only download posts created between two dates, for example \
+            '--between 2020-01-01..2020-02-01'. This is the same as \
+            '--after <start> --before <end>', the dates are formatted like there.\
+        "
+    )]
+    between: Option<(u64, u64)>,
+
+    #[structopt(
+        long, parse(try_from_str = parse_post_id), conflicts_with("after"), value_name = "id",
+        help = "Filter for posts after this post",
+        long_help = "\
+            Only download posts created after the post with this ID, excluding \
+            the post itself. The creation time of the post is looked up on \
+            Pushshift once at the start, so the boundary stays the same across \
+            runs, unlike a date which has to be chosen by hand. Accepts the ID \
+            with or without the 't3_' prefix.\
+        "
+    )]
+    created_after_id: Option<String>,
+
+    #[structopt(
+        long, parse(try_from_str = parse_post_id), conflicts_with("before"), value_name = "id",
+        help = "Filter for posts before this post",
+        long_help = "\
+            Only download posts created before the post with this ID, excluding \
+            the post itself. See '--created-after-id'.\
+        "
+    )]
+    created_before_id: Option<String>,
+
+    #[structopt(
+        long,
+        short = "b",
+        default_value = "16",
+        parse(try_from_str = parse_queue_size),
+        value_name = "size",
+        alias = "batch-size",
+        help = "The number of simultaneous downloads",
+        long_help = "\
+            A number between 1 and 1000 that specifies the number of simultaneous \
+            download jobs. A higher number eats more resources, but is faster. \
+        "
+    )]
+    queue_size: usize,
+
+    #[structopt(
+        long,
+        help = "Lower the simultaneous downloads while many of them fail",
+        long_help = "\
+            Adapt the number of simultaneous downloads to the failures, for \
+            example when a host starts throttling. The downloads are evaluated \
+            in groups as large as the current limit: if more than half of a \
+            group failed, the limit is halved, and if none failed, it grows by \
+            one again, up to '--queue-size'.\
+        "
+    )]
+    concurrency_adaptive: bool,
+
+    #[structopt(
+        long,
+        default_value = "250",
+        parse(try_from_str = parse_page_size),
+        value_name = "size",
+        help = "The number of posts requested from Pushshift at once",
+        long_help = "\
+            A number between 1 and 1000 that specifies the number of posts \
+            requested from Pushshift at once. Pushshift may return fewer posts \
+            than requested. This is independent of '--queue-size', which only \
+            limits the simultaneous downloads.\
+        "
+    )]
+    page_size: usize,
+
+    #[structopt(
+        long,
+        default_value = "1",
+        parse(try_from_str = parse_concurrent_subreddits),
+        value_name = "n",
+        help = "The number of subreddits downloaded at once",
+        long_help = "\
+            The number of subreddits which are downloaded at once, each with its \
+            own queries to Pushshift. The downloads of all of them together are \
+            still limited by '--queue-size'. If more than one subreddit is \
+            downloaded at once, the messages about files name their subreddit. \
+            '--interval' is waited before each subreddit after the first.\
+        "
+    )]
+    concurrent_subreddits: usize,
+
+    #[structopt(
+        name = "SUBREDDITS", parse(try_from_str = parse_input),
+        help = "A list of subreddits or profiles to download",
+        long_help = "\
+            The input subreddits or profiles. Unless prefixed with 'u/' or '/u/', \
+            it is assumed that the input is a subreddit. Multireddits can be passed \
+            as 'u/<user>/m/<name>' and are downloaded as their subreddits. Single \
+            posts can be passed as the URL of their comments page, as 'redd.it' \
+            short link or as 't3_<id>', and are saved directly within the output \
+            directory.\
+        "
+    )]
+    subreddits: Vec<Subreddit>,
+
+    #[structopt(short, long, help = "Download self posts as text files")]
+    selfposts: bool,
+
+    #[structopt(
+        long,
+        conflicts_with("no-nsfw"),
+        help = "Only download posts marked as NSFW"
+    )]
+    only_nsfw: bool,
+
+    #[structopt(
+        long,
+        alias = "exclude-nsfw",
+        help = "Do not download posts marked as NSFW"
+    )]
+    no_nsfw: bool,
+
+    #[structopt(
+        long,
+        help = "Do not download stickied posts",
+        long_help = "\
+            Do not download posts which are stickied by the moderators of the \
+            subreddit, like rules and megathreads. Posts without the information \
+            are downloaded.\
+        "
+    )]
+    exclude_stickied: bool,
+
+    #[structopt(
+        long,
+        help = "Do not download pinned posts",
+        long_help = "\
+            Do not download posts which are pinned to the profile of the author. \
+            Posts without the information are downloaded.\
+        "
+    )]
+    exclude_pinned: bool,
+
+    #[structopt(
+        long,
+        help = "Download posts removed by moderators or reddit",
+        long_help = "\
+            Also download posts which were removed by the moderators, reddit \
+            or their author. By default, they are skipped, because their \
+            links are mostly dead already. This is useful if the media is \
+            still available on the host.\
+        "
+    )]
+    include_removed: bool,
+
+    #[structopt(
+        long,
+        help = "Do not create subdirectories for galleries",
+        long_help = "\
+            Normally, the items of galleries and albums are saved to a subdirectory \
+            named after the post. This option causes them to be placed directly \
+            next to the other files instead, named after the post with the index \
+            of the item appended.\
+        "
+    )]
+    flatten_galleries: bool,
+
+    #[structopt(
+        long,
+        parse(from_str),
+        possible_values = &["zip"],
+        value_name = "format",
+        conflicts_with_all(&["flatten-galleries", "checksum-file", "dedupe-db"]),
+        help = "Save galleries as an archive",
+        long_help = "\
+            Save the items of galleries and albums to a single archive named \
+            after the post, like 'abc-Title.zip', instead of a subdirectory. \
+            Every item is moved into the archive as soon as it is downloaded, \
+            so the items do not take up the disk space twice. The items are \
+            stored without compression. Can not be combined with \
+            '--flatten-galleries', '--checksum-file' or '--dedupe-db'.\
+        "
+    )]
+    gallery_archive: Option<ArchiveFormat>,
+
+    #[structopt(
+        long,
+        alias = "max-depth",
+        default_value = "1",
+        parse(try_from_str = parse_gallery_depth),
+        value_name = "n",
+        help = "The number of levels of linked Imgur albums to download",
+        long_help = "\
+            The number of levels of Imgur albums which are downloaded. Albums \
+            linked in the descriptions of the images of an album are saved to a \
+            subdirectory named after their ID, up to this many levels deep. Every \
+            album is only downloaded once per post, so albums linking to each \
+            other do not loop.\
+        "
+    )]
+    gallery_depth: usize,
+
+    #[structopt(
+        long,
+        help = "Prepend a sequence number to the file names",
+        long_help = "\
+            Prepend a zero-padded sequence number to every file name, for example \
+            '001-'. The numbers follow the order of the posts, newest first, and \
+            not the order in which the downloads complete.\
+        "
+    )]
+    number: bool,
+
+    #[structopt(
+        long,
+        help = "Name the files after the ID of the media on its host",
+        long_help = "\
+            Name the files after the ID of the media on its host instead of \
+            '--title', so the same media always gets the same file name, no \
+            matter which post linked it. This covers the hashes of Imgur images, \
+            the IDs of Gfycat and RedGifs videos and the files on 'v.redd.it' \
+            and 'i.redd.it'. Other posts are still named after '--title'. \
+            '--number', '--filename-prefix' and '--filename-suffix' are applied \
+            as usual.\
+        "
+    )]
+    name_by_source: bool,
+
+    #[structopt(
+        long,
+        help = "Convert file names to lowercase",
+        long_help = "\
+            Convert file names to lowercase, so archives behave the same on \
+            case-insensitive file systems.\
+        "
+    )]
+    lowercase_filenames: bool,
+
+    #[structopt(
+        long,
+        help = "Do not normalize the Unicode characters of file names",
+        long_help = "\
+            Keep the characters of file names as they are. By default, file \
+            names are converted to the Unicode normalization form C, so accented \
+            characters which can be written in multiple ways always result in \
+            the same file name, for example across macOS and Linux.\
+        "
+    )]
+    no_normalize_unicode: bool,
+
+    #[structopt(
+        long,
+        alias = "title-max-words",
+        help = "Shorten long file names between words",
+        long_help = "\
+            Shorten file names which are longer than '--max-file-name-length' \
+            at the last whitespace before the limit, instead of in the middle \
+            of a word. If the first word alone is already too long, it is cut \
+            at the limit.\
+        "
+    )]
+    truncate_at_word: bool,
+
+    #[structopt(
+        long,
+        help = "Only use ASCII characters in file names",
+        long_help = "\
+            Remove all characters which are not ASCII from file names, so they \
+            are portable to any file system. Accented letters like 'é' and \
+            common punctuation are replaced by their closest ASCII equivalent. \
+            If nothing remains of a file name, the post ID is used instead.\
+        "
+    )]
+    ascii_filenames: bool,
+
+    #[structopt(
+        long,
+        alias = "prefix",
+        parse(from_str = title::clean),
+        value_name = "text",
+        help = "Prepend a text to every file name",
+        long_help = "\
+            Prepend a text to every file name, independent of '--title', for \
+            example to tag the files with the date of the download. Characters \
+            which are not allowed in file names are replaced with '_'. The text \
+            counts towards '--max-file-name-length', and is shortened if it \
+            leaves no room for the post ID.\
+        "
+    )]
+    filename_prefix: Option<String>,
+
+    #[structopt(
+        long,
+        alias = "suffix",
+        parse(from_str = title::clean),
+        value_name = "text",
+        help = "Append a text to every file name",
+        long_help = "\
+            Append a text to every file name before the file extension, \
+            independent of '--title'. Characters which are not allowed in file \
+            names are replaced with '_'. The text counts towards \
+            '--max-file-name-length', and is shortened if it leaves no room \
+            for the post ID.\
+        "
+    )]
+    filename_suffix: Option<String>,
+
+    #[structopt(
+        long,
+        value_name = "width",
+        help = "Zero-pad numbers in file names to this width",
+        long_help = "\
+            Pad the values of numeric placeholders of '--title', like \
+            '{created_utc}' or '{score}', with leading zeros to this width, so \
+            the file names sort in numeric order in file managers. '{index}' \
+            and the numbers of '--number' are padded to the number of digits \
+            of the post count, but at least to this width.\
+        "
+    )]
+    zero_pad: Option<usize>,
+
+    #[structopt(
+        long, parse(try_from_str = parse_domains), multiple = true, value_name = "domain",
+        help = "Only download from the domain",
+        long_help = "\
+            Only allows downloading from a domain. It is practical to use brace \
+            expansion syntax for this argument: '--allow={\"i.redd.it\",\"i.imgur.com\"}'. \
+            Can be combined with '--exclude': a post is downloaded if its domain is \
+            allowed and not excluded, so '--exclude' takes precedence.\
+        "
+    )]
+    allow: Option<Vec<String>>,
+
+    #[structopt(
+        short, long, parse(try_from_str = parse_domains), multiple = true, value_name = "domain",
+        help = "Do not download from the domain",
+        long_help = "\
+            Prevents downloading from a domain. It is practical to use brace \
+            expansion syntax for this argument: '--exclude={\"i.redd.it\",\"i.imgur.com\"}'. \
+            Takes precedence over '--allow'.\
+        "
+    )]
+    exclude: Option<Vec<String>>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "file",
+        help = "Only download from the domains in a file",
+        long_help = "\
+            Read domains for '--allow' from a file, one per line. Blank lines and \
+            lines starting with '#' are ignored. The domains are combined with \
+            the ones of '--allow'.\
+        "
+    )]
+    allow_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "file",
+        help = "Do not download from the domains in a file",
+        long_help = "\
+            Read domains for '--exclude' from a file, one per line, for example a \
+            long list of dead or spam hosts. Blank lines and lines starting with \
+            '#' are ignored. The domains are combined with the ones of '--exclude'.\
+        "
+    )]
+    exclude_file: Option<PathBuf>,
+
+    #[structopt(
+        long, parse(try_from_str = parse_domains), number_of_values = 1, value_name = "domain",
+        help = "Only record the posts of the domain",
+        long_help = "\
+            Record the posts linking to a domain in '--manifest' and \
+            '--posts-ndjson' without downloading them, for example for hosts \
+            without support where '--force' would only save a web page. They \
+            are counted as recorded in the summary. Can be given multiple times.\
+        "
+    )]
+    record_only: Vec<String>,
+
+    #[structopt(
+        long, parse(try_from_str = parse_filter), number_of_values = 1, value_name = "field=value",
+        help = "Filter the posts on the Pushshift server",
+        long_help = "\
+            Only download posts whose field has the value, for example \
+            '--filter link_flair_text=OC' or '--filter post_hint=image'. The filter is \
+            applied by Pushshift, and can be given multiple times. The value is \
+            URL-encoded. The field must be one of '--formatting-fields', so typos \
+            are rejected. Private listings and single posts are filtered locally, \
+            comparing text case-insensitively.\
+        "
+    )]
+    filter: Vec<(String, String)>,
 
-    #[structopt(long, hidden = true, requires = "verbose", conflicts_with("quiet"))]
-    very_verbose: bool,
+    #[structopt(
+        long,
+        number_of_values = 1,
+        value_name = "text",
+        help = "Filter the posts by their flair",
+        long_help = "\
+            Only download posts whose flair is one of the given texts, or skip \
+            them with '--flair-filter-mode exclude'. For example '--flair OC \
+            --flair \"Original Content\"'. The comparison ignores the case unless \
+            '--flair-case-sensitive' is given. Posts without a flair are skipped \
+            when including and downloaded when excluding.\
+        "
+    )]
+    flair: Vec<String>,
 
     #[structopt(
-        short = "C", long, possible_values = &["always", "auto", "never"], default_value = "auto", value_name = "'auto'|'always'|'never'",
-        help = "Enable colored output"
+        long, parse(from_str), possible_values = &["include", "exclude"], default_value = "include", value_name = "mode",
+        help = "Whether '--flair' includes or excludes posts",
+        long_help = "\
+            Whether '--flair' selects the posts which are downloaded ('include') \
+            or the posts which are skipped ('exclude').\
+        "
     )]
-    color: String,
+    flair_filter_mode: FlairFilterMode,
 
-    #[structopt(long, help = "Output a list of supported domains")]
-    domains: bool,
+    #[structopt(long, help = "Compare the flairs of '--flair' case-sensitively")]
+    flair_case_sensitive: bool,
 
     #[structopt(
         long,
-        value_name = "length",
-        default_value = "255",
-        help = "The maximum file name length in bytes",
+        alias = "flair-required",
+        help = "Skip posts without a flair",
         long_help = "\
-            Some systems impose restrictions to file names. If you run \
-            into a \"File name too long\" error, look up what the maximum \
-            allowed length on your system is and pass it with this parameter. \
-            The value of this argument is in bytes, not characters.\
+            Skip posts without a flair, for subreddits which mark their content \
+            with flairs. This also applies with '--flair-filter-mode exclude'.\
         "
     )]
-    max_file_name_length: usize,
+    require_flair: bool,
+
+    #[structopt(
+        long, parse(from_str), possible_values = &["mp4", "webm"], default_value = "mp4", value_name = "type",
+        help = "The media type of gfycat videos"
+    )]
+    gfycat_type: GfycatType,
+
+    #[structopt(
+        long, parse(from_str), possible_values = &["none", "wayback"], default_value = "none", value_name = "mode",
+        help = "Fall back to archived gfycat videos",
+        long_help = "\
+            Gfycat is offline, so its videos usually fail to download. With the value \
+            'wayback', an archived copy is looked up and downloaded from the Wayback \
+            Machine at archive.org instead. This is opt-in because it sends requests \
+            to archive.org.\
+        "
+    )]
+    gfycat_fallback: GfycatFallback,
 
     #[structopt(
-        short,
         long,
-        parse(from_os_str),
-        value_name = "directory",
-        default_value = ".",
-        help = "The output directory"
+        help = "Check whether links are dead before downloading them",
+        long_help = "\
+            Before downloading a post, check with a HEAD request whether the \
+            link is dead, falling back to GET for hosts which do not support \
+            HEAD. Links returning 404 or 410 are counted as failed without \
+            being downloaded. This reduces the wasted connections on old \
+            subreddits full of dead links, at the cost of an additional \
+            request for every working link. Links which are fetched from the \
+            Wayback Machine with '--gfycat-fallback wayback' are not checked.\
+        "
     )]
-    output: PathBuf,
+    precheck: bool,
 
     #[structopt(
-        short,
         long,
-        help = "Force downloads from unknown domains",
+        help = "Disable the verification of TLS certificates",
         long_help = "\
-            Whether to force the download from unsupported domains \
-            by simpling writing whatever is on the page to disk.\
+            Accept any TLS certificate and host name, for example to run behind \
+            an intercepting proxy. This makes every connection vulnerable to \
+            interception, prefer adding the certificate of the proxy with \
+            '--ca-cert'.\
         "
     )]
-    force: bool,
+    insecure: bool,
 
     #[structopt(
-        short,
         long,
-        help = "Update the local copy",
+        help = "Download animated Imgur and reddit GIFs as mp4",
         long_help = "\
-            Stop at the first already existing file for each subreddit. \
-            If this flag is not given, everything is overwritten if it exists.\
+            Download the '.mp4' variant of animated '.gif' and '.gifv' files from \
+            Imgur, which is a lot smaller. If the variant is not available, the \
+            original file is downloaded. GIFs hosted on 'i.redd.it' are downloaded \
+            as the video reddit converted them to, if the post has one.\
         "
     )]
-    update: bool,
+    prefer_mp4: bool,
 
     #[structopt(
         long,
-        help = "Do not create a subdirectory",
+        conflicts_with_all(&["reconcile", "retry-failed"]),
+        help = "Only download the thumbnails of posts",
         long_help = "\
-            Normally, a directory is created as a subdirectory of '--output'. \
-            This option causes the files to be placed directly within '--output'. \
+            Download the small thumbnail reddit shows next to every post \
+            instead of the linked media. Posts without a thumbnail, like most \
+            self posts, are skipped. This is useful for previewing a subreddit \
+            cheaply before downloading everything.\
         "
     )]
-    no_parent: bool,
+    thumbnails_only: bool,
 
     #[structopt(
-        long, parse(try_from_str = parse_date), value_name = "date",
-        help = "Filter for posts after this date",
+        long,
+        conflicts_with_all(&["reconcile", "retry-failed"]),
+        help = "Print the URLs of the posts instead of downloading them",
         long_help = "\
-            Only download posts after this date. The date should be formatted like \
-            'YYYY-MM-DD', with an optionally appended time in the format 'HH:MM:SS', \
-            or a UNIX timestamp with second precision.\
+            Print the URL of every post to stdout, one per line, instead of \
+            downloading it, for example to pass them to another downloader. \
+            All filters and dates are applied, and unsupported domains are \
+            listed as well. Reddit galleries are listed as their images and \
+            'v.redd.it' videos as the streams of '--vreddit-mode'. No \
+            directories or update files are created, and the informational \
+            output is disabled like with '--quiet'.\
         "
     )]
-    after: Option<u64>,
+    list_only: bool,
 
     #[structopt(
-        long, parse(try_from_str = parse_date), value_name = "date",
-        help = "Filter for posts before this date",
+        long, use_delimiter = true, value_name = "fields",
+        parse(try_from_str = parse_field),
+        conflicts_with_all(&["list-only", "stdout", "reconcile", "retry-failed"]),
+        help = "Print fields of the posts as tab-separated values",
         long_help = "\
-            Only download posts before this date. The date should be formatted like \
-            'YYYY-MM-DD', with an optionally appended time in the format 'HH:MM:SS', \
-            or a UNIX timestamp with second precision.\
+            Print the given fields of every post to stdout as tab-separated \
+            values instead of downloading it, for example \
+            '--print-fields id,score,author,url'. The fields are the ones of \
+            '--formatting-fields'. Fields which are not set for a post are empty. \
+            All filters and dates are applied. No directories or update files are \
+            created, and the informational output is disabled like with '--quiet'.\
         "
     )]
-    before: Option<u64>,
+    print_fields: Vec<String>,
 
     #[structopt(
         long,
-        short = "b",
-        default_value = "16",
-        value_name = "size",
-        alias = "batch-size",
-        help = "The number of simultaneous downloads",
+        conflicts_with_all(&["list-only", "reconcile", "retry-failed", "exec", "manifest", "checksum-file"]),
+        help = "Write the media of a single post to stdout",
         long_help = "\
-            A number between 1 and 1000 that specifies the number of simultaneous \
-            download jobs. A higher number eats more resources, but is faster. \
+            Write the media of the newest matching post to stdout instead of a \
+            file, for example to pipe it into another program. Exactly one \
+            subreddit or user has to be given; '--before', '--after' and the \
+            filters select the post. Galleries and '--vreddit-mode ffmpeg' are \
+            not supported. The log output is sent to stderr.\
         "
     )]
-    queue_size: usize,
+    stdout: bool,
 
     #[structopt(
-        name = "SUBREDDITS", parse(try_from_str = parse_input),
-        help = "A list of subreddits or profiles to download",
+        long,
+        value_name = "height",
+        help = "The maximum height of Vimeo videos",
         long_help = "\
-            The input subreddits or profiles. Unless prefixed with 'u/' or '/u/', \
-            it is assumed that the input is a subreddit.
+            Download the Vimeo video with the highest resolution not exceeding \
+            this height, for example '720'. If every available resolution is \
+            higher, the lowest one is downloaded. By default, the highest \
+            resolution is downloaded.\
         "
     )]
-    subreddits: Vec<Subreddit>,
-
-    #[structopt(short, long, help = "Download self posts as text files")]
-    selfposts: bool,
+    max_resolution: Option<u64>,
 
     #[structopt(
-        long, parse(try_from_str = parse_domains), multiple = true, value_name = "domain", conflicts_with("exclude"),
-        help = "Only download from the domain",
+        long, parse(try_from_str = parse_size), value_name = "size",
+        help = "Stop after downloading this many bytes",
         long_help = "\
-            Only allows downloading from a domain. It is practical to use brace \
-            expansion syntax for this argument: '--allow={\"i.redd.it\",\"i.imgur.com\"}'.\
+            Stop starting new downloads once this many bytes were downloaded in \
+            total, across all subreddits. Downloads which are already running \
+            are finished, so the total can be slightly exceeded. The size can \
+            have a unit, for example '500MB' or '2GiB'.\
         "
     )]
-    allow: Option<Vec<String>>,
+    max_total_size: Option<u64>,
 
     #[structopt(
-        short, long, parse(try_from_str = parse_domains), multiple = true, value_name = "domain",
-        help = "Do not download from the domain",
+        long,
+        value_name = "n",
+        help = "Only download posts with at least this many comments",
         long_help = "\
-            Prevents downloading from a domain. It is practical to use brace \
-            expansion syntax for this argument: '--exclude={\"i.redd.it\",\"i.imgur.com\"}'.\
+            Only download posts with at least this many comments. The filter \
+            is applied by Pushshift, and checked again for private listings. \
+            Posts without a comment count are downloaded.\
         "
     )]
-    exclude: Option<Vec<String>>,
+    min_comments: Option<u64>,
 
     #[structopt(
-        long, parse(from_str), possible_values = &["mp4", "webm"], default_value = "mp4", value_name = "type",
-        help = "The media type of gfycat videos"
+        long,
+        value_name = "secs",
+        help = "Wait between subreddits",
+        long_help = "\
+            Wait this many seconds before starting the next subreddit, to avoid \
+            bursts of requests to reddit and Pushshift when downloading many \
+            subreddits. The waiting does not block the downloads which are \
+            still running.\
+        "
     )]
-    gfycat_type: GfycatType,
+    interval: Option<u64>,
 
     #[structopt(
         long,
@@ -320,13 +1598,28 @@ pub struct Parameters {
             This setting specifies how videos are downloaded from `v.redd.it`. \
             The value 'no-audio' downloads videos without audio. The value \
             'ffmpeg' downloads video and audio separately and combines them using \
-            the `ffmpeg` command, which must be installed locally. Any other value \
+            the `ffmpeg` command, which must be installed locally. The value 'mux' \
+            does the same without requiring any external program; videos without \
+            audio are saved as they are. The value 'audio-only' downloads only \
+            the audio into an '.m4a' file, videos without audio fail. Any other value \
             must be a valid URL, in which the string `{}` is replaced by the video \
             ID, that is the part after that comes after `v.redd.it/` in URLs.\
         "
     )]
     vreddit_mode: VRedditMode,
 
+    #[structopt(
+        long, parse(from_str), possible_values = &["raw", "md", "html"], default_value = "raw", value_name = "format",
+        help = "The file format of self posts",
+        long_help = "\
+            The file format self posts are saved in when using '--selfposts'. The \
+            value 'raw' saves the text as it is to a '.txt' file, 'md' does the same \
+            with a '.md' file. The value 'html' renders the Markdown to a standalone \
+            '.html' file, with the title of the post as heading.\
+        "
+    )]
+    selftext_format: SelftextFormat,
+
     #[structopt(
         long,
         help = "Display the available formatting fields",
@@ -338,7 +1631,20 @@ pub struct Parameters {
     formatting_fields: bool,
 
     #[structopt(
-        short, long, parse(from_str = Title::new), default_value = "{id}-{title}",
+        long,
+        conflicts_with("formatting-fields"),
+        help = "Output the available formatting fields as JSON",
+        long_help = "\
+            Output the possible placeholders for the '--title' argument as a \
+            JSON array, for use in scripts. Every object has the fields 'name', \
+            'type' and 'always_present', which is true if the field is set for \
+            every post, like 'id' and 'created_utc'.\
+        "
+    )]
+    formatting_fields_json: bool,
+
+    #[structopt(
+        short, long, parse(try_from_str = Title::parse), default_value = "{id}-{title}",
         help = "Use a custom title format",
         long_help = "\
             This argument takes a string containing placeholders which \
@@ -346,25 +1652,100 @@ pub struct Parameters {
             possible placeholders can be retrieved by running the program \
             with '--formatting-fields'. The placeholders are enclosed \
             in curly braces. For example: '--title \"{author}_{title}-\
-            {created_utc}\"'. Note that not all fields are set for every \
-            post. Unset placeholder values are replaced by an empty string.
+            {created_utc}\"'. Unknown placeholders are an error, literal \
+            braces are written as '{{' and '}}'. Note that not all fields are \
+            set for every post. Unset placeholder values are replaced by an \
+            empty string.
 \
             Also note that the formatted string is always followed by the \
-            file extension, if any. The file name length  is also limited \
+            file extension, if any. Characters which are not allowed in \
+            file names, including '/', are replaced with '_', so the title \
+            can not create directories. The file name length  is also limited \
             on most file systems. The '--max-file-name-length' argument \
             is used to truncate the generated name. It is moreover \
             advised to include `{id}` in the title to prevent collisions.\
         "
     )]
     title: Title,
+
+    #[structopt(
+        long, parse(from_str = Exec::new), value_name = "command",
+        help = "Run a command on every downloaded file",
+        long_help = "\
+            Run a shell command after every successful download. The string '{}' \
+            is replaced by the path of the file, and the placeholders of '--title', \
+            like '{id}', by the values of the post. All inserted values are quoted. \
+            For example: '--exec \"exiftool -Title={title} {}\"'. A failing command \
+            is reported, but does not stop the download.\
+        "
+    )]
+    exec: Option<Exec>,
+
+    #[structopt(
+        long, value_name = "id", requires_all = &["client-secret", "refresh-token"],
+        help = "The client ID of a reddit app",
+        long_help = "\
+            The client ID of a reddit app. Together with '--client-secret' and \
+            '--refresh-token', this enables downloading private listings of the \
+            authenticated user by passing 'u/me/saved' or 'u/me/upvoted'.\
+        "
+    )]
+    client_id: Option<String>,
+
+    #[structopt(
+        long,
+        value_name = "secret",
+        requires = "client-id",
+        help = "The client secret of a reddit app"
+    )]
+    client_secret: Option<String>,
+
+    #[structopt(
+        long,
+        value_name = "token",
+        requires = "client-id",
+        help = "An OAuth refresh token with the 'identity' and 'history' scopes"
+    )]
+    refresh_token: Option<String>,
 }
 
 /// Parses a subreddit name.
 ///
 /// The input is assumed to be a subreddit unless prefixed with `u/` or `/u/`.
+/// The private listings `u/me/saved` and `u/me/upvoted` are recognized as well,
+/// and so are multireddits like `u/<user>/m/<name>` or `/user/<user>/m/<name>`.
+/// Single posts are given as URL or as `t3_<id>`.
 /// The prefixes `r/`, `/r/`, `u/` and `/u/` are automatically removed.
 /// An error is returned if the name is invalid.
 fn parse_input(name: &str) -> Result<Subreddit, String> {
+    let trimmed = name.trim_start_matches('/').trim_end_matches('/');
+    match trimmed {
+        "u/me/saved" => return Ok(Subreddit::Saved),
+        "u/me/upvoted" => return Ok(Subreddit::Upvoted),
+        _ => (),
+    };
+
+    if name.starts_with("https://") || name.starts_with("http://") {
+        return parse_post_url(name).map(Subreddit::Post);
+    };
+    if trimmed.starts_with("t3_") {
+        return parse_post_id(trimmed).map(Subreddit::Post);
+    };
+
+    if let ["u", user, "m", multi] | ["user", user, "m", multi] =
+        trimmed.split('/').collect::<Vec<_>>().as_slice()
+    {
+        verify_name(user)?;
+        if multi.is_empty() || !multi.chars().all(|i| i.is_alphanumeric() || i == '_') {
+            return Err(format!("Invalid multireddit name '{}'", multi));
+        };
+
+        return Ok(Subreddit::Multi {
+            user: user.to_string(),
+            name: multi.to_string(),
+        });
+    };
+
     if name.starts_with("/u/") {
         return verify_name(&name[3..]).map(|_| Subreddit::Profile(name[3..].to_string()));
     };
@@ -382,6 +1763,85 @@ fn parse_input(name: &str) -> Result<Subreddit, String> {
     Ok(Subreddit::Subreddit(name.to_string()))
 }
 
+/// Parses an alias like `<input>=<dirname>`, see `--alias`.
+///
+/// The input is returned as the directory name it would have without the alias,
+/// so all spellings of a subreddit match.
+fn parse_alias(input: &str) -> Result<(String, String), String> {
+    let equals = input
+        .rfind('=')
+        .ok_or_else(|| format!("Expected 'input=dirname', found '{}'", input))?;
+    let (subreddit, name) = (&input[..equals], input[equals + 1..].trim());
+
+    let subreddit = parse_input(subreddit)?;
+    if subreddit.is_post() {
+        return Err(String::from("Single posts can not have an alias"));
+    };
+    let name = title::clean(name);
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(format!("Invalid directory name '{}'", name));
+    };
+
+    Ok((subreddit.to_path(), name))
+}
+
+/// Parses the ID of a post, with an optional `t3_` prefix.
+fn parse_post_id(input: &str) -> Result<String, String> {
+    let id = input.strip_prefix("t3_").unwrap_or(input);
+
+    if id.is_empty() || !id.chars().all(|i| i.is_ascii_alphanumeric()) {
+        return Err(format!("Invalid post ID '{}'", id));
+    };
+
+    Ok(id.to_string())
+}
+
+/// Extracts the ID of a post from the URL of its comments page,
+/// like `https://www.reddit.com/r/<subreddit>/comments/<id>/<title>/`,
+/// or from a short link like `https://redd.it/<id>`.
+fn parse_post_url(input: &str) -> Result<String, String> {
+    let url: Uri = input
+        .parse()
+        .map_err(|e| format!("Invalid URL '{}': {}", input, e))?;
+
+    let id = match url.host() {
+        Some("redd.it") => Some(url.path().trim_matches('/')),
+        _ => reddit::permalink_id(&url),
+    };
+
+    match id {
+        Some(id) if !id.is_empty() && id.chars().all(|i| i.is_ascii_alphanumeric()) => {
+            Ok(id.to_string())
+        }
+        _ => Err(format!("'{}' is not the URL of a reddit post", input)),
+    }
+}
+
+/// Reads a file with one value per line, like the subreddits of `--from-file`.
+///
+/// Blank lines and comments starting with `#` are ignored.
+/// Invalid lines are skipped with a warning.
+fn read_list_file<T>(path: &Path, parse: impl Fn(&str) -> Result<T, String>) -> io::Result<Vec<T>> {
+    trace!("read_list_file({:?})", path);
+
+    let content = fs::read_to_string(path)?;
+    let mut values = Vec::new();
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        };
+
+        match parse(line) {
+            Ok(value) => values.push(value),
+            Err(e) => warn!("Skipping line {} of {}: {}", number + 1, path.display(), e),
+        };
+    }
+
+    Ok(values)
+}
+
 /// Verifies a subreddit name.
 fn verify_name(name: &str) -> Result<(), String> {
     if name.len() > 21 {
@@ -419,6 +1879,114 @@ fn parse_date(input: &str) -> Result<u64, &'static str> {
         .map_err(|_| "Invalid date format")
 }
 
+/// Parses a date range like `2020-01-01..2020-02-01`, see `parse_date()`.
+/// The start has to be before the end.
+fn parse_between(input: &str) -> Result<(u64, u64), String> {
+    let separator = input
+        .find("..")
+        .ok_or_else(|| format!("Expected 'start..end', found '{}'", input))?;
+    let start = parse_date(&input[..separator])?;
+    let end = parse_date(&input[separator + 2..])?;
+
+    if start < end {
+        Ok((start, end))
+    } else {
+        Err(String::from(
+            "The start of the range has to be before its end",
+        ))
+    }
+}
+
+/// Parses a Pushshift filter like `field=value`.
+/// The field has to be one of the known fields.
+fn parse_filter(input: &str) -> Result<(String, String), String> {
+    let equals = input
+        .find('=')
+        .ok_or_else(|| format!("Expected 'field=value', found '{}'", input))?;
+    let (field, value) = (&input[..equals], &input[equals + 1..]);
+
+    Ok((parse_field(field)?, value.to_string()))
+}
+
+/// Parses the name of a post field, which has to be one of `title::FIELDS`.
+fn parse_field(input: &str) -> Result<String, String> {
+    if input == "test" || !title::FIELDS.contains(&input) {
+        return Err(format!(
+            "Unknown field '{}', see '--formatting-fields'",
+            input
+        ));
+    };
+
+    Ok(input.to_string())
+}
+
+/// The valid values of `--queue-size`.
+const QUEUE_SIZES: RangeInclusive<usize> = 1..=1000;
+
+/// Parses the queue size, which has to be within `QUEUE_SIZES`.
+fn parse_queue_size(input: &str) -> Result<usize, String> {
+    let size = usize::from_str(input).map_err(|e| format!("{}", e))?;
+
+    if QUEUE_SIZES.contains(&size) {
+        Ok(size)
+    } else {
+        Err(format!(
+            "The queue size must be between {} and {}, {} given",
+            QUEUE_SIZES.start(),
+            QUEUE_SIZES.end(),
+            size
+        ))
+    }
+}
+
+/// The valid values of `--page-size`.
+const PAGE_SIZES: RangeInclusive<usize> = 1..=1000;
+
+/// Parses the page size, which has to be within `PAGE_SIZES`.
+fn parse_page_size(input: &str) -> Result<usize, String> {
+    let size = usize::from_str(input).map_err(|e| format!("{}", e))?;
+
+    if PAGE_SIZES.contains(&size) {
+        Ok(size)
+    } else {
+        Err(format!(
+            "The page size must be between {} and {}, {} given",
+            PAGE_SIZES.start(),
+            PAGE_SIZES.end(),
+            size
+        ))
+    }
+}
+
+/// Parses the number of subreddits downloaded at once, which has to be at least 1.
+fn parse_concurrent_subreddits(input: &str) -> Result<usize, String> {
+    match usize::from_str(input).map_err(|e| format!("{}", e))? {
+        0 => Err(String::from(
+            "At least one subreddit has to be downloaded at once",
+        )),
+        count => Ok(count),
+    }
+}
+
+/// Parses the gallery depth, which has to be at least 1.
+fn parse_gallery_depth(input: &str) -> Result<usize, String> {
+    match usize::from_str(input).map_err(|e| format!("{}", e))? {
+        0 => Err(String::from(
+            "At least the album itself has to be downloaded",
+        )),
+        depth => Ok(depth),
+    }
+}
+
+/// Parses the address of `--metrics-addr`, resolving host names.
+fn parse_address(input: &str) -> Result<SocketAddr, String> {
+    input
+        .to_socket_addrs()
+        .map_err(|e| format!("{}", e))?
+        .next()
+        .ok_or_else(|| format!("No address found for '{}'", input))
+}
+
 /// Parses an input and returns the domain.
 /// This function automatically detects URL-like input and extracts the host.
 fn parse_domains(input: &str) -> Result<String, String> {
@@ -434,18 +2002,49 @@ fn parse_domains(input: &str) -> Result<String, String> {
 
 /// Parses the command line arguments and runs the tool.
 fn main() {
-    let mut parameters = Parameters::from_args();
+    let matches = Parameters::clap().get_matches();
+    let mut parameters = Parameters::from_clap(&matches);
+
+    // Errors are reported once the logger is initialized
+    let config = match parameters.config {
+        Some(ref path) => Config::load(path),
+        None => Config::load_default(),
+    }
+    .and_then(|config| config.apply(&mut parameters, &matches));
+    if let Some((after, before)) = parameters.between {
+        parameters.after = Some(after);
+        parameters.before = Some(before);
+    };
+    if let Some(width) = parameters.zero_pad {
+        parameters.title.set_zero_pad(width);
+    };
+    parameters
+        .title
+        .set_normalize_unicode(!parameters.no_normalize_unicode);
+    parameters
+        .title
+        .set_truncate_at_word(parameters.truncate_at_word);
 
     if parameters.domains {
         println!("{}", sites::supported_domains());
         return;
     };
 
+    if parameters.domains_json {
+        println!("{}", sites::supported_domains_json());
+        return;
+    };
+
     if parameters.formatting_fields {
         print!("{}", title::formatting_help());
         return;
     };
 
+    if parameters.formatting_fields_json {
+        println!("{}", title::formatting_fields_json());
+        return;
+    };
+
     let colors = match parameters.color.as_ref() {
         "always" => (true, true),
         "never" => (false, false),
@@ -470,26 +2069,137 @@ fn main() {
         } else {
             4
         }
-    } else if parameters.quiet {
+    } else if parameters.quiet || parameters.list_only || !parameters.print_fields.is_empty() {
         2
     } else {
         3
     };
 
-    logger::init(verbosity, colors.0, colors.1);
+    // Everything is logged to stderr if stdout is used for the download
+    let info_colors = if parameters.stdout {
+        colors.1
+    } else {
+        colors.0
+    };
+    let log_file = parameters.log_file.as_ref().map(|path| {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open log file {}: {}", path.display(), e))
+    });
+    let (log_file, log_file_error) = match log_file {
+        Some(Ok(file)) => (Some(file), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+    logger::init(
+        verbosity,
+        info_colors,
+        colors.1,
+        parameters.stdout,
+        log_file,
+    );
+
+    if let Some(e) = log_file_error {
+        error!("{}", e);
+        process::exit(1);
+    };
+
+    if let Err(e) = config {
+        error!("{}", e);
+        process::exit(1);
+    };
+
+    if let Some(ref path) = parameters.from_file {
+        match read_list_file(path, parse_input) {
+            Ok(subreddits) => parameters.subreddits.extend(subreddits),
+            Err(e) => {
+                error!("Failed to read {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        };
+    };
+
+    // An empty file does not restrict the domains
+    let domain_files = [
+        (parameters.allow_file.clone(), &mut parameters.allow),
+        (parameters.exclude_file.clone(), &mut parameters.exclude),
+    ];
+    for (path, domains) in domain_files {
+        if let Some(path) = path {
+            match read_list_file(&path, parse_domains) {
+                Ok(values) if values.is_empty() => (),
+                Ok(values) => domains.get_or_insert_with(Vec::new).extend(values),
+                Err(e) => {
+                    error!("Failed to read {}: {}", path.display(), e);
+                    process::exit(1);
+                }
+            };
+        };
+    }
 
-    if parameters.subreddits.is_empty() {
+    if parameters.subreddits.is_empty()
+        && parameters.reconcile.is_none()
+        && parameters.retry_failed.is_none()
+    {
         info!("No input subreddit given");
         return;
     };
 
-    if !parameters.title.utilizes_id() {
+    if parameters.stdout {
+        let single = match parameters.subreddits.as_slice() {
+            [Subreddit::Subreddit(name)] => !name.is_empty(),
+            [Subreddit::Multi { .. }] => false,
+            [_] => true,
+            _ => false,
+        };
+
+        if !single {
+            error!("'--stdout' requires exactly one subreddit or user, multiple and multireddits are not supported");
+            process::exit(1);
+        };
+    };
+
+    // Fail before any network activity
+    if parameters.reconcile.is_none()
+        && parameters.retry_failed.is_none()
+        && !parameters.list_only
+        && !parameters.stdout
+        && parameters.print_fields.is_empty()
+    {
+        if let Ok(metadata) = fs::metadata(&parameters.output) {
+            if !metadata.is_dir() {
+                error!(
+                    "The output {} is not a directory",
+                    parameters.output.display()
+                );
+                process::exit(1);
+            };
+        };
+    };
+
+    if let Some(ref temp_dir) = parameters.temp_dir {
+        if !temp_dir.is_dir() {
+            error!(
+                "The temporary directory {} does not exist",
+                temp_dir.display()
+            );
+            process::exit(1);
+        };
+    };
+
+    if parameters.insecure {
+        warn!("TLS certificates are not verified because of '--insecure', connections can be intercepted");
+    };
+
+    if !parameters.title.utilizes_id() && !parameters.stdout && parameters.print_fields.is_empty() {
         let warn: Box<dyn Display> = if cfg!(not(windows)) && colors.0 {
             Box::new(Color::Yellow.paint("[WARN]"))
         } else {
             Box::new("[WARN]")
         };
-        println!("{}    The title formatting string does not contain `{{id}}`. File name collisions may occur.", warn);
+        println!("{}    The title formatting string does not contain `{{id}}`. File name collisions may occur, consider '--collision-strategy rename'.", warn);
     };
 
     for i in parameters.subreddits.iter() {
@@ -513,6 +2223,11 @@ fn main() {
         };
     }
 
+    if parameters.client_id.is_none() && parameters.subreddits.iter().any(Subreddit::is_private) {
+        error!("Private listings like 'u/me/saved' require '--client-id', '--client-secret' and '--refresh-token'\n\n{}", error::HELP_OAUTH);
+        process::exit(1);
+    };
+
     // Check whether ffmpeg is installed
     if let VRedditMode::Ffmpeg = parameters.vreddit_mode {
         if let Err(e) = Command::new("ffmpeg")
@@ -557,36 +2272,41 @@ fn main() {
 
     match Builder::new().threaded_scheduler().enable_all().build() {
         Ok(mut runtime) => {
-            if let Err(e) = runtime.block_on(subreddit::rip(parameters, subreddits)) {
-                if e.source().is_none() {
-                    error!("Error: {}", e);
-                    process::exit(3);
-                };
-                let e = e.into_source().unwrap();
-
-                let e = match e.downcast::<hyper::Error>() {
-                    Ok(e) => {
-                        if e.is_connect() {
-                            error!("Essential HTTP request failed: {}\n\n{}", e, HELP_NETWORK);
-                        } else {
-                            error!("Essential HTTP request failed: {}", e);
-                        };
-                        process::exit(2);
-                    }
-                    Err(e) => e,
-                };
-
-                let e = match e.downcast::<serde_json::Error>() {
-                    Ok(e) => {
-                        error!("Unexpectedly received invalid JSON: {}\n\n{}", e, HELP_JSON);
-                        process::exit(3);
-                    }
-                    Err(e) => e,
-                };
+            let e = match runtime.block_on(subreddit::rip(parameters, subreddits)) {
+                Ok(Finished::Failed(0)) => return,
+                Ok(Finished::Failed(_)) => process::exit(4),
+                Ok(Finished::StorageFull) => process::exit(5),
+                Err(e) => e,
+            };
 
+            if e.source().is_none() {
                 error!("Error: {}", e);
                 process::exit(3);
-            }
+            };
+            let e = e.into_source().unwrap();
+
+            let e = match e.downcast::<hyper::Error>() {
+                Ok(e) => {
+                    if e.is_connect() {
+                        error!("Essential HTTP request failed: {}\n\n{}", e, HELP_NETWORK);
+                    } else {
+                        error!("Essential HTTP request failed: {}", e);
+                    };
+                    process::exit(2);
+                }
+                Err(e) => e,
+            };
+
+            let e = match e.downcast::<serde_json::Error>() {
+                Ok(e) => {
+                    error!("Unexpectedly received invalid JSON: {}\n\n{}", e, HELP_JSON);
+                    process::exit(3);
+                }
+                Err(e) => e,
+            };
+
+            error!("Error: {}", e);
+            process::exit(3);
         }
         Err(e) => {
             error!("Failed to start runtime: {}\n\n{}", e, error::HELP_BUG);
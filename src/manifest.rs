@@ -0,0 +1,224 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+Manifests of finished downloads.
+
+A manifest contains one JSON object per line, each describing the outcome
+of a post. Entries are appended, so the same manifest can be passed to
+multiple runs. If an output file is listed more than once, the last entry wins.
+*/
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+
+/// The outcome of a post.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Saved,
+    Skipped,
+    Failed,
+
+    /// Only the post was recorded, see `--record-only`.
+    Recorded,
+}
+
+/// A single line of a manifest.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The ID of the post.
+    pub id: String,
+
+    /// The domain of the post.
+    pub domain: String,
+
+    /// The URL the post links to.
+    pub url: String,
+
+    /// The output file.
+    pub output: PathBuf,
+
+    /// Whether the post is a self post.
+    #[serde(default)]
+    pub is_selfpost: bool,
+
+    /// The outcome of the download.
+    pub outcome: Outcome,
+
+    /// The hash of the output file, see [`hash()`].
+    /// Not present for directories, failed downloads and recorded posts.
+    ///
+    /// [`hash()`]: fn.hash.html
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+/// A manifest opened for appending entries.
+#[derive(Debug)]
+pub struct Manifest {
+    file: File,
+}
+
+impl Manifest {
+    /// Opens the manifest, creating it if it does not exist.
+    pub fn open(path: &Path) -> Result<Manifest> {
+        trace!("Manifest::open({:?})", path);
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Manifest { file })
+    }
+
+    /// Appends an entry.
+    pub fn write(&mut self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+
+        Ok(())
+    }
+}
+
+/// Reads the entries of a manifest.
+///
+/// Only the last entry of every output file is returned,
+/// in the order the output files first appear.
+pub fn read(path: &Path) -> Result<Vec<Entry>> {
+    trace!("read({:?})", path);
+
+    let file = BufReader::new(File::open(path)?);
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut positions = HashMap::new();
+
+    for (number, line) in file.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        };
+
+        let entry: Entry = serde_json::from_str(&line).map_err(|e| {
+            Error::new(format!(
+                "Invalid manifest entry on line {}: {}",
+                number + 1,
+                e
+            ))
+        })?;
+
+        match positions.get(&entry.output) {
+            Some(&index) => entries[index] = entry,
+            None => {
+                positions.insert(entry.output.clone(), entries.len());
+                entries.push(entry);
+            }
+        };
+    }
+
+    Ok(entries)
+}
+
+/// Hashes the content of a file using 64-bit FNV-1a.
+/// The hash is stable across platforms and versions.
+///
+/// Returns `None` for directories, as created for galleries.
+pub fn hash(path: &Path) -> io::Result<Option<String>> {
+    if fs::metadata(path)?.is_dir() {
+        return Ok(None);
+    };
+
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; 64 * 1024];
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        };
+
+        for i in &buffer[..read] {
+            hash ^= u64::from(*i);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    Ok(Some(format!("{:016x}", hash)))
+}
+
+#[test]
+#[allow(unused_must_use)]
+fn manifest() {
+    let mut path = std::env::temp_dir();
+    path.push("redditrip_manifest");
+    fs::remove_file(&path);
+
+    let entry = |id: &str, output: &str, outcome| Entry {
+        id: id.to_string(),
+        domain: String::from("i.redd.it"),
+        url: format!("https://i.redd.it/{}.jpg", id),
+        output: PathBuf::from(output),
+        is_selfpost: false,
+        outcome,
+        hash: None,
+    };
+
+    {
+        let mut manifest = Manifest::open(&path).unwrap();
+        manifest
+            .write(&entry("a", "a.jpg", Outcome::Failed))
+            .unwrap();
+        manifest
+            .write(&entry("b", "b.jpg", Outcome::Saved))
+            .unwrap();
+    };
+    {
+        let mut manifest = Manifest::open(&path).unwrap();
+        manifest
+            .write(&entry("a", "a.jpg", Outcome::Saved))
+            .unwrap();
+        manifest
+            .write(&entry("b", "b.jpg", Outcome::Failed))
+            .unwrap();
+        manifest
+            .write(&entry("c", "b.jpg", Outcome::Failed))
+            .unwrap();
+    };
+
+    // Only the latest entry of an output is kept
+    assert_eq!(
+        vec![
+            entry("a", "a.jpg", Outcome::Saved),
+            entry("c", "b.jpg", Outcome::Failed)
+        ],
+        read(&path).unwrap()
+    );
+
+    fs::write(&path, "").unwrap();
+    assert_eq!(Some(String::from("cbf29ce484222325")), hash(&path).unwrap());
+    fs::write(&path, "a").unwrap();
+    assert_eq!(Some(String::from("af63dc4c8601ec8c")), hash(&path).unwrap());
+    assert_eq!(None, hash(&std::env::temp_dir()).unwrap());
+
+    fs::remove_file(&path);
+}
@@ -0,0 +1,190 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+Counters of a run in the Prometheus text format, see `--metrics-addr`.
+
+The counters are served by a small HTTP server at `/metrics`,
+which runs next to the downloads and is stopped when the run finishes.
+*/
+
+use std::{
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use http::header::CONTENT_TYPE;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::prelude::*;
+
+/// The counters of a run.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    posts_listed: AtomicU64,
+    downloads_succeeded: AtomicU64,
+    downloads_failed: AtomicU64,
+    bytes_downloaded: AtomicU64,
+}
+
+impl Metrics {
+    /// Counts posts read from a listing.
+    pub fn add_listed(&self, posts: u64) {
+        self.posts_listed.fetch_add(posts, Ordering::Relaxed);
+    }
+
+    /// Counts a finished download.
+    pub fn add_download(&self, succeeded: bool, bytes: u64) {
+        if succeeded {
+            self.downloads_succeeded.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.downloads_failed.fetch_add(1, Ordering::Relaxed);
+        };
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Returns the number of downloaded bytes.
+    pub fn bytes_downloaded(&self) -> u64 {
+        self.bytes_downloaded.load(Ordering::Relaxed)
+    }
+
+    /// Formats the counters in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let counters = [
+            (
+                "posts_listed",
+                "The number of posts read from the listings",
+                &self.posts_listed,
+            ),
+            (
+                "downloads_succeeded",
+                "The number of saved downloads",
+                &self.downloads_succeeded,
+            ),
+            (
+                "downloads_failed",
+                "The number of failed downloads",
+                &self.downloads_failed,
+            ),
+            (
+                "bytes_downloaded",
+                "The number of downloaded bytes",
+                &self.bytes_downloaded,
+            ),
+        ];
+
+        let mut result = String::new();
+        for (name, help, value) in counters.iter() {
+            let _ = write!(
+                result,
+                "# HELP redditrip_{0}_total {1}\n# TYPE redditrip_{0}_total counter\nredditrip_{0}_total {2}\n",
+                name,
+                help,
+                value.load(Ordering::Relaxed)
+            );
+        }
+
+        result
+    }
+}
+
+/// A running metrics server.
+/// The server is shut down when this is dropped.
+#[derive(Debug)]
+pub struct MetricsServer {
+    shutdown: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl MetricsServer {
+    /// Shuts the server down, waiting for the open connections to finish.
+    pub async fn stop(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.handle.await;
+    }
+}
+
+/// Starts serving the counters at `/metrics` of the address.
+pub fn serve(address: SocketAddr, metrics: Arc<Metrics>) -> Result<MetricsServer> {
+    trace!("serve({:?})", address);
+
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = respond(&metrics, &request);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    let (shutdown, signal) = oneshot::channel::<()>();
+    let server = Server::try_bind(&address)?
+        .serve(make_service)
+        .with_graceful_shutdown(async {
+            let _ = signal.await;
+        });
+    info!("Serving metrics at http://{}/metrics", address);
+
+    let handle = tokio::spawn(async move {
+        if let Err(e) = server.await {
+            warn!("The metrics server failed: {}", e);
+        };
+    });
+
+    Ok(MetricsServer { shutdown, handle })
+}
+
+/// Answers a request to the metrics server.
+fn respond(metrics: &Metrics, request: &Request<Body>) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+
+    if request.uri().path() == "/metrics" {
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, "text/plain; version=0.0.4".parse().unwrap());
+        *response.body_mut() = Body::from(metrics.render());
+    } else {
+        *response.status_mut() = StatusCode::NOT_FOUND;
+    };
+
+    response
+}
+
+#[test]
+fn metrics_render() {
+    let metrics = Metrics::default();
+    metrics.add_listed(3);
+    metrics.add_download(true, 1024);
+    metrics.add_download(false, 0);
+
+    let text = metrics.render();
+    assert!(text
+        .contains("# TYPE redditrip_posts_listed_total counter\nredditrip_posts_listed_total 3\n"));
+    assert!(text.contains("redditrip_downloads_succeeded_total 1\n"));
+    assert!(text.contains("redditrip_downloads_failed_total 1\n"));
+    assert!(text.contains("redditrip_bytes_downloaded_total 1024\n"));
+    assert_eq!(1024, metrics.bytes_downloaded());
+}
@@ -0,0 +1,186 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+Browsable archives, see `--mirror`.
+
+The posts are sorted into `r/<subreddit>/<year>/<month>` directories,
+and every directory up to the output directory gets an `index.html` file
+listing its contents, so the whole archive can be browsed from the top.
+The index files are generated from the directory contents, so they
+also cover the files of earlier runs.
+*/
+
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use time::Timespec;
+
+use crate::sites::selfpost::escape;
+
+/// The name of the generated index files.
+pub const INDEX_FILE_NAME: &str = "index.html";
+
+/// Returns the directory of a post relative to the subreddit directory,
+/// which is `<year>/<month>` of its creation time in UTC.
+pub fn directory(created_utc: u64) -> PathBuf {
+    let time = time::at_utc(Timespec {
+        sec: created_utc as i64,
+        nsec: 0,
+    });
+
+    // `tm_year` counts from 1900, `tm_mon` from 0
+    [
+        (time.tm_year + 1900).to_string(),
+        format!("{:02}", time.tm_mon + 1),
+    ]
+    .iter()
+    .collect()
+}
+
+/// Returns the directories whose index files have to be written after
+/// the given directories were changed, which includes their parents up to
+/// and including `root`. Subdirectories come before their parents,
+/// so every index is written after the ones it links to.
+pub fn changed_directories<'a>(
+    root: &Path,
+    directories: impl IntoIterator<Item = &'a PathBuf>,
+) -> Vec<PathBuf> {
+    let mut result = BTreeSet::new();
+
+    for i in directories {
+        for directory in i.ancestors().take_while(|path| path.starts_with(root)) {
+            result.insert(directory.to_path_buf());
+        }
+    }
+
+    result.into_iter().rev().collect()
+}
+
+/// Writes the index file of a directory, listing its subdirectories and files.
+/// Hidden files, like the update file, are left out.
+pub fn write_index(directory: &Path) -> io::Result<()> {
+    trace!("write_index({:?})", directory);
+
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || name == INDEX_FILE_NAME {
+            continue;
+        };
+
+        entries.push((name, entry.file_type()?.is_dir()));
+    }
+    // Directories first, then files, both in alphabetical order
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let title = escape(
+        &directory
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    );
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n<body>\n<h1>{0}</h1>\n<ul>\n",
+        title
+    );
+    for (name, is_dir) in entries {
+        let (href, text) = if is_dir {
+            (
+                format!("{}/{}", encode(&name), INDEX_FILE_NAME),
+                format!("{}/", name),
+            )
+        } else {
+            (encode(&name), name)
+        };
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            href,
+            escape(&text)
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+
+    fs::write(directory.join(INDEX_FILE_NAME), html)
+}
+
+/// Percent-encodes a file name for use in a link.
+fn encode(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+
+    for i in name.bytes() {
+        match i {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                result.push(i as char)
+            }
+            other => result.push_str(&format!("%{:02X}", other)),
+        };
+    }
+
+    result
+}
+
+#[test]
+#[allow(unused_must_use)]
+fn mirror_index() {
+    assert_eq!(Path::new("2020/01"), directory(1577836800));
+    assert_eq!(Path::new("1999/12"), directory(946684799));
+    assert_eq!("a%20b%23.jpg", encode("a b#.jpg"));
+
+    let mut root = std::env::temp_dir();
+    root.push("redditrip_mirror");
+    fs::remove_dir_all(&root);
+    let r = root.join("r");
+    let subreddit = r.join("pics");
+    let year = subreddit.join("2020");
+    let month = year.join("01");
+    fs::create_dir_all(&month).unwrap();
+    fs::write(month.join("abc-Lorem & ipsum.jpg"), "").unwrap();
+    fs::write(subreddit.join(".redditrip"), "").unwrap();
+
+    let directories = changed_directories(&root, std::slice::from_ref(&month));
+    assert_eq!(
+        vec![
+            month.clone(),
+            year.clone(),
+            subreddit.clone(),
+            r.clone(),
+            root.clone()
+        ],
+        directories
+    );
+    for i in directories {
+        write_index(&i).unwrap();
+    }
+
+    let index = fs::read_to_string(month.join(INDEX_FILE_NAME)).unwrap();
+    assert!(index.contains(
+        "<li><a href=\"abc-Lorem%20%26%20ipsum.jpg\">abc-Lorem &amp; ipsum.jpg</a></li>"
+    ));
+    let index = fs::read_to_string(subreddit.join(INDEX_FILE_NAME)).unwrap();
+    assert!(index.contains("<li><a href=\"2020/index.html\">2020/</a></li>"));
+    assert!(!index.contains(".redditrip"));
+    assert!(year.join(INDEX_FILE_NAME).exists());
+    let index = fs::read_to_string(root.join(INDEX_FILE_NAME)).unwrap();
+    assert!(index.contains("<li><a href=\"r/index.html\">r/</a></li>"));
+    assert!(r.join(INDEX_FILE_NAME).exists());
+
+    fs::remove_dir_all(&root);
+}
@@ -0,0 +1,795 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+A minimal MP4 muxer.
+
+Combines the separate video and audio files served by `v.redd.it` into
+a single file without re-encoding anything. Only the boxes describing
+the tracks are rewritten, the media data is copied as it is.
+
+Both progressive and fragmented (DASH) files are supported. The result
+has the same layout as the video input: progressive files get a single
+`mdat` box, fragmented files get the video fragments followed by the
+audio fragments.
+
+No MP4 crate is used: merging only rewrites a handful of boxes, while
+the available crates model every sample and can not write fragmented files.
+*/
+
+use std::convert::TryInto;
+
+use crate::error::{Error, Result};
+
+/// The boxes whose children have to be accessed while muxing.
+const CONTAINERS: &[[u8; 4]] = &[
+    *b"moov", *b"trak", *b"mdia", *b"minf", *b"stbl", *b"edts", *b"mvex", *b"moof", *b"traf",
+];
+
+/// A box of an MP4 file.
+#[derive(Debug, Clone, PartialEq)]
+struct Mp4Box {
+    /// The four character type.
+    kind: [u8; 4],
+
+    /// The payload of the box.
+    content: Content,
+}
+
+/// The payload of a box.
+#[derive(Debug, Clone, PartialEq)]
+enum Content {
+    /// Raw data, for every box which is not a container.
+    Data(Vec<u8>),
+
+    /// The boxes within a container.
+    Children(Vec<Mp4Box>),
+}
+
+impl Mp4Box {
+    /// Parses a box and its children.
+    fn parse(kind: [u8; 4], payload: &[u8]) -> Result<Mp4Box> {
+        let content = if CONTAINERS.contains(&kind) {
+            Content::Children(
+                split(payload)?
+                    .into_iter()
+                    .map(|(kind, _, payload)| Mp4Box::parse(kind, payload))
+                    .collect::<Result<_>>()?,
+            )
+        } else {
+            Content::Data(payload.to_vec())
+        };
+
+        Ok(Mp4Box { kind, content })
+    }
+
+    /// Serializes the box.
+    fn write(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.extend_from_slice(&[0; 4]); // The size is written later
+        buf.extend_from_slice(&self.kind);
+
+        match &self.content {
+            Content::Data(data) => buf.extend_from_slice(data),
+            Content::Children(children) => {
+                for child in children {
+                    child.write(buf);
+                }
+            }
+        };
+
+        // Boxes other than `mdat` are always small enough
+        let size = (buf.len() - start) as u32;
+        buf[start..start + 4].copy_from_slice(&size.to_be_bytes());
+    }
+
+    /// Returns the serialized size of the box.
+    fn size(&self) -> usize {
+        8 + match &self.content {
+            Content::Data(data) => data.len(),
+            Content::Children(children) => children.iter().map(Mp4Box::size).sum(),
+        }
+    }
+
+    /// Returns the children of a container, or an empty slice.
+    fn children(&self) -> &[Mp4Box] {
+        match &self.content {
+            Content::Children(children) => children,
+            Content::Data(_) => &[],
+        }
+    }
+
+    /// Returns the children of a container.
+    fn children_mut(&mut self) -> Result<&mut Vec<Mp4Box>> {
+        match &mut self.content {
+            Content::Children(children) => Ok(children),
+            Content::Data(_) => Err(invalid()),
+        }
+    }
+
+    /// Returns the payload of a box which is not a container.
+    fn data(&self) -> Result<&[u8]> {
+        match &self.content {
+            Content::Data(data) => Ok(data),
+            Content::Children(_) => Err(invalid()),
+        }
+    }
+
+    /// Returns the payload of a box which is not a container.
+    fn data_mut(&mut self) -> Result<&mut Vec<u8>> {
+        match &mut self.content {
+            Content::Data(data) => Ok(data),
+            Content::Children(_) => Err(invalid()),
+        }
+    }
+
+    /// Follows the path of box types starting at this box.
+    fn find(&self, path: &[&[u8; 4]]) -> Option<&Mp4Box> {
+        match path.split_first() {
+            None => Some(self),
+            Some((kind, rest)) => self
+                .children()
+                .iter()
+                .find(|child| &child.kind == *kind)
+                .and_then(|child| child.find(rest)),
+        }
+    }
+
+    /// Follows the path of box types starting at this box.
+    fn find_mut(&mut self, path: &[&[u8; 4]]) -> Option<&mut Mp4Box> {
+        match path.split_first() {
+            None => Some(self),
+            Some((kind, rest)) => match &mut self.content {
+                Content::Children(children) => children
+                    .iter_mut()
+                    .find(|child| &child.kind == *kind)
+                    .and_then(|child| child.find_mut(rest)),
+                Content::Data(_) => None,
+            },
+        }
+    }
+}
+
+/// A parsed input file.
+#[derive(Debug)]
+struct File<'a> {
+    /// The `ftyp` box.
+    ftyp: Option<Mp4Box>,
+
+    /// The `moov` box.
+    moov: Mp4Box,
+
+    /// The payloads of the `mdat` boxes with their offset in the file.
+    mdat: Vec<(usize, &'a [u8])>,
+
+    /// The `moof` boxes and the media data following them, if the file is fragmented.
+    fragments: Vec<Fragment<'a>>,
+}
+
+/// A part of a fragmented file.
+#[derive(Debug)]
+enum Fragment<'a> {
+    /// A `moof` box.
+    Moof(Mp4Box),
+
+    /// The payload of a `mdat` box.
+    Mdat(&'a [u8]),
+}
+
+impl<'a> File<'a> {
+    /// Parses the top level boxes.
+    fn parse(data: &'a [u8]) -> Result<File<'a>> {
+        let mut ftyp = None;
+        let mut moov = None;
+        let mut mdat = Vec::new();
+        let mut fragments = Vec::new();
+
+        for (kind, offset, payload) in split(data)? {
+            match &kind {
+                b"ftyp" => ftyp = Some(Mp4Box::parse(kind, payload)?),
+                b"moov" => moov = Some(Mp4Box::parse(kind, payload)?),
+                b"moof" => fragments.push(Fragment::Moof(Mp4Box::parse(kind, payload)?)),
+                b"mdat" => {
+                    mdat.push((offset, payload));
+                    fragments.push(Fragment::Mdat(payload));
+                }
+                // `sidx`, `mfra`, `free` and others are left out
+                _ => (),
+            };
+        }
+
+        let fragmented = fragments
+            .iter()
+            .any(|fragment| matches!(fragment, Fragment::Moof(_)));
+        if !fragmented {
+            fragments.clear();
+        };
+
+        Ok(File {
+            ftyp,
+            moov: moov.ok_or_else(|| Error::new("Invalid MP4 file: 'moov' box missing"))?,
+            mdat,
+            fragments,
+        })
+    }
+
+    /// Returns whether the file is fragmented.
+    fn is_fragmented(&self) -> bool {
+        !self.fragments.is_empty()
+    }
+
+    /// Returns the total length of the media data.
+    fn mdat_len(&self) -> usize {
+        self.mdat.iter().map(|(_, payload)| payload.len()).sum()
+    }
+}
+
+/// Combines a video file and an audio file into a single file.
+///
+/// The tracks of the audio file are appended to the video file.
+pub fn merge(video: &[u8], audio: &[u8]) -> Result<Vec<u8>> {
+    let mut video = File::parse(video)?;
+    let mut audio = File::parse(audio)?;
+
+    if video.is_fragmented() != audio.is_fragmented() {
+        return Err(Error::new(
+            "Cannot combine a fragmented and a progressive MP4 file",
+        ));
+    };
+
+    let (video_timescale, video_duration) = movie_header(&video.moov)?;
+    let (audio_timescale, audio_duration) = movie_header(&audio.moov)?;
+
+    // Renumber the audio tracks so they follow the video tracks
+    let mut next_id = video
+        .moov
+        .children()
+        .iter()
+        .filter(|child| &child.kind == b"trak")
+        .map(track_id)
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .max()
+        .unwrap_or(0)
+        + 1;
+    let mut ids = Vec::new();
+
+    for trak in audio.moov.children_mut()?.iter_mut() {
+        if &trak.kind != b"trak" {
+            continue;
+        };
+
+        let old = track_id(trak)?;
+        ids.push((old, next_id));
+        set_track_header(trak, next_id, video_timescale, audio_timescale)?;
+        next_id += 1;
+    }
+
+    // Update the movie header
+    {
+        let mvhd = video
+            .moov
+            .find_mut(&[b"mvhd"])
+            .ok_or_else(invalid)?
+            .data_mut()?;
+        let duration =
+            video_duration.max(rescale(audio_duration, audio_timescale, video_timescale));
+        if mvhd.first() == Some(&1) {
+            write_u64(mvhd, 24, duration)?;
+        } else {
+            write_u32(mvhd, 16, duration.min(u32::MAX as u64) as u32)?;
+        };
+        let len = mvhd.len();
+        write_u32(mvhd, len.checked_sub(4).ok_or_else(invalid)?, next_id)?;
+    }
+
+    if video.is_fragmented() {
+        merge_fragmented(video, audio, &ids)
+    } else {
+        merge_progressive(video, audio)
+    }
+}
+
+/// Combines two progressive files.
+fn merge_progressive(mut video: File<'_>, audio: File<'_>) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    if let Some(ref ftyp) = video.ftyp {
+        ftyp.write(&mut buf);
+    };
+
+    let mut audio_traks: Vec<Mp4Box> = audio
+        .moov
+        .children()
+        .iter()
+        .filter(|child| &child.kind == b"trak")
+        .cloned()
+        .collect();
+
+    // The size of the `moov` box does not depend on the chunk offsets,
+    // so the start of the media data is known beforehand
+    let mdat_len = video.mdat_len() + audio.mdat_len();
+    let mdat_header = if mdat_len + 8 > u32::MAX as usize {
+        16
+    } else {
+        8
+    };
+    let video_start = (buf.len()
+        + video.moov.size()
+        + audio_traks.iter().map(Mp4Box::size).sum::<usize>()
+        + mdat_header) as u64;
+    let audio_start = video_start + video.mdat_len() as u64;
+
+    let video_mdat = &video.mdat;
+    for trak in video.moov.children_mut()?.iter_mut() {
+        if &trak.kind == b"trak" {
+            rewrite_chunk_offsets(trak, |offset| {
+                Ok(video_start + relative_offset(video_mdat, offset)?)
+            })?;
+        };
+    }
+    for trak in audio_traks.iter_mut() {
+        rewrite_chunk_offsets(trak, |offset| {
+            Ok(audio_start + relative_offset(&audio.mdat, offset)?)
+        })?;
+    }
+
+    video.moov.children_mut()?.extend(audio_traks);
+    video.moov.write(&mut buf);
+
+    if mdat_header == 16 {
+        buf.extend_from_slice(&1u32.to_be_bytes());
+        buf.extend_from_slice(b"mdat");
+        buf.extend_from_slice(&(mdat_len as u64 + 16).to_be_bytes());
+    } else {
+        buf.extend_from_slice(&(mdat_len as u32 + 8).to_be_bytes());
+        buf.extend_from_slice(b"mdat");
+    };
+    for (_, payload) in video.mdat.iter().chain(audio.mdat.iter()) {
+        buf.extend_from_slice(payload);
+    }
+
+    Ok(buf)
+}
+
+/// Combines two fragmented files.
+fn merge_fragmented(mut video: File<'_>, audio: File<'_>, ids: &[(u32, u32)]) -> Result<Vec<u8>> {
+    let new_id = |old: u32| {
+        ids.iter()
+            .find(|(from, _)| *from == old)
+            .map(|(_, to)| *to)
+            .ok_or_else(invalid)
+    };
+
+    // Add the tracks and their defaults
+    let mut audio_traks = Vec::new();
+    let mut audio_trex = Vec::new();
+    for child in audio.moov.children() {
+        if &child.kind == b"trak" {
+            audio_traks.push(child.clone());
+        } else if &child.kind == b"mvex" {
+            for trex in child.children().iter().filter(|i| &i.kind == b"trex") {
+                let mut trex = trex.clone();
+                let data = trex.data_mut()?;
+                let id = new_id(read_u32(data, 4)?)?;
+                write_u32(data, 4, id)?;
+                audio_trex.push(trex);
+            }
+        };
+    }
+
+    let moov = video.moov.children_mut()?;
+    let len = moov.len();
+    let position = moov
+        .iter()
+        .position(|child| &child.kind == b"mvex")
+        .unwrap_or(len);
+    for (i, trak) in audio_traks.into_iter().enumerate() {
+        moov.insert(position + i, trak);
+    }
+    video
+        .moov
+        .find_mut(&[b"mvex"])
+        .ok_or_else(|| Error::new("Invalid MP4 file: 'mvex' box missing"))?
+        .children_mut()?
+        .extend(audio_trex);
+
+    let mut buf = Vec::new();
+    if let Some(ref ftyp) = video.ftyp {
+        ftyp.write(&mut buf);
+    };
+    video.moov.write(&mut buf);
+
+    let mut sequence = 1;
+    let fragments = video
+        .fragments
+        .into_iter()
+        .map(|fragment| (fragment, false))
+        .chain(audio.fragments.into_iter().map(|fragment| (fragment, true)));
+    for (fragment, is_audio) in fragments {
+        match fragment {
+            Fragment::Moof(mut moof) => {
+                let mfhd = moof.find_mut(&[b"mfhd"]).ok_or_else(invalid)?.data_mut()?;
+                write_u32(mfhd, 4, sequence)?;
+                sequence += 1;
+
+                for traf in moof.children_mut()?.iter_mut() {
+                    if &traf.kind != b"traf" {
+                        continue;
+                    };
+
+                    let tfhd = traf.find_mut(&[b"tfhd"]).ok_or_else(invalid)?.data_mut()?;
+                    if read_u32(tfhd, 0)? & 0x000001 != 0 {
+                        // The offset would be wrong after moving the fragment
+                        return Err(Error::new(
+                            "Unsupported MP4 file: absolute base data offset",
+                        ));
+                    };
+                    if is_audio {
+                        let id = new_id(read_u32(tfhd, 4)?)?;
+                        write_u32(tfhd, 4, id)?;
+                    };
+                }
+
+                moof.write(&mut buf);
+            }
+            Fragment::Mdat(payload) => {
+                if payload.len() + 8 > u32::MAX as usize {
+                    buf.extend_from_slice(&1u32.to_be_bytes());
+                    buf.extend_from_slice(b"mdat");
+                    buf.extend_from_slice(&(payload.len() as u64 + 16).to_be_bytes());
+                } else {
+                    buf.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+                    buf.extend_from_slice(b"mdat");
+                };
+                buf.extend_from_slice(payload);
+            }
+        };
+    }
+
+    Ok(buf)
+}
+
+/// Translates an offset in a file to an offset
+/// within the concatenated `mdat` payloads.
+fn relative_offset(mdat: &[(usize, &[u8])], offset: u64) -> Result<u64> {
+    let mut skipped = 0;
+    for (start, payload) in mdat {
+        let start = *start as u64;
+        if offset >= start && offset <= start + payload.len() as u64 {
+            return Ok(skipped + offset - start);
+        };
+        skipped += payload.len() as u64;
+    }
+
+    Err(Error::new("Invalid MP4 file: chunk outside of media data"))
+}
+
+/// The type, the offset of the payload and the payload of a box.
+type RawBox<'a> = ([u8; 4], usize, &'a [u8]);
+
+/// Splits data into boxes.
+fn split(data: &[u8]) -> Result<Vec<RawBox<'_>>> {
+    let mut boxes = Vec::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let mut size = read_u32(data, offset)? as u64;
+        let kind: [u8; 4] = data
+            .get(offset + 4..offset + 8)
+            .ok_or_else(invalid)?
+            .try_into()
+            .unwrap();
+        let mut header = 8;
+
+        if size == 1 {
+            size = read_u64(data, offset + 8)?;
+            header = 16;
+        } else if size == 0 {
+            // The box extends to the end of the file
+            size = (data.len() - offset) as u64;
+        };
+
+        if size < header as u64 || offset as u64 + size > data.len() as u64 {
+            return Err(invalid());
+        };
+
+        let end = offset + size as usize;
+        boxes.push((kind, offset + header, &data[offset + header..end]));
+        offset = end;
+    }
+
+    Ok(boxes)
+}
+
+/// Returns the timescale and the duration from the `mvhd` box.
+fn movie_header(moov: &Mp4Box) -> Result<(u32, u64)> {
+    let mvhd = moov.find(&[b"mvhd"]).ok_or_else(invalid)?.data()?;
+
+    if mvhd.first() == Some(&1) {
+        Ok((read_u32(mvhd, 20)?, read_u64(mvhd, 24)?))
+    } else {
+        Ok((read_u32(mvhd, 12)?, read_u32(mvhd, 16)? as u64))
+    }
+}
+
+/// Returns the ID of a track.
+fn track_id(trak: &Mp4Box) -> Result<u32> {
+    let tkhd = trak.find(&[b"tkhd"]).ok_or_else(invalid)?.data()?;
+
+    if tkhd.first() == Some(&1) {
+        read_u32(tkhd, 20)
+    } else {
+        read_u32(tkhd, 12)
+    }
+}
+
+/// Sets the ID of a track and converts its durations to another movie timescale.
+fn set_track_header(trak: &mut Mp4Box, id: u32, timescale: u32, old_timescale: u32) -> Result<()> {
+    let tkhd = trak.find_mut(&[b"tkhd"]).ok_or_else(invalid)?.data_mut()?;
+
+    if tkhd.first() == Some(&1) {
+        write_u32(tkhd, 20, id)?;
+        let duration = rescale(read_u64(tkhd, 28)?, old_timescale, timescale);
+        write_u64(tkhd, 28, duration)?;
+    } else {
+        write_u32(tkhd, 12, id)?;
+        let duration = rescale(read_u32(tkhd, 20)? as u64, old_timescale, timescale);
+        write_u32(tkhd, 20, duration.min(u32::MAX as u64) as u32)?;
+    };
+
+    // The edit list durations are in the movie timescale as well
+    if let Some(elst) = trak.find_mut(&[b"edts", b"elst"]) {
+        let elst = elst.data_mut()?;
+        let version = elst.first().copied();
+        let count = read_u32(elst, 4)? as usize;
+
+        for i in 0..count {
+            if version == Some(1) {
+                let offset = 8 + i * 20;
+                let duration = rescale(read_u64(elst, offset)?, old_timescale, timescale);
+                write_u64(elst, offset, duration)?;
+            } else {
+                let offset = 8 + i * 12;
+                let duration = rescale(read_u32(elst, offset)? as u64, old_timescale, timescale);
+                write_u32(elst, offset, duration.min(u32::MAX as u64) as u32)?;
+            };
+        }
+    };
+
+    Ok(())
+}
+
+/// Applies the function to every chunk offset of a track.
+fn rewrite_chunk_offsets(trak: &mut Mp4Box, mut f: impl FnMut(u64) -> Result<u64>) -> Result<()> {
+    let stbl = trak
+        .find_mut(&[b"mdia", b"minf", b"stbl"])
+        .ok_or_else(invalid)?;
+
+    for table in stbl.children_mut()?.iter_mut() {
+        let wide = match &table.kind {
+            b"stco" => false,
+            b"co64" => true,
+            _ => continue,
+        };
+        let data = table.data_mut()?;
+        let count = read_u32(data, 4)? as usize;
+
+        for i in 0..count {
+            if wide {
+                let offset = f(read_u64(data, 8 + i * 8)?)?;
+                write_u64(data, 8 + i * 8, offset)?;
+            } else {
+                let offset = f(read_u32(data, 8 + i * 4)? as u64)?;
+                if offset > u32::MAX as u64 {
+                    return Err(Error::new("Unsupported MP4 file: the result is too large"));
+                };
+                write_u32(data, 8 + i * 4, offset as u32)?;
+            };
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts a duration between timescales.
+fn rescale(value: u64, from: u32, to: u32) -> u64 {
+    if from == 0 || from == to {
+        value
+    } else {
+        (value as u128 * to as u128 / from as u128) as u64
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(invalid)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    data.get(offset..offset + 8)
+        .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(invalid)
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) -> Result<()> {
+    data.get_mut(offset..offset + 4)
+        .ok_or_else(invalid)?
+        .copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+fn write_u64(data: &mut [u8], offset: usize, value: u64) -> Result<()> {
+    data.get_mut(offset..offset + 8)
+        .ok_or_else(invalid)?
+        .copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+fn invalid() -> Error {
+    Error::new("Invalid MP4 file")
+}
+
+/// Serializes a box for the tests.
+#[cfg(test)]
+fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut buf = (payload.len() as u32 + 8).to_be_bytes().to_vec();
+    buf.extend_from_slice(kind);
+    buf.extend_from_slice(payload);
+    buf
+}
+
+#[test]
+fn mp4_merge() {
+    // A progressive file with one track and one second of media
+    fn file(timescale: u32, media: &[u8]) -> Vec<u8> {
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0isom");
+        let mut mvhd = vec![0; 100];
+        mvhd[12..16].copy_from_slice(&timescale.to_be_bytes());
+        mvhd[16..20].copy_from_slice(&timescale.to_be_bytes());
+        mvhd[96..100].copy_from_slice(&2u32.to_be_bytes());
+        let mut tkhd = vec![0; 84];
+        tkhd[12..16].copy_from_slice(&1u32.to_be_bytes());
+        tkhd[20..24].copy_from_slice(&timescale.to_be_bytes());
+
+        let moov = |offset: u32| {
+            let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            stco.extend_from_slice(&offset.to_be_bytes());
+            let stbl = mp4_box(b"stbl", &mp4_box(b"stco", &stco));
+            let mdia = mp4_box(b"mdia", &mp4_box(b"minf", &stbl));
+            let trak = mp4_box(b"trak", &[mp4_box(b"tkhd", &tkhd), mdia].concat());
+            mp4_box(b"moov", &[mp4_box(b"mvhd", &mvhd), trak].concat())
+        };
+        let offset = ftyp.len() + moov(0).len() + 8;
+
+        [ftyp, moov(offset as u32), mp4_box(b"mdat", media)].concat()
+    }
+
+    let output = merge(&file(1000, b"VIDEO"), &file(48000, b"AUDIO")).unwrap();
+    let parsed = File::parse(&output).unwrap();
+    let traks: Vec<_> = parsed
+        .moov
+        .children()
+        .iter()
+        .filter(|child| &child.kind == b"trak")
+        .collect();
+
+    assert_eq!(2, traks.len());
+    for (trak, (id, media)) in traks.iter().zip(&[(1, b"VIDEO"), (2, b"AUDIO")]) {
+        assert_eq!(*id, track_id(trak).unwrap());
+        let stco = trak
+            .find(&[b"mdia", b"minf", b"stbl", b"stco"])
+            .unwrap()
+            .data()
+            .unwrap();
+        let offset = read_u32(stco, 8).unwrap() as usize;
+        assert_eq!(&media[..], &output[offset..offset + 5]);
+    }
+
+    // The audio duration is converted to the timescale of the video
+    let tkhd = traks[1].find(&[b"tkhd"]).unwrap().data().unwrap();
+    assert_eq!(1000, read_u32(tkhd, 20).unwrap());
+    assert_eq!((1000, 1000), movie_header(&parsed.moov).unwrap());
+
+    assert!(merge(b"", b"").is_err());
+}
+
+#[test]
+fn mp4_merge_fragmented() {
+    // A DASH file with one track and a single fragment, like `DASH_720.mp4`
+    fn file(timescale: u32, media: &[u8], tfhd_flags: u8) -> Vec<u8> {
+        let ftyp = mp4_box(b"ftyp", b"iso5\0\0\0\0iso5dash");
+        let mut mvhd = vec![0; 100];
+        mvhd[12..16].copy_from_slice(&timescale.to_be_bytes());
+        mvhd[96..100].copy_from_slice(&2u32.to_be_bytes());
+        let mut tkhd = vec![0; 84];
+        tkhd[12..16].copy_from_slice(&1u32.to_be_bytes());
+        let mut trex = vec![0; 24];
+        trex[4..8].copy_from_slice(&1u32.to_be_bytes());
+
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stco", &[0; 8]));
+        let mdia = mp4_box(b"mdia", &mp4_box(b"minf", &stbl));
+        let trak = mp4_box(b"trak", &[mp4_box(b"tkhd", &tkhd), mdia].concat());
+        let mvex = mp4_box(b"mvex", &mp4_box(b"trex", &trex));
+        let moov = mp4_box(b"moov", &[mp4_box(b"mvhd", &mvhd), trak, mvex].concat());
+
+        // The data offset of `trun` is relative to the start of `moof`
+        let moof = |data_offset: u32| {
+            let mfhd = mp4_box(b"mfhd", &[0, 0, 0, 0, 0, 0, 0, 7]);
+            let mut tfhd = vec![0, 0x02, 0, tfhd_flags];
+            tfhd.extend_from_slice(&1u32.to_be_bytes());
+            if tfhd_flags & 1 != 0 {
+                tfhd.extend_from_slice(&0u64.to_be_bytes());
+            };
+            let mut trun = vec![0, 0, 0, 1, 0, 0, 0, 1];
+            trun.extend_from_slice(&data_offset.to_be_bytes());
+            let traf = mp4_box(
+                b"traf",
+                &[mp4_box(b"tfhd", &tfhd), mp4_box(b"trun", &trun)].concat(),
+            );
+            mp4_box(b"moof", &[mfhd, traf].concat())
+        };
+        let data_offset = moof(0).len() + 8;
+
+        [
+            ftyp,
+            moov,
+            moof(data_offset as u32),
+            mp4_box(b"mdat", media),
+        ]
+        .concat()
+    }
+
+    let output = merge(&file(90000, b"VIDEO", 0), &file(48000, b"AUDIO", 0)).unwrap();
+    let parsed = File::parse(&output).unwrap();
+
+    let ids = |kind: &[u8; 4], id: fn(&Mp4Box) -> Result<u32>| {
+        parsed
+            .moov
+            .children()
+            .iter()
+            .chain(parsed.moov.find(&[b"mvex"]).unwrap().children())
+            .filter(|child| &child.kind == kind)
+            .map(|child| id(child).unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec![1, 2], ids(b"trak", track_id));
+    assert_eq!(vec![1, 2], ids(b"trex", |trex| read_u32(trex.data()?, 4)));
+
+    // The audio fragment follows the video fragment, with its own sequence number
+    let moofs: Vec<_> = split(&output)
+        .unwrap()
+        .into_iter()
+        .filter(|(kind, _, _)| kind == b"moof")
+        .collect();
+    assert_eq!(2, moofs.len());
+    for (i, ((kind, offset, payload), media)) in moofs.iter().zip(&[b"VIDEO", b"AUDIO"]).enumerate()
+    {
+        let moof = Mp4Box::parse(*kind, payload).unwrap();
+        let sequence = moof.find(&[b"mfhd"]).unwrap().data().unwrap();
+        assert_eq!(i as u32 + 1, read_u32(sequence, 4).unwrap());
+        let tfhd = moof.find(&[b"traf", b"tfhd"]).unwrap().data().unwrap();
+        assert_eq!(i as u32 + 1, read_u32(tfhd, 4).unwrap());
+
+        let trun = moof.find(&[b"traf", b"trun"]).unwrap().data().unwrap();
+        let start = offset - 8 + read_u32(trun, 8).unwrap() as usize;
+        assert_eq!(&media[..], &output[start..start + 5]);
+    }
+
+    // Moving a fragment would break an absolute base data offset
+    assert!(merge(&file(90000, b"VIDEO", 1), &file(48000, b"AUDIO", 1)).is_err());
+    assert!(merge(&file(90000, b"VIDEO", 0), &[]).is_err());
+}
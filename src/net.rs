@@ -18,50 +18,275 @@
 Networking tools for the program.
 */
 
-use std::path::Path;
+use std::{
+    fmt,
+    io::{self, BufReader, ErrorKind, Read},
+    ops::AddAssign,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-use bytes::buf::BufExt;
+use bytes::{buf::BufExt, Buf, Bytes};
 use futures_util::stream::StreamExt;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
 pub use http::{request::Builder, Method, StatusCode, Uri};
 pub use hyper::Body;
 use hyper::{client::connect::HttpConnector, Response};
 use hyper_tls::HttpsConnector;
+use native_tls::{Certificate, TlsConnector};
 use serde::de::DeserializeOwned;
-use tokio::{fs::File, io::AsyncWriteExt};
+use tokio::{
+    runtime::Handle,
+    sync::mpsc::{self, Receiver},
+    task,
+};
 
 use crate::prelude::*;
 
 /// A client to perform HTTP requests with.
 #[derive(Debug)]
-pub struct Client(hyper::Client<HttpsConnector<HttpConnector>>);
+pub struct Client {
+    inner: hyper::Client<HttpsConnector<HttpConnector>>,
+
+    /// Whether connections are kept open for reuse, see [`with_pool()`].
+    ///
+    /// [`with_pool()`]: #method.with_pool
+    keep_alive: bool,
+
+    /// The number of redirects followed by downloads, see `--max-redirects`.
+    pub max_redirects: usize,
+}
 
 impl Client {
-    #[inline]
-    pub fn new() -> Client {
-        Client(hyper::Client::builder().build(HttpsConnector::new()))
+    /// Creates a client using a connector with a custom TLS configuration, see [`tls_connector()`].
+    ///
+    /// [`tls_connector()`]: fn.tls_connector.html
+    pub fn with_connector(connector: HttpsConnector<HttpConnector>) -> Client {
+        Client {
+            inner: hyper::Client::builder().build(connector),
+            keep_alive: false,
+            max_redirects: 5,
+        }
+    }
+
+    /// Creates a client which keeps connections open for reuse,
+    /// instead of closing them after every request.
+    ///
+    /// `max_idle_per_host` limits the idle connections to a single host,
+    /// `idle_timeout` closes connections which were not used for that long.
+    /// Both are unlimited if not given.
+    pub fn with_pool(
+        max_idle_per_host: Option<usize>,
+        idle_timeout: Option<Duration>,
+        connector: HttpsConnector<HttpConnector>,
+    ) -> Client {
+        let mut builder = hyper::Client::builder();
+        if let Some(max_idle_per_host) = max_idle_per_host {
+            builder.pool_max_idle_per_host(max_idle_per_host);
+        };
+        builder.pool_idle_timeout(idle_timeout);
+
+        Client {
+            inner: builder.build(connector),
+            keep_alive: true,
+            max_redirects: 5,
+        }
     }
 
     /// Executes a HTTP request.
-    /// The body can be read using [`to_disk()`] or [`to_json()`].
+    /// The body can be read using [`Storage::to_disk()`] or [`to_json()`].
     ///
     /// Takes a `Result<...>` for convenience.
     ///
-    /// [`to_disk()`]: fn.to_disk.html
+    /// [`Storage::to_disk()`]: ../storage/struct.Storage.html#method.to_disk
     /// [`to_json()`]: fn.to_json.html
     pub async fn request(&self, request: Builder) -> Result<Response<Body>> {
+        self.request_with_body(request, Body::empty()).await
+    }
+
+    /// Executes a HTTP request with a body.
+    /// See [`request()`] for details.
+    ///
+    /// [`request()`]: #method.request
+    pub async fn request_with_body(&self, request: Builder, body: Body) -> Result<Response<Body>> {
         trace!("request({:?})", request);
 
-        let request = request
-            .header("Connection", "Close")
-            .header("Accept-Encoding", "identity")
-            .body(Body::empty())?;
+        let request = if self.keep_alive {
+            request
+        } else {
+            request.header("Connection", "Close")
+        };
+        let request = request.header("Accept-Encoding", "identity").body(body)?;
 
-        let response = self.0.request(request).await?;
+        let response = self.inner.request(request).await?;
 
         Ok(response)
     }
 }
 
+/// Returns whether an error was caused by a full disk.
+/// A write which makes no progress is treated the same.
+pub fn is_storage_full(e: &Error) -> bool {
+    match e.source().and_then(|e| e.downcast_ref::<io::Error>()) {
+        Some(e) => matches!(e.kind(), ErrorKind::StorageFull | ErrorKind::WriteZero),
+        None => false,
+    }
+}
+
+/// Creates a connector with a custom TLS configuration, see `--ca-cert` and `--insecure`.
+///
+/// `ca_cert` is a PEM or DER encoded root certificate, which is trusted in
+/// addition to the ones of the system. `insecure` disables the verification
+/// of certificates and host names entirely.
+pub fn tls_connector(
+    ca_cert: Option<&Path>,
+    insecure: bool,
+) -> Result<HttpsConnector<HttpConnector>> {
+    trace!("tls_connector({:?}, {:?})", ca_cert, insecure);
+
+    let mut builder = TlsConnector::builder();
+    if let Some(path) = ca_cert {
+        let data = std::fs::read(path)?;
+        let certificate = Certificate::from_pem(&data).or_else(|_| Certificate::from_der(&data))?;
+        builder.add_root_certificate(certificate);
+    };
+    if insecure {
+        builder
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+    };
+
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+
+    Ok(HttpsConnector::from((http, builder.build()?.into())))
+}
+
+/// Statistics about a finished download.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DownloadStats {
+    /// The number of bytes written to disk.
+    pub bytes: u64,
+
+    /// The time spent receiving the data.
+    pub duration: Duration,
+
+    /// Whether the file already existed and was left unchanged.
+    pub skipped: bool,
+
+    /// The file extension of the received media, detected from its first
+    /// bytes or the `Content-Type` header, see `--rename-on-extension-mismatch`.
+    pub extension: Option<&'static str>,
+}
+
+impl DownloadStats {
+    /// Creates the statistics of a file which was not overwritten.
+    pub fn skipped() -> DownloadStats {
+        DownloadStats {
+            skipped: true,
+            ..DownloadStats::default()
+        }
+    }
+
+    /// Returns the throughput in bytes per second.
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.duration.as_secs_f64();
+        if seconds > 0.0 {
+            self.bytes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+impl AddAssign for DownloadStats {
+    fn add_assign(&mut self, other: DownloadStats) {
+        self.bytes += other.bytes;
+        self.duration += other.duration;
+        // Multiple files do not have a single extension
+        self.extension = None;
+    }
+}
+
+impl fmt::Display for DownloadStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} in {:.1}s, {}/s",
+            format_size(self.bytes as f64),
+            self.duration.as_secs_f64(),
+            format_size(self.throughput())
+        )
+    }
+}
+
+/// Formats a number of bytes in a human readable way.
+pub fn format_size(bytes: f64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", size, UNITS[unit])
+    } else {
+        format!("{:.2} {}", size, UNITS[unit])
+    }
+}
+
+/// Parses a human readable size like `500MB` or `2GiB` into bytes.
+///
+/// Units containing an `i` are powers of 1024, the others powers of 1000.
+/// A number without a unit is in bytes. Fractions of a byte are rounded down.
+pub fn parse_size(input: &str) -> std::result::Result<u64, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    let invalid = || format!("Invalid size '{}'", input);
+    let (integer, fraction) = match number.find('.') {
+        Some(index) => (&number[..index], &number[index + 1..]),
+        None => (number, ""),
+    };
+    if (integer.is_empty() && fraction.is_empty()) || fraction.contains('.') {
+        return Err(invalid());
+    };
+    let integer: u64 = if integer.is_empty() {
+        0
+    } else {
+        integer.parse().map_err(|_| invalid())?
+    };
+
+    let factor: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "kib" => 1 << 10,
+        "m" | "mb" => 1000 * 1000,
+        "mib" => 1 << 20,
+        "g" | "gb" => 1000 * 1000 * 1000,
+        "gib" => 1 << 30,
+        "t" | "tb" => 1000 * 1000 * 1000 * 1000,
+        "tib" => 1 << 40,
+        _ => return Err(format!("Unknown size unit '{}'", unit.trim())),
+    };
+
+    // Evaluated from the last digit, which is exact without floating point numbers
+    let fraction = fraction.bytes().rev().fold(0, |carry, digit| {
+        (u64::from(digit - b'0') * factor + carry) / 10
+    });
+
+    integer
+        .checked_mul(factor)
+        .and_then(|bytes| bytes.checked_add(fraction))
+        .ok_or_else(|| format!("Size '{}' is too large", input))
+}
+
 /// Parses a response as JSON.
 pub async fn to_json<T: DeserializeOwned>(response: Response<Body>) -> Result<T> {
     trace!("to_json({:?})", response);
@@ -72,39 +297,576 @@ pub async fn to_json<T: DeserializeOwned>(response: Response<Body>) -> Result<T>
     Ok(value)
 }
 
-/// Writes a response to the disk.
-pub async fn to_disk(response: Response<Body>, output: &Path) -> Result<()> {
-    trace!("to_disk({:?}, {:?})", response, output);
+/// The number of received chunks which can wait for the parser of [`to_json_streaming()`].
+///
+/// [`to_json_streaming()`]: fn.to_json_streaming.html
+const CHUNK_BUFFER: usize = 16;
+
+/// Parses a response as JSON while it is being received.
+///
+/// Unlike [`to_json()`], the body is not aggregated first. Every chunk is
+/// dropped as soon as it was parsed, so large responses are not held in
+/// memory as both raw bytes and parsed values. The parser runs on a
+/// blocking thread. At most [`CHUNK_BUFFER`] chunks wait for the parser,
+/// receiving the body is paused while it is behind.
+///
+/// [`CHUNK_BUFFER`]: constant.CHUNK_BUFFER.html
+/// [`to_json()`]: fn.to_json.html
+pub async fn to_json_streaming<T>(response: Response<Body>) -> Result<T>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    trace!("to_json_streaming({:?})", response);
+
+    let (mut sender, receiver) = mpsc::channel(CHUNK_BUFFER);
+    let handle = Handle::current();
+    let parser = task::spawn_blocking(move || {
+        serde_json::from_reader::<_, T>(BufReader::new(ChunkReader {
+            handle,
+            receiver,
+            chunk: Bytes::new(),
+        }))
+    });
 
-    let mut file = File::create(output).await?;
     let mut body = response.into_body();
+    let mut error = None;
 
     while let Some(i) = body.next().await {
-        let i = i?;
-        file.write_all(&i).await?;
+        match i {
+            Ok(chunk) => {
+                if sender.send(chunk).await.is_err() {
+                    // The parser already failed
+                    break;
+                };
+            }
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        };
+    }
+
+    // Signals the end of the body to the parser
+    drop(sender);
+    let value = parser.await?;
+
+    // A truncated body is reported as network error instead of invalid JSON
+    if let Some(e) = error {
+        return Err(e.into());
+    };
+
+    Ok(value?)
+}
+
+/// A reader over the chunks of a body received by [`to_json_streaming()`].
+///
+/// [`to_json_streaming()`]: fn.to_json_streaming.html
+struct ChunkReader {
+    /// The runtime the chunks are received on.
+    handle: Handle,
+    receiver: Receiver<Bytes>,
+
+    /// The remainder of the current chunk.
+    chunk: Bytes,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.handle.block_on(self.receiver.recv()) {
+                Some(chunk) => self.chunk = chunk,
+                // The body is complete
+                None => return Ok(0),
+            };
+        }
+
+        let length = buf.len().min(self.chunk.len());
+        buf[..length].copy_from_slice(&self.chunk[..length]);
+        self.chunk.advance(length);
+
+        Ok(length)
+    }
+}
+
+/// Reads a response into memory.
+///
+/// Like `Storage::to_disk()`, truncated downloads are detected using the `Content-Length` header.
+pub async fn to_memory(response: Response<Body>) -> Result<(Vec<u8>, DownloadStats)> {
+    trace!("to_memory({:?})", response);
+
+    let start = Instant::now();
+    let expected = content_length(&response);
+    let mut data = Vec::with_capacity(expected.unwrap_or(0) as usize);
+    let mut body = response.into_body();
+
+    while let Some(i) = body.next().await {
+        data.extend_from_slice(&i?);
+    }
+
+    let received = data.len() as u64;
+    check_length(received, expected)?;
+
+    Ok((
+        data,
+        DownloadStats {
+            bytes: received,
+            duration: start.elapsed(),
+            skipped: false,
+            extension: None,
+        },
+    ))
+}
+
+/// Checks the number of received bytes against the `Content-Length` header, if any.
+pub fn check_length(received: u64, expected: Option<u64>) -> Result<()> {
+    match expected {
+        Some(expected) if received != expected => Err(Error::new(format!(
+            "Incomplete download: received {} of {} bytes",
+            received, expected
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the value of the `Content-Length` header, if present and valid.
+/// Chunked responses do not have this header.
+pub fn content_length(response: &Response<Body>) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+/// Returns the file extension of the `Content-Type` header for common media types.
+pub fn content_type_extension(response: &Response<Body>) -> Option<&'static str> {
+    let content_type = response.headers().get(CONTENT_TYPE)?.to_str().ok()?;
+
+    media_type_extension(content_type)
+}
+
+/// Returns the file extension of a media type like `image/jpeg`.
+pub fn media_type_extension(media_type: &str) -> Option<&'static str> {
+    match media_type.split(';').next()?.trim() {
+        "image/jpeg" => Some(".jpg"),
+        "image/png" => Some(".png"),
+        "image/gif" => Some(".gif"),
+        "image/webp" => Some(".webp"),
+        "video/mp4" => Some(".mp4"),
+        "video/webm" => Some(".webm"),
+        "audio/mp4" => Some(".m4a"),
+        _ => None,
     }
+}
+
+/// Returns the file extension of media by its first bytes, the so-called magic number.
+pub fn sniff_extension(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some(".jpg")
+    } else if data.starts_with(b"\x89PNG") {
+        Some(".png")
+    } else if data.starts_with(b"GIF8") {
+        Some(".gif")
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(&b"WEBP"[..]) {
+        Some(".webp")
+    } else if data.get(4..8) == Some(&b"ftyp"[..]) {
+        // The major brand tells audio apart from video
+        if data.get(8..12) == Some(&b"M4A "[..]) {
+            Some(".m4a")
+        } else {
+            Some(".mp4")
+        }
+    } else if data.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        Some(".webm")
+    } else {
+        None
+    }
+}
+
+/// Returns whether a media type like `text/html; charset=utf-8` is a web page.
+pub fn is_html(media_type: &str) -> bool {
+    let media_type = media_type.split(';').next().unwrap_or_default().trim();
+
+    media_type.eq_ignore_ascii_case("text/html")
+        || media_type.eq_ignore_ascii_case("application/xhtml+xml")
+}
+
+/// Returns whether a path has the file extension of an image or video.
+pub fn is_media_file(path: &Path) -> bool {
+    const MEDIA_EXTENSIONS: [&str; 10] = [
+        "jpg", "jpeg", "png", "gif", "gifv", "webp", "mp4", "m4a", "webm", "mov",
+    ];
 
-    Ok(())
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| {
+            MEDIA_EXTENSIONS
+                .iter()
+                .any(|i| i.eq_ignore_ascii_case(extension))
+        })
+        .unwrap_or(false)
+}
+
+/// Requests a file, failing on unsuccessful responses.
+/// Redirects are followed up to the `max_redirects` of the client.
+async fn get(client: &Client, url: &Uri) -> Result<Response<Body>> {
+    let mut url = url.clone();
+    let mut redirects = 0;
+
+    loop {
+        let response = client
+            .request(Builder::new().method(Method::GET).uri(url.clone()))
+            .await?;
+        let status = response.status();
+
+        if status.is_success() {
+            debug!("Received {} from {:?}", status, url);
+        } else if status.as_u16() == 404 {
+            return Err(Error::new("File not found"));
+        } else if status.as_u16() == 302 && url.host() == Some("i.imgur.com") {
+            // Imgur redirects to `imgur.com/*` instead of a normal 404.
+            return Err(Error::new("File not found"));
+        } else if status.is_redirection() && response.headers().contains_key(LOCATION) {
+            if redirects == client.max_redirects {
+                return Err(Error::new(format!(
+                    "Too many redirects, the last one went to {}",
+                    url
+                )));
+            };
+
+            let location = response.headers()[LOCATION]
+                .to_str()
+                .map_err(|_| Error::new("Invalid redirect location"))?;
+            let location = resolve_location(&url, location)?;
+            debug!(
+                "Received {} from {:?}, redirecting to {:?}",
+                status, url, location
+            );
+
+            url = location;
+            redirects += 1;
+            continue;
+        } else {
+            return Err(Error::new(format!("Unexpected response code {}", status)));
+        };
+
+        return Ok(response);
+    }
+}
+
+/// Resolves the `Location` header of a redirect against the requested URL.
+/// The location can be absolute, relative to the scheme, the host or the path.
+fn resolve_location(base: &Uri, location: &str) -> Result<Uri> {
+    let scheme = base.scheme_str().unwrap_or("https");
+    let authority = base.authority().map(|i| i.as_str()).unwrap_or("");
+
+    let url = if location.contains("://") {
+        location.to_owned()
+    } else if location.starts_with("//") {
+        format!("{}:{}", scheme, location)
+    } else if location.starts_with('/') {
+        format!("{}://{}{}", scheme, authority, location)
+    } else {
+        let path = base.path();
+        let directory = &path[..path.rfind('/').map(|i| i + 1).unwrap_or(0)];
+        format!("{}://{}{}{}", scheme, authority, directory, location)
+    };
+
+    Ok(url.parse()?)
+}
+
+/// Checks whether a link is dead without downloading it.
+///
+/// A `HEAD` request is sent, or a `GET` request whose body is not read
+/// if the server does not support `HEAD`. Returns the status code if it
+/// is `404` or `410`, any other response is left to the actual download.
+pub async fn precheck(client: &Client, url: &Uri) -> Option<StatusCode> {
+    trace!("precheck({:?})", url);
+
+    let response = client
+        .request(Builder::new().method(Method::HEAD).uri(url))
+        .await
+        .ok()?;
+    let mut status = response.status();
+
+    if status == StatusCode::METHOD_NOT_ALLOWED || status == StatusCode::NOT_IMPLEMENTED {
+        debug!("Received {} from {:?}, retrying with GET", status, url);
+
+        let response = client
+            .request(Builder::new().method(Method::GET).uri(url))
+            .await
+            .ok()?;
+        status = response.status();
+    };
+
+    debug!("Precheck of {:?} returned {}", url, status);
+
+    match status {
+        StatusCode::NOT_FOUND | StatusCode::GONE => Some(status),
+        _ => None,
+    }
 }
 
 /// Downloads a file.
-pub async fn download(client: &Client, url: &Uri, output: &Path) -> Result<()> {
+pub async fn download(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: &Path,
+) -> Result<DownloadStats> {
     trace!("download({:?}, {:?})", url, output);
 
-    let response = client
-        .request(Builder::new().method(Method::GET).uri(url))
-        .await?;
-    let status = response.status();
-
-    if status.is_success() {
-        debug!("Received {} from {:?}", status, url);
-    } else if status.as_u16() == 404 {
-        return Err(Error::new("File not found"));
+    // Avoid the request if possible, `to_disk()` checks again
+    if storage.no_overwrite && output.exists() {
+        debug!("Not overwriting existing file {:?}", output);
+        return Ok(DownloadStats::skipped());
+    };
+
+    let response = get(client, url).await?;
+
+    storage.to_disk(response, output).await
+}
+
+/// Downloads a temporary file, like [`download()`].
+/// The file is not recorded in the checksum file of `--checksum-file`.
+///
+/// [`download()`]: fn.download.html
+pub async fn download_temp(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: &Path,
+) -> Result<DownloadStats> {
+    trace!("download_temp({:?}, {:?})", url, output);
+
+    let response = get(client, url).await?;
+
+    storage.write_response(response, output, false).await
+}
+
+/// Downloads a file from an unsupported domain, see `--force`.
+///
+/// Such servers often answer with an HTML error page instead of the media.
+/// If the output is an image or video but the response is HTML, the page
+/// is saved with the `.html` extension instead, or the download fails
+/// with `skip_html`. The output is returned together with the statistics.
+pub async fn download_forced(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: &Path,
+    skip_html: bool,
+) -> Result<(DownloadStats, PathBuf)> {
+    trace!("download_forced({:?}, {:?}, {:?})", url, output, skip_html);
+
+    // Avoid the request if possible, `to_disk()` checks again
+    if storage.no_overwrite && output.exists() {
+        debug!("Not overwriting existing file {:?}", output);
+        return Ok((DownloadStats::skipped(), output.to_path_buf()));
+    };
+
+    let response = get(client, url).await?;
+
+    let html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(is_html)
+        .unwrap_or(false);
+    let output = if html && is_media_file(output) {
+        if skip_html {
+            return Err(Error::new("Received a web page instead of media"));
+        };
+
+        warn!(
+            "Received a web page instead of media from {}, saving it as HTML",
+            url
+        );
+        output.with_extension("html")
     } else {
-        return Err(Error::new(format!("Unexpected response code {}", status)));
+        output.to_path_buf()
+    };
+
+    let stats = storage.to_disk(response, &output).await?;
+
+    Ok((stats, output))
+}
+
+/// Downloads a file whose URL does not tell the file extension.
+///
+/// The extension is derived from the `Content-Type` header and appended
+/// to the output, which is returned together with the statistics.
+pub async fn download_with_extension(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: &Path,
+) -> Result<(DownloadStats, PathBuf)> {
+    trace!("download_with_extension({:?}, {:?})", url, output);
+
+    let response = get(client, url).await?;
+
+    let mut output = output.to_path_buf().into_os_string();
+    if let Some(extension) = content_type_extension(&response) {
+        debug!("Resolved the file extension of {:?} to {}", url, extension);
+        output.push(extension);
     };
+    let output = PathBuf::from(output);
+
+    let stats = storage.to_disk(response, &output).await?;
+
+    Ok((stats, output))
+}
+
+/// Downloads a file into memory.
+pub async fn download_to_memory(client: &Client, url: &Uri) -> Result<(Vec<u8>, DownloadStats)> {
+    trace!("download_to_memory({:?})", url);
+
+    let response = get(client, url).await?;
+
+    to_memory(response).await
+}
+
+#[test]
+fn redirect_location() {
+    let base: Uri = "https://bit.ly/a/b?c=d".parse().unwrap();
+
+    assert_eq!(
+        "https://i.imgur.com/abc.jpg",
+        resolve_location(&base, "https://i.imgur.com/abc.jpg")
+            .unwrap()
+            .to_string()
+    );
+    assert_eq!(
+        "https://example.com/abc.jpg",
+        resolve_location(&base, "//example.com/abc.jpg")
+            .unwrap()
+            .to_string()
+    );
+    assert_eq!(
+        "https://bit.ly/abc.jpg",
+        resolve_location(&base, "/abc.jpg").unwrap().to_string()
+    );
+    assert_eq!(
+        "https://bit.ly/a/abc.jpg",
+        resolve_location(&base, "abc.jpg").unwrap().to_string()
+    );
+}
+
+#[test]
+fn sniff_media() {
+    assert_eq!(
+        Some(".jpg"),
+        sniff_extension(&[0xff, 0xd8, 0xff, 0xe0, 0, 0x10])
+    );
+    assert_eq!(Some(".png"), sniff_extension(b"\x89PNG\r\n\x1a\n"));
+    assert_eq!(Some(".gif"), sniff_extension(b"GIF89a"));
+    assert_eq!(Some(".webp"), sniff_extension(b"RIFF\0\0\0\0WEBPVP8 "));
+    assert_eq!(Some(".mp4"), sniff_extension(b"\0\0\0\x18ftypmp42"));
+    assert_eq!(Some(".m4a"), sniff_extension(b"\0\0\0\x18ftypM4A "));
+    assert_eq!(
+        Some(".webm"),
+        sniff_extension(&[0x1a, 0x45, 0xdf, 0xa3, 0x9f])
+    );
+    assert_eq!(None, sniff_extension(b"<!DOCTYPE html>"));
+    assert_eq!(None, sniff_extension(b""));
+}
+
+#[test]
+fn html_page() {
+    assert!(is_html("text/html"));
+    assert!(is_html("text/HTML; charset=utf-8"));
+    assert!(is_html("application/xhtml+xml"));
+    assert!(!is_html("image/jpeg"));
+    assert!(!is_html("text/plain"));
+
+    assert!(is_media_file(Path::new("abc-Lorem.JPG")));
+    assert!(is_media_file(Path::new("dir.html/abc.mp4")));
+    assert!(!is_media_file(Path::new("abc.txt")));
+    assert!(!is_media_file(Path::new("abc")));
+}
+
+#[test]
+fn storage_full() {
+    let error = |kind| Error::from(io::Error::new(kind, "Lorem"));
+
+    assert!(is_storage_full(&error(ErrorKind::StorageFull)));
+    assert!(is_storage_full(&error(ErrorKind::WriteZero)));
+    assert!(!is_storage_full(&error(ErrorKind::NotFound)));
+    assert!(!is_storage_full(&Error::new("No space left on device")));
+}
+
+#[test]
+fn download_stats() {
+    assert_eq!("0 B", format_size(0.0));
+    assert_eq!("1023 B", format_size(1023.0));
+    assert_eq!("1.50 KiB", format_size(1536.0));
+    assert_eq!("2.00 MiB", format_size(2.0 * 1024.0 * 1024.0));
+
+    let mut stats = DownloadStats {
+        bytes: 1024,
+        duration: Duration::from_secs(1),
+        skipped: false,
+        extension: None,
+    };
+    stats += DownloadStats {
+        bytes: 1024,
+        duration: Duration::from_secs(1),
+        skipped: false,
+        extension: None,
+    };
+    assert_eq!(1024.0, stats.throughput());
+    assert_eq!("2.00 KiB in 2.0s, 1.00 KiB/s", stats.to_string());
+    assert_eq!(0.0, DownloadStats::default().throughput());
+    assert!(DownloadStats::skipped().skipped);
+
+    assert_eq!(Some(".jpg"), media_type_extension("image/jpeg"));
+    assert_eq!(
+        Some(".mp4"),
+        media_type_extension("video/mp4; charset=binary")
+    );
+    assert_eq!(None, media_type_extension("application/octet-stream"));
+
+    assert_eq!(Ok(1024), parse_size("1024"));
+    assert_eq!(Ok(500_000_000), parse_size("500MB"));
+    assert_eq!(Ok(2 << 30), parse_size("2GiB"));
+    assert_eq!(Ok(1536), parse_size("1.5 KiB"));
+    assert_eq!(Ok(100), parse_size("0.1KB"));
+    assert_eq!(Ok(500), parse_size(".5k"));
+    assert_eq!(Ok(1_209_462_790_553), parse_size("1.1TiB"));
+    assert_eq!(Ok(1), parse_size("1.9"));
+    assert_eq!(Ok(u64::MAX), parse_size("18446744073709551615"));
+    assert!(parse_size("18446744073709551616").is_err());
+    assert!(parse_size("20000000000GB").is_err());
+    assert!(parse_size("1..5MB").is_err());
+    assert!(parse_size(".").is_err());
+    assert!(parse_size("10XB").is_err());
+    assert!(parse_size("GB").is_err());
+}
+
+#[tokio::test]
+async fn json_streaming() {
+    use serde_json::{json, Value};
+
+    let chunks: Vec<std::result::Result<_, io::Error>> = vec![
+        Ok(r#"{"data": [{"id": "a"}, "#),
+        Ok(""),
+        Ok(r#"{"id": "b"}]}"#),
+    ];
+    let response = Response::new(Body::wrap_stream(futures_util::stream::iter(chunks)));
+    assert_eq!(
+        json!({ "data": [{ "id": "a" }, { "id": "b" }] }),
+        to_json_streaming::<Value>(response).await.unwrap()
+    );
 
-    to_disk(response, output).await?;
+    // More chunks than fit into the buffer
+    let mut chunks: Vec<std::result::Result<_, io::Error>> = vec![Ok("[")];
+    chunks.extend((0..CHUNK_BUFFER * 4).map(|_| Ok("1, ")));
+    chunks.push(Ok("1]"));
+    let response = Response::new(Body::wrap_stream(futures_util::stream::iter(chunks)));
+    let value = to_json_streaming::<Vec<u8>>(response).await.unwrap();
+    assert_eq!(CHUNK_BUFFER * 4 + 1, value.len());
 
-    Ok(())
+    let response = Response::new(Body::from(r#"{"data": ["#));
+    assert!(to_json_streaming::<Value>(response).await.is_err());
 }
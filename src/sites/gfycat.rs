@@ -19,10 +19,10 @@ Support for [Gfycat](https://gfycat.com/) and [Redgifs](https://redgifs.com/) do
 
 # Domains
 
-- `gfycat.com`
+- `gfycat.com`, `www.gfycat.com`
 - `thumbs.gfycat.com`
 - `giant.gfycat.com`
-- `redgifs.com`
+- `redgifs.com`, `www.redgifs.com`
 - `thumbs1.redgifs.com`
 */
 
@@ -32,6 +32,7 @@ use http::Uri;
 use serde::*;
 
 use crate::prelude::*;
+use crate::sites::normalize_domain;
 
 /// Specifies the format videos from Gfycat are downloaded in.
 #[derive(Debug, Clone, Copy)]
@@ -63,6 +64,45 @@ impl<'a> From<&'a str> for GfycatType {
     }
 }
 
+/// Specifies what happens when a video cannot be fetched from Gfycat.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GfycatFallback {
+    /// Fail the download.
+    None,
+
+    /// Try an archived copy from the Wayback Machine.
+    Wayback,
+}
+
+impl<'a> From<&'a str> for GfycatFallback {
+    fn from(s: &str) -> Self {
+        match s {
+            "none" => GfycatFallback::None,
+            "wayback" => GfycatFallback::Wayback,
+            _ => unreachable!(), // Guaranteed by clap's `possible_values`
+        }
+    }
+}
+
+/// The response of the Wayback Machine availability API.
+#[derive(Deserialize)]
+struct Availability {
+    archived_snapshots: Snapshots,
+}
+
+/// The snapshots returned by the availability API.
+#[derive(Deserialize)]
+struct Snapshots {
+    closest: Option<Snapshot>,
+}
+
+/// An archived copy of a URL.
+#[derive(Deserialize)]
+struct Snapshot {
+    available: bool,
+    timestamp: String,
+}
+
 /// Information about a Gfycat video.
 #[derive(Deserialize)]
 #[allow(non_snake_case)]
@@ -81,13 +121,17 @@ struct GfyItem {
 /// Fetches a video from `gfycat.com`.
 pub async fn fetch_gfycat(
     client: &Client,
+    storage: &Storage,
     url: &Uri,
     output: &Path,
     gfycat_type: GfycatType,
-) -> Result<()> {
+) -> Result<DownloadStats> {
     trace!("fetch({:?}, {:?}, {:?})", url, output, gfycat_type);
 
     let (id, well_formed) = extract_id(url.path());
+    if id.is_empty() {
+        return Err(Error::new("Malformed URL"));
+    };
 
     // If the ID seems to be well-formed, use it directly.
     if well_formed {
@@ -95,31 +139,31 @@ pub async fn fetch_gfycat(
 
         let url = format!("https://giant.gfycat.com/{}.{}", id, gfycat_type.as_str());
 
-        if fetch_giant(client, &url.parse()?, output).await.is_ok() {
-            return Ok(());
+        if let Ok(stats) = fetch_giant(client, storage, &url.parse()?, output).await {
+            return Ok(stats);
         };
     };
 
     let mut url = String::from("https://api.gfycat.com/v1/gfycats/");
     url += id;
 
-    api(client, &url, output, gfycat_type).await
+    api(client, storage, &url, output, gfycat_type).await
 }
 
 /// Fetches a video from `redgifs.com`.
 pub async fn fetch_redgifs(
     client: &Client,
+    storage: &Storage,
     url: &Uri,
     output: &Path,
     gfycat_type: GfycatType,
-) -> Result<()> {
+) -> Result<DownloadStats> {
     trace!("fetch({:?}, {:?}, {:?})", url, output, gfycat_type);
 
-    let (id, well_formed) = extract_id(
-        url.path()
-            .get(6..) // Cut off the `/watch`
-            .ok_or_else(|| Error::new("Malformed URL"))?,
-    );
+    let (id, well_formed) = extract_id(url.path());
+    if id.is_empty() {
+        return Err(Error::new("Malformed URL"));
+    };
 
     // If the ID seems to be well-formed, use it directly.
     if well_formed {
@@ -131,19 +175,19 @@ pub async fn fetch_redgifs(
             gfycat_type.as_str()
         );
 
-        if fetch_giant(client, &url.parse()?, output).await.is_ok() {
-            return Ok(());
+        if let Ok(stats) = fetch_giant(client, storage, &url.parse()?, output).await {
+            return Ok(stats);
         };
     };
 
     let mut url = String::from("https://api.redgifs.com/v1/gfycats/");
     url += id;
 
-    api(client, &url, output, gfycat_type).await
+    api(client, storage, &url, output, gfycat_type).await
 }
 
 /// Extracts the Gfycat ID from the URL.
-fn extract_id(url: &str) -> (&str, bool) {
+pub fn extract_id(path: &str) -> (&str, bool) {
     // Gfycat URLs a fascinating thing. They occur
     // as all-lowercase, well-formed, and with
     // the title appended in the wild. This part
@@ -151,34 +195,57 @@ fn extract_id(url: &str) -> (&str, bool) {
     // be used to retrieve the video directly
     // without an API call if it is well-formed.
 
-    // Get the part between the initial `/` and the first `-`, if any.
-    let id = if let Some(index) = url.chars().position(|c| c == '-') {
-        &url[1..index]
-    } else {
-        &url[1..]
+    // Skip prefixes like `/watch` or `/ifr` of the player pages
+    let mut segments = path.split('/').filter(|i| !i.is_empty());
+    let mut id = segments.next().unwrap_or_default();
+    if ID_PREFIXES.contains(&id) {
+        id = segments.next().unwrap_or_default();
     };
 
+    // Get the part before the first `-`, if any.
+    let id = id.split('-').next().unwrap_or_default();
+
     (id, id.chars().any(|c| c.is_ascii_uppercase()))
 }
 
+/// The path segments which can precede the ID, as in `redgifs.com/watch/<id>`,
+/// `redgifs.com/ifr/<id>`, `redgifs.com/i/<id>` and `gfycat.com/ifr/<id>`.
+const ID_PREFIXES: &[&str] = &["watch", "ifr", "i"];
+
 /// Fetches a video from `giant.gfycat.com`.
-pub async fn fetch_giant(client: &Client, url: &Uri, output: &Path) -> Result<()> {
+pub async fn fetch_giant(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: &Path,
+) -> Result<DownloadStats> {
     trace!("fetch_giant({:?}, {:?})", url, output);
 
-    download(client, url, output).await
+    download(client, storage, url, output).await
 }
 
 /// Fetches a video from `thumbs.gfycat.com`.
-pub async fn fetch_thumbs(client: &Client, url: &Uri, output: &Path) -> Result<()> {
+pub async fn fetch_thumbs(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: &Path,
+) -> Result<DownloadStats> {
     trace!("fetch_thumbs({:?}, {:?})", url, output);
 
-    download(client, url, output).await
+    download(client, storage, url, output).await
 }
 
 /// Use the Gfycat API to retrieve the download link.
 /// Rate limits may be encountered because an API
 /// key is required to thoroughly use the API.
-async fn api(client: &Client, url: &str, output: &Path, gfycat_type: GfycatType) -> Result<()> {
+async fn api(
+    client: &Client,
+    storage: &Storage,
+    url: &str,
+    output: &Path,
+    gfycat_type: GfycatType,
+) -> Result<DownloadStats> {
     trace!("api({:?}, {:?}, {:?})", url, output, gfycat_type);
     debug!("Querying Gfycat api about {}", url);
 
@@ -207,9 +274,102 @@ async fn api(client: &Client, url: &str, output: &Path, gfycat_type: GfycatType)
         GfycatType::Webm => gfycat.gfyItem.webmUrl,
     };
 
-    fetch_giant(client, &url.parse()?, output).await?;
+    fetch_giant(client, storage, &url.parse()?, output).await
+}
 
-    Ok(())
+/// Fetches an archived copy of a Gfycat video from the Wayback Machine.
+///
+/// For `gfycat.com` pages, the archived page is searched for the video link,
+/// which is then retrieved from the archive as well.
+pub async fn fetch_wayback(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: &Path,
+    gfycat_type: GfycatType,
+) -> Result<DownloadStats> {
+    trace!("fetch_wayback({:?}, {:?}, {:?})", url, output, gfycat_type);
+
+    let mut video = url.to_string();
+
+    if url.host().map(normalize_domain) == Some("gfycat.com") {
+        let page = wayback_snapshot(client, &video).await?;
+        let (html, _) = download_to_memory(client, &page.parse()?).await?;
+
+        video = find_video(&String::from_utf8_lossy(&html), gfycat_type)
+            .ok_or_else(|| Error::new("No video found in the archived page"))?;
+    };
+
+    let snapshot = wayback_snapshot(client, &video).await?;
+    download(client, storage, &snapshot.parse()?, output).await
+}
+
+/// Queries the Wayback Machine for the closest snapshot of the URL.
+/// Returns the URL of the unmodified archived file.
+async fn wayback_snapshot(client: &Client, url: &str) -> Result<String> {
+    trace!("wayback_snapshot({:?})", url);
+    debug!("Querying the Wayback Machine about {}", url);
+
+    let api_url = format!(
+        "https://archive.org/wayback/available?url={}",
+        url.replace('%', "%25")
+            .replace('&', "%26")
+            .replace('?', "%3F")
+            .replace('#', "%23")
+    );
+    let response = client
+        .request(
+            Builder::new()
+                .method(Method::GET)
+                .uri(&api_url)
+                .header("Accept", "application/json"),
+        )
+        .await?;
+    let status = response.status();
+
+    if status.is_success() {
+        debug!("Received {} from {:?}", status, api_url);
+    } else {
+        return Err(Error::new(format!("Unexpected response code {}", status)));
+    };
+
+    let availability: Availability = to_json(response).await?;
+
+    match availability.archived_snapshots.closest {
+        // The `id_` suffix returns the file without the Wayback Machine's modifications
+        Some(snapshot) if snapshot.available => Ok(format!(
+            "https://web.archive.org/web/{}id_/{}",
+            snapshot.timestamp, url
+        )),
+        _ => Err(Error::new("No archived copy available")),
+    }
+}
+
+/// Finds the link of a video in a Gfycat page.
+/// Videos in the preferred format are returned first.
+fn find_video(html: &str, gfycat_type: GfycatType) -> Option<String> {
+    let mut videos = Vec::new();
+
+    for host in &["giant.gfycat.com/", "thumbs.gfycat.com/"] {
+        for (start, _) in html.match_indices(host) {
+            let link = &html[start..];
+            let end = link
+                .find(|c: char| c == '"' || c == '\'' || c == '<' || c.is_whitespace())
+                .unwrap_or(link.len());
+            let link = &link[..end];
+
+            if link.ends_with(".mp4") || link.ends_with(".webm") {
+                videos.push(format!("https://{}", link));
+            };
+        }
+    }
+
+    let extension = format!(".{}", gfycat_type.as_str());
+    videos
+        .iter()
+        .find(|video| video.ends_with(&extension))
+        .or_else(|| videos.first())
+        .cloned()
 }
 
 #[test]
@@ -218,4 +378,32 @@ fn gfycat_id() {
     assert_eq!(("LoremIpsum", true), extract_id("/LoremIpsum"));
     assert_eq!(("loremipsum", false), extract_id("/loremipsum-some-text"));
     assert_eq!(("LoremIpsum", true), extract_id("/LoremIpsum-some-text"));
+    assert_eq!(("LoremIpsum", true), extract_id("/ifr/LoremIpsum"));
+    assert_eq!(("loremipsum", false), extract_id("/watch/loremipsum"));
+    assert_eq!(
+        ("loremipsum", false),
+        extract_id("/watch/loremipsum-some-text/")
+    );
+    assert_eq!(("loremipsum", false), extract_id("/i/loremipsum"));
+    assert_eq!(("", false), extract_id("/watch/"));
+    assert_eq!(("", false), extract_id("/"));
+}
+
+#[test]
+fn gfycat_wayback_video() {
+    let html = r#"
+        <video><source src="https://thumbs.gfycat.com/LoremIpsum-mobile.mp4" type="video/mp4">
+        <source src="https://giant.gfycat.com/LoremIpsum.webm" type="video/webm">
+        <a href='https://giant.gfycat.com/LoremIpsum.mp4'>Download</a></video>
+    "#;
+
+    assert_eq!(
+        Some(String::from("https://giant.gfycat.com/LoremIpsum.mp4")),
+        find_video(html, GfycatType::Mp4)
+    );
+    assert_eq!(
+        Some(String::from("https://giant.gfycat.com/LoremIpsum.webm")),
+        find_video(html, GfycatType::Webm)
+    );
+    assert_eq!(None, find_video("<html></html>", GfycatType::Mp4));
 }
@@ -20,22 +20,59 @@ Support for [Imgur](https://imgur.com/) downloads.
 # Domains
 
 - `i.imgur.com`
-- `imgur.com`
+- `i.stack.imgur.com`
+- `imgur.com`, `www.imgur.com`, `m.imgur.com`
+- `imgur.io`
 */
 
-use std::{io::BufRead, path::Path};
+use std::{
+    collections::HashSet,
+    io::BufRead,
+    path::{Path, PathBuf},
+};
 
 use bytes::buf::BufExt;
+use futures_util::future::{FutureExt, LocalBoxFuture};
 use http::Uri;
 use serde::Deserialize;
 use serde_json::Value;
-use tokio::fs;
 
 use crate::prelude::*;
+use crate::sites::GalleryOutput;
 
 /// Fetches an image from `i.imgur.com`.
-pub async fn fetch(client: &Client, url: &Uri, output: &Path) -> Result<()> {
-    trace!("fetch({:?}, {:?})", url, output);
+///
+/// If `prefer_mp4` is set, animated GIFs are downloaded as `.mp4`
+/// if possible, with the extension of the output changed accordingly.
+///
+/// `.gifv` links point to a HTML page embedding the video,
+/// so the `.mp4` file is downloaded instead.
+pub async fn fetch(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: &Path,
+    prefer_mp4: bool,
+) -> Result<(DownloadStats, PathBuf)> {
+    trace!("fetch({:?}, {:?}, {:?})", url, output, prefer_mp4);
+
+    let video;
+    let url = match split_file_name(url.path()) {
+        Some((hash, ".gifv")) => {
+            debug!("Downloading Imgur video {} as mp4", hash);
+            video = format!("https://i.imgur.com/{}.mp4", hash).parse()?;
+            &video
+        }
+        _ => url,
+    };
+
+    if prefer_mp4 {
+        if let Some((hash, ext)) = split_file_name(url.path()) {
+            if let Some(stats) = fetch_mp4(client, storage, hash, ext, output).await {
+                return Ok((stats, output.with_extension("mp4")));
+            };
+        };
+    };
 
     let response = client.request(Builder::new().uri(url.clone())).await?;
     let status = response.status();
@@ -49,9 +86,9 @@ pub async fn fetch(client: &Client, url: &Uri, output: &Path) -> Result<()> {
         return Err(Error::new(format!("Unexpected response code {}", status)));
     };
 
-    to_disk(response, output).await?;
+    let stats = storage.to_disk(response, output).await?;
 
-    Ok(())
+    Ok((stats, output.to_path_buf()))
 }
 
 /// An image on Imgur.
@@ -59,20 +96,71 @@ pub async fn fetch(client: &Client, url: &Uri, output: &Path) -> Result<()> {
 struct Image {
     hash: String,
     ext: String,
+
+    /// The description, which can link to other albums.
+    #[serde(default)]
+    description: Option<String>,
 }
 
 /// Fetches Imgur albums and galleries.
-pub async fn fetch_album(client: &Client, url: &Uri, output: &Path) -> Result<()> {
+///
+/// Albums linked in the descriptions of the images are downloaded into
+/// subdirectories, up to `depth` levels of albums including this one.
+/// Single images are saved like [`fetch()`], the output is returned
+/// together with the statistics.
+///
+/// [`fetch()`]: fn.fetch.html
+pub async fn fetch_album(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: GalleryOutput<'_>,
+    prefer_mp4: bool,
+    depth: usize,
+) -> Result<(DownloadStats, PathBuf)> {
     if url.path().starts_with("/a/") {
-        download_images(client, album(client, url).await?, output).await
+        let id = album_id(url.path());
+        let images = album(client, id).await?;
+        let mut visited = HashSet::new();
+        visited.insert(id.to_string());
+
+        let stats = download_images(
+            client,
+            storage,
+            images,
+            output,
+            prefer_mp4,
+            depth,
+            &mut visited,
+        )
+        .await?;
+
+        Ok((stats, output.path.to_path_buf()))
     } else if url.path().starts_with("/gallery/") {
         let mut id = url.path();
         // Remove trailing `/`
         if id.ends_with('/') {
             id = &id[..id.len() - 1];
         };
+        let id = &id[9..];
+        let images = gallery(client, id).await?;
+        let mut visited = HashSet::new();
+        visited.insert(id.to_string());
 
-        download_images(client, gallery(client, &id[9..]).await?, output).await
+        let stats = download_images(
+            client,
+            storage,
+            images,
+            output,
+            prefer_mp4,
+            depth,
+            &mut visited,
+        )
+        .await?;
+
+        Ok((stats, output.path.to_path_buf()))
+    } else if let Some(hash) = single_image_hash(url.path()) {
+        fetch_single(client, storage, hash, output.path, prefer_mp4).await
     } else {
         // Just assume that a direct link was used without the
         // `i.` prefix. An `imgur.com/*` link redirects to
@@ -80,21 +168,96 @@ pub async fn fetch_album(client: &Client, url: &Uri, output: &Path) -> Result<()
         debug!("Trying to directly download image {}", url);
         fetch(
             client,
+            storage,
             &format!("https://i.imgur.com{}", url.path())
                 .parse()
                 .unwrap(),
-            output,
+            output.path,
+            prefer_mp4,
         )
         .await
     }
 }
 
+/// Fetches the image of a single image page like `imgur.com/<hash>`.
+///
+/// The page does not tell the file type, but `i.imgur.com` serves the file
+/// with its real `Content-Type` regardless of the requested extension.
+/// The common extensions are tried until one of them is found,
+/// and the real extension is appended to the output file, which is returned.
+async fn fetch_single(
+    client: &Client,
+    storage: &Storage,
+    hash: &str,
+    output: &Path,
+    prefer_mp4: bool,
+) -> Result<(DownloadStats, PathBuf)> {
+    trace!("fetch_single({:?}, {:?})", hash, output);
+
+    for i in &[".jpg", ".png", ".gif", ".mp4"] {
+        let url: Uri = format!("https://i.imgur.com/{}{}", hash, i).parse()?;
+        let response = client
+            .request(Builder::new().method(Method::HEAD).uri(&url))
+            .await?;
+        let status = response.status();
+
+        if !status.is_success() {
+            // Imgur redirects to `imgur.com/*` instead of a normal 404.
+            debug!("Received {} from {:?}", status, url);
+            continue;
+        };
+
+        let extension = content_type_extension(&response).unwrap_or(i);
+
+        debug!("Resolved Imgur image {} to {}{}", hash, hash, extension);
+
+        let mut output = output.to_path_buf().into_os_string();
+        output.push(extension);
+        let url = format!("https://i.imgur.com/{}{}", hash, extension).parse()?;
+
+        return fetch(client, storage, &url, &PathBuf::from(output), prefer_mp4).await;
+    }
+
+    Err(Error::new("File not found"))
+}
+
+/// Returns the hash if the path is the one of a single image page, like `/<hash>`.
+pub fn single_image_hash(path: &str) -> Option<&str> {
+    let hash = path.trim_start_matches('/').trim_end_matches('/');
+
+    if !hash.is_empty() && hash.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Some(hash)
+    } else {
+        None
+    }
+}
+
+/// Returns the ID of an album from a path like `/a/<id>/...`.
+fn album_id(path: &str) -> &str {
+    let slash = path[3..].find('/').map(|n| n + 3);
+
+    &path[3..slash.unwrap_or(path.len())]
+}
+
+/// Returns the IDs of the albums linked in a text, like `https://imgur.com/a/<id>`.
+fn linked_albums(text: &str) -> Vec<&str> {
+    text.match_indices("imgur.com/a/")
+        .map(|(i, pattern)| {
+            let id = &text[i + pattern.len()..];
+            let end = id
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(id.len());
+
+            &id[..end]
+        })
+        .filter(|id| !id.is_empty())
+        .collect()
+}
+
 /// Fetches an album using a HTML scraper.
-async fn album(client: &Client, url: &Uri) -> Result<Vec<Image>> {
-    trace!("album({:?})", url);
+async fn album(client: &Client, id: &str) -> Result<Vec<Image>> {
+    trace!("album({:?})", id);
 
-    let slash = &url.path()[3..].find('/').map(|n| n + 3);
-    let id = &url.path()[3..slash.unwrap_or_else(|| url.path().len())];
     let url = format!("https://imgur.com/a/{}/embed", id);
 
     let response = client
@@ -160,54 +323,192 @@ async fn gallery(client: &Client, id: &str) -> Result<Vec<Image>> {
 }
 
 /// Downloads the set of images.
-async fn download_images(client: &Client, images: Vec<Image>, output: &Path) -> Result<()> {
+///
+/// Linked albums are downloaded into subdirectories named after their ID,
+/// while `depth` is greater than 1. The IDs of all albums of the post are
+/// `visited`, so albums linking to each other are only downloaded once.
+async fn download_images(
+    client: &Client,
+    storage: &Storage,
+    images: Vec<Image>,
+    output: GalleryOutput<'_>,
+    prefer_mp4: bool,
+    depth: usize,
+    visited: &mut HashSet<String>,
+) -> Result<DownloadStats> {
     trace!("download_images({:?}, {:?})", images, output);
 
     debug!("Found Imgur gallery containing {} entries", images.len());
 
-    fs::create_dir_all(output).await?;
-    let mut path = output.to_path_buf();
-    path.push("index"); // later overwritten
+    output.create(storage).await?;
+    let mut stats = DownloadStats::default();
+    let linked: Vec<String> = if depth > 1 {
+        images
+            .iter()
+            .filter_map(|image| image.description.as_deref())
+            .flat_map(linked_albums)
+            .map(String::from)
+            .collect()
+    } else {
+        Vec::new()
+    };
+
     for (i, image) in images.into_iter().enumerate() {
-        let path = path.with_file_name(format!("{}{}", i, image.ext));
+        let path = output.item(&format!("{}{}", i, image.ext));
+
+        if prefer_mp4 {
+            if let Some(mp4) = fetch_mp4(client, storage, &image.hash, &image.ext, &path).await {
+                output.store(&path.with_extension("mp4")).await;
+                stats += mp4;
+                continue;
+            };
+        };
+
         debug!("Saving individual image \"{}{}\"", image.hash, image.ext);
-        download(
+        // Ignore individual errors
+        if let Ok(image) = download(
             client,
+            storage,
             &format!("https://i.imgur.com/{}{}", image.hash, image.ext).parse()?,
             &path,
         )
-        .await; // ignore individual errors
+        .await
+        {
+            output.store(&path).await;
+            stats += image;
+        };
     }
 
     // Todo: A future join could be of use here.
 
-    Ok(())
+    for id in linked {
+        if !visited.insert(id.clone()) {
+            debug!("Skipping Imgur album {}, it was already downloaded", id);
+            continue;
+        };
+
+        let path = output.item(&id);
+        let output = GalleryOutput {
+            path: &path,
+            ..output
+        };
+        // Ignore individual errors
+        match download_linked(client, storage, &id, output, prefer_mp4, depth - 1, visited).await {
+            Ok(album) => stats += album,
+            Err(e) => warn!("Failed to fetch linked Imgur album {}: {}", id, e),
+        };
+    }
+
+    Ok(stats)
+}
+
+/// Downloads an album linked in another album, see [`download_images()`].
+/// The future is boxed, because the two functions call each other.
+///
+/// [`download_images()`]: fn.download_images.html
+fn download_linked<'a>(
+    client: &'a Client,
+    storage: &'a Storage,
+    id: &'a str,
+    output: GalleryOutput<'a>,
+    prefer_mp4: bool,
+    depth: usize,
+    visited: &'a mut HashSet<String>,
+) -> LocalBoxFuture<'a, Result<DownloadStats>> {
+    async move {
+        debug!("Saving linked Imgur album {}", id);
+        let images = album(client, id).await?;
+
+        download_images(client, storage, images, output, prefer_mp4, depth, visited).await
+    }
+    .boxed_local()
+}
+
+/// Downloads the `.mp4` variant of an animated GIF, which is a lot smaller.
+/// Returns `None` if the file is not a GIF or the variant is not available.
+async fn fetch_mp4(
+    client: &Client,
+    storage: &Storage,
+    hash: &str,
+    ext: &str,
+    output: &Path,
+) -> Option<DownloadStats> {
+    trace!("fetch_mp4({:?}, {:?}, {:?})", hash, ext, output);
+
+    if ext != ".gif" && ext != ".gifv" {
+        return None;
+    };
+
+    let url = format!("https://i.imgur.com/{}.mp4", hash).parse().ok()?;
+    match download(client, storage, &url, &output.with_extension("mp4")).await {
+        Ok(stats) => Some(stats),
+        Err(e) => {
+            debug!("Failed to fetch {} as mp4, using {}: {}", hash, ext, e);
+            None
+        }
+    }
+}
+
+/// Splits the path of an `i.imgur.com` URL into the hash and the extension.
+pub fn split_file_name(path: &str) -> Option<(&str, &str)> {
+    let name = path.trim_start_matches('/');
+    let dot = name.rfind('.')?;
+
+    Some((&name[..dot], &name[dot..]))
+}
+
+#[test]
+fn imgur_file_name() {
+    assert_eq!(Some(("bxv008g", ".gifv")), split_file_name("/bxv008g.gifv"));
+    assert_eq!(Some(("bxv008g", ".png")), split_file_name("/bxv008g.png"));
+    assert_eq!(None, split_file_name("/bxv008g"));
+
+    assert_eq!(Some("bxv008g"), single_image_hash("/bxv008g"));
+    assert_eq!(Some("bxv008g"), single_image_hash("/bxv008g/"));
+    assert_eq!(None, single_image_hash("/bxv008g.gif"));
+    assert_eq!(None, single_image_hash("/r/pics/bxv008g"));
+    assert_eq!(None, single_image_hash("/"));
+}
+
+#[test]
+fn imgur_nested_album() {
+    assert_eq!("dFz23", album_id("/a/dFz23"));
+    assert_eq!("dFz23", album_id("/a/dFz23/embed"));
+
+    assert_eq!(
+        vec!["dFz23", "x9Kq1"],
+        linked_albums("Part 2: https://imgur.com/a/dFz23, part 3: imgur.com/a/x9Kq1")
+    );
+    assert!(linked_albums("https://imgur.com/gallery/dFz23").is_empty());
+    assert!(linked_albums("imgur.com/a/").is_empty());
 }
 
 #[tokio::test]
 #[cfg_attr(not(feature = "__tests-network"), ignore)]
 async fn imgur_album() {
-    let client = Client::new();
-    let images = album(&client, &"https://imgur.com/a/dFz23".parse().unwrap())
-        .await
-        .unwrap();
+    let client = Client::with_connector(tls_connector(None, false).unwrap());
+    let images = album(&client, "dFz23").await.unwrap();
     assert_eq!(
         vec![
             Image {
                 hash: "bxv008g".to_string(),
-                ext: ".gif".to_string()
+                ext: ".gif".to_string(),
+                description: None
             },
             Image {
                 hash: "oXx9m52".to_string(),
-                ext: ".gif".to_string()
+                ext: ".gif".to_string(),
+                description: None
             },
             Image {
                 hash: "s3XOVHt".to_string(),
-                ext: ".png".to_string()
+                ext: ".png".to_string(),
+                description: None
             },
             Image {
                 hash: "EanxY6r".to_string(),
-                ext: ".gif".to_string()
+                ext: ".gif".to_string(),
+                description: None
             }
         ],
         images
@@ -217,25 +518,29 @@ async fn imgur_album() {
 #[tokio::test]
 #[cfg_attr(not(feature = "__tests-network"), ignore)]
 async fn imgur_gallery() {
-    let client = Client::new();
+    let client = Client::with_connector(tls_connector(None, false).unwrap());
     let images = gallery(&client, "dFz23").await.unwrap();
     assert_eq!(
         vec![
             Image {
                 hash: "bxv008g".to_string(),
-                ext: ".gif".to_string()
+                ext: ".gif".to_string(),
+                description: None
             },
             Image {
                 hash: "oXx9m52".to_string(),
-                ext: ".gif".to_string()
+                ext: ".gif".to_string(),
+                description: None
             },
             Image {
                 hash: "s3XOVHt".to_string(),
-                ext: ".png".to_string()
+                ext: ".png".to_string(),
+                description: None
             },
             Image {
                 hash: "EanxY6r".to_string(),
-                ext: ".gif".to_string()
+                ext: ".gif".to_string(),
+                description: None
             }
         ],
         images
@@ -18,22 +18,32 @@
 Download support for the individual sites.
 */
 
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 use http::Uri;
-use tokio::{fs::File, io::AsyncWriteExt};
+use serde::Serialize;
+use tokio::fs;
 
-use gfycat::GfycatType;
+use gfycat::{GfycatFallback, GfycatType};
+use reddit::VRedditMode;
+use selfpost::SelftextFormat;
 
+use crate::archive::{Archive, ArchiveFormat};
 use crate::prelude::*;
 use crate::sites::pushshift::{Gallery, SecureMedia};
 
 pub mod gfycat;
 pub mod imgur;
+pub mod oauth;
 pub mod pinterest;
 pub mod postimages;
 pub mod pushshift;
 pub mod reddit;
+pub mod selfpost;
+pub mod vimeo;
 
 /// A fetching job.
 /// Used for describing every download job.
@@ -42,9 +52,15 @@ pub struct FetchJob<'a> {
     /// The HTTP client to use.
     pub client: &'a Client,
 
+    /// How the downloads are written.
+    pub storage: &'a Storage,
+
     /// The parameters passed to the program.
     pub parameters: &'a Parameters,
 
+    /// The ID of the post.
+    pub id: String,
+
     /// The domain of the post.
     pub domain: String,
 
@@ -58,6 +74,11 @@ pub struct FetchJob<'a> {
     /// The output file.
     pub output: PathBuf,
 
+    /// Whether the URL did not contain a file extension.
+    /// If so, forced downloads append one based on the `Content-Type` header,
+    /// and some sites like Imgur append the one of the media.
+    pub resolve_extension: bool,
+
     /// The directory for temporary files.
     /// Used only while processing with `ffmpeg`.
     pub temp_dir: &'a Path,
@@ -65,6 +86,9 @@ pub struct FetchJob<'a> {
     /// The text of the post if it is a self post.
     pub text: Option<String>,
 
+    /// The title of the post, if it was retrieved.
+    pub title: Option<String>,
+
     /// The gallery data if the post is an image gallery.
     pub gallery: Option<Gallery>,
 
@@ -72,15 +96,89 @@ pub struct FetchJob<'a> {
     pub media: Option<SecureMedia>,
 }
 
+/// The location the items of a gallery are saved to.
+#[derive(Debug, Clone, Copy)]
+pub struct GalleryOutput<'a> {
+    /// The output path of the post.
+    pub path: &'a Path,
+
+    /// The ID of the post.
+    /// Used to prevent collisions between flattened galleries.
+    pub id: &'a str,
+
+    /// Whether the items are placed next to each other
+    /// instead of in a subdirectory named after the post.
+    pub flatten: bool,
+
+    /// The archive the items are moved into, see `--gallery-archive`.
+    pub archive: Option<&'a Archive>,
+}
+
+impl<'a> GalleryOutput<'a> {
+    /// Creates the subdirectory for the items, if necessary.
+    /// Galleries can not be written to stdout.
+    pub async fn create(&self, storage: &Storage) -> Result<()> {
+        if storage.stdout {
+            return Err(Error::new(
+                "Galleries can not be written to stdout: they consist of multiple files",
+            ));
+        };
+
+        if !self.flatten {
+            fs::create_dir_all(self.path).await?;
+        };
+
+        Ok(())
+    }
+
+    /// Returns the path of an item within the gallery.
+    ///
+    /// Flattened items are named `<post>-<name>`, where the post ID is
+    /// inserted if it is not already part of the post file name.
+    pub fn item(&self, name: &str) -> PathBuf {
+        if self.flatten {
+            let post = self
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default();
+
+            if post.contains(self.id) {
+                self.path.with_file_name(format!("{}-{}", post, name))
+            } else {
+                self.path
+                    .with_file_name(format!("{}-{}-{}", post, self.id, name))
+            }
+        } else {
+            self.path.join(name)
+        }
+    }
+
+    /// Moves a downloaded item into the archive, if the gallery is archived.
+    /// Failures are only logged, the item is kept in the directory then.
+    pub async fn store(&self, item: &Path) {
+        if let Some(archive) = self.archive {
+            if let Err(e) = archive.add(item).await {
+                warn!("Failed to add {:?} to the archive: {}", item, e);
+            };
+        };
+    }
+}
+
 /// Runs the fetch job.
-pub async fn fetch(config: FetchJob<'_>) -> (FetchJob<'_>, Result<()>) {
+pub async fn fetch(mut config: FetchJob<'_>) -> (FetchJob<'_>, Result<DownloadStats>) {
     trace!("fetch({:?})", config.url);
 
     let result = if config.is_selfpost {
         debug!("Detected self post {:?}", config.url);
 
         if let Some(text) = config.text.as_ref() {
-            fetch_selfpost(&config.output, text).await
+            let text = selfpost::format(
+                config.parameters.selftext_format,
+                config.title.as_deref(),
+                text,
+            );
+            fetch_selfpost(config.storage, &config.output, &text).await
         } else {
             // Seriously reddit?
             return (
@@ -91,11 +189,30 @@ pub async fn fetch(config: FetchJob<'_>) -> (FetchJob<'_>, Result<()>) {
     } else {
         debug!("Fetching {:?}", config.url);
 
-        match config.domain.as_ref() {
-            "i.redd.it" => reddit::fetch_image(config.client, &config.url, &config.output).await,
-            "v.redd.it" => {
+        let archive = match config.parameters.gallery_archive {
+            Some(ArchiveFormat::Zip) => {
+                Some(Archive::new(&config.output, config.storage.no_overwrite))
+            }
+            None => None,
+        };
+        let gallery_output = GalleryOutput {
+            path: &config.output,
+            id: &config.id,
+            flatten: config.parameters.flatten_galleries,
+            archive: archive.as_ref(),
+        };
+
+        let mut resolved_output = None;
+        let domain = normalize_domain(&config.domain);
+        let result = match handler(domain) {
+            Some(Handler::RedditImage) => {
+                reddit::fetch_image(config.client, config.storage, &config.url, &config.output)
+                    .await
+            }
+            Some(Handler::RedditVideo) => {
                 reddit::fetch_video(
                     config.client,
+                    config.storage,
                     &config.url,
                     &config.output,
                     &config.temp_dir,
@@ -104,91 +221,419 @@ pub async fn fetch(config: FetchJob<'_>) -> (FetchJob<'_>, Result<()>) {
                 )
                 .await
             }
-            "reddit.com" => {
+            Some(Handler::Reddit) => {
                 if let Some(ref gallery) = config.gallery {
-                    reddit::fetch_gallery(config.client, &config.url, &config.output, gallery).await
+                    reddit::fetch_gallery(
+                        config.client,
+                        config.storage,
+                        &config.url,
+                        gallery_output,
+                        gallery,
+                    )
+                    .await
+                } else if let Some(id) = reddit::permalink_id(&config.url) {
+                    fetch_linked_post(&config, id).await
                 } else {
                     // This normally indicates a selfpost
-                    Ok(())
+                    Ok(DownloadStats::default())
+                }
+            }
+            Some(Handler::ImgurImage) => {
+                match imgur::fetch(
+                    config.client,
+                    config.storage,
+                    &config.url,
+                    &config.output,
+                    config.parameters.prefer_mp4,
+                )
+                .await
+                {
+                    Ok((stats, output)) => {
+                        resolved_output = Some(output);
+                        Ok(stats)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Some(Handler::StackImgur) => {
+                // Hosted by Imgur for Stack Exchange, without the mp4 variants
+                download(config.client, config.storage, &config.url, &config.output).await
+            }
+            Some(Handler::Imgur) => {
+                match imgur::fetch_album(
+                    config.client,
+                    config.storage,
+                    &config.url,
+                    gallery_output,
+                    config.parameters.prefer_mp4,
+                    config.parameters.gallery_depth,
+                )
+                .await
+                {
+                    Ok((stats, output)) => {
+                        resolved_output = Some(output);
+                        Ok(stats)
+                    }
+                    Err(e) => Err(e),
                 }
             }
-            "i.imgur.com" => imgur::fetch(config.client, &config.url, &config.output).await,
-            "imgur.com" => imgur::fetch_album(config.client, &config.url, &config.output).await,
-            "gfycat.com" => {
+            Some(Handler::Gfycat) => {
                 gfycat::fetch_gfycat(
                     config.client,
+                    config.storage,
                     &config.url,
                     &config.output,
                     config.parameters.gfycat_type,
                 )
                 .await
             }
-            "redgifs.com" => {
+            Some(Handler::Redgifs) => {
                 gfycat::fetch_redgifs(
                     config.client,
+                    config.storage,
                     &config.url,
                     &config.output,
                     config.parameters.gfycat_type,
                 )
                 .await
             }
-            "giant.gfycat.com" => {
-                gfycat::fetch_giant(config.client, &config.url, &config.output).await
+            Some(Handler::GfycatGiant) => {
+                gfycat::fetch_giant(config.client, config.storage, &config.url, &config.output)
+                    .await
             }
-            "thumbs.gfycat.com" | "thumbs1.redgifs.com" => {
-                gfycat::fetch_thumbs(config.client, &config.url, &config.output).await
+            Some(Handler::GfycatThumbs) => {
+                gfycat::fetch_thumbs(config.client, config.storage, &config.url, &config.output)
+                    .await
             }
-            "i.pinimg.com" => pinterest::fetch(config.client, &config.url, &config.output).await,
-            "i.postimg.cc" => postimages::fetch(config.client, &config.url, &config.output).await,
-            domain => {
-                if config.parameters.force {
-                    download(config.client, &config.url, &config.output).await
+            Some(Handler::PinterestImage) => {
+                pinterest::fetch(config.client, config.storage, &config.url, &config.output).await
+            }
+            Some(Handler::Pinterest) => {
+                match pinterest::fetch_pin(
+                    config.client,
+                    config.storage,
+                    &config.url,
+                    &config.output,
+                )
+                .await
+                {
+                    Ok((stats, output)) => {
+                        resolved_output = Some(output);
+                        Ok(stats)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Some(Handler::PostimagesImage) => {
+                postimages::fetch(config.client, config.storage, &config.url, &config.output).await
+            }
+            Some(Handler::Postimages) => {
+                postimages::fetch_page(config.client, config.storage, &config.url, gallery_output)
+                    .await
+            }
+            Some(Handler::Vimeo) => {
+                vimeo::fetch(
+                    config.client,
+                    config.storage,
+                    &config.url,
+                    &config.output,
+                    config.parameters.max_resolution,
+                )
+                .await
+            }
+            None => {
+                if config.parameters.force && config.resolve_extension {
+                    // The output is only known after receiving the `Content-Type`
+                    match download_with_extension(
+                        config.client,
+                        config.storage,
+                        &config.url,
+                        &config.output,
+                    )
+                    .await
+                    {
+                        Ok((stats, output)) => {
+                            resolved_output = Some(output);
+                            Ok(stats)
+                        }
+                        Err(e) => Err(e),
+                    }
+                } else if config.parameters.force {
+                    match download_forced(
+                        config.client,
+                        config.storage,
+                        &config.url,
+                        &config.output,
+                        config.parameters.skip_html_pages,
+                    )
+                    .await
+                    {
+                        Ok((stats, output)) => {
+                            resolved_output = Some(output);
+                            Ok(stats)
+                        }
+                        Err(e) => Err(e),
+                    }
                 } else {
                     Err(Error::new(format!("Unsupported domain '{}'", domain)))
                 }
             }
-        }
+        };
+
+        // Gfycat is offline, but many videos were archived
+        let result = match (result, config.parameters.gfycat_fallback) {
+            (Err(e), GfycatFallback::Wayback) if WAYBACK_DOMAINS.contains(&domain) => {
+                debug!(
+                    "Failed to fetch {:?}, trying the Wayback Machine: {}",
+                    config.url, e
+                );
+                gfycat::fetch_wayback(
+                    config.client,
+                    config.storage,
+                    &config.url,
+                    &config.output,
+                    config.parameters.gfycat_type,
+                )
+                .await
+            }
+            (result, _) => result,
+        };
+
+        // The post is only saved as an archive if it turned out to be a gallery
+        let finished = match archive {
+            Some(archive) => Some(archive.finish().await),
+            None => None,
+        };
+        let result = match finished {
+            Some(Ok(Some(path))) => {
+                resolved_output = Some(path);
+                result
+            }
+            Some(Err(e)) => result.and(Err(e)),
+            _ => result,
+        };
+
+        if let Some(output) = resolved_output {
+            config.output = output;
+        };
+
+        result
     };
 
     (config, result)
 }
 
+/// Returns the URLs a post is downloaded from, see `--list-only`.
+///
+/// Reddit galleries are listed as their images and `v.redd.it` videos as
+/// the streams of `--vreddit-mode`. Other links, like Imgur albums, are
+/// only resolved while downloading and are listed as they are.
+pub fn listed_urls(
+    domain: &str,
+    url: &Uri,
+    gallery: Option<&Gallery>,
+    media: Option<&SecureMedia>,
+    vreddit_mode: &VRedditMode,
+) -> Vec<String> {
+    let video = media.and_then(|media| media.reddit_video.as_ref());
+
+    match (handler(normalize_domain(domain)), gallery, video) {
+        (Some(Handler::Reddit), Some(gallery), _) => reddit::gallery_urls(gallery),
+        (Some(Handler::RedditVideo), _, Some(video)) => match reddit::video_id(url) {
+            Some(id) => reddit::video_urls(id, video, vreddit_mode),
+            None => vec![url.to_string()],
+        },
+        _ => vec![url.to_string()],
+    }
+}
+
+/// Checks whether the link of a job is dead, see `--precheck`.
+///
+/// This is done before the job waits for a download slot, so dead links
+/// do not hold one. Links which can still be fetched from elsewhere,
+/// like Gfycat from the Wayback Machine, are not checked.
+pub async fn precheck_job(config: &FetchJob<'_>) -> Result<()> {
+    let domain = normalize_domain(&config.domain);
+    if config.is_selfpost
+        || config.parameters.gfycat_fallback == GfycatFallback::Wayback
+            && WAYBACK_DOMAINS.contains(&domain)
+    {
+        return Ok(());
+    };
+
+    match precheck(config.client, &config.url).await {
+        Some(status) => Err(Error::new(format!(
+            "Dead link: the precheck returned {}",
+            status
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Fetches the post linked to by another post.
+///
+/// The linked post is retrieved from reddit and handled like a normal post,
+/// with the file extension appended to the output file.
+async fn fetch_linked_post(config: &FetchJob<'_>, id: &str) -> Result<DownloadStats> {
+    trace!("fetch_linked_post({:?})", id);
+
+    debug!("Resolving linked post {}", id);
+
+    let post = reddit::fetch_post(config.client, id).await?;
+
+    let malformed = || Error::new(format!("Malformed post {}\n\n{}", id, HELP_JSON));
+    let is_selfpost = post["is_self"].as_bool().ok_or_else(malformed)?;
+    let url: Uri = post["url"].as_str().ok_or_else(malformed)?.parse()?;
+
+    if is_selfpost && !config.parameters.selfposts {
+        debug!("Linked post {} is a self post, skipping it", id);
+        return Ok(DownloadStats::default());
+    };
+    if !is_selfpost && reddit::permalink_id(&url).is_some() {
+        return Err(Error::new("The linked post links to another post"));
+    };
+
+    let extension = file_extension(
+        &url,
+        config.parameters.gfycat_type,
+        &config.parameters.vreddit_mode,
+        config.parameters.selftext_format,
+        is_selfpost,
+    );
+    let resolve_extension = extension.is_none();
+    let mut output = config.output.clone().into_os_string();
+    output.push(extension.unwrap_or(""));
+
+    let post: pushshift::Post = serde_json::from_value(post)?;
+
+    let job = FetchJob {
+        client: config.client,
+        storage: config.storage,
+        parameters: config.parameters,
+        id: config.id.clone(),
+        domain: post.domain,
+        is_selfpost,
+        url,
+        output: PathBuf::from(output),
+        resolve_extension,
+        temp_dir: config.temp_dir,
+        text: post.selftext,
+        title: post.title,
+        gallery: post.media_metadata,
+        media: post.secure_media,
+    };
+
+    // Boxed because `fetch()` is recursive here
+    let (_, result) = Box::pin(fetch(job)).await;
+    result
+}
+
 /// Fetches a self post.
-pub async fn fetch_selfpost(output: &PathBuf, text: &str) -> Result<()> {
+pub async fn fetch_selfpost(
+    storage: &Storage,
+    output: &PathBuf,
+    text: &str,
+) -> Result<DownloadStats> {
     trace!("fetch_selfpost({:?}, {:?})", output, text);
 
-    let mut file = File::create(&output).await?;
-    file.write_all(text.as_bytes()).await?;
+    let start = Instant::now();
+    if !storage.write(output, text.as_bytes()).await? {
+        return Ok(DownloadStats::skipped());
+    };
 
-    Ok(())
+    Ok(DownloadStats {
+        bytes: text.len() as u64,
+        duration: start.elapsed(),
+        skipped: false,
+        extension: None,
+    })
 }
 
+/// The file extensions which can be appended to an output without one,
+/// see [`FetchJob::resolve_extension`].
+///
+/// [`FetchJob::resolve_extension`]: struct.FetchJob.html#structfield.resolve_extension
+pub const RESOLVED_EXTENSIONS: &[&str] = &[
+    ".jpg", ".jpeg", ".png", ".gif", ".webp", ".mp4", ".webm", ".m4a", ".html",
+];
+
 /// Gets the file extension of an URL.
-pub fn file_extension(url: &Uri, gfycat_type: GfycatType, is_selfpost: bool) -> Option<&str> {
+pub fn file_extension<'a>(
+    url: &'a Uri,
+    gfycat_type: GfycatType,
+    vreddit_mode: &VRedditMode,
+    selftext_format: SelftextFormat,
+    is_selfpost: bool,
+) -> Option<&'a str> {
     if is_selfpost {
-        return Some(".txt");
+        return Some(selftext_format.extension());
     };
 
-    if url.host() == Some("reddit.com") {
+    let host = url.host().map(normalize_domain);
+
+    // Galleries and links to other posts have no extension of their own
+    if let Some("reddit.com") | Some("old.reddit.com") | Some("np.reddit.com") = host {
         return Some("");
-    }
+    };
+
+    if let (Some("v.redd.it"), VRedditMode::AudioOnly) = (host, vreddit_mode) {
+        return Some(".m4a");
+    };
 
-    if url.host() == Some("v.redd.it") {
+    if let Some("v.redd.it") | Some("vimeo.com") | Some("player.vimeo.com") = host {
         return Some(".mp4");
     };
 
-    if url.host() == Some("gfycat.com") {
+    if let Some("i.imgur.com") | Some("imgur.com") = host {
+        if url.path().ends_with(".gifv") {
+            // The `.gifv` page embeds an mp4 video
+            return Some(".mp4");
+        };
+    };
+
+    if let Some("gfycat.com") | Some("redgifs.com") = host {
         return match gfycat_type {
             GfycatType::Mp4 => Some(".mp4"),
             GfycatType::Webm => Some(".webm"),
         };
     };
 
-    let mut chars = url.path().char_indices();
+    path_extension(url.path()).or_else(|| url.query().and_then(query_extension))
+}
+
+/// Returns the ID of the media on its host, see `--name-by-source`.
+///
+/// This is the hash of Imgur images, the ID of Gfycat and RedGifs videos
+/// and the ID of files on `v.redd.it` and `i.redd.it`. Other hosts and
+/// IDs which are not alphanumeric return `None`.
+pub fn source_id(url: &Uri) -> Option<&str> {
+    let path = url.path();
+    let id = match url.host().map(normalize_domain)? {
+        "i.imgur.com" => imgur::split_file_name(path).map(|(hash, _)| hash),
+        "imgur.com" => imgur::single_image_hash(path),
+        "gfycat.com" | "redgifs.com" => Some(gfycat::extract_id(path).0),
+        // Like `giant.gfycat.com/<id>.mp4` or `thumbs.gfycat.com/<id>-mobile.mp4`
+        "giant.gfycat.com" | "thumbs.gfycat.com" | "thumbs1.redgifs.com" | "i.redd.it" => {
+            path.trim_start_matches('/').split(&['.', '-'][..]).next()
+        }
+        "v.redd.it" => reddit::video_id(url),
+        _ => None,
+    }?;
+
+    if !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// Gets the file extension of the last segment of a path.
+fn path_extension(path: &str) -> Option<&str> {
+    let mut chars = path.char_indices();
 
     while let Some((index, c)) = chars.next_back() {
         if c == '.' {
-            return Some(&url.path()[index..]);
+            return Some(&path[index..]);
         } else if c == '/' {
             // Abort at the first slash
             return None;
@@ -198,22 +643,185 @@ pub fn file_extension(url: &Uri, gfycat_type: GfycatType, is_selfpost: bool) ->
     None
 }
 
-/// Returns the currently supported domains.
-pub fn supported_domains() -> &'static str {
-    "\
-i.redd.it
-v.redd.it
-reddit.com
-i.imgur.com
-imgur.com
-gfycat.com
-thumbs.gfycat.com
-giant.gfycat.com
-redgifs.com
-thumbs1.redgifs.com
-i.pinimg.com
-i.postimg.cc\
-    "
+/// Gets a file extension hinted at by the query string, like in
+/// `?format=jpg` or `?filename=image.jpg`.
+fn query_extension(query: &str) -> Option<&str> {
+    for i in query.split('&') {
+        let (key, value) = match i.find('=') {
+            Some(index) => (&i[..index], &i[index + 1..]),
+            None => continue,
+        };
+
+        let extension = match key {
+            "format" | "fm" | "ext" => media_extension(value),
+            _ => path_extension(value)
+                .filter(|extension| extension[1..].starts_with(|c: char| c.is_ascii_alphabetic())),
+        };
+        if extension.is_some() {
+            return extension;
+        };
+    }
+
+    None
+}
+
+/// Returns the file extension of a common media format name like `jpg` or `mp4`.
+fn media_extension(name: &str) -> Option<&'static str> {
+    match name.to_ascii_lowercase().as_str() {
+        "jpg" | "jpeg" | "pjpg" => Some(".jpg"),
+        "png" => Some(".png"),
+        "gif" => Some(".gif"),
+        "webp" => Some(".webp"),
+        "mp4" => Some(".mp4"),
+        "webm" => Some(".webm"),
+        _ => None,
+    }
+}
+
+/// A supported domain.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Domain {
+    /// The name of the domain.
+    pub domain: &'static str,
+
+    /// Whether the media is resolved using an API or web page,
+    /// instead of being downloaded directly.
+    pub api: bool,
+
+    /// The external programs used by some modes, like `ffmpeg` for `v.redd.it`.
+    pub tools: &'static [&'static str],
+
+    /// How posts linking to the domain are fetched.
+    #[serde(skip)]
+    pub handler: Handler,
+}
+
+impl Domain {
+    const fn new(domain: &'static str, handler: Handler, api: bool) -> Domain {
+        Domain {
+            domain,
+            api,
+            tools: &[],
+            handler,
+        }
+    }
+}
+
+/// The way posts of a supported domain are fetched, see [`fetch()`].
+///
+/// [`fetch()`]: fn.fetch.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Handler {
+    RedditImage,
+    RedditVideo,
+    Reddit,
+    ImgurImage,
+    StackImgur,
+    Imgur,
+    Gfycat,
+    Redgifs,
+    GfycatGiant,
+    GfycatThumbs,
+    PinterestImage,
+    Pinterest,
+    PostimagesImage,
+    Postimages,
+    Vimeo,
+}
+
+/// The domains whose media is fetched from the Wayback Machine
+/// if it is gone, see `--gfycat-fallback`.
+const WAYBACK_DOMAINS: &[&str] = &["gfycat.com", "giant.gfycat.com", "thumbs.gfycat.com"];
+
+/// The currently supported domains.
+///
+/// This is the only list of supported domains: [`fetch()`] dispatches
+/// by the handler of the domain, and other domains require `--force`.
+///
+/// [`fetch()`]: fn.fetch.html
+pub const DOMAINS: &[Domain] = &[
+    Domain::new("i.redd.it", Handler::RedditImage, false),
+    Domain {
+        domain: "v.redd.it",
+        api: false,
+        tools: &["ffmpeg"],
+        handler: Handler::RedditVideo,
+    },
+    Domain::new("reddit.com", Handler::Reddit, true),
+    Domain::new("www.reddit.com", Handler::Reddit, true),
+    Domain::new("old.reddit.com", Handler::Reddit, true),
+    Domain::new("a.thumbs.redditmedia.com", Handler::RedditImage, false),
+    Domain::new("b.thumbs.redditmedia.com", Handler::RedditImage, false),
+    Domain::new("i.imgur.com", Handler::ImgurImage, false),
+    Domain::new("i.stack.imgur.com", Handler::StackImgur, false),
+    Domain::new("imgur.com", Handler::Imgur, true),
+    Domain::new("www.imgur.com", Handler::Imgur, true),
+    Domain::new("m.imgur.com", Handler::Imgur, true),
+    Domain::new("imgur.io", Handler::Imgur, true),
+    Domain::new("gfycat.com", Handler::Gfycat, true),
+    Domain::new("www.gfycat.com", Handler::Gfycat, true),
+    Domain::new("thumbs.gfycat.com", Handler::GfycatThumbs, false),
+    Domain::new("giant.gfycat.com", Handler::GfycatGiant, false),
+    Domain::new("redgifs.com", Handler::Redgifs, true),
+    Domain::new("www.redgifs.com", Handler::Redgifs, true),
+    Domain::new("thumbs1.redgifs.com", Handler::GfycatThumbs, false),
+    Domain::new("i.pinimg.com", Handler::PinterestImage, false),
+    Domain::new("pinterest.com", Handler::Pinterest, true),
+    Domain::new("www.pinterest.com", Handler::Pinterest, true),
+    Domain::new("pin.it", Handler::Pinterest, true),
+    Domain::new("i.postimg.cc", Handler::PostimagesImage, false),
+    Domain::new("postimg.cc", Handler::Postimages, true),
+    Domain::new("postimages.org", Handler::Postimages, true),
+    Domain::new("vimeo.com", Handler::Vimeo, true),
+    Domain::new("player.vimeo.com", Handler::Vimeo, true),
+];
+
+/// Returns the handler of a domain, or `None` if it is only supported with `--force`.
+pub fn handler(domain: &str) -> Option<Handler> {
+    let domain = normalize_domain(domain);
+    DOMAINS
+        .iter()
+        .find(|i| i.domain == domain)
+        .map(|i| i.handler)
+}
+
+/// Returns whether the domain is supported without '--force'.
+pub fn is_supported(domain: &str) -> bool {
+    handler(domain).is_some()
+}
+
+/// Maps a domain to the name it is handled under.
+///
+/// The `www.` and `m.` prefixes are removed, since those hosts serve
+/// the same content as the bare domain, and alternative domains of
+/// a site are mapped to its main domain. Only a leading label is
+/// removed, and only if a registrable domain remains, so `m.me`
+/// or `i.redd.it` are left as they are.
+pub fn normalize_domain(domain: &str) -> &str {
+    let domain = domain
+        .strip_prefix("www.")
+        .or_else(|| domain.strip_prefix("m."))
+        .filter(|rest| rest.contains('.'))
+        .unwrap_or(domain);
+
+    match domain {
+        "imgur.io" => "imgur.com",
+        domain => domain,
+    }
+}
+
+/// Returns the currently supported domains, one per line.
+pub fn supported_domains() -> String {
+    DOMAINS
+        .iter()
+        .map(|i| i.domain)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Returns the currently supported domains as a JSON array.
+pub fn supported_domains_json() -> String {
+    serde_json::to_string(DOMAINS).unwrap() // Serializing plain structs cannot fail
 }
 
 #[test]
@@ -221,65 +829,426 @@ fn test_url_extension() {
     let data = "http://example.com/";
     assert_eq!(
         Some(".txt"),
-        file_extension(&Uri::from_static(data), GfycatType::Mp4, true)
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            true
+        )
+    );
+
+    assert_eq!(
+        Some(".html"),
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Html,
+            true
+        )
     );
 
     let data = "http://example.com/a/b.c";
     assert_eq!(
         Some(".c"),
-        file_extension(&Uri::from_static(data), GfycatType::Mp4, false)
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
     );
     let data = "http://example.com/a.bc";
     assert_eq!(
         Some(".bc"),
-        file_extension(&Uri::from_static(data), GfycatType::Mp4, false)
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
     );
 
     let data = "http://example.com/";
     assert_eq!(
         None,
-        file_extension(&Uri::from_static(data), GfycatType::Mp4, false)
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
     );
     let data = "http://example.com/none";
     assert_eq!(
         None,
-        file_extension(&Uri::from_static(data), GfycatType::Mp4, false)
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
+    );
+
+    let data = "https://example.com/a.jpg?width=640#top";
+    assert_eq!(
+        Some(".jpg"),
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
+    );
+    let data = "https://example.com/image?format=pjpg&auto=webp";
+    assert_eq!(
+        Some(".jpg"),
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
+    );
+    let data = "https://example.com/download?filename=image.png";
+    assert_eq!(
+        Some(".png"),
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
+    );
+    let data = "https://example.com/image?width=640";
+    assert_eq!(
+        None,
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
+    );
+
+    for data in &[
+        "https://www.reddit.com/gallery/abc",
+        "https://old.reddit.com/gallery/abc",
+        "https://old.reddit.com/r/pics/comments/abc/lorem.jpg",
+        "https://np.reddit.com/r/pics/comments/abc/lorem/",
+    ] {
+        assert_eq!(
+            Some(""),
+            file_extension(
+                &data.parse().unwrap(),
+                GfycatType::Mp4,
+                &VRedditMode::NoAudio,
+                SelftextFormat::Raw,
+                false
+            )
+        );
+    }
+    let data = "https://example.com/image?v=1.2";
+    assert_eq!(
+        None,
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
+    );
+    let data = "https://i.imgur.com/bxv008g.gifv";
+    assert_eq!(
+        Some(".mp4"),
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
+    );
+    let data = "https://example.com/a.gifv";
+    assert_eq!(
+        Some(".gifv"),
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
+    );
+    let data = "https://redgifs.com/watch/abc";
+    assert_eq!(
+        Some(".mp4"),
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
     );
 
     let data = "https://gfycat.com/";
     assert_eq!(
         Some(".mp4"),
-        file_extension(&Uri::from_static(data), GfycatType::Mp4, false)
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
     );
     let data = "https://gfycat.com/";
     assert_eq!(
         Some(".webm"),
-        file_extension(&Uri::from_static(data), GfycatType::Webm, false)
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Webm,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
     );
     let data = "http://gfycat.com/.webm";
     assert_eq!(
         Some(".mp4"),
-        file_extension(&Uri::from_static(data), GfycatType::Mp4, false)
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
     );
     let data = "http://gfycat.com/.mp4";
     assert_eq!(
         Some(".webm"),
-        file_extension(&Uri::from_static(data), GfycatType::Webm, false)
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Webm,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
     );
 
     let data = "http://imgur.com/image.jpg";
     assert_eq!(
         Some(".jpg"),
-        file_extension(&Uri::from_static(data), GfycatType::Mp4, false)
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
     );
     let data = "http://imgur.com/a/id";
     assert_eq!(
         None,
-        file_extension(&Uri::from_static(data), GfycatType::Mp4, false)
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
     );
     let data = "http://imgur.com/a/id/";
     assert_eq!(
         None,
-        file_extension(&Uri::from_static(data), GfycatType::Mp4, false)
+        file_extension(
+            &Uri::from_static(data),
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
+    );
+}
+
+#[test]
+fn test_gallery_item() {
+    let path = Path::new("output/abc-Lorem ipsum");
+
+    let output = GalleryOutput {
+        path,
+        id: "abc",
+        flatten: false,
+        archive: None,
+    };
+    assert_eq!(
+        Path::new("output/abc-Lorem ipsum/0.jpg"),
+        output.item("0.jpg")
+    );
+
+    let output = GalleryOutput {
+        path,
+        id: "abc",
+        flatten: true,
+        archive: None,
+    };
+    assert_eq!(
+        Path::new("output/abc-Lorem ipsum-0.jpg"),
+        output.item("0.jpg")
+    );
+
+    let output = GalleryOutput {
+        path,
+        id: "def",
+        flatten: true,
+        archive: None,
+    };
+    assert_eq!(
+        Path::new("output/abc-Lorem ipsum-def-0.jpg"),
+        output.item("0.jpg")
+    );
+}
+
+#[test]
+fn test_source_id() {
+    let id = |url: &'static str| source_id(&Uri::from_static(url)).map(String::from);
+
+    assert_eq!(
+        Some(String::from("bxv008g")),
+        id("https://i.imgur.com/bxv008g.gifv")
+    );
+    assert_eq!(
+        Some(String::from("bxv008g")),
+        id("https://imgur.com/bxv008g")
+    );
+    assert_eq!(None, id("https://imgur.com/a/bxv008g"));
+    assert_eq!(
+        Some(String::from("LoremIpsum")),
+        id("https://gfycat.com/LoremIpsum-some-text")
     );
+    assert_eq!(
+        Some(String::from("loremipsum")),
+        id("https://www.redgifs.com/watch/loremipsum")
+    );
+    assert_eq!(
+        Some(String::from("LoremIpsum")),
+        id("https://thumbs.gfycat.com/LoremIpsum-mobile.mp4")
+    );
+    assert_eq!(
+        Some(String::from("abc123")),
+        id("https://i.redd.it/abc123.jpg")
+    );
+    assert_eq!(Some(String::from("abc123")), id("https://v.redd.it/abc123"));
+    assert_eq!(None, id("https://example.com/abc123.jpg"));
+    assert_eq!(None, id("https://i.redd.it/"));
+}
+
+#[test]
+fn test_listed_urls() {
+    use serde_json::json;
+
+    let gallery: Gallery = serde_json::from_value(json!({
+        "abc": { "e": "Image", "id": "abc", "m": "image/png", "status": "valid" },
+        "def": { "status": "failed" }
+    }))
+    .unwrap();
+    let media: SecureMedia = serde_json::from_value(json!({
+        "reddit_video": {
+            "fallback_url": "https://v.redd.it/ghi/DASH_720.mp4",
+            "height": 720
+        }
+    }))
+    .unwrap();
+    let listed = |domain, url, mode: &str| {
+        listed_urls(
+            domain,
+            &Uri::from_static(url),
+            Some(&gallery),
+            Some(&media),
+            &VRedditMode::from(mode),
+        )
+    };
+
+    assert_eq!(
+        vec!["https://i.redd.it/abc.png"],
+        listed(
+            "reddit.com",
+            "https://www.reddit.com/gallery/jkl",
+            "no-audio"
+        )
+    );
+    assert_eq!(
+        vec!["https://v.redd.it/ghi/DASH_720.mp4"],
+        listed("v.redd.it", "https://v.redd.it/ghi", "no-audio")
+    );
+    assert_eq!(
+        vec![
+            "https://v.redd.it/ghi/DASH_720",
+            "https://v.redd.it/ghi/audio"
+        ],
+        listed("v.redd.it", "https://v.redd.it/ghi", "ffmpeg")
+    );
+    assert_eq!(
+        vec![
+            "https://v.redd.it/ghi/DASH_720.mp4",
+            "https://v.redd.it/ghi/DASH_audio.mp4"
+        ],
+        listed("v.redd.it", "https://v.redd.it/ghi", "mux")
+    );
+    assert_eq!(
+        vec!["https://example.com/ghi.mp4"],
+        listed(
+            "v.redd.it",
+            "https://v.redd.it/ghi",
+            "https://example.com/{}.mp4"
+        )
+    );
+    // Albums are only resolved while downloading
+    assert_eq!(
+        vec!["https://imgur.com/a/mno"],
+        listed("imgur.com", "https://imgur.com/a/mno", "no-audio")
+    );
+}
+
+#[test]
+fn domains() {
+    assert!(is_supported("i.redd.it"));
+    assert!(!is_supported("example.com"));
+    assert!(is_supported("m.imgur.com"));
+    assert!(is_supported("imgur.io"));
+    assert!(is_supported("www.reddit.com"));
+    assert!(!is_supported("www.example.com"));
+    assert_eq!("imgur.com", normalize_domain("www.imgur.com"));
+    assert_eq!("imgur.com", normalize_domain("imgur.io"));
+    assert_eq!("i.stack.imgur.com", normalize_domain("i.stack.imgur.com"));
+    assert_eq!("gfycat.com", normalize_domain("www.gfycat.com"));
+    assert_eq!("redgifs.com", normalize_domain("m.redgifs.com"));
+    assert!(is_supported("www.gfycat.com"));
+    assert!(is_supported("www.redgifs.com"));
+    assert_eq!("i.redd.it", normalize_domain("i.redd.it"));
+    assert_eq!("m.me", normalize_domain("m.me"));
+    assert_eq!("www.com", normalize_domain("www.com"));
+    assert_eq!("thumbs.gfycat.com", normalize_domain("thumbs.gfycat.com"));
+    assert_eq!(Some(Handler::Imgur), handler("m.imgur.com"));
+    assert_eq!(Some(Handler::GfycatThumbs), handler("thumbs1.redgifs.com"));
+    assert_eq!(None, handler("example.com"));
+    // Listed aliases like `www.` are handled like their main domain
+    for i in DOMAINS {
+        assert_eq!(Some(i.handler), handler(i.domain), "{}", i.domain);
+    }
+    assert!(supported_domains().starts_with("i.redd.it\nv.redd.it\n"));
+    assert!(supported_domains_json().starts_with(
+        r#"[{"domain":"i.redd.it","api":false,"tools":[]},{"domain":"v.redd.it","api":false,"tools":["ffmpeg"]},"#
+    ));
 }
@@ -0,0 +1,283 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+Utilities for retrieving private listings from the authenticated reddit API.
+
+Pushshift only archives public data, so listings like the saved
+or upvoted posts of a user have to be read from reddit directly.
+*/
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::prelude::*;
+use crate::sites::pushshift::Subreddit;
+
+/// The user agent reddit requires for API clients.
+pub const USER_AGENT: &str = concat!("redditrip/", env!("CARGO_PKG_VERSION"));
+
+/// The number of posts requested per listing page. This is the maximum reddit allows.
+const PAGE_SIZE: usize = 100;
+
+/// An authenticated session with the reddit API.
+#[derive(Debug)]
+pub struct Session {
+    /// The OAuth bearer token.
+    token: String,
+
+    /// The name of the authenticated user.
+    name: String,
+}
+
+/// The response of the token endpoint.
+#[derive(Deserialize, Debug)]
+struct AccessToken {
+    access_token: String,
+}
+
+/// The response of the `/api/v1/me` endpoint.
+#[derive(Deserialize, Debug)]
+struct Me {
+    name: String,
+}
+
+/// Obtains a bearer token using the OAuth refresh token flow
+/// and looks up the name of the authenticated user.
+pub async fn authorize(
+    client: &Client,
+    client_id: &str,
+    client_secret: &str,
+    refresh_token: &str,
+) -> Result<Session> {
+    trace!("authorize({:?})", client_id);
+
+    let credentials = base64::encode(format!("{}:{}", client_id, client_secret));
+    let response = client
+        .request_with_body(
+            Builder::new()
+                .method(Method::POST)
+                .uri("https://www.reddit.com/api/v1/access_token")
+                .header("Authorization", format!("Basic {}", credentials))
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .header("User-Agent", USER_AGENT),
+            Body::from(refresh_body(refresh_token)),
+        )
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(Error::new(format!(
+            "Invalid response code {} from reddit while authorizing\n    Note: {}",
+            response.status(),
+            HELP_OAUTH
+        )));
+    };
+
+    let token: AccessToken = to_json(response).await?;
+    debug!("Obtained OAuth bearer token");
+
+    let response = client
+        .request(
+            Builder::new()
+                .method(Method::GET)
+                .uri("https://oauth.reddit.com/api/v1/me")
+                .header("Authorization", format!("bearer {}", token.access_token))
+                .header("User-Agent", USER_AGENT),
+        )
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(Error::new(format!(
+            "Invalid response code {} from reddit while authorizing\n    Note: {}",
+            response.status(),
+            HELP_OAUTH
+        )));
+    };
+
+    let me: Me = to_json(response).await?;
+    debug!("Authorized as /u/{}", me.name);
+
+    Ok(Session {
+        token: token.access_token,
+        name: me.name,
+    })
+}
+
+/// Returns the form body requesting a bearer token.
+/// Refresh tokens can contain characters like `+` or `=`, so they are encoded.
+fn refresh_body(refresh_token: &str) -> String {
+    form_urlencoded::Serializer::new(String::new())
+        .append_pair("grant_type", "refresh_token")
+        .append_pair("refresh_token", refresh_token)
+        .finish()
+}
+
+/// Retrieves a private listing of the authenticated user.
+///
+/// The `after` parameter is automatically set by the function:
+/// the next call retrieves the next data. If the returned `Vec`
+/// has a length of `0`, the available data was read completely.
+///
+/// Only posts are returned, saved comments are left out.
+pub async fn api(
+    client: &Client,
+    session: &Session,
+    listing: &Subreddit,
+    after: &mut Option<String>,
+) -> Result<Vec<Value>> {
+    trace!("api({:?}, {:?})", listing, after);
+
+    let listing = match listing {
+        Subreddit::Saved => "saved",
+        Subreddit::Upvoted => "upvoted",
+        _ => return Err(Error::bug()),
+    };
+
+    // Reddit signals the end of the listing by omitting `after`
+    if after.as_ref().map(|after| after.is_empty()) == Some(true) {
+        return Ok(Vec::new());
+    };
+
+    let mut url = format!(
+        "https://oauth.reddit.com/user/{}/{}?limit={}&raw_json=1",
+        session.name, listing, PAGE_SIZE
+    );
+    if let Some(after) = after {
+        url.push_str("&after=");
+        url.push_str(after);
+    };
+
+    let response = client
+        .request(
+            Builder::new()
+                .method(Method::GET)
+                .uri(&url)
+                .header("Accept", "application/json")
+                .header("Authorization", format!("bearer {}", session.token))
+                .header("User-Agent", USER_AGENT),
+        )
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(Error::new(format!(
+            "Invalid response code {} from reddit",
+            response.status()
+        )));
+    };
+
+    debug!("Received {} from {:?}", response.status(), url);
+
+    let mut value: Value = to_json(response).await?;
+
+    *after = Some(value["data"]["after"].as_str().unwrap_or("").to_string());
+
+    if let Value::Array(children) = value["data"]["children"].take() {
+        Ok(children
+            .into_iter()
+            .filter(|child| child["kind"] == "t3")
+            .map(|mut child| child["data"].take())
+            .collect())
+    } else {
+        Err(Error::new(format!(
+            "Unexpectedly received invalid JSON\n\n{}",
+            HELP_JSON
+        )))
+    }
+}
+
+/// Applies the filters Pushshift evaluates on the server side.
+/// Returns whether the post should be downloaded.
+///
+/// The domain filters are applied to every listing by `subreddit::rip()`.
+pub fn filter(post: &Value, parameters: &Parameters) -> bool {
+    if !parameters.selfposts && post["is_self"].as_bool() == Some(true) {
+        return false;
+    };
+
+    let created = post["created_utc"].as_f64().unwrap_or(0.0) as u64;
+    if parameters.after.map(|after| created <= after) == Some(true)
+        || parameters.before.map(|before| created >= before) == Some(true)
+    {
+        return false;
+    };
+
+    // Pushshift compares text case-insensitively
+    parameters
+        .filter
+        .iter()
+        .all(|(field, value)| match &post[field.as_str()] {
+            Value::String(text) => text.to_lowercase() == value.to_lowercase(),
+            Value::Null => false,
+            other => value.parse::<Value>().ok().as_ref() == Some(other),
+        })
+}
+
+#[test]
+fn oauth_filter() {
+    use serde_json::json;
+    use structopt::StructOpt;
+
+    let post = json!({
+        "is_self": false,
+        "domain": "i.redd.it",
+        "created_utc": 946684800.0
+    });
+
+    assert!(filter(&post, &Parameters::from_iter(&["test"])));
+    assert!(!filter(
+        &post,
+        &Parameters::from_iter(&["test", "--after", "2000-1-2"])
+    ));
+    assert!(!filter(
+        &json!({ "is_self": true, "domain": "self.test" }),
+        &Parameters::from_iter(&["test"])
+    ));
+
+    let post = json!({
+        "is_self": false,
+        "created_utc": 946684800.0,
+        "link_flair_text": "OC",
+        "over_18": false
+    });
+    let filters = |filters: &[&str]| {
+        let mut arguments = vec!["test"];
+        for i in filters {
+            arguments.push("--filter");
+            arguments.push(i);
+        }
+        Parameters::from_iter(&arguments)
+    };
+
+    assert!(filter(&post, &filters(&["link_flair_text=oc"])));
+    assert!(filter(
+        &post,
+        &filters(&["link_flair_text=OC", "over_18=false"])
+    ));
+    assert!(!filter(&post, &filters(&["link_flair_text=Meme"])));
+    assert!(!filter(&post, &filters(&["post_hint=image"])));
+}
+
+#[test]
+fn oauth_refresh_body() {
+    assert_eq!(
+        "grant_type=refresh_token&refresh_token=abc-def",
+        refresh_body("abc-def")
+    );
+    assert_eq!(
+        "grant_type=refresh_token&refresh_token=a%2Bb%3D%26c",
+        refresh_body("a+b=&c")
+    );
+}
@@ -20,15 +20,149 @@ Support for [Pinterest](https://www.pinterest.com/) downloads.
 # Domains
 
 - `i.pinimg.com`
+- `pinterest.com`
+- `www.pinterest.com`
+- `pin.it`
 */
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use http::header::LOCATION;
 
 use crate::prelude::*;
+use crate::sites::normalize_domain;
+
+/// The maximum number of redirects followed for `pin.it` short links.
+const MAX_REDIRECTS: usize = 5;
 
 /// Fetches an image from `i.pinimg.com`.
-pub async fn fetch(client: &Client, url: &Uri, output: &Path) -> Result<()> {
+pub async fn fetch(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: &Path,
+) -> Result<DownloadStats> {
     trace!("fetch({:?}, {:?})", url, output);
 
-    download(client, url, output).await
+    download(client, storage, url, output).await
+}
+
+/// Fetches the original image of a pin from `pinterest.com` or a `pin.it` short link.
+///
+/// The pin page embeds its data as JSON, which contains the link
+/// to the original image. Its file extension is appended to the output,
+/// which is returned together with the statistics.
+pub async fn fetch_pin(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: &Path,
+) -> Result<(DownloadStats, PathBuf)> {
+    trace!("fetch_pin({:?}, {:?})", url, output);
+
+    let page = resolve_short_link(client, url).await?;
+    let id = pin_id(&page).ok_or_else(|| Error::new("Not a Pinterest pin"))?;
+
+    let page = format!("https://www.pinterest.com/pin/{}/", id);
+    let (html, _) = download_to_memory(client, &page.parse()?).await?;
+    let image = extract_original(&String::from_utf8_lossy(&html))
+        .ok_or_else(|| Error::new("Pinterest parser error"))?;
+
+    debug!("Found Pinterest image {}", image);
+
+    let name = &image[image.rfind('/').map(|index| index + 1).unwrap_or(0)..];
+    let mut output = output.to_path_buf().into_os_string();
+    if let Some(index) = name.rfind('.') {
+        output.push(&name[index..]);
+    };
+
+    let output = PathBuf::from(output);
+    let stats = download(client, storage, &image.parse()?, &output).await?;
+
+    Ok((stats, output))
+}
+
+/// Follows the redirects of a `pin.it` short link to the pin page.
+/// Other links are returned unchanged.
+async fn resolve_short_link(client: &Client, url: &Uri) -> Result<Uri> {
+    trace!("resolve_short_link({:?})", url);
+
+    let mut url = url.clone();
+
+    for _ in 0..MAX_REDIRECTS {
+        if pin_id(&url).is_some() {
+            return Ok(url);
+        };
+
+        let response = client
+            .request(Builder::new().method(Method::GET).uri(url.clone()))
+            .await?;
+        let status = response.status();
+
+        if !status.is_redirection() {
+            return Err(Error::new(format!("Unexpected response code {}", status)));
+        };
+
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| Error::new("Pinterest redirect without location"))?;
+        debug!(
+            "Received {} from {:?}, redirecting to {}",
+            status, url, location
+        );
+
+        url = location.parse()?;
+    }
+
+    Err(Error::new("Too many redirects"))
+}
+
+/// Returns the ID of a pin page like `pinterest.com/pin/<id>/`.
+fn pin_id(url: &Uri) -> Option<&str> {
+    if url.host().map(normalize_domain) == Some("pinterest.com") {
+        let mut segments = url.path().split('/').filter(|i| !i.is_empty());
+
+        if segments.next() == Some("pin") {
+            return segments.next();
+        };
+    };
+
+    None
+}
+
+/// Extracts the link to the original image from the JSON embedded in a pin page.
+fn extract_original(html: &str) -> Option<String> {
+    let object = &html[html.find("\"orig\"")?..];
+    let object = &object[..object.find('}')?];
+    let url = &object[object.find("\"url\"")? + 5..];
+    let url = &url[url.find('"')? + 1..];
+    let url = &url[..url.find('"')?];
+
+    Some(url.replace("\\/", "/").replace("\\u002F", "/"))
+}
+
+#[test]
+fn pinterest_pin() {
+    assert_eq!(
+        Some("123"),
+        pin_id(&Uri::from_static("https://www.pinterest.com/pin/123/"))
+    );
+    assert_eq!(
+        Some("123"),
+        pin_id(&Uri::from_static("https://pinterest.com/pin/123"))
+    );
+    assert_eq!(
+        None,
+        pin_id(&Uri::from_static("https://www.pinterest.com/user/board/"))
+    );
+    assert_eq!(None, pin_id(&Uri::from_static("https://pin.it/abc")));
+
+    let html = r#"<script>{"images": {"236x": {"url": "https://i.pinimg.com/236x/ab/cd.jpg"}, "orig": {"width": 736, "url": "https:\/\/i.pinimg.com\/originals\/ab\/cd.jpg"}}}</script>"#;
+    assert_eq!(
+        Some(String::from("https://i.pinimg.com/originals/ab/cd.jpg")),
+        extract_original(html)
+    );
+    assert_eq!(None, extract_original("<html></html>"));
 }
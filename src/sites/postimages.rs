@@ -20,15 +20,132 @@ Support for [Postimages](https://postimages.org/) downloads.
 # Domains
 
 - `i.postimg.cc`
+- `postimg.cc`
+- `postimages.org`
 */
 
 use std::path::Path;
 
 use crate::prelude::*;
+use crate::sites::GalleryOutput;
 
-/// Fetches an image from `i.postimg.c`.
-pub async fn fetch(client: &Client, url: &Uri, output: &Path) -> Result<()> {
+/// Fetches an image from `i.postimg.cc`.
+pub async fn fetch(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: &Path,
+) -> Result<DownloadStats> {
     trace!("fetch({:?}, {:?})", url, output);
 
-    download(client, url, output).await
+    download(client, storage, url, output).await
+}
+
+/// Fetches galleries and image pages from `postimg.cc` and `postimages.org`.
+pub async fn fetch_page(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: GalleryOutput<'_>,
+) -> Result<DownloadStats> {
+    trace!("fetch_page({:?}, {:?})", url, output);
+
+    let images = scrape(client, url).await?;
+
+    if url.path().starts_with("/gallery/") {
+        download_images(client, storage, images, output).await
+    } else {
+        // A page of a single image
+        let image = images
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::new("Postimages parser error"))?;
+
+        debug!("Found Postimages image {}", image);
+
+        download(client, storage, &image.parse()?, output.path).await
+    }
+}
+
+/// Retrieves the page and extracts the direct image links.
+async fn scrape(client: &Client, url: &Uri) -> Result<Vec<String>> {
+    trace!("scrape({:?})", url);
+
+    let url = format!("https://postimg.cc{}", url.path());
+    let (body, _) = download_to_memory(client, &url.parse()?).await?;
+
+    Ok(extract_images(&String::from_utf8_lossy(&body)))
+}
+
+/// Extracts every distinct `i.postimg.cc` link from a HTML page, in order of appearance.
+fn extract_images(html: &str) -> Vec<String> {
+    const PREFIX: &str = "https://i.postimg.cc/";
+
+    let mut images: Vec<String> = Vec::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find(PREFIX) {
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| c == '"' || c == '\'' || c == '?' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let image = &rest[..end];
+
+        if !images.iter().any(|i| i == image) {
+            images.push(image.to_string());
+        };
+
+        rest = &rest[end..];
+    }
+
+    images
+}
+
+/// Downloads the set of images.
+async fn download_images(
+    client: &Client,
+    storage: &Storage,
+    images: Vec<String>,
+    output: GalleryOutput<'_>,
+) -> Result<DownloadStats> {
+    trace!("download_images({:?}, {:?})", images, output);
+
+    debug!(
+        "Found Postimages gallery containing {} entries",
+        images.len()
+    );
+
+    output.create(storage).await?;
+    let mut stats = DownloadStats::default();
+    for (i, image) in images.into_iter().enumerate() {
+        let name = &image[image.rfind('/').map(|index| index + 1).unwrap_or(0)..];
+        let extension = name.rfind('.').map(|index| &name[index..]).unwrap_or("");
+        let path = output.item(&format!("{}{}", i, extension));
+        debug!("Saving individual image {:?}", image);
+        // Ignore individual errors
+        if let Ok(image) = download(client, storage, &image.parse()?, &path).await {
+            output.store(&path).await;
+            stats += image;
+        };
+    }
+
+    Ok(stats)
+}
+
+#[test]
+fn postimages_extract() {
+    let html = r#"
+        <a href="https://postimg.cc/abc"><img src="https://i.postimg.cc/abc/one.jpg"></a>
+        <a href="https://i.postimg.cc/abc/one.jpg?dl=1">Download</a>
+        <img src='https://i.postimg.cc/def/two.png'>
+    "#;
+
+    assert_eq!(
+        vec![
+            "https://i.postimg.cc/abc/one.jpg".to_string(),
+            "https://i.postimg.cc/def/two.png".to_string()
+        ],
+        extract_images(html)
+    );
+    assert!(extract_images("<html></html>").is_empty());
 }
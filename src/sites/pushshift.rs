@@ -21,9 +21,22 @@ Utilities for retrieving data from the Pushshift API.
 use serde::Deserialize;
 use serde_json::Value;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf, time::Duration};
 
+use http::header::RETRY_AFTER;
+use hyper::{Body, Response};
+use tokio::time::delay_for;
+
+use crate::cache::Cache;
 use crate::prelude::*;
+use crate::sites::selfpost::SelftextFormat;
+
+/// How often a request is retried if Pushshift is overloaded.
+const MAX_RETRIES: u32 = 5;
+
+/// The longest wait between retries in seconds,
+/// also if the `Retry-After` header asks for more.
+const MAX_RETRY_DELAY: u64 = 300;
 
 /// A subreddit on reddit.
 ///
@@ -37,6 +50,22 @@ pub enum Subreddit {
 
     /// The profile of a user.
     Profile(String),
+
+    /// The saved posts of the authenticated user.
+    /// Requires OAuth credentials.
+    Saved,
+
+    /// The upvoted posts of the authenticated user.
+    /// Requires OAuth credentials.
+    Upvoted,
+
+    /// A multireddit of a user, which combines several subreddits.
+    /// It is expanded to its subreddits before downloading.
+    Multi { user: String, name: String },
+
+    /// A single post, identified by its ID.
+    /// It is retrieved from reddit and saved directly within the output directory.
+    Post(String),
 }
 
 impl Subreddit {
@@ -45,8 +74,34 @@ impl Subreddit {
         match self {
             Subreddit::Subreddit(name) => name.to_owned(),
             Subreddit::Profile(name) => format!("u_{}", name),
+            Subreddit::Saved => String::from("u_me_saved"),
+            Subreddit::Upvoted => String::from("u_me_upvoted"),
+            Subreddit::Multi { user, name } => format!("{}_{}", user, name),
+            Subreddit::Post(_) => String::new(),
         }
     }
+
+    /// Converts this subreddit into the path of its URL on reddit, see `--mirror`.
+    pub fn to_mirror_path(&self) -> PathBuf {
+        match self {
+            Subreddit::Subreddit(name) => ["r", name].iter().collect(),
+            Subreddit::Profile(name) => ["u", name].iter().collect(),
+            Subreddit::Saved => ["u", "me", "saved"].iter().collect(),
+            Subreddit::Upvoted => ["u", "me", "upvoted"].iter().collect(),
+            Subreddit::Multi { user, name } => ["u", user, "m", name].iter().collect(),
+            Subreddit::Post(_) => PathBuf::new(),
+        }
+    }
+
+    /// Returns whether the listing is private and has to be retrieved using OAuth.
+    pub fn is_private(&self) -> bool {
+        matches!(self, Subreddit::Saved | Subreddit::Upvoted)
+    }
+
+    /// Returns whether this is a single post instead of a listing.
+    pub fn is_post(&self) -> bool {
+        matches!(self, Subreddit::Post(_))
+    }
 }
 
 impl ToString for Subreddit {
@@ -54,14 +109,31 @@ impl ToString for Subreddit {
         match self {
             Subreddit::Subreddit(name) => format!("/r/{}", name),
             Subreddit::Profile(name) => format!("/u/{}", name),
+            Subreddit::Saved => String::from("/u/me/saved"),
+            Subreddit::Upvoted => String::from("/u/me/upvoted"),
+            Subreddit::Multi { user, name } => format!("/user/{}/m/{}", user, name),
+            Subreddit::Post(id) => format!("/comments/{}", id),
         }
     }
 }
 
 /// The output of the Pushshift API.
+///
+/// The posts are kept as JSON, since their fields depend on the parameters.
 #[derive(Deserialize, Debug)]
 pub struct PushShift {
-    data: Vec<Post>,
+    data: Vec<Value>,
+
+    /// Only present when requested with `metadata=true`, see [`count()`].
+    ///
+    /// [`count()`]: fn.count.html
+    metadata: Option<Metadata>,
+}
+
+/// Information about the whole listing.
+#[derive(Deserialize, Debug)]
+struct Metadata {
+    total_results: u64,
 }
 
 /// A post on reddit.
@@ -70,6 +142,7 @@ pub struct Post {
     pub domain: String,
     pub secure_media: Option<SecureMedia>,
     pub selftext: Option<String>,
+    pub title: Option<String>,
     pub media_metadata: Option<HashMap<String, GalleryItem>>,
 }
 
@@ -87,6 +160,11 @@ pub struct RedditVideo {
 
     /// The video height.
     pub height: u64,
+
+    /// Whether the video has an audio track.
+    /// Older posts do not have this field.
+    #[serde(default)]
+    pub has_audio: Option<bool>,
 }
 
 /// Data of a reddit image gallery.
@@ -109,36 +187,83 @@ pub struct GalleryItem {
 /// Creates an URL for the Pushshift API which can later be reused.
 pub fn build_api_url(parameters: &Parameters) -> String {
     format!(
-        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size={size:}&fields={fields:}{selfposts:}{domains:}{after:}",
-        size = parameters.queue_size,
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size={size:}&fields={fields:}{selfposts:}{domains:}{after:}{filters:}{comments:}",
+        size = parameters.page_size,
         fields = {
             let mut fields = String::from("id,created_utc,domain,url,media_metadata,secure_media,is_self");
+            if parameters.only_nsfw || parameters.no_nsfw {
+                fields.push_str(",over_18");
+            };
+            if !parameters.include_removed && !parameters.title.utilizes("removed_by_category") {
+                fields.push_str(",removed_by_category");
+            };
+            if (!parameters.flair.is_empty() || parameters.require_flair)
+                && !parameters.title.utilizes("link_flair_text")
+            {
+                fields.push_str(",link_flair_text");
+            };
+            if parameters.exclude_stickied && !parameters.title.utilizes("stickied") {
+                fields.push_str(",stickied");
+            };
+            if parameters.exclude_pinned && !parameters.title.utilizes("pinned") {
+                fields.push_str(",pinned");
+            };
+            if parameters.min_comments.is_some() && !parameters.title.utilizes("num_comments") {
+                fields.push_str(",num_comments");
+            };
+            if parameters.prefer_mp4 {
+                fields.push_str(",preview");
+            };
+            if parameters.thumbnails_only && !parameters.title.utilizes("thumbnail") {
+                fields.push_str(",thumbnail");
+            };
             for i in parameters.title.iter() {
                 fields.push(',');
                 fields.push_str(i);
             };
+            for i in parameters
+                .print_fields
+                .iter()
+                .filter(|i| !parameters.title.utilizes(i))
+            {
+                fields.push(',');
+                fields.push_str(i);
+            };
+            if let Some(ref exec) = parameters.exec {
+                for i in exec.iter().filter(|i| !parameters.title.utilizes(i)) {
+                    fields.push(',');
+                    fields.push_str(i);
+                };
+            };
             fields
         },
         selfposts = if parameters.selfposts {
-            ",selftext"
+            // The title is the heading of HTML files
+            if parameters.selftext_format == SelftextFormat::Html && !parameters.title.utilizes("title") {
+                ",selftext,title"
+            } else {
+                ",selftext"
+            }
         } else {
             "&is_self=false"
         },
-        domains = if let Some(ref allowed_domains) = parameters.allow {
-            allowed_domains.iter().enumerate().fold(String::from("&domain="), |mut accumulator,(i, domain)| {
+        // Pushshift cannot combine both filters, so if both are given
+        // only `exclude` is sent and `allow` is checked by `rip()`
+        domains = if let Some(ref exluded_domains) = parameters.exclude {
+            exluded_domains.iter().enumerate().fold(String::from("&domain="), |mut accumulator,(i, domain)| {
                 if i != 0 {
                     accumulator.push(',');
                 };
+                accumulator.push('!');
                 accumulator.push_str(domain);
 
                 accumulator
             })
-        } else if let Some(ref exluded_domains) = parameters.exclude {
-            exluded_domains.iter().enumerate().fold(String::from("&domain="), |mut accumulator,(i, domain)| {
+        } else if let Some(ref allowed_domains) = parameters.allow {
+            allowed_domains.iter().enumerate().fold(String::from("&domain="), |mut accumulator,(i, domain)| {
                 if i != 0 {
                     accumulator.push(',');
                 };
-                accumulator.push('!');
                 accumulator.push_str(domain);
 
                 accumulator
@@ -149,10 +274,39 @@ pub fn build_api_url(parameters: &Parameters) -> String {
         after = match parameters.after {
             Some(time) => format!("&after={}", time),
             None => String::new(),
+        },
+        filters = parameters.filter.iter().fold(String::new(), |mut accumulator, (field, value)| {
+            accumulator.push('&');
+            accumulator.push_str(field);
+            accumulator.push('=');
+            accumulator.push_str(&encode(value));
+
+            accumulator
+        }),
+        // Pushshift only supports `>`, which is not allowed unencoded
+        comments = match parameters.min_comments {
+            Some(min) if min > 0 => format!("&num_comments=%3E{}", min - 1),
+            _ => String::new(),
         }
     )
 }
 
+/// Percent-encodes a value for the query string.
+fn encode(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+
+    for i in value.bytes() {
+        match i {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                result.push(i as char)
+            }
+            other => result.push_str(&format!("%{:02X}", other)),
+        };
+    }
+
+    result
+}
+
 /// Retrieves data from the Pushshift API.
 ///
 /// The `before` parameter is automatically set by the function:
@@ -160,7 +314,13 @@ pub fn build_api_url(parameters: &Parameters) -> String {
 /// has a length of `0`, the available data was read completely.
 ///
 /// The data is always returned from new to old.
-pub async fn api(client: &Client, url: &str, before: &mut Option<u64>) -> Result<Vec<Value>> {
+/// With the `cache` of `--cache-dir`, fresh cached responses are used instead of a request.
+pub async fn api(
+    client: &Client,
+    cache: Option<&Cache>,
+    url: &str,
+    before: &mut Option<u64>,
+) -> Result<Vec<Value>> {
     trace!("api({:?}, {:?})", url, before);
 
     let mut url = url.to_owned();
@@ -169,75 +329,238 @@ pub async fn api(client: &Client, url: &str, before: &mut Option<u64>) -> Result
         None => String::new(),
     });
 
-    let response = client
-        .request(
-            Builder::new()
-                .method(Method::GET)
-                .uri(&url)
-                .header("Accept", "application/json"),
-        )
-        .await?;
-
-    if !response.status().is_success() {
-        return Err(Error::new(format!(
-            "Invalid response code {} from API",
-            response.status()
-        )));
+    // Unreadable entries are requested again
+    let cached = cache
+        .and_then(|cache| cache.get(&url))
+        .and_then(|data| serde_json::from_slice::<PushShift>(&data).ok());
+    let posts = match cached {
+        Some(listing) => {
+            debug!("Read {:?} from the cache", url);
+            listing.data
+        }
+        None => request(client, cache, &url).await?,
     };
 
-    debug!("Received {} from {:?}", response.status(), url);
-
     let err = || {
         Error::new(format!(
             "Unexpectedly received invalid JSON\n\n{}",
             HELP_JSON
         ))
     };
-    let mut value: Value = to_json(response).await?;
-    if let Value::Array(posts) = value["data"].take() {
-        // Update the `before` parameter.
-        // The next call automatically retrieves the next batch of data.
-        // This is correct even when using `after` because the `sort_type` is set to `desc` (descending).
-        if let Some(post) = posts.last() {
-            *before = Some(post["created_utc"].as_u64().ok_or_else(err)?);
+
+    // Update the `before` parameter.
+    // The next call automatically retrieves the next batch of data.
+    // This is correct even when using `after` because the `sort_type` is set to `desc` (descending).
+    if let Some(post) = posts.last() {
+        *before = Some(post["created_utc"].as_u64().ok_or_else(err)?);
+    };
+
+    Ok(posts)
+}
+
+/// Returns the number of posts of a listing without retrieving them.
+///
+/// The `url` is the one passed to [`api()`].
+///
+/// [`api()`]: fn.api.html
+pub async fn count(client: &Client, url: &str, before: Option<u64>) -> Result<u64> {
+    trace!("count({:?}, {:?})", url, before);
+
+    let mut url = url
+        .split('&')
+        .map(|i| if i.starts_with("size=") { "size=0" } else { i })
+        .collect::<Vec<_>>()
+        .join("&");
+    url.push_str("&metadata=true");
+    if let Some(time) = before {
+        url.push_str(&format!("&before={}", time));
+    };
+
+    let response = send(client, &url).await?;
+    debug!("Received {} from {:?}", response.status(), url);
+
+    to_json::<PushShift>(response)
+        .await?
+        .metadata
+        .map(|metadata| metadata.total_results)
+        .ok_or_else(|| Error::new("No metadata in the response of the API"))
+}
+
+/// Requests a page of the Pushshift API, retrying if Pushshift is overloaded.
+async fn request(client: &Client, cache: Option<&Cache>, url: &str) -> Result<Vec<Value>> {
+    trace!("request({:?})", url);
+
+    let response = send(client, url).await?;
+    debug!("Received {} from {:?}", response.status(), url);
+
+    if let Some(cache) = cache {
+        // The raw response is needed for the cache, so it can not be parsed while streaming
+        let data = hyper::body::to_bytes(response).await?;
+        let posts = serde_json::from_slice::<PushShift>(&data)?.data;
+        cache.insert(url, &data);
+
+        return Ok(posts);
+    };
+
+    // Pages can be large with a high `--page-size`
+    Ok(to_json_streaming::<PushShift>(response).await?.data)
+}
+
+/// Sends a request to the Pushshift API, retrying if Pushshift is overloaded.
+async fn send(client: &Client, url: &str) -> Result<Response<Body>> {
+    trace!("send({:?})", url);
+
+    let mut retries = 0;
+    loop {
+        let response = client
+            .request(
+                Builder::new()
+                    .method(Method::GET)
+                    .uri(url)
+                    .header("Accept", "application/json"),
+            )
+            .await?;
+        let status = response.status();
+
+        if status.is_success() {
+            return Ok(response);
         };
 
-        Ok(posts)
-    } else {
-        Err(err())
+        // Pushshift is frequently overloaded, which is only temporary
+        if (status.as_u16() == 429 || status.is_server_error()) && retries < MAX_RETRIES {
+            let delay = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(retry_after)
+                .unwrap_or(5 << retries)
+                .min(MAX_RETRY_DELAY);
+            retries += 1;
+
+            info!(
+                "Received {} from the Pushshift API, retrying in {} seconds ({}/{})",
+                status, delay, retries, MAX_RETRIES
+            );
+            delay_for(Duration::from_secs(delay)).await;
+            continue;
+        };
+
+        return Err(Error::new(format!(
+            "Invalid response code {} from API",
+            status
+        )));
     }
 }
 
+/// Looks up the creation time of a post, as UNIX timestamp.
+pub async fn created_utc(client: &Client, id: &str) -> Result<u64> {
+    trace!("created_utc({:?})", id);
+
+    let url = format!(
+        "https://api.pushshift.io/reddit/search/submission?ids={}&fields=id,created_utc",
+        id
+    );
+    let response = send(client, &url).await?;
+    debug!("Received {} from {:?}", response.status(), url);
+
+    let posts = to_json::<PushShift>(response).await?.data;
+
+    posts
+        .first()
+        .and_then(|post| post["created_utc"].as_u64())
+        .ok_or_else(|| Error::new(format!("Post {} not found on Pushshift", id)))
+}
+
+/// Parses the value of a `Retry-After` header, which is either
+/// a number of seconds or a HTTP date, into a number of seconds.
+fn retry_after(value: &str) -> Option<u64> {
+    if let Ok(seconds) = value.trim().parse() {
+        return Some(seconds);
+    };
+
+    let date = time::strptime(value.trim(), "%a, %d %b %Y %H:%M:%S GMT").ok()?;
+    let seconds = date.to_timespec().sec - time::get_time().sec;
+
+    Some(seconds.max(0) as u64)
+}
+
+#[test]
+fn test_retry_after() {
+    assert_eq!(Some(120), retry_after("120"));
+    assert_eq!(Some(0), retry_after("Wed, 21 Oct 2015 07:28:00 GMT"));
+    assert_eq!(None, retry_after("soon"));
+}
+
 #[test]
 fn test_build_api_url() {
     use structopt::StructOpt;
 
     assert_eq!(
-        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=16&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,id,title&is_self=false",
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,id,title&is_self=false",
         build_api_url(&Parameters::from_iter(&["test"]))
     );
     assert_eq!(
-        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=0&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,id,title,selftext",
-        build_api_url(&Parameters::from_iter(&["test", "--queue-size", "0", "--selfposts"]))
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=1&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,id,title,selftext",
+        build_api_url(&Parameters::from_iter(&["test", "--page-size", "1", "--selfposts"]))
+    );
+    assert_eq!(
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,id,title,selftext",
+        build_api_url(&Parameters::from_iter(&["test", "--batch-size", "1", "--selfposts"]))
+    );
+    assert_eq!(
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,id&is_self=false",
+        build_api_url(&Parameters::from_iter(&["test", "--selftext-format", "html", "--title", "{id}"]))
+    );
+    assert_eq!(
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,id,selftext,title",
+        build_api_url(&Parameters::from_iter(&["test", "--selftext-format", "html", "--title", "{id}", "--selfposts"]))
+    );
+    assert_eq!(
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,thumbnail,id,title&is_self=false",
+        build_api_url(&Parameters::from_iter(&["test", "--thumbnails-only"]))
     );
     assert_eq!(
-        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=0&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,id,title,selftext",
-        build_api_url(&Parameters::from_iter(&["test", "--batch-size", "0", "--selfposts"]))
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,link_flair_text,id,title&is_self=false",
+        build_api_url(&Parameters::from_iter(&["test", "--require-flair"]))
     );
     assert_eq!(
-        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=16&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,id,title&is_self=false&domain=domain1,domain2",
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,id,title&is_self=false&domain=domain1,domain2",
         build_api_url(&Parameters::from_iter(&["test", "--allow", "domain1", "--allow", "domain2"]))
     );
     assert_eq!(
-        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=16&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,id,title&is_self=false&domain=!domain1,!domain2",
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,id,title&is_self=false&domain=!domain1,!domain2",
         build_api_url(&Parameters::from_iter(&["test", "--exclude", "domain1", "--exclude", "domain2"]))
     );
     assert_eq!(
-        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=16&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,id,title&is_self=false&after=946684800",
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,id,title&is_self=false&domain=!domain2",
+        build_api_url(&Parameters::from_iter(&["test", "--allow", "domain1", "--exclude", "domain2"]))
+    );
+    assert_eq!(
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,id,title&is_self=false&link_flair_text=Original%20Content&post_hint=image",
+        build_api_url(&Parameters::from_iter(&["test", "--filter", "link_flair_text=Original Content", "--filter", "post_hint=image"]))
+    );
+    assert_eq!(
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,id,title&is_self=false&after=946684800",
         build_api_url(&Parameters::from_iter(&["test", "--after", "2000-1-1"]))
     );
     assert_eq!(
-        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=16&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,author,full_link,id&is_self=false",
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,author,full_link,id&is_self=false",
         build_api_url(&Parameters::from_iter(&["test", "--title", "{id}{author}{full_link}"]))
     );
+    assert_eq!(
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,over_18,removed_by_category,id,title&is_self=false",
+        build_api_url(&Parameters::from_iter(&["test", "--no-nsfw"]))
+    );
+    assert_eq!(
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,num_comments,id,title&is_self=false&num_comments=%3E9",
+        build_api_url(&Parameters::from_iter(&["test", "--min-comments", "10"]))
+    );
+    assert_eq!(
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,removed_by_category,stickied,pinned,id,title&is_self=false",
+        build_api_url(&Parameters::from_iter(&["test", "--exclude-stickied", "--exclude-pinned"]))
+    );
+    assert_eq!(
+        "https://api.pushshift.io/reddit/search/submission?sort_type=created_utc&sort=desc&size=250&fields=id,created_utc,domain,url,media_metadata,secure_media,is_self,id,title&is_self=false",
+        build_api_url(&Parameters::from_iter(&["test", "--include-removed"]))
+    );
 }
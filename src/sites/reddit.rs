@@ -21,16 +21,29 @@ Support for reddit downloads.
 
 - `i.redd.it`
 - `v.redd.it`
+- `reddit.com` (galleries and links to other posts)
+- `a.thumbs.redditmedia.com`, `b.thumbs.redditmedia.com` (thumbnails)
 */
 
-use std::path::Path;
+use std::{
+    path::{Path, PathBuf},
+    process::{self, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use http::Uri;
-use std::process::Stdio;
 use tokio::{fs, process::Command};
 
+use serde_json::Value;
+
+use crate::mp4;
 use crate::prelude::*;
-use crate::sites::pushshift::{Gallery, SecureMedia};
+use crate::sites::{
+    normalize_domain,
+    oauth::USER_AGENT,
+    pushshift::{Gallery, GalleryItem, RedditVideo, SecureMedia},
+    GalleryOutput,
+};
 use std::io::ErrorKind;
 
 /// Specifies how videos from `v.redd.it` are downloaded.
@@ -42,6 +55,12 @@ pub enum VRedditMode {
     /// Use ffmpeg to combine the audio and video.
     Ffmpeg,
 
+    /// Combine the audio and video without external programs.
+    Mux,
+
+    /// Only download the audio, into an `.m4a` file.
+    AudioOnly,
+
     /// Use a website to download the video.
     /// The characters `{}` are replaced by the ID.
     Website(String),
@@ -52,30 +71,37 @@ impl<'a> From<&'a str> for VRedditMode {
         match s {
             "no-audio" => VRedditMode::NoAudio,
             "ffmpeg" => VRedditMode::Ffmpeg,
+            "mux" => VRedditMode::Mux,
+            "audio-only" => VRedditMode::AudioOnly,
             other => VRedditMode::Website(other.to_string()),
         }
     }
 }
 
-/// Fetches an image from `i.redd.it`.
-pub async fn fetch_image(client: &Client, url: &Uri, output: &Path) -> Result<()> {
+/// Fetches an image from `i.redd.it` or a thumbnail from `thumbs.redditmedia.com`.
+pub async fn fetch_image(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: &Path,
+) -> Result<DownloadStats> {
     trace!("fetch({:?}, {:?})", url, output);
 
-    download(client, url, output).await
+    download(client, storage, url, output).await
 }
 
 /// Fetches images from a reddit gallery.
 pub async fn fetch_gallery(
     client: &Client,
+    storage: &Storage,
     url: &Uri,
-    output: &Path,
+    output: GalleryOutput<'_>,
     gallery: &Gallery,
-) -> Result<()> {
+) -> Result<DownloadStats> {
     trace!("fetch_gallery({}, {:?})", url, output);
 
-    fs::create_dir_all(output).await?;
-    let mut path = output.to_path_buf();
-    path.push("index"); // later overwritten
+    output.create(storage).await?;
+    let mut stats = DownloadStats::default();
 
     for (name, item) in gallery {
         if item.status == "failed" {
@@ -84,85 +110,346 @@ pub async fn fetch_gallery(
             let r#type = item.e.as_ref().unwrap();
 
             if r#type == "Image" {
-                let id = item.id.as_ref().unwrap();
-                let extension = match item.m.as_ref().unwrap().as_ref() {
-                    "image/jpg" => "jpg",
-                    "image/png" => "png",
-                    "image/webp" => "webp",
-                    _ => "",
-                };
+                let name = gallery_item_name(item).unwrap();
 
-                debug!(
-                    "Saving individual image \"{}.{}\" from gallery",
-                    id, extension
-                );
+                debug!("Saving individual image {:?} from gallery", name);
 
-                let path = path.with_file_name(format!("{}.{}", id, extension));
+                let path = output.item(&name);
 
-                download(
+                // Ignore individual errors
+                if let Ok(image) = download(
                     client,
-                    &format!("https://i.redd.it/{}.{}", id, extension).parse()?,
+                    storage,
+                    &format!("https://i.redd.it/{}", name).parse()?,
                     &path,
                 )
-                .await; // ignore individual errors
+                .await
+                {
+                    output.store(&path).await;
+                    stats += image;
+                };
             } else {
                 warn!("The gallery item type {:?} is not supported", r#type);
             }
         }
     }
 
-    Ok(())
+    Ok(stats)
+}
+
+/// Returns the file name of an image in a reddit gallery, like `abc.jpg`.
+/// Failed items and other types than images have no file.
+pub fn gallery_item_name(item: &GalleryItem) -> Option<String> {
+    if item.status == "failed" || item.e.as_deref() != Some("Image") {
+        return None;
+    };
+
+    let id = item.id.as_ref()?;
+    let extension = match item.m.as_deref()? {
+        "image/jpg" => "jpg",
+        "image/png" => "png",
+        "image/webp" => "webp",
+        _ => "",
+    };
+
+    Some(format!("{}.{}", id, extension))
+}
+
+/// Returns the post ID if the URL is the comments page of a reddit post,
+/// like `https://www.reddit.com/r/<subreddit>/comments/<id>/<title>/`.
+pub fn permalink_id(url: &Uri) -> Option<&str> {
+    match url.host().map(normalize_domain) {
+        Some("reddit.com") | Some("old.reddit.com") | Some("np.reddit.com") => (),
+        _ => return None,
+    };
+
+    let mut segments = url.path().split('/').skip_while(|i| *i != "comments");
+    segments.next()?;
+
+    segments
+        .next()
+        .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Parses the `url` field of a post.
+///
+/// Reddit escapes `&` in some URLs as `&amp;`, which breaks the query string.
+/// Newer `i.redd.it` and `preview.redd.it` images require their `s` signature
+/// in the query, so the download would fail with `403 Forbidden`.
+pub fn post_url(url: &str) -> Result<Uri> {
+    Ok(url.replace("&amp;", "&").parse()?)
+}
+
+/// Returns the URL in the `thumbnail` field of a post.
+///
+/// Posts without a thumbnail have placeholders like `self`,
+/// `default`, `nsfw` or `spoiler` instead of a URL.
+pub fn thumbnail_url(thumbnail: &str) -> Option<Uri> {
+    let url = post_url(thumbnail).ok()?;
+
+    match (url.scheme_str(), url.host()) {
+        (Some("http"), Some(_)) | (Some("https"), Some(_)) => Some(url),
+        _ => None,
+    }
+}
+
+/// Returns the URL of the video variant of a GIF hosted on `i.redd.it`, see `--prefer-mp4`.
+///
+/// Reddit converts uploaded GIFs to much smaller videos,
+/// which are found in `preview.reddit_video_preview` of the post.
+pub fn gif_preview_url(url: &Uri, post: &Value) -> Option<Uri> {
+    if url.host() != Some("i.redd.it") || !url.path().ends_with(".gif") {
+        return None;
+    };
+
+    post["preview"]["reddit_video_preview"]["fallback_url"]
+        .as_str()?
+        .replace("&amp;", "&")
+        .parse()
+        .ok()
+}
+
+/// Returns the external URL wrapped in a reddit media link,
+/// like `https://www.reddit.com/media?url=<encoded URL>`.
+pub fn media_url(url: &Uri) -> Option<Uri> {
+    match url.host().map(normalize_domain) {
+        Some("reddit.com") | Some("old.reddit.com") if url.path() == "/media" => (),
+        _ => return None,
+    };
+
+    let encoded = url
+        .query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("url="))?;
+    let url: Uri = percent_decode(encoded)?.parse().ok()?;
+
+    match (url.scheme_str(), url.host()) {
+        (Some("http"), Some(_)) | (Some("https"), Some(_)) => Some(url),
+        _ => None,
+    }
+}
+
+/// Decodes `%XX` escapes, returning `None` for invalid escapes or UTF-8.
+fn percent_decode(input: &str) -> Option<String> {
+    let mut result = Vec::with_capacity(input.len());
+    let mut bytes = input.bytes();
+
+    while let Some(i) = bytes.next() {
+        if i == b'%' {
+            let high = (bytes.next()? as char).to_digit(16)?;
+            let low = (bytes.next()? as char).to_digit(16)?;
+            result.push((high * 16 + low) as u8);
+        } else {
+            result.push(i);
+        };
+    }
+
+    String::from_utf8(result).ok()
+}
+
+/// Retrieves the names of the subreddits of a public multireddit.
+pub async fn fetch_multireddit(client: &Client, user: &str, name: &str) -> Result<Vec<String>> {
+    trace!("fetch_multireddit({:?}, {:?})", user, name);
+
+    let url = format!(
+        "https://www.reddit.com/user/{}/m/{}/about.json?raw_json=1",
+        user, name
+    );
+    let response = client
+        .request(
+            Builder::new()
+                .method(Method::GET)
+                .uri(&url)
+                .header("Accept", "application/json")
+                .header("User-Agent", USER_AGENT),
+        )
+        .await?;
+    let status = response.status();
+
+    if status.is_success() {
+        debug!("Received {} from {:?}", status, url);
+    } else if status.as_u16() == 403 || status.as_u16() == 404 {
+        return Err(Error::new("Multireddit not found or private"));
+    } else {
+        return Err(Error::new(format!("Unexpected response code {}", status)));
+    };
+
+    let value: Value = to_json(response).await?;
+
+    multireddit_subreddits(&value).ok_or_else(|| {
+        Error::new(format!(
+            "Unexpectedly received invalid JSON\n\n{}",
+            HELP_JSON
+        ))
+    })
+}
+
+/// Extracts the subreddit names from the `about.json` of a multireddit.
+fn multireddit_subreddits(value: &Value) -> Option<Vec<String>> {
+    value["data"]["subreddits"]
+        .as_array()?
+        .iter()
+        .map(|i| i["name"].as_str().map(String::from))
+        .collect()
+}
+
+/// Retrieves a single post from the reddit API.
+/// The returned JSON has the same fields as posts from Pushshift.
+pub async fn fetch_post(client: &Client, id: &str) -> Result<Value> {
+    trace!("fetch_post({:?})", id);
+
+    let url = format!("https://www.reddit.com/by_id/t3_{}.json?raw_json=1", id);
+    let response = client
+        .request(
+            Builder::new()
+                .method(Method::GET)
+                .uri(&url)
+                .header("Accept", "application/json")
+                .header("User-Agent", USER_AGENT),
+        )
+        .await?;
+    let status = response.status();
+
+    if status.is_success() {
+        debug!("Received {} from {:?}", status, url);
+    } else if status.as_u16() == 404 {
+        return Err(Error::new("Post not found"));
+    } else {
+        return Err(Error::new(format!("Unexpected response code {}", status)));
+    };
+
+    let mut value: Value = to_json(response).await?;
+
+    match value["data"]["children"][0]["data"].take() {
+        Value::Null => Err(Error::new("Post not found")),
+        post => Ok(post),
+    }
 }
 
 /// Fetches a video from `v.redd.it`.
 pub async fn fetch_video(
     client: &Client,
+    storage: &Storage,
     url: &Uri,
     output: &Path,
     temp_dir: &Path,
     vreddit_mode: &VRedditMode,
     media: &Option<SecureMedia>,
-) -> Result<()> {
+) -> Result<DownloadStats> {
     let media = &media
         .as_ref()
         .and_then(|media| media.reddit_video.as_ref())
         .ok_or_else(|| Error::new("No downloadable media found"))?;
 
-    let id = &url.path()[1..];
+    let id = video_id(url).ok_or_else(|| Error::new("Malformed URL"))?;
+
+    // The video and audio are only written after downloading both
+    if storage.no_overwrite && output.exists() {
+        debug!("Not overwriting existing file {:?}", output);
+        return Ok(DownloadStats::skipped());
+    };
+
+    match vreddit_mode {
+        VRedditMode::NoAudio => no_audio(client, storage, &media.fallback_url, output).await,
+        VRedditMode::Ffmpeg => ffmpeg(client, storage, id, media.height, output, temp_dir).await,
+        VRedditMode::Mux => mux(client, storage, id, &media.fallback_url, output).await,
+        VRedditMode::AudioOnly => audio_only(client, storage, id, media.has_audio, output).await,
+        VRedditMode::Website(url) => {
+            website(client, storage, &url.replacen("{}", id, 1), output).await
+        }
+    }
+}
+
+/// Returns the URLs of the images of a gallery, see `--list-only`.
+pub fn gallery_urls(gallery: &Gallery) -> Vec<String> {
+    gallery
+        .values()
+        .filter_map(gallery_item_name)
+        .map(|name| format!("https://i.redd.it/{}", name))
+        .collect()
+}
+
+/// Returns the URLs of the streams [`fetch_video()`] downloads, see `--list-only`.
+///
+/// [`fetch_video()`]: fn.fetch_video.html
+pub fn video_urls(id: &str, media: &RedditVideo, vreddit_mode: &VRedditMode) -> Vec<String> {
+    let audio = format!("https://v.redd.it/{}/DASH_audio.mp4", id);
 
     match vreddit_mode {
-        VRedditMode::NoAudio => no_audio(client, &media.fallback_url, output).await,
-        VRedditMode::Ffmpeg => ffmpeg(client, id, media.height, output, temp_dir).await,
-        VRedditMode::Website(url) => website(client, &url.replacen("{}", id, 1), output).await,
+        VRedditMode::NoAudio => vec![media.fallback_url.clone()],
+        VRedditMode::Ffmpeg => vec![
+            format!("https://v.redd.it/{}/DASH_{}", id, media.height),
+            format!("https://v.redd.it/{}/audio", id),
+        ],
+        VRedditMode::Mux => vec![media.fallback_url.clone(), audio],
+        VRedditMode::AudioOnly => vec![audio],
+        VRedditMode::Website(url) => vec![url.replacen("{}", id, 1)],
     }
 }
 
+/// Returns the video ID, which is the first segment of the path.
+/// Some URLs point to a file of the video, like `/<id>/DASH_720.mp4`.
+pub fn video_id(url: &Uri) -> Option<&str> {
+    url.path().split('/').find(|i| !i.is_empty())
+}
+
 /// Downloads the video without audio.
-async fn no_audio(client: &Client, url: &str, output: &Path) -> Result<()> {
+async fn no_audio(
+    client: &Client,
+    storage: &Storage,
+    url: &str,
+    output: &Path,
+) -> Result<DownloadStats> {
     trace!("no_audio({}, {:?})", url, output);
 
-    download(client, &url.parse()?, output).await?;
+    download(client, storage, &url.parse()?, output).await
+}
 
-    Ok(())
+/// Counts the temporary files of this process,
+/// so that concurrent jobs for the same video do not share files.
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns unique paths for the video and audio of a video.
+///
+/// The names contain the process ID and a counter, so neither
+/// other jobs nor other runs using the same directory collide.
+fn temp_paths(temp_dir: &Path, id: &str) -> (PathBuf, PathBuf) {
+    let name = format!(
+        "v_redd_it_{}_{}_{}",
+        id,
+        process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    );
+
+    (
+        temp_dir.with_file_name(format!("{}_video", name)),
+        temp_dir.with_file_name(format!("{}_audio", name)),
+    )
 }
 
 /// Download video and audio, then merge them using `ffmpeg -y -i video -i audio output`.
 async fn ffmpeg(
     client: &Client,
+    storage: &Storage,
     id: &str,
     resolution: u64,
     output: &Path,
     temp_dir: &Path,
-) -> Result<()> {
+) -> Result<DownloadStats> {
     trace!("ffmpeg({:?}, {:?})", id, output);
 
+    if storage.stdout {
+        return Err(Error::new(
+            "Videos merged by `ffmpeg` can not be written to stdout, use `--vreddit-mode mux`",
+        ));
+    };
+
     let video_url = format!("https://v.redd.it/{}/DASH_{}", id, resolution).parse()?;
-    let video_path = temp_dir.with_file_name(format!("v_redd_it_{}_video", id));
     let audio_url = format!("https://v.redd.it/{}/audio", id).parse()?;
-    let audio_path = temp_dir.with_file_name(format!("v_redd_it_{}_audio", id));
+    let (video_path, audio_path) = temp_paths(temp_dir, id);
 
-    let video = download(client, &video_url, &video_path);
-    let audio = download(client, &audio_url, &audio_path);
+    let video = download_temp(client, storage, &video_url, &video_path);
+    let audio = download_temp(client, storage, &audio_url, &audio_path);
 
     let (video, audio) = futures_util::join!(video, audio);
 
@@ -189,6 +476,8 @@ async fn ffmpeg(
 
     debug!("Generating file {:?} with `ffmpeg`", output);
 
+    // The output only appears once `ffmpeg` succeeded
+    let partial = Partial::new(output);
     match Command::new("ffmpeg")
         .arg("-y")
         .arg("-i")
@@ -197,7 +486,7 @@ async fn ffmpeg(
         .arg(&audio_path)
         .arg("-c")
         .arg("copy")
-        .arg(&output)
+        .arg(partial.path())
         .stdin(Stdio::null())
         .stdout(Stdio::null())
         .stderr(Stdio::null())
@@ -225,12 +514,374 @@ async fn ffmpeg(
 
     clear(&video_path, &audio_path).await;
 
-    Ok(())
+    if !partial.persist(storage.no_overwrite).await? {
+        return Ok(DownloadStats::skipped());
+    };
+
+    // The file is written by ffmpeg, so it is hashed afterwards
+    if storage.checksums.is_some() {
+        match fs::read(output).await {
+            Ok(data) => storage.record_checksum(output, &data),
+            Err(e) => warn!("Failed to hash {:?} for the checksum file: {}", output, e),
+        };
+    };
+
+    let mut stats = DownloadStats::default();
+    if let Ok(video) = video {
+        stats += video;
+    };
+    if let Ok(audio) = audio {
+        stats += audio;
+    };
+
+    Ok(stats)
+}
+
+/// Download video and audio, then combine them in memory.
+///
+/// Videos without audio are saved as they are.
+async fn mux(
+    client: &Client,
+    storage: &Storage,
+    id: &str,
+    video_url: &str,
+    output: &Path,
+) -> Result<DownloadStats> {
+    trace!("mux({:?}, {:?})", id, output);
+
+    let video_url = video_url.parse()?;
+    let video = download_to_memory(client, &video_url);
+    let audio = download_audio(client, id);
+
+    let (video, audio) = futures_util::join!(video, audio);
+    let (video, mut stats) = video?;
+
+    let data = match audio {
+        Ok((audio, audio_stats)) => match mp4::merge(&video, &audio) {
+            Ok(data) => {
+                stats += audio_stats;
+                data
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to combine audio and video of {}, saving without audio: {}",
+                    id, e
+                );
+                video
+            }
+        },
+        Err(e) => {
+            debug!("No audio available for {}: {}", id, e);
+            video
+        }
+    };
+
+    debug!("Generating file {:?}", output);
+    if !storage.write(output, &data).await? {
+        return Ok(DownloadStats::skipped());
+    };
+
+    Ok(stats)
+}
+
+/// Downloads the audio of a video into memory.
+/// Older videos use a different file name.
+async fn download_audio(client: &Client, id: &str) -> Result<(Vec<u8>, DownloadStats)> {
+    trace!("download_audio({:?})", id);
+
+    let url = format!("https://v.redd.it/{}/DASH_audio.mp4", id).parse()?;
+    match download_to_memory(client, &url).await {
+        Ok(value) => Ok(value),
+        Err(_) => {
+            let url = format!("https://v.redd.it/{}/audio", id).parse()?;
+            download_to_memory(client, &url).await
+        }
+    }
+}
+
+/// Downloads only the audio of the video.
+///
+/// Videos without audio fail, instead of saving an empty file.
+async fn audio_only(
+    client: &Client,
+    storage: &Storage,
+    id: &str,
+    has_audio: Option<bool>,
+    output: &Path,
+) -> Result<DownloadStats> {
+    trace!("audio_only({:?}, {:?})", id, output);
+
+    if has_audio == Some(false) {
+        return Err(Error::new("The video has no audio"));
+    };
+
+    let url = format!("https://v.redd.it/{}/DASH_audio.mp4", id).parse()?;
+    match download(client, storage, &url, output).await {
+        Ok(stats) => Ok(stats),
+        Err(_) => {
+            let url = format!("https://v.redd.it/{}/audio", id).parse()?;
+            download(client, storage, &url, output)
+                .await
+                .map_err(|e| Error::new(format!("The video has no audio: {}", e)))
+        }
+    }
 }
 
 /// Use the URL to download the video.
-async fn website(client: &Client, url: &str, output: &Path) -> Result<()> {
+async fn website(
+    client: &Client,
+    storage: &Storage,
+    url: &str,
+    output: &Path,
+) -> Result<DownloadStats> {
     trace!("website({:?}, {:?})", url, output);
 
-    download(client, &url.parse()?, output).await
+    download(client, storage, &url.parse()?, output).await
+}
+
+#[test]
+fn reddit_permalink() {
+    let id = |url: &'static str| permalink_id(&Uri::from_static(url)).map(String::from);
+
+    assert_eq!(
+        Some(String::from("abc123")),
+        id("https://www.reddit.com/r/test/comments/abc123/lorem_ipsum/")
+    );
+    assert_eq!(
+        Some(String::from("abc123")),
+        id("https://old.reddit.com/comments/abc123")
+    );
+    assert_eq!(None, id("https://www.reddit.com/r/test/"));
+    assert_eq!(None, id("https://www.reddit.com/gallery/abc123"));
+    assert_eq!(None, id("https://example.com/r/test/comments/abc123/"));
+}
+
+#[test]
+fn reddit_media() {
+    let url = |url: &'static str| media_url(&Uri::from_static(url));
+
+    assert_eq!(
+        Some(Uri::from_static("https://i.imgur.com/abc.jpg")),
+        url("https://www.reddit.com/media?url=https%3A%2F%2Fi.imgur.com%2Fabc.jpg")
+    );
+    assert_eq!(
+        Some(Uri::from_static("https://example.com/a.png?b=c")),
+        url("https://reddit.com/media?x=1&url=https%3A%2F%2Fexample.com%2Fa.png%3Fb%3Dc")
+    );
+    assert_eq!(None, url("https://www.reddit.com/media?url=self"));
+    assert_eq!(None, url("https://www.reddit.com/media?url=%ZZ"));
+    assert_eq!(None, url("https://www.reddit.com/r/test/"));
+    assert_eq!(
+        None,
+        url("https://example.com/media?url=https%3A%2F%2Fi.imgur.com%2Fabc.jpg")
+    );
+}
+
+#[test]
+fn reddit_signed_url() {
+    use crate::sites::{file_extension, gfycat::GfycatType, selfpost::SelftextFormat};
+
+    let url =
+        post_url("https://i.redd.it/lorem.jpg?width=640&amp;crop=smart&amp;s=0a1b2c").unwrap();
+    assert_eq!(Some("width=640&crop=smart&s=0a1b2c"), url.query());
+    assert_eq!(
+        Some(".jpg"),
+        file_extension(
+            &url,
+            GfycatType::Mp4,
+            &VRedditMode::NoAudio,
+            SelftextFormat::Raw,
+            false
+        )
+    );
+
+    // The request is sent to the complete URL
+    let request = Builder::new()
+        .method(Method::GET)
+        .uri(url.clone())
+        .body(())
+        .unwrap();
+    assert_eq!(
+        "/lorem.jpg?width=640&crop=smart&s=0a1b2c",
+        request.uri().path_and_query().unwrap().as_str()
+    );
+    assert_eq!(&url, request.uri());
+}
+
+#[test]
+fn reddit_audio_only() {
+    use crate::sites::{file_extension, gfycat::GfycatType, selfpost::SelftextFormat};
+
+    let mode = VRedditMode::from("audio-only");
+    assert!(matches!(mode, VRedditMode::AudioOnly));
+
+    let video = Uri::from_static("https://v.redd.it/abc123");
+    let vimeo = Uri::from_static("https://vimeo.com/123");
+    let (gfycat_type, selftext_format) = (GfycatType::Mp4, SelftextFormat::Raw);
+    assert_eq!(
+        Some(".m4a"),
+        file_extension(&video, gfycat_type, &mode, selftext_format, false)
+    );
+    assert_eq!(
+        Some(".mp4"),
+        file_extension(
+            &video,
+            gfycat_type,
+            &VRedditMode::Mux,
+            selftext_format,
+            false
+        )
+    );
+    assert_eq!(
+        Some(".mp4"),
+        file_extension(&vimeo, gfycat_type, &mode, selftext_format, false)
+    );
+}
+
+#[tokio::test]
+#[allow(unused_must_use)]
+async fn reddit_signed_url_request() {
+    use std::convert::Infallible;
+
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server, StatusCode,
+    };
+
+    // Answers like `i.redd.it`, which rejects requests without the signature
+    let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|request: Request<Body>| async move {
+            let mut response = Response::new(Body::empty());
+            if request.uri().path_and_query().map(|i| i.as_str())
+                == Some("/lorem.jpg?width=640&crop=smart&s=0a1b2c")
+            {
+                *response.body_mut() = Body::from("Lorem");
+            } else {
+                *response.status_mut() = StatusCode::FORBIDDEN;
+            };
+            Ok::<_, Infallible>(response)
+        }))
+    });
+    let server = Server::try_bind(&([127, 0, 0, 1], 0).into())
+        .unwrap()
+        .serve(make_service);
+    let address = server.local_addr();
+    tokio::spawn(server);
+
+    let mut root = std::env::temp_dir();
+    root.push("redditrip_reddit_signed");
+    std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let output = root.join("lorem.jpg");
+
+    let url = post_url(&format!(
+        "http://{}/lorem.jpg?width=640&amp;crop=smart&amp;s=0a1b2c",
+        address
+    ))
+    .unwrap();
+    let client = Client::with_connector(tls_connector(None, false).unwrap());
+    fetch_image(&client, &Storage::default(), &url, &output)
+        .await
+        .unwrap();
+    assert_eq!("Lorem", std::fs::read_to_string(&output).unwrap());
+
+    let unsigned = format!("http://{}/lorem.jpg?width=640", address)
+        .parse()
+        .unwrap();
+    assert!(
+        fetch_image(&client, &Storage::default(), &unsigned, &output)
+            .await
+            .is_err()
+    );
+}
+
+#[test]
+fn reddit_thumbnail() {
+    assert_eq!(
+        Some(Uri::from_static(
+            "https://b.thumbs.redditmedia.com/lorem.jpg"
+        )),
+        thumbnail_url("https://b.thumbs.redditmedia.com/lorem.jpg")
+    );
+    assert_eq!(None, thumbnail_url("self"));
+    assert_eq!(None, thumbnail_url("default"));
+    assert_eq!(None, thumbnail_url("nsfw"));
+    assert_eq!(None, thumbnail_url(""));
+}
+
+#[test]
+fn reddit_gif_preview() {
+    let post = serde_json::json!({
+        "preview": {
+            "reddit_video_preview": {
+                "fallback_url": "https://v.redd.it/abc123/DASH_480.mp4?source=fallback"
+            }
+        }
+    });
+
+    assert_eq!(
+        Some(Uri::from_static(
+            "https://v.redd.it/abc123/DASH_480.mp4?source=fallback"
+        )),
+        gif_preview_url(&Uri::from_static("https://i.redd.it/lorem.gif"), &post)
+    );
+    assert_eq!(
+        None,
+        gif_preview_url(&Uri::from_static("https://i.redd.it/lorem.jpg"), &post)
+    );
+    assert_eq!(
+        None,
+        gif_preview_url(
+            &Uri::from_static("https://i.redd.it/lorem.gif"),
+            &serde_json::json!({})
+        )
+    );
+}
+
+#[test]
+fn reddit_multireddit() {
+    let value = serde_json::json!({
+        "kind": "LabeledMulti",
+        "data": {
+            "name": "lorem",
+            "subreddits": [{ "name": "pics" }, { "name": "aww" }]
+        }
+    });
+    assert_eq!(
+        Some(vec![String::from("pics"), String::from("aww")]),
+        multireddit_subreddits(&value)
+    );
+    assert_eq!(None, multireddit_subreddits(&serde_json::json!({})));
+}
+
+#[test]
+fn reddit_video_id() {
+    let id = |url: &'static str| video_id(&Uri::from_static(url)).map(String::from);
+
+    assert_eq!(Some(String::from("abc123")), id("https://v.redd.it/abc123"));
+    assert_eq!(
+        Some(String::from("abc123")),
+        id("https://v.redd.it/abc123/")
+    );
+    assert_eq!(
+        Some(String::from("abc123")),
+        id("https://v.redd.it/abc123/DASH_480")
+    );
+    assert_eq!(
+        Some(String::from("abc123")),
+        id("https://v.redd.it/abc123?source=fallback")
+    );
+    assert_eq!(None, id("https://v.redd.it/"));
+}
+
+#[test]
+fn reddit_temp_paths() {
+    let temp_dir = Path::new("/tmp/index");
+    let (video, audio) = temp_paths(temp_dir, "abc123");
+    let (other_video, _) = temp_paths(temp_dir, "abc123");
+
+    assert_eq!(Some(Path::new("/tmp")), video.parent());
+    assert!(video.to_string_lossy().ends_with("_video"));
+    assert!(audio.to_string_lossy().ends_with("_audio"));
+    assert_ne!(video, other_video);
 }
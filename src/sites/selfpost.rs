@@ -0,0 +1,113 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+Formatting of self posts.
+
+The text of self posts is reddit-flavored Markdown.
+*/
+
+use pulldown_cmark::{html, Options, Parser};
+
+/// Specifies the format self posts are saved in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelftextFormat {
+    /// Save the text as it is, in a `.txt` file.
+    Raw,
+
+    /// Save the text as it is, in a `.md` file.
+    Markdown,
+
+    /// Render the text to a standalone HTML file.
+    Html,
+}
+
+impl SelftextFormat {
+    /// Returns the file extension of self posts.
+    pub fn extension(self) -> &'static str {
+        match self {
+            SelftextFormat::Raw => ".txt",
+            SelftextFormat::Markdown => ".md",
+            SelftextFormat::Html => ".html",
+        }
+    }
+}
+
+impl<'a> From<&'a str> for SelftextFormat {
+    fn from(s: &str) -> Self {
+        match s {
+            "raw" => SelftextFormat::Raw,
+            "md" => SelftextFormat::Markdown,
+            "html" => SelftextFormat::Html,
+            _ => unreachable!(), // Guaranteed by clap's `possible_values`
+        }
+    }
+}
+
+/// Converts the text of a self post into the format.
+pub fn format(format: SelftextFormat, title: Option<&str>, text: &str) -> String {
+    match format {
+        SelftextFormat::Raw | SelftextFormat::Markdown => text.to_string(),
+        SelftextFormat::Html => render_html(title.unwrap_or(""), text),
+    }
+}
+
+/// Renders the Markdown to a HTML document with the title as heading.
+fn render_html(title: &str, text: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+
+    let mut body = String::with_capacity(text.len() * 3 / 2);
+    html::push_html(&mut body, Parser::new_ext(text, options));
+
+    let title = escape(title);
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n<body>\n<h1>{0}</h1>\n{1}</body>\n</html>\n",
+        title, body
+    )
+}
+
+/// Escapes the characters with a special meaning in HTML.
+pub fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+
+    for i in text.chars() {
+        match i {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            other => result.push(other),
+        };
+    }
+
+    result
+}
+
+#[test]
+fn selfpost_format() {
+    assert_eq!(
+        "**Lorem**",
+        format(SelftextFormat::Markdown, Some("Title"), "**Lorem**")
+    );
+
+    let html = format(SelftextFormat::Html, Some("Lorem & <ipsum>"), "**dolor**");
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<title>Lorem &amp; &lt;ipsum&gt;</title>"));
+    assert!(html.contains("<h1>Lorem &amp; &lt;ipsum&gt;</h1>"));
+    assert!(html.contains("<p><strong>dolor</strong></p>"));
+}
@@ -0,0 +1,147 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+Support for [Vimeo](https://vimeo.com/) downloads.
+
+# Domains
+
+- `vimeo.com`
+- `player.vimeo.com`
+*/
+
+use std::path::Path;
+
+use http::Uri;
+use serde::Deserialize;
+
+use crate::prelude::*;
+
+/// The player configuration of a video.
+#[derive(Deserialize, Debug)]
+struct Config {
+    request: Request,
+}
+
+/// The part of the configuration containing the files.
+#[derive(Deserialize, Debug)]
+struct Request {
+    files: Files,
+}
+
+/// The available files of a video.
+#[derive(Deserialize, Debug)]
+struct Files {
+    #[serde(default)]
+    progressive: Vec<Progressive>,
+}
+
+/// A MP4 file of a video.
+#[derive(Deserialize, Debug, PartialEq)]
+struct Progressive {
+    url: String,
+    height: u64,
+}
+
+/// Fetches a video from `vimeo.com` or `player.vimeo.com`.
+///
+/// The video with the highest resolution not exceeding
+/// `max_resolution` is downloaded.
+pub async fn fetch(
+    client: &Client,
+    storage: &Storage,
+    url: &Uri,
+    output: &Path,
+    max_resolution: Option<u64>,
+) -> Result<DownloadStats> {
+    trace!("fetch({:?}, {:?}, {:?})", url, output, max_resolution);
+
+    let id = extract_id(url.path()).ok_or_else(|| Error::new("Malformed URL"))?;
+    let url = format!("https://player.vimeo.com/video/{}/config", id);
+
+    let response = client
+        .request(
+            Builder::new()
+                .method(Method::GET)
+                .uri(&url)
+                .header("Accept", "application/json"),
+        )
+        .await?;
+    let status = response.status();
+
+    if status.is_success() {
+        debug!("Received {} from {:?}", status, url);
+    } else if status.as_u16() == 403 || status.as_u16() == 404 {
+        // Private videos and videos which may not be embedded
+        return Err(Error::new(
+            "Video not downloadable: it is private or may not be embedded",
+        ));
+    } else {
+        return Err(Error::new(format!("Unexpected response code {}", status)));
+    };
+
+    let config: Config = to_json(response).await?;
+    let video = select(&config.request.files.progressive, max_resolution)
+        .ok_or_else(|| Error::new("Video not downloadable: no MP4 file available"))?;
+
+    debug!("Downloading {}p video {}", video.height, id);
+
+    download(client, storage, &video.url.parse()?, output).await
+}
+
+/// Extracts the video ID from the path, which is its last numeric segment.
+/// For example `/123`, `/channels/staffpicks/123` and `/video/123`.
+fn extract_id(path: &str) -> Option<&str> {
+    path.split('/')
+        .filter(|i| !i.is_empty())
+        .next_back()
+        .filter(|id| id.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Selects the file with the highest resolution not exceeding the maximum.
+/// If every file exceeds it, the smallest one is selected.
+fn select(files: &[Progressive], max_resolution: Option<u64>) -> Option<&Progressive> {
+    let max_resolution = max_resolution.unwrap_or(u64::MAX);
+
+    files
+        .iter()
+        .filter(|i| i.height <= max_resolution)
+        .max_by_key(|i| i.height)
+        .or_else(|| files.iter().min_by_key(|i| i.height))
+}
+
+#[test]
+fn vimeo_select() {
+    assert_eq!(Some("123"), extract_id("/123"));
+    assert_eq!(Some("123"), extract_id("/channels/staffpicks/123/"));
+    assert_eq!(Some("123"), extract_id("/video/123"));
+    assert_eq!(None, extract_id("/staffpicks"));
+
+    let files: Vec<Progressive> = serde_json::from_str(
+        r#"[
+            { "url": "https://example.com/360.mp4", "height": 360 },
+            { "url": "https://example.com/1080.mp4", "height": 1080 },
+            { "url": "https://example.com/720.mp4", "height": 720 }
+        ]"#,
+    )
+    .unwrap();
+
+    assert_eq!(1080, select(&files, None).unwrap().height);
+    assert_eq!(720, select(&files, Some(720)).unwrap().height);
+    assert_eq!(720, select(&files, Some(1000)).unwrap().height);
+    assert_eq!(360, select(&files, Some(240)).unwrap().height);
+    assert_eq!(None, select(&[], None));
+}
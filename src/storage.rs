@@ -0,0 +1,371 @@
+/*
+ * Copyright 2020 Draphar
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ * http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*!
+Writing downloads to the output.
+
+The [`Client`] only performs the requests, the [`Storage`] decides how the
+received data is written. This covers `--no-overwrite`, `--stdout`,
+`--checksum-file` and `--dedupe-db`.
+
+[`Client`]: ../net/struct.Client.html
+[`Storage`]: struct.Storage.html
+*/
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use futures_util::stream::StreamExt;
+use hyper::{Body, Response};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncWrite, AsyncWriteExt},
+};
+
+use crate::checksum::{Checksums, Digest, Sha256};
+use crate::dedupe::Dedupe;
+use crate::prelude::*;
+
+/// The destination of a download, either a file or stdout.
+type Writer = Box<dyn AsyncWrite + Unpin + Send>;
+
+/// The policies for writing downloads, shared by all download jobs.
+#[derive(Debug, Default)]
+pub struct Storage {
+    /// Whether existing files are kept instead of being overwritten.
+    /// Checked by every function writing downloads to disk.
+    pub no_overwrite: bool,
+
+    /// Whether downloads are written to stdout instead of files, see `--stdout`.
+    pub stdout: bool,
+
+    /// The file every saved download is recorded in, see `--checksum-file`.
+    pub checksums: Option<Checksums>,
+
+    /// The database identical downloads are linked with, see `--dedupe-db`.
+    pub dedupe: Option<Dedupe>,
+}
+
+impl Storage {
+    /// Creates a file for writing a download to.
+    ///
+    /// If `--no-overwrite` is set and the file already exists, `None` is returned.
+    /// The data is written to a temporary file first, see [`OutputFile`].
+    ///
+    /// With `--stdout`, stdout is written to instead and `output` is ignored.
+    ///
+    /// [`OutputFile`]: struct.OutputFile.html
+    pub async fn create_file(&self, output: &Path) -> Result<Option<OutputFile>> {
+        trace!("create_file({:?})", output);
+
+        if self.stdout {
+            return Ok(Some(OutputFile {
+                writer: Box::new(tokio::io::stdout()),
+                partial: None,
+                no_overwrite: self.no_overwrite,
+            }));
+        };
+
+        if self.no_overwrite && output.exists() {
+            debug!("Not overwriting existing file {:?}", output);
+            return Ok(None);
+        };
+
+        let partial = Partial::new(output);
+        let file = File::create(partial.path()).await?;
+
+        Ok(Some(OutputFile {
+            writer: Box::new(file),
+            partial: Some(partial),
+            no_overwrite: self.no_overwrite,
+        }))
+    }
+
+    /// Writes data which is already in memory, like the text of a self post.
+    /// The output is recorded in the checksum file of `--checksum-file`.
+    ///
+    /// Returns whether the data was written, see [`create_file()`].
+    ///
+    /// [`create_file()`]: #method.create_file
+    pub async fn write(&self, output: &Path, data: &[u8]) -> Result<bool> {
+        trace!("write({:?})", output);
+
+        let mut file = match self.create_file(output).await? {
+            Some(file) => file,
+            None => return Ok(false),
+        };
+        file.write_all(data).await?;
+        if !file.finish().await? {
+            return Ok(false);
+        };
+        self.record_checksum(output, data);
+
+        Ok(true)
+    }
+
+    /// Writes a response to the disk.
+    ///
+    /// If the response has a `Content-Length` header, the number of
+    /// written bytes is compared against it to detect truncated downloads.
+    pub async fn to_disk(&self, response: Response<Body>, output: &Path) -> Result<DownloadStats> {
+        self.write_response(response, output, true).await
+    }
+
+    /// Writes a response to the disk, see [`to_disk()`].
+    ///
+    /// If `record` is set, the file is hashed while it is written
+    /// and recorded in the checksum file of `--checksum-file`.
+    ///
+    /// [`to_disk()`]: #method.to_disk
+    pub async fn write_response(
+        &self,
+        response: Response<Body>,
+        output: &Path,
+        record: bool,
+    ) -> Result<DownloadStats> {
+        trace!("write_response({:?}, {:?}, {:?})", response, output, record);
+
+        let start = Instant::now();
+        let expected = content_length(&response);
+        let mut extension = content_type_extension(&response);
+        let mut file = match self.create_file(output).await? {
+            Some(file) => file,
+            None => return Ok(DownloadStats::skipped()),
+        };
+        let mut body = response.into_body();
+        let mut written = 0;
+        let hashed = self.checksums.is_some() || self.dedupe.is_some();
+        let mut hasher = if hashed && record {
+            Some(Sha256::new())
+        } else {
+            None
+        };
+
+        while let Some(i) = body.next().await {
+            let i = i?;
+            if written == 0 {
+                // The content is more reliable than the header
+                extension = sniff_extension(&i).or(extension);
+            };
+            file.write_all(&i).await?;
+            if let Some(ref mut hasher) = hasher {
+                hasher.input(&i);
+            };
+            written += i.len() as u64;
+        }
+
+        // The partial file is removed when `file` is dropped
+        check_length(written, expected)?;
+
+        if !file.finish().await? {
+            return Ok(DownloadStats::skipped());
+        };
+
+        if let (Some(checksums), Some(hasher)) = (&self.checksums, &hasher) {
+            checksums.record(output, hasher.clone());
+        };
+        if let (Some(dedupe), Some(hasher)) = (&self.dedupe, hasher) {
+            dedupe.deduplicate(output, hasher);
+        };
+
+        Ok(DownloadStats {
+            bytes: written,
+            duration: start.elapsed(),
+            skipped: false,
+            extension,
+        })
+    }
+
+    /// Records data which was written to the output in the checksum file of `--checksum-file`.
+    /// Used for files which are not written by [`to_disk()`].
+    ///
+    /// [`to_disk()`]: #method.to_disk
+    pub fn record_checksum(&self, output: &Path, data: &[u8]) {
+        if let Some(ref checksums) = self.checksums {
+            let mut hasher = Sha256::new();
+            hasher.input(data);
+            checksums.record(output, hasher);
+        };
+    }
+}
+
+/// A download being written, see [`Storage::create_file()`].
+///
+/// The data is written to a partial file next to the output, which only
+/// replaces the output in [`finish()`]. Failed or cancelled downloads thus
+/// never leave a truncated file at the output: the partial file is removed
+/// when this is dropped before finishing.
+///
+/// [`Storage::create_file()`]: struct.Storage.html#method.create_file
+/// [`finish()`]: #method.finish
+pub struct OutputFile {
+    writer: Writer,
+
+    /// The partial file, `None` when writing to stdout.
+    partial: Option<Partial>,
+    no_overwrite: bool,
+}
+
+impl OutputFile {
+    pub async fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        self.writer.write_all(data).await?;
+        Ok(())
+    }
+
+    /// Moves the complete file to the output.
+    ///
+    /// If `--no-overwrite` is set and the output was created by another
+    /// download in the meantime, the data is discarded and `false` is returned.
+    pub async fn finish(self) -> Result<bool> {
+        let OutputFile {
+            mut writer,
+            partial,
+            no_overwrite,
+        } = self;
+
+        writer.flush().await?;
+        // Closes the file before moving it
+        drop(writer);
+
+        match partial {
+            Some(partial) => partial.persist(no_overwrite).await,
+            None => Ok(true),
+        }
+    }
+}
+
+/// A file which is written next to the output until it is complete.
+/// It is removed when dropped unless it was persisted.
+///
+/// Used directly for files written by other programs, like `ffmpeg`.
+#[derive(Debug)]
+pub struct Partial {
+    path: PathBuf,
+    output: PathBuf,
+    persisted: bool,
+}
+
+impl Partial {
+    /// Creates the partial file of an output.
+    ///
+    /// The extension is kept, because `ffmpeg` derives the format from it:
+    /// `video.mp4` is written to `video.part.mp4`.
+    pub fn new(output: &Path) -> Partial {
+        let mut name = output.file_stem().unwrap_or_default().to_os_string();
+        name.push(".part");
+        if let Some(extension) = output.extension() {
+            name.push(".");
+            name.push(extension);
+        };
+
+        Partial {
+            path: output.with_file_name(name),
+            output: output.to_path_buf(),
+            persisted: false,
+        }
+    }
+
+    /// Returns the path the data is written to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the file to the output.
+    ///
+    /// If `no_overwrite` is set and the output already exists,
+    /// the file is removed instead and `false` is returned.
+    pub async fn persist(mut self, no_overwrite: bool) -> Result<bool> {
+        if no_overwrite {
+            // Unlike a rename, a link fails if the output exists
+            match fs::hard_link(&self.path, &self.output).await {
+                Ok(()) => return Ok(true),
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {
+                    debug!("Not overwriting existing file {:?}", self.output);
+                    return Ok(false);
+                }
+                // The file system might not support links
+                Err(_) if self.output.exists() => return Ok(false),
+                Err(_) => (),
+            };
+        };
+
+        fs::rename(&self.path, &self.output).await?;
+        self.persisted = true;
+
+        Ok(true)
+    }
+}
+
+impl Drop for Partial {
+    fn drop(&mut self) {
+        if !self.persisted {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                debug!("Failed to remove partial file {:?}: {}", self.path, e);
+            };
+        };
+    }
+}
+
+#[tokio::test]
+#[allow(unused_must_use)]
+async fn storage_partial() {
+    use http::header::CONTENT_LENGTH;
+
+    let mut root = std::env::temp_dir();
+    root.push("redditrip_storage");
+    std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let output = root.join("abc.jpg");
+    let storage = Storage::default();
+    let truncated = || {
+        Response::builder()
+            .header(CONTENT_LENGTH, "10")
+            .body(Body::from("Lorem"))
+            .unwrap()
+    };
+
+    assert!(storage.to_disk(truncated(), &output).await.is_err());
+    assert!(!output.exists());
+    assert!(!root.join("abc.part.jpg").exists());
+
+    // The existing file is kept if the download fails
+    std::fs::write(&output, "ipsum").unwrap();
+    assert!(storage.to_disk(truncated(), &output).await.is_err());
+    assert_eq!("ipsum", std::fs::read_to_string(&output).unwrap());
+
+    // A cancelled download does not leave the partial file behind
+    let mut file = storage.create_file(&output).await.unwrap().unwrap();
+    file.write_all(b"Lorem").await.unwrap();
+    drop(file);
+    assert_eq!("ipsum", std::fs::read_to_string(&output).unwrap());
+    assert!(!root.join("abc.part.jpg").exists());
+
+    let no_overwrite = Storage {
+        no_overwrite: true,
+        ..Storage::default()
+    };
+    assert!(!no_overwrite.write(&output, b"Lorem").await.unwrap());
+    assert!(storage.write(&output, b"Lorem").await.unwrap());
+    assert_eq!("Lorem", std::fs::read_to_string(&output).unwrap());
+
+    assert_eq!(
+        root.join("abc.part"),
+        Partial::new(&root.join("abc")).path()
+    );
+}
@@ -18,216 +18,2472 @@
 Fetches posts from a subreddit.
 */
 
-use std::{env, fs, io::ErrorKind, path::Path, process};
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    env, fmt,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    ops::AddAssign,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use futures_util::stream::{FuturesUnordered, StreamExt};
 use http::Uri;
-use tokio::io;
+use serde_json::Value;
+use tokio::{
+    io,
+    sync::{Semaphore, SemaphorePermit},
+    time,
+};
 
+use crate::archive;
+use crate::cache::Cache;
+use crate::checksum::Checksums;
+use crate::dedupe::Dedupe;
+use crate::exec;
 use crate::logger::{color_stderr, color_stdout};
+use crate::manifest::{self, Entry, Manifest, Outcome};
+use crate::metrics::{self, Metrics};
+use crate::mirror;
 use crate::prelude::*;
 use crate::sites::{
-    fetch, file_extension,
+    self, fetch, file_extension, is_supported, oauth, precheck_job,
     pushshift::{self, Subreddit},
-    FetchJob,
+    reddit, FetchJob, GalleryOutput, Handler, RESOLVED_EXTENSIONS,
 };
+use crate::title;
+
+const UPDATE_FILE_NAME: &'static str = ".redditrip";
+
+/// The prefix of the line in the update file containing the `--resume-queue` position.
+const RESUME_PREFIX: &str = "before=";
+
+/// The prefix of the lines in the update file containing the complete months, see `--date-index`.
+const COMPLETE_PREFIX: &str = "complete=";
+
+/// The query parameters which only track where a link was shared, see `--skip-duplicate-urls`.
+/// Parameters starting with `utm_` are tracking as well.
+const TRACKING_PARAMETERS: &[&str] = &[
+    "fbclid", "gclid", "igshid", "mc_cid", "mc_eid", "ref", "ref_src", "ref_url",
+];
+
+/// The outcomes of the posts of a subreddit.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Summary {
+    /// The number of saved posts.
+    saved: usize,
+
+    /// The number of posts whose files already existed.
+    skipped: usize,
+
+    /// The number of posts left out by the filters.
+    filtered: usize,
+
+    /// The number of posts which failed to download.
+    failed: usize,
+
+    /// The number of posts from unsupported domains.
+    unsupported: usize,
+
+    /// The number of posts which were only recorded, see `--record-only`.
+    recorded: usize,
+
+    /// The statistics of the saved posts.
+    stats: DownloadStats,
+}
+
+impl AddAssign for Summary {
+    fn add_assign(&mut self, other: Summary) {
+        self.saved += other.saved;
+        self.skipped += other.skipped;
+        self.filtered += other.filtered;
+        self.failed += other.failed;
+        self.unsupported += other.unsupported;
+        self.recorded += other.recorded;
+        self.stats += other.stats;
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} saved ({}), {} skipped, {} filtered, {} failed, {} unsupported",
+            self.saved,
+            format_size(self.stats.bytes as f64),
+            self.skipped,
+            self.filtered,
+            self.failed,
+            self.unsupported
+        )?;
+        if self.recorded > 0 {
+            write!(f, ", {} recorded", self.recorded)?;
+        };
+
+        Ok(())
+    }
+}
+
+/// Specifies when the files are placed directly within the output directory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flatten {
+    /// Create a subdirectory for every subreddit.
+    Never,
+
+    /// Never create a subdirectory, like `--no-parent`.
+    Always,
+
+    /// Only create subdirectories if multiple subreddits are downloaded.
+    Auto,
+}
+
+impl<'a> From<&'a str> for Flatten {
+    fn from(s: &str) -> Self {
+        match s {
+            "never" => Flatten::Never,
+            "always" => Flatten::Always,
+            "auto" => Flatten::Auto,
+            _ => unreachable!(), // Guaranteed by clap's `possible_values`
+        }
+    }
+}
+
+/// Specifies what happens if multiple posts map to the same file name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Collision {
+    /// Overwrite the earlier file.
+    Overwrite,
+
+    /// Keep the earlier file.
+    Skip,
+
+    /// Append a number to the file name.
+    Rename,
+}
+
+impl<'a> From<&'a str> for Collision {
+    fn from(s: &str) -> Self {
+        match s {
+            "overwrite" => Collision::Overwrite,
+            "skip" => Collision::Skip,
+            "rename" => Collision::Rename,
+            _ => unreachable!(), // Guaranteed by clap's `possible_values`
+        }
+    }
+}
+
+/// Specifies whether the posts matching `--flair` are downloaded or skipped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlairFilterMode {
+    /// Only download the matching posts.
+    Include,
+
+    /// Skip the matching posts.
+    Exclude,
+}
+
+impl<'a> From<&'a str> for FlairFilterMode {
+    fn from(s: &str) -> Self {
+        match s {
+            "include" => FlairFilterMode::Include,
+            "exclude" => FlairFilterMode::Exclude,
+            _ => unreachable!(), // Guaranteed by clap's `possible_values`
+        }
+    }
+}
+
+/// The state of a run, shared by the listings which are downloaded at once.
+struct Run<'a> {
+    parameters: &'a Parameters,
+    client: Client,
+    storage: Storage,
+
+    /// The cache of Pushshift listings, see `--cache-dir`.
+    cache: Option<Cache>,
+    session: Option<oauth::Session>,
+
+    /// The Pushshift URL without the listing, see [`pushshift::build_api_url()`].
+    ///
+    /// [`pushshift::build_api_url()`]: ../sites/pushshift/fn.build_api_url.html
+    api_url: String,
+    temp_dir: PathBuf,
+    untouched: bool,
+    resume_queue: bool,
+    flatten: bool,
+
+    /// Whether the downloads are logged with their listing,
+    /// because multiple listings are downloaded at once.
+    attributed: bool,
+    manifest: RefCell<Option<Manifest>>,
+
+    /// The file the data of the posts is appended to, see `--posts-ndjson`.
+    posts_file: Option<RefCell<File>>,
+
+    /// The output files of this run, to detect collisions.
+    claimed: RefCell<HashSet<PathBuf>>,
+
+    /// The counters of the run, which also track `--max-total-size`.
+    metrics: Arc<Metrics>,
+    size_reached: Cell<bool>,
+
+    /// Limits the simultaneous downloads of all listings to `--queue-size`.
+    downloads: Semaphore,
+
+    /// The current limit of `--concurrency-adaptive`.
+    adaptive: Option<AdaptiveLimit>,
+
+    /// The number of listings skipped because their directory could not be created.
+    skipped_listings: Cell<usize>,
+
+    /// Whether a download failed because the disk is full, which stops the run.
+    storage_full: Cell<bool>,
+
+    /// The number of posts by unsupported domain, see `--fail-on-unsupported`.
+    unsupported_domains: RefCell<BTreeMap<String, usize>>,
+
+    /// The normalized URLs of the posts of this run, see `--skip-duplicate-urls`.
+    seen_urls: RefCell<HashSet<String>>,
+}
+
+impl Run<'_> {
+    /// Handles the job output of a listing, see [`evaluate_job()`].
+    ///
+    /// [`evaluate_job()`]: fn.evaluate_job.html
+    fn evaluate(
+        &self,
+        output: (FetchJob<'_>, Result<DownloadStats>),
+        summary: &mut Summary,
+        listing: &str,
+    ) {
+        let (saved, failed, bytes) = (summary.saved, summary.failed, summary.stats.bytes);
+        let listing = if self.attributed { Some(listing) } else { None };
+        let storage_full = matches!(output.1, Err(ref e) if is_storage_full(e));
+        evaluate_job(output, summary, &mut self.manifest.borrow_mut(), listing);
+        if storage_full && !self.storage_full.replace(true) {
+            error!("The disk is full, stopping the downloads");
+        };
+        if summary.saved > saved || summary.failed > failed {
+            self.metrics
+                .add_download(summary.saved > saved, summary.stats.bytes - bytes);
+        };
+    }
+
+    /// Returns the download permit of a finished job, see `--concurrency-adaptive`.
+    ///
+    /// The semaphore can not shrink, so lowering the limit is done by forgetting
+    /// the permits of the next finished jobs instead of returning them.
+    fn release(&self, permit: SemaphorePermit<'_>, succeeded: bool) {
+        let adaptive = match self.adaptive {
+            Some(ref adaptive) => adaptive,
+            None => return,
+        };
+
+        let limit = adaptive.limit.get();
+        let new_limit = adaptive.finish(succeeded);
+        match new_limit.cmp(&limit) {
+            Ordering::Greater => {
+                if adaptive.debt.get() > 0 {
+                    adaptive.debt.set(adaptive.debt.get() - 1);
+                } else {
+                    self.downloads.add_permits(1);
+                };
+                debug!("Raised the simultaneous downloads to {}", new_limit);
+            }
+            Ordering::Less => {
+                adaptive.debt.set(adaptive.debt.get() + limit - new_limit);
+                info!(
+                    "Many downloads failed, lowered the simultaneous downloads to {}",
+                    new_limit
+                );
+            }
+            Ordering::Equal => (),
+        };
+
+        if adaptive.debt.get() > 0 {
+            adaptive.debt.set(adaptive.debt.get() - 1);
+            permit.forget();
+        };
+    }
+}
+
+/// The number of simultaneous downloads with `--concurrency-adaptive`.
+///
+/// The outcomes are evaluated in windows of as many downloads as the limit.
+/// If more than half of a window failed, the limit is halved, and if none
+/// failed, it grows by one, up to `--queue-size`.
+#[derive(Debug)]
+struct AdaptiveLimit {
+    limit: Cell<usize>,
+    max: usize,
+
+    /// The number of permits which are forgotten instead of returned.
+    debt: Cell<usize>,
+
+    /// The finished and failed downloads of the current window.
+    finished: Cell<usize>,
+    failed: Cell<usize>,
+}
+
+impl AdaptiveLimit {
+    fn new(max: usize) -> AdaptiveLimit {
+        AdaptiveLimit {
+            limit: Cell::new(max),
+            max,
+            debt: Cell::new(0),
+            finished: Cell::new(0),
+            failed: Cell::new(0),
+        }
+    }
+
+    /// Records a finished download, returning the new limit.
+    fn finish(&self, succeeded: bool) -> usize {
+        let finished = self.finished.get() + 1;
+        let failed = self.failed.get() + if succeeded { 0 } else { 1 };
+        let limit = self.limit.get();
+
+        if finished < limit {
+            self.finished.set(finished);
+            self.failed.set(failed);
+            return limit;
+        };
+
+        self.finished.set(0);
+        self.failed.set(0);
+        if failed * 2 > finished {
+            self.limit.set((limit / 2).max(1));
+        } else if failed == 0 {
+            self.limit.set((limit + 1).min(self.max));
+        };
+
+        self.limit.get()
+    }
+}
+
+/// How a run ended, which decides the exit code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Finished {
+    /// The number of failed downloads and skipped subreddits.
+    Failed(usize),
+
+    /// A download failed because the disk is full, which stopped the run.
+    StorageFull,
+}
+
+/// Initiates the subreddit download.
+pub async fn rip(mut parameters: Parameters, subreddits: Vec<Subreddit>) -> Result<Finished> {
+    trace!("rip({:?}, {:?})", parameters, subreddits);
+
+    let connector = match tls_connector(parameters.ca_cert.as_deref(), parameters.insecure) {
+        Ok(value) => value,
+        Err(e) => {
+            error!("Failed to set up TLS: {}", e);
+            process::exit(1);
+        }
+    };
+    let mut client =
+        if parameters.pool_idle_per_host.is_some() || parameters.pool_idle_timeout.is_some() {
+            Client::with_pool(
+                parameters.pool_idle_per_host,
+                parameters.pool_idle_timeout.map(Duration::from_secs),
+                connector,
+            )
+        } else {
+            Client::with_connector(connector)
+        };
+    client.max_redirects = parameters.max_redirects;
+    let mut storage = Storage {
+        no_overwrite: parameters.no_overwrite,
+        stdout: parameters.stdout,
+        ..Storage::default()
+    };
+    if let Some(ref path) = parameters.checksum_file {
+        match Checksums::open(path) {
+            Ok(checksums) => storage.checksums = Some(checksums),
+            Err(e) => {
+                error!("Failed to open checksum file {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        };
+    };
+    if let Some(ref path) = parameters.dedupe_db {
+        match Dedupe::open(path) {
+            Ok(dedupe) => storage.dedupe = Some(dedupe),
+            Err(e) => {
+                error!("Failed to open dedupe database {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        };
+    };
+    let cache = match parameters.cache_dir {
+        Some(ref path) => match Cache::open(path, Duration::from_secs(parameters.cache_ttl)) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                error!("Failed to open cache directory {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let session = if subreddits.iter().any(Subreddit::is_private) {
+        Some(
+            oauth::authorize(
+                &client,
+                parameters.client_id.as_ref().unwrap(), // Guaranteed by `main()`
+                parameters.client_secret.as_ref().unwrap(),
+                parameters.refresh_token.as_ref().unwrap(),
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+    let mut temp_dir = parameters.temp_dir.clone().unwrap_or_else(env::temp_dir);
+
+    // The boundaries of `--created-after-id` and `--created-before-id` are fixed for the run
+    if let Some(ref id) = parameters.created_after_id {
+        let time = pushshift::created_utc(&client, id).await?;
+        if parameters.after.is_some() {
+            warn!(
+                "The creation time of post {} replaces the date of '--after'",
+                color_stdout(id)
+            );
+        };
+        info!("Downloading posts after post {}", color_stdout(id));
+        parameters.after = Some(time);
+    };
+    if let Some(ref id) = parameters.created_before_id {
+        let time = pushshift::created_utc(&client, id).await?;
+        if parameters.before.is_some() {
+            warn!(
+                "The creation time of post {} replaces the date of '--before'",
+                color_stdout(id)
+            );
+        };
+        info!("Downloading posts before post {}", color_stdout(id));
+        parameters.before = Some(time);
+    };
+    let api_url = pushshift::build_api_url(&parameters);
+
+    debug!("The initial API url is `{}`", api_url);
+
+    temp_dir.push("index"); // overwritten later by `with_file_name()`
+
+    let mut manifest = match parameters.manifest {
+        Some(ref path) => match Manifest::open(path) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                error!("Failed to open manifest {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    if let Some(ref path) = parameters.reconcile {
+        return reconcile(
+            &client,
+            &storage,
+            &parameters,
+            path,
+            &temp_dir,
+            &mut manifest,
+        )
+        .await;
+    };
+    if let Some(ref path) = parameters.retry_failed {
+        return retry_failed(
+            &client,
+            &storage,
+            &parameters,
+            path,
+            &temp_dir,
+            &mut manifest,
+        )
+        .await;
+    };
+
+    let posts_file = match parameters.posts_ndjson {
+        Some(ref path) => match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(value) => Some(RefCell::new(value)),
+            Err(e) => {
+                error!("Failed to open {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let metrics = Arc::new(Metrics::default());
+    let metrics_server = match parameters.metrics_addr {
+        Some(address) => match metrics::serve(address, metrics.clone()) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                error!("Failed to start the metrics server at {}: {}", address, e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // Multireddits are downloaded as their subreddits, within a common directory
+    let mut listings = Vec::with_capacity(subreddits.len());
+    for subreddit in subreddits {
+        if let Subreddit::Multi { ref user, ref name } = subreddit {
+            let members = match reddit::fetch_multireddit(&client, user, name).await {
+                Ok(value) => value,
+                Err(e) => {
+                    warn!(
+                        "Failed to expand multireddit {}:\n    {}",
+                        color_stderr(&subreddit.to_string()),
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            info!(
+                "Expanded {} to {} subreddits",
+                color_stdout(&subreddit.to_string()),
+                members.len()
+            );
+
+            let parent = PathBuf::from(directory_name(&parameters.aliases, &subreddit));
+            for i in members {
+                let member = Subreddit::Subreddit(i);
+                let directory = if parameters.mirror {
+                    member.to_mirror_path()
+                } else {
+                    parent.join(directory_name(&parameters.aliases, &member))
+                };
+                listings.push((directory, member));
+            }
+        } else if parameters.mirror {
+            listings.push((subreddit.to_mirror_path(), subreddit));
+        } else {
+            let directory = PathBuf::from(directory_name(&parameters.aliases, &subreddit));
+            listings.push((directory, subreddit));
+        };
+    }
+
+    let subreddit_count = listings.len();
+    // Listing the URLs or fields or writing to stdout leaves the local copy untouched
+    let untouched =
+        parameters.list_only || parameters.stdout || !parameters.print_fields.is_empty();
+    let flatten = match parameters.flatten {
+        _ if parameters.mirror => false,
+        Flatten::Never => parameters.no_parent,
+        Flatten::Always => true,
+        Flatten::Auto => subreddit_count == 1,
+    };
+    let run = Run {
+        parameters: &parameters,
+        client,
+        storage,
+        cache,
+        session,
+        api_url,
+        temp_dir,
+        untouched,
+        resume_queue: parameters.resume_queue && !untouched,
+        flatten,
+        attributed: parameters.concurrent_subreddits > 1 && subreddit_count > 1,
+        manifest: RefCell::new(manifest),
+        posts_file,
+        claimed: RefCell::new(HashSet::new()),
+        metrics: metrics.clone(),
+        size_reached: Cell::new(false),
+        downloads: Semaphore::new(parameters.queue_size),
+        adaptive: if parameters.concurrency_adaptive {
+            Some(AdaptiveLimit::new(parameters.queue_size))
+        } else {
+            None
+        },
+        skipped_listings: Cell::new(0),
+        storage_full: Cell::new(false),
+        unsupported_domains: RefCell::new(BTreeMap::new()),
+        seen_urls: RefCell::new(HashSet::new()),
+    };
+
+    let mut grand_total = Summary::default();
+    let run_start = Instant::now();
+    let mut listings = listings.into_iter().enumerate();
+    let mut running = FuturesUnordered::new();
+    // The first error is returned once the running listings finished
+    let mut error = None;
+    loop {
+        while running.len() < parameters.concurrent_subreddits
+            && error.is_none()
+            && !run.size_reached.get()
+            && !run.storage_full.get()
+        {
+            match listings.next() {
+                Some((position, (directory, subreddit))) => {
+                    running.push(rip_listing(&run, position, directory, subreddit))
+                }
+                None => break,
+            };
+        }
+
+        match running.next().await {
+            Some(Ok(summary)) => grand_total += summary,
+            Some(Err(e)) if error.is_none() => error = Some(e),
+            Some(Err(e)) => error!("{}", e),
+            None => break,
+        };
+    }
+
+    if let Some(e) = error {
+        return Err(e);
+    };
+
+    if subreddit_count > 1 {
+        info!(
+            "Finished all {} in {}: {}",
+            subreddit_count,
+            format_duration(run_start.elapsed()),
+            grand_total
+        );
+    };
+
+    let skipped_listings = run.skipped_listings.get();
+    if skipped_listings > 0 {
+        warn!(
+            "Skipped {} of {} subreddits whose directory could not be created",
+            skipped_listings, subreddit_count
+        );
+    };
+
+    if let Some(server) = metrics_server {
+        server.stop().await;
+    };
+
+    if run.storage_full.get() {
+        return Ok(stopped_storage_full());
+    };
+
+    // Skipped subreddits count as failures for the exit code
+    let mut failed = grand_total.failed + skipped_listings;
+
+    let unsupported_domains = run.unsupported_domains.borrow();
+    if parameters.fail_on_unsupported && !unsupported_domains.is_empty() {
+        warn!(
+            "Found {} posts from {} unsupported domains:\n{}",
+            grand_total.unsupported,
+            unsupported_domains.len(),
+            format_unsupported(&unsupported_domains)
+        );
+        failed += grand_total.unsupported;
+    };
+
+    Ok(Finished::Failed(failed))
+}
+
+/// Downloads a single listing, returning its summary.
+async fn rip_listing(
+    run: &Run<'_>,
+    position: usize,
+    directory: PathBuf,
+    subreddit: Subreddit,
+) -> Result<Summary> {
+    trace!("rip_listing({:?}, {:?})", directory, subreddit);
+
+    let parameters = run.parameters;
+    let client = &run.client;
+    let storage = &run.storage;
+    let session = &run.session;
+    let api_url = &run.api_url;
+    let temp_dir = &run.temp_dir;
+    let untouched = run.untouched;
+    let resume_queue = run.resume_queue;
+    let flatten = run.flatten;
+    let mut queue = FuturesUnordered::new();
+
+    if let (Some(interval), true) = (parameters.interval, position > 0) {
+        debug!("Waiting {} seconds before the next subreddit", interval);
+        time::delay_for(Duration::from_secs(interval)).await;
+    };
+
+    let subreddit_name = subreddit.to_string();
+    let mut before = parameters.before;
+    let mut after = None;
+    // A single post has no update file, it does not belong to a listing
+    let mut updated = untouched || subreddit.is_post();
+    let resumable = resume_queue && !subreddit.is_private() && !subreddit.is_post();
+    let mut completed = true;
+    // The year and month directories of the posts, see `--mirror`
+    let mirrored = parameters.mirror && !untouched && !subreddit.is_post();
+    let grouped = parameters.group_by_domain && !untouched;
+    // The directories created within the subreddit directory
+    let mut directories = HashSet::new();
+    let mut index = 0;
+    let mut summary = Summary::default();
+    let start = Instant::now();
+    let api_url = format!(
+        "{}{}",
+        api_url,
+        match &subreddit {
+            Subreddit::Subreddit(name) => format!("&subreddit={}", name),
+            Subreddit::Profile(name) => format!("&author={}", name),
+            Subreddit::Saved | Subreddit::Upvoted | Subreddit::Post(_) => String::new(),
+            Subreddit::Multi { .. } => unreachable!(), // Expanded above
+        }
+    );
+
+    let mut output = parameters.output.to_owned();
+    if !flatten {
+        output.push(directory);
+    };
+    if !untouched {
+        if let Err(e) = fs::create_dir_all(&output) {
+            match e.kind() {
+                ErrorKind::PermissionDenied => error!(
+                    "Permission denied to create directory {}, check the permissions or choose a different '--output'",
+                    output.display()
+                ),
+                ErrorKind::AlreadyExists => error!(
+                    "Failed to create directory {}, a file with the same name exists",
+                    output.display()
+                ),
+                _ => error!("Failed to create directory {}: {}", output.display(), e),
+            };
+            if parameters.fail_fast {
+                process::exit(1);
+            };
+
+            warn!("Skipping {}", color_stderr(&subreddit_name));
+            run.skipped_listings.set(run.skipped_listings.get() + 1);
+            return Ok(Summary::default());
+        };
+    };
+
+    output.push("index"); // overwritten later by `with_file_name()`
+
+    let update_file = match read_update_file(&output) {
+        Ok(value) => Some(value),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => {
+            warn!(
+                "Failed to open the update file `.redditrip`, even though it is present: {}",
+                e
+            );
+            None
+        }
+    };
+    // The ID of the newest file in the directory
+    let newest_id = update_file.as_ref().map(|file| file.id.clone());
+    let resume_cursor = update_file.as_ref().and_then(|file| file.cursor);
+
+    // The content of the update file, the months are kept across all writes
+    let mut update = UpdateFile {
+        complete: update_file.map(|file| file.complete).unwrap_or_default(),
+        ..UpdateFile::default()
+    };
+    // The ID written to the update file, kept when saving the cursor
+    let mut update_id = None;
+    // The cursor of the previous page, whose jobs may still be running
+    let mut cursor = None;
+
+    if resumable {
+        if let (Some(id), Some(value)) = (newest_id.as_ref(), resume_cursor) {
+            info!(
+                "Resuming {} from an interrupted download",
+                color_stdout(&subreddit_name)
+            );
+            before = Some(before.map_or(value, |before| before.min(value)));
+            update_id = Some(id.to_owned());
+            updated = true;
+        };
+    };
+
+    // The listing is walked without gaps from this time on, see `--date-index`
+    let date_index =
+        parameters.date_index && !untouched && !subreddit.is_private() && !subreddit.is_post();
+    let walked_from = before.unwrap_or_else(unix_time);
+
+    // The numbers are as wide as the number of posts, so the files sort in post order
+    let numbered = parameters.number
+        || parameters.title.utilizes("index")
+        || matches!(parameters.exec, Some(ref exec) if exec.utilizes("index"));
+    let count = match &subreddit {
+        Subreddit::Post(_) => Some(1),
+        _ if !numbered || subreddit.is_private() => None,
+        _ => match pushshift::count(client, &api_url, before).await {
+            Ok(count) => Some(count),
+            Err(e) => {
+                debug!("Failed to count the posts of {}: {}", subreddit_name, e);
+                None
+            }
+        },
+    };
+    let index_width = title::index_width(count, parameters.zero_pad);
+
+    info!(
+        "Started ripping {} to {}",
+        color_stdout(&subreddit_name),
+        color_stdout(&output.parent().unwrap().display())
+    );
+
+    'chunks: loop {
+        if resumable {
+            if let (Some(id), Some(value)) = (update_id.as_ref(), cursor) {
+                update.id = id.to_owned();
+                update.cursor = Some(value);
+                if let Err(e) = create_update_file(&output, &update).await {
+                    warn!(
+                        "Failed to save the position in update file `{}`: {}",
+                        UPDATE_FILE_NAME, e
+                    );
+                };
+            };
+            cursor = before;
+        };
+
+        if let (true, Some(value)) = (date_index, before) {
+            let skipped = skip_complete_months(&update.complete, value);
+            if skipped < value {
+                debug!(
+                    "Skipping the complete months of {} from {} back to {}",
+                    subreddit_name,
+                    format_month(month(value - 1)),
+                    format_month(month(skipped))
+                );
+                before = Some(skipped);
+            };
+        };
+
+        let data = match (&subreddit, &session) {
+            (Subreddit::Post(id), _) => match reddit::fetch_post(client, id).await {
+                Ok(post) => vec![post],
+                Err(e) => {
+                    warn!(
+                        "Failed to retrieve post {}:\n    Error: {}",
+                        color_stderr(&id),
+                        e
+                    );
+                    summary.failed += 1;
+                    break;
+                }
+            },
+            (_, Some(session)) if subreddit.is_private() => {
+                oauth::api(client, session, &subreddit, &mut after).await?
+            }
+            _ => pushshift::api(client, run.cache.as_ref(), &api_url, &mut before).await?,
+        };
+
+        if data.is_empty() {
+            break;
+        };
+
+        debug!("Read {} posts from {}", data.len(), subreddit_name);
+        run.metrics.add_listed(data.len() as u64);
+
+        for mut i in data {
+            // Posts from reddit are not filtered by Pushshift
+            if (subreddit.is_private() || subreddit.is_post()) && !oauth::filter(&i, parameters) {
+                summary.filtered += 1;
+                continue;
+            };
+
+            // Posts without the flag are treated as SFW
+            let nsfw = i["over_18"].as_bool().unwrap_or(false);
+            if (parameters.only_nsfw && !nsfw) || (parameters.no_nsfw && nsfw) {
+                summary.filtered += 1;
+                continue;
+            };
+
+            // Posts without the flags are treated as neither stickied nor pinned
+            if (parameters.exclude_stickied && i["stickied"].as_bool().unwrap_or(false))
+                || (parameters.exclude_pinned && i["pinned"].as_bool().unwrap_or(false))
+            {
+                summary.filtered += 1;
+                continue;
+            };
+
+            // Removed posts mostly link to deleted media
+            if !parameters.include_removed && !i["removed_by_category"].is_null() {
+                debug!(
+                    "Skipping removed post {}",
+                    i["id"].as_str().unwrap_or_default()
+                );
+                summary.filtered += 1;
+                continue;
+            };
+
+            // Posts without the field are kept
+            if let (Some(min), Some(comments)) =
+                (parameters.min_comments, i["num_comments"].as_u64())
+            {
+                if comments < min {
+                    summary.filtered += 1;
+                    continue;
+                };
+            };
+
+            if !flair_allowed(parameters, i["link_flair_text"].as_str()) {
+                summary.filtered += 1;
+                continue;
+            };
+
+            if !domain_allowed(parameters, i["domain"].as_str().unwrap_or("")) {
+                summary.filtered += 1;
+                continue;
+            };
+
+            let id = if let Some(id) = i["id"].as_str() {
+                if parameters.update && Some(id) == newest_id.as_ref().map(|s| s.as_str()) {
+                    info!("Post {} already exists", color_stdout(&id));
+                    break 'chunks;
+                };
+
+                if !updated {
+                    update_id = Some(id.to_string());
+                    update.id = id.to_string();
+                    update.cursor = None;
+                    if let Err(e) = create_update_file(&output, &update).await {
+                        warn!("Failed to create update file `{}`: {}\n    Using the '--update' argument will not work", UPDATE_FILE_NAME, e);
+                    } else {
+                        debug!("Created update file `{}`", UPDATE_FILE_NAME);
+                    };
+                    updated = true;
+                };
+
+                id.to_string()
+            } else {
+                warn!("Malformed JSON response");
+                summary.failed += 1;
+                continue;
+            };
+
+            if let Some(ref file) = run.posts_file {
+                if let Err(e) = write_post(file, &i) {
+                    warn!("Failed to write the data of post {}: {}", id, e);
+                };
+            };
+
+            if !parameters.print_fields.is_empty() {
+                println!("{}", title::tsv_row(&parameters.print_fields, &i));
+                continue;
+            };
+
+            let url = if let Some(url) = i["url"].as_str() {
+                match reddit::post_url(url) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        warn!("Invalid URL {}: {}", color_stderr(&url), e);
+                        summary.failed += 1;
+                        continue;
+                    }
+                }
+            } else {
+                warn!("Malformed JSON response");
+                summary.failed += 1;
+                continue;
+            };
+            let is_self = if let Some(value) = i["is_self"].as_bool() {
+                value
+            } else {
+                warn!("Malformed JSON response");
+                summary.failed += 1;
+                continue;
+            };
+
+            // The thumbnail replaces the linked media
+            let (url, is_self) = if parameters.thumbnails_only {
+                match i["thumbnail"].as_str().and_then(reddit::thumbnail_url) {
+                    Some(thumbnail) => (thumbnail, false),
+                    None => {
+                        summary.filtered += 1;
+                        continue;
+                    }
+                }
+            } else {
+                (url, is_self)
+            };
+            // External media can be wrapped in `reddit.com/media?url=...`
+            let (url, wrapped) = match reddit::media_url(&url) {
+                Some(media) if !is_self => (media, true),
+                _ => (url, false),
+            };
+            if wrapped && !domain_allowed(parameters, url.host().unwrap_or_default()) {
+                summary.filtered += 1;
+                continue;
+            };
+            // Reddit hosted GIFs are also available as video
+            let url = if parameters.prefer_mp4 && !is_self {
+                reddit::gif_preview_url(&url, &i).unwrap_or(url)
+            } else {
+                url
+            };
+            let extension = file_extension(
+                &url,
+                parameters.gfycat_type,
+                &parameters.vreddit_mode,
+                parameters.selftext_format,
+                is_self,
+            );
+            let resolve_extension = extension.is_none();
+            let extension = extension.unwrap_or("");
+            // An extension resolved while downloading is appended to the title
+            let extension_length = if resolve_extension {
+                RESOLVED_EXTENSIONS
+                    .iter()
+                    .map(|i| i.len())
+                    .max()
+                    .unwrap_or(0)
+            } else {
+                extension.len()
+            };
+
+            index += 1;
+            let utilizes = |field| {
+                parameters.title.utilizes(field)
+                    || matches!(parameters.exec, Some(ref exec) if exec.utilizes(field))
+            };
+            if utilizes("index") {
+                i["index"] = Value::String(format!("{:01$}", index, index_width));
+            };
+            if utilizes("ext") {
+                i["ext"] = Value::String(extension.trim_start_matches('.').to_string());
+            };
+            if utilizes("host") {
+                i["host"] = Value::String(url.host().unwrap_or_default().to_string());
+            };
+
+            let mut title = if parameters.number {
+                format!("{:01$}-", index, index_width)
+            } else {
+                String::new()
+            };
+            let mut prefix = parameters.filename_prefix.clone().unwrap_or_default();
+            let mut suffix = parameters.filename_suffix.clone().unwrap_or_default();
+            let length = parameters
+                .max_file_name_length
+                .saturating_sub(extension_length + title.len());
+            // The title keeps at least the room for the post ID
+            title::truncate_affixes(&mut prefix, &mut suffix, length, id.len());
+            title.push_str(&prefix);
+            let title_length = length.saturating_sub(prefix.len() + suffix.len());
+            // The ID on the host stays the same for every post linking the media
+            match sites::source_id(&url) {
+                Some(source) if parameters.name_by_source && !is_self => title.push_str(source),
+                _ => title.push_str(&parameters.title.format(&mut i, title_length)),
+            };
+            title.push_str(&suffix);
+            if parameters.lowercase_filenames || parameters.ascii_filenames {
+                let normalized = title::normalize(
+                    &title,
+                    parameters.lowercase_filenames,
+                    parameters.ascii_filenames,
+                    parameters
+                        .max_file_name_length
+                        .saturating_sub(extension_length),
+                );
+                title = if normalized.is_empty() && !title.is_empty() {
+                    id.clone()
+                } else {
+                    normalized
+                };
+            };
+
+            let domain = if parameters.thumbnails_only || wrapped {
+                url.host().unwrap_or_default().to_string()
+            } else {
+                i["domain"].as_str().unwrap_or_default().to_string()
+            };
+
+            // The components are `<year>/<month>/<domain>/<title>`
+            let mut directory = output.parent().unwrap().to_path_buf();
+            if mirrored {
+                let created_utc = i["created_utc"].as_f64().unwrap_or_default() as u64;
+                directory.push(mirror::directory(created_utc));
+            };
+            if grouped && !domain.is_empty() {
+                directory.push(title::clean(&domain));
+            };
+            if mirrored || grouped {
+                if let Err(e) = create_directory(&mut directories, &directory) {
+                    warn!("Failed to create directory {}: {}", directory.display(), e);
+                    summary.failed += 1;
+                    continue;
+                };
+            };
+            let output = directory.join("index");
+
+            if parameters.collision_strategy != Collision::Overwrite {
+                let taken = |title: &str| {
+                    run.claimed
+                        .borrow()
+                        .contains(&output.with_file_name(format!("{}{}", title, extension)))
+                };
+
+                if taken(&title) {
+                    if parameters.collision_strategy == Collision::Skip {
+                        info!(
+                            "Skipping post {}, its file name {} is already used",
+                            color_stdout(&id),
+                            color_stdout(&format!("{}{}", title, extension))
+                        );
+                        summary.skipped += 1;
+                        continue;
+                    };
+
+                    let length = parameters
+                        .max_file_name_length
+                        .saturating_sub(extension_length);
+                    title = (1..)
+                        .map(|number| title::numbered(&title, number, length))
+                        .find(|title| !taken(title))
+                        .unwrap(); // The iterator is infinite
+                };
+                run.claimed
+                    .borrow_mut()
+                    .insert(output.with_file_name(format!("{}{}", title, extension)));
+            };
+
+            title.push_str(extension);
+
+            // The command is created after the download,
+            // since the file extension may only be known then
+            let exec = parameters.exec.as_ref().map(|exec| (exec, exec.values(&i)));
+
+            let post: pushshift::Post = match serde_json::from_value(i) {
+                Ok(value) => value,
+                Err(e) => {
+                    warn!("Malformed JSON response: {}", e);
+                    summary.failed += 1;
+                    continue;
+                }
+            };
+
+            let output = output.with_file_name(title);
+
+            if parameters.list_only {
+                for url in sites::listed_urls(
+                    &domain,
+                    &url,
+                    post.media_metadata.as_ref(),
+                    post.secure_media.as_ref(),
+                    &parameters.vreddit_mode,
+                ) {
+                    println!("{}", url);
+                }
+                continue;
+            };
+
+            if !is_self && parameters.record_only.contains(&domain) {
+                debug!("Recording {:?} without downloading it", url);
+                summary.recorded += 1;
+                record(
+                    &mut run.manifest.borrow_mut(),
+                    &Entry {
+                        id,
+                        domain,
+                        url: url.to_string(),
+                        output,
+                        is_selfpost: false,
+                        outcome: Outcome::Recorded,
+                        hash: None,
+                    },
+                );
+                continue;
+            };
+
+            if !is_self && !parameters.force && !is_supported(&domain) {
+                warn!(
+                    "Failed to retrieve {}:\n    Error: Unsupported domain '{}'",
+                    color_stderr(&url),
+                    domain
+                );
+                summary.unsupported += 1;
+                *run.unsupported_domains
+                    .borrow_mut()
+                    .entry(domain)
+                    .or_insert(0) += 1;
+                continue;
+            };
+
+            if parameters.skip_duplicate_urls
+                && !is_self
+                && !run.seen_urls.borrow_mut().insert(normalize_url(&url))
+            {
+                debug!("Skipping post {}, its URL {:?} was already seen", id, url);
+                summary.skipped += 1;
+                continue;
+            };
+
+            // Archived galleries are saved next to their directory
+            if parameters.skip_existing
+                && (exists(&output)
+                    || parameters.gallery_archive.is_some() && exists(&archive::path(&output))
+                    || resolve_extension && resolved_exists(&output)
+                    || parameters.flatten_galleries
+                        && flattened_exists(&output, &id, post.media_metadata.as_ref()))
+            {
+                debug!(
+                    "Skipping {}, it already exists",
+                    Path::new(output.file_name().unwrap()).display()
+                );
+                summary.skipped += 1;
+                continue;
+            };
+
+            if queue.len() >= parameters.queue_size {
+                // Run one job to completion
+                if let Some(output) = queue.next().await {
+                    run.evaluate(output, &mut summary, &subreddit_name);
+                };
+            };
+
+            if run.storage_full.get() {
+                completed = false;
+                break 'chunks;
+            };
+
+            if let Some(max_total_size) = parameters.max_total_size {
+                if run.metrics.bytes_downloaded() >= max_total_size {
+                    info!(
+                        "Reached the maximum total size of {}, finishing the running downloads",
+                        format_size(max_total_size as f64)
+                    );
+                    run.size_reached.set(true);
+                    completed = false;
+                    break 'chunks;
+                };
+            };
+
+            let job = FetchJob {
+                client,
+                storage,
+                parameters,
+                is_selfpost: is_self,
+                id,
+                domain,
+                url,
+                output,
+                resolve_extension,
+                temp_dir,
+                text: post.selftext,
+                title: post.title,
+                gallery: post.media_metadata,
+                media: post.secure_media,
+            };
+            queue.push(async move {
+                if parameters.precheck {
+                    if let Err(e) = precheck_job(&job).await {
+                        return (job, Err(e));
+                    };
+                };
+
+                // Limits the downloads of all listings together
+                let permit = run.downloads.acquire().await;
+                let (mut job, result) = fetch(job).await;
+
+                // The file extension of the URL can be missing or wrong
+                if let (true, Ok(stats)) = (parameters.rename_on_extension_mismatch, &result) {
+                    let corrected = stats
+                        .extension
+                        .and_then(|extension| corrected_output(&job.output, extension))
+                        .filter(|output| !(parameters.no_overwrite && exists(output)));
+                    if let Some(output) = corrected {
+                        match fs::rename(&job.output, &output) {
+                            Ok(()) => {
+                                debug!(
+                                    "Renamed {:?} to {:?} after its content",
+                                    job.output, output
+                                );
+                                job.output = output;
+                            }
+                            Err(e) => warn!("Failed to rename {}: {}", job.output.display(), e),
+                        };
+                    };
+                };
+
+                match (exec, &result) {
+                    (Some((exec, values)), Ok(stats)) if !stats.skipped => {
+                        exec::run(&exec.command(&values, &job.output)).await
+                    }
+                    _ => (),
+                };
+
+                run.release(permit, result.is_ok());
+                (job, result)
+            });
+
+            // Only the first post is written to stdout
+            if parameters.stdout {
+                break 'chunks;
+            };
+        }
+
+        // Every post of the months above the oldest post of the page was seen
+        if let (true, Some(value)) = (date_index, before) {
+            update.complete.extend(complete_months(value, walked_from));
+        };
+
+        // A single post is a listing with only one page
+        if subreddit.is_post() {
+            break;
+        };
+    }
+
+    // Run the remaining jobs, the ones of a full disk are dropped
+    while !run.storage_full.get() {
+        match queue.next().await {
+            Some(i) => run.evaluate(i, &mut summary, &subreddit_name),
+            None => break,
+        };
+    }
+
+    // The index files list the files of previous runs too, so they are rewritten.
+    // The directories above the subreddit, like `r/`, link to it as well.
+    let changed = if mirrored {
+        mirror::changed_directories(&parameters.output, &directories)
+    } else {
+        Vec::new()
+    };
+    for directory in changed {
+        if let Err(e) = mirror::write_index(&directory) {
+            warn!(
+                "Failed to write the index of {}: {}",
+                directory.display(),
+                e
+            );
+        };
+    }
+
+    // Remove the position, the next run starts at the top again
+    let reset = completed && resume_queue;
+    if let (true, Some(id)) = (
+        reset || date_index,
+        update_id.as_ref().or(newest_id.as_ref()),
+    ) {
+        update.id = id.to_owned();
+        update.cursor = if reset { None } else { cursor };
+        if let Err(e) = create_update_file(&output, &update).await {
+            warn!("Failed to update update file `{}`: {}", UPDATE_FILE_NAME, e);
+        };
+    };
+
+    info!(
+        "Finished ripping {} in {}: {}",
+        color_stdout(&subreddit_name),
+        format_duration(start.elapsed()),
+        summary
+    );
+
+    Ok(summary)
+}
+
+/// Creates a directory for the files of a listing, unless it is in `created`.
+/// The subreddit directory itself is created beforehand.
+fn create_directory(created: &mut HashSet<PathBuf>, directory: &Path) -> std::io::Result<()> {
+    if !created.contains(directory) {
+        fs::create_dir_all(directory)?;
+        created.insert(directory.to_path_buf());
+    };
+
+    Ok(())
+}
+
+/// Handles the job output.
+/// The outcome is counted in `summary` and recorded in the manifest.
+fn evaluate_job(
+    output: (FetchJob<'_>, Result<DownloadStats>),
+    summary: &mut Summary,
+    manifest: &mut Option<Manifest>,
+    listing: Option<&str>,
+) {
+    let (job, result) = output;
+    let source = listing
+        .map(|name| format!(" of {}", name))
+        .unwrap_or_default();
+    let outcome = match result {
+        Ok(stats) if stats.skipped => {
+            info!(
+                "Kept existing {}{}",
+                color_stdout(&Path::new(job.output.file_name().unwrap()).display()),
+                source
+            );
+            summary.skipped += 1;
+            Outcome::Skipped
+        }
+        Ok(stats) => {
+            info!(
+                "Saved {}{} ({})",
+                color_stdout(&Path::new(job.output.file_name().unwrap()).display()),
+                source,
+                stats
+            );
+            summary.saved += 1;
+            summary.stats += stats;
+            Outcome::Saved
+        }
+        Err(e) => {
+            if job.parameters.quiet_errors {
+                debug!("Failed to retrieve {:?}{}: {}", job.url, source, e);
+            } else {
+                warn!(
+                    "Failed to retrieve {}{}:\n    {}",
+                    color_stderr(&job.url),
+                    source,
+                    e
+                );
+            };
+            summary.failed += 1;
+            Outcome::Failed
+        }
+    };
+
+    if manifest.is_some() {
+        let hash = match outcome {
+            Outcome::Failed => None,
+            _ => manifest::hash(&job.output).unwrap_or_else(|e| {
+                debug!("Failed to hash {:?}: {}", job.output, e);
+                None
+            }),
+        };
+
+        record(
+            manifest,
+            &Entry {
+                id: job.id,
+                domain: job.domain,
+                url: job.url.to_string(),
+                output: job.output,
+                is_selfpost: job.is_selfpost,
+                outcome,
+                hash,
+            },
+        );
+    };
+}
 
-const UPDATE_FILE_NAME: &'static str = ".redditrip";
+/// Appends the data of a post as a line to the file of `--posts-ndjson`.
+fn write_post(file: &RefCell<File>, post: &Value) -> Result<()> {
+    let mut line = serde_json::to_vec(post)?;
+    line.push(b'\n');
+    file.borrow_mut().write_all(&line)?;
 
-/// Initiates the subreddit download.
-pub async fn rip(parameters: Parameters, subreddits: Vec<Subreddit>) -> Result<()> {
-    trace!("rip({:?}, {:?})", parameters, subreddits);
+    Ok(())
+}
+
+/// Appends an entry to the manifest, if `--manifest` is given.
+fn record(manifest: &mut Option<Manifest>, entry: &Entry) {
+    if let Some(manifest) = manifest {
+        if let Err(e) = manifest.write(entry) {
+            warn!("Failed to write to the manifest: {}", e);
+        };
+    };
+}
 
-    let client = Client::new();
-    let mut temp_dir = env::temp_dir();
+/// Verifies the local copy against a manifest, see `--reconcile`.
+///
+/// Every saved entry is downloaded again. Missing files are written directly,
+/// existing ones are first downloaded next to the original and only replace
+/// it if either differs from the hash in the manifest.
+async fn reconcile(
+    client: &Client,
+    storage: &Storage,
+    parameters: &Parameters,
+    path: &Path,
+    temp_dir: &Path,
+    manifest: &mut Option<Manifest>,
+) -> Result<Finished> {
+    trace!("reconcile({:?})", path);
+
+    let entries = match manifest::read(path) {
+        Ok(value) => value,
+        Err(e) => {
+            error!("Failed to read manifest {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    };
     let mut queue = FuturesUnordered::new();
-    let api_url = pushshift::build_api_url(&parameters);
+    let mut summary = Summary::default();
+    let mut storage_full = false;
+    let start = Instant::now();
 
-    debug!("The initial API url is `{}`", api_url);
+    info!(
+        "Started reconciling {} entries of {}",
+        entries.len(),
+        color_stdout(&path.display())
+    );
 
-    temp_dir.push("index"); // overwritten later by `with_file_name()`
+    for entry in entries {
+        if storage_full {
+            break;
+        };
 
-    for subreddit in subreddits {
-        let subreddit_name = subreddit.to_string();
-        let mut before = parameters.before;
-        let mut updated = false;
-        let api_url = format!(
-            "{}{}",
-            api_url,
-            match &subreddit {
-                Subreddit::Subreddit(name) => format!("&subreddit={}", name),
-                Subreddit::Profile(name) => format!("&author={}", name),
+        // Failed and recorded posts have no file and the text of self posts is not recorded
+        if entry.outcome == Outcome::Failed
+            || entry.outcome == Outcome::Recorded
+            || entry.is_selfpost
+        {
+            summary.filtered += 1;
+            continue;
+        };
+
+        let url = match entry.url.parse::<Uri>() {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Invalid URL {}: {}", color_stderr(&entry.url), e);
+                summary.failed += 1;
+                continue;
             }
-        );
+        };
 
-        let mut output = parameters.output.to_owned();
-        if !parameters.no_parent {
-            output.push(subreddit.to_path());
+        let output = if !exists(&entry.output) {
+            entry.output.clone()
+        } else if entry.hash.is_some() {
+            reconcile_path(&entry)
+        } else {
+            // Directories can not be compared
+            summary.skipped += 1;
+            continue;
         };
-        if let Err(e) = fs::create_dir_all(&output) {
-            error!("Failed to create directory: {}", e);
+
+        if queue.len() >= parameters.queue_size {
+            // Run one job to completion
+            if let Some((entry, (job, result))) = queue.next().await {
+                check_storage_full(&result, &mut storage_full);
+                evaluate_reconcile(
+                    (entry, (job, result)),
+                    &mut summary,
+                    manifest,
+                    parameters.no_overwrite,
+                );
+            };
+        };
+
+        let job = FetchJob {
+            client,
+            storage,
+            parameters,
+            is_selfpost: false,
+            id: entry.id.clone(),
+            domain: entry.domain.clone(),
+            url,
+            output,
+            resolve_extension: false,
+            temp_dir,
+            text: None,
+            title: None,
+            gallery: None,
+            media: None,
+        };
+        queue.push(async move { (entry, fetch(job).await) });
+    }
+
+    // Run the remaining jobs
+    while let Some(i) = queue.next().await {
+        check_storage_full(&(i.1).1, &mut storage_full);
+        evaluate_reconcile(i, &mut summary, manifest, parameters.no_overwrite);
+    }
+
+    info!(
+        "Finished reconciling {} in {}: {}",
+        color_stdout(&path.display()),
+        format_duration(start.elapsed()),
+        summary
+    );
+
+    if storage_full {
+        return Ok(stopped_storage_full());
+    };
+
+    Ok(Finished::Failed(summary.failed))
+}
+
+/// Downloads the failed entries of a manifest again, see `--retry-failed`.
+///
+/// Entries whose file exists by now, for example from a later run, are skipped.
+async fn retry_failed(
+    client: &Client,
+    storage: &Storage,
+    parameters: &Parameters,
+    path: &Path,
+    temp_dir: &Path,
+    manifest: &mut Option<Manifest>,
+) -> Result<Finished> {
+    trace!("retry_failed({:?})", path);
+
+    let entries = match manifest::read(path) {
+        Ok(value) => value,
+        Err(e) => {
+            error!("Failed to read manifest {}: {}", path.display(), e);
             process::exit(1);
+        }
+    };
+    // Only the latest entry of every output is read, so outputs saved by a later run are left out
+    let entries: Vec<Entry> = entries
+        .into_iter()
+        .filter(|entry| entry.outcome == Outcome::Failed)
+        .collect();
+    let mut queue = FuturesUnordered::new();
+    let mut summary = Summary::default();
+    let mut storage_full = false;
+    let start = Instant::now();
+
+    info!(
+        "Started retrying {} failed entries of {}",
+        entries.len(),
+        color_stdout(&path.display())
+    );
+
+    for entry in entries {
+        if storage_full {
+            break;
         };
 
-        output.push("index"); // overwritten later by `with_file_name()`
+        // The text of self posts is not recorded
+        if entry.is_selfpost {
+            summary.filtered += 1;
+            continue;
+        };
 
-        // The ID of the newest file in the directory
-        let newest_id = match read_update_file(&output) {
-            Ok(value) => Some(value),
-            Err(ref e) if e.kind() == ErrorKind::NotFound => None,
+        if exists(&entry.output) {
+            debug!("Skipping {:?}, it already exists", entry.output);
+            summary.skipped += 1;
+            continue;
+        };
+
+        let url = match entry.url.parse::<Uri>() {
+            Ok(value) => value,
             Err(e) => {
-                warn!(
-                    "Failed to open the update file `.redditrip`, even though it is present: {}",
-                    e
-                );
-                None
+                warn!("Invalid URL {}: {}", color_stderr(&entry.url), e);
+                summary.failed += 1;
+                continue;
             }
         };
 
-        info!(
-            "Started ripping {} to {}",
-            color_stdout(&subreddit_name),
-            color_stdout(&output.parent().unwrap().display())
-        );
+        if needs_listing(&entry.domain, &url) {
+            warn!(
+                "Can not retry {}, it needs the data of the listing",
+                color_stderr(&entry.url)
+            );
+            summary.filtered += 1;
+            continue;
+        };
 
-        'chunks: loop {
-            let data = pushshift::api(&client, &api_url, &mut before).await?;
+        if let Some(parent) = entry.output.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                warn!("Failed to create directory {:?}: {}", parent, e);
+                summary.failed += 1;
+                continue;
+            };
+        };
 
-            if data.is_empty() {
-                break;
+        if queue.len() >= parameters.queue_size {
+            // Run one job to completion
+            if let Some((job, result)) = queue.next().await {
+                check_storage_full(&result, &mut storage_full);
+                evaluate_job((job, result), &mut summary, manifest, None);
             };
+        };
 
-            debug!("Read {} posts from {}", data.len(), subreddit_name);
+        let job = FetchJob {
+            client,
+            storage,
+            parameters,
+            is_selfpost: false,
+            id: entry.id,
+            domain: entry.domain,
+            resolve_extension: entry.output.extension().is_none(),
+            url,
+            output: entry.output,
+            temp_dir,
+            text: None,
+            title: None,
+            gallery: None,
+            media: None,
+        };
+        queue.push(fetch(job));
+    }
 
-            for mut i in data {
-                if let Some(id) = i["id"].as_str() {
-                    if parameters.update && Some(id) == newest_id.as_ref().map(|s| s.as_str()) {
-                        info!("Post {} already exists", color_stdout(&id));
-                        break 'chunks;
-                    };
+    // Run the remaining jobs
+    while let Some(i) = queue.next().await {
+        check_storage_full(&i.1, &mut storage_full);
+        evaluate_job(i, &mut summary, manifest, None);
+    }
 
-                    if !updated {
-                        if let Err(e) = create_update_file(&output, id).await {
-                            warn!("Failed to create update file `{}`: {}\n    Using the '--update' argument will not work", UPDATE_FILE_NAME, e);
-                        } else {
-                            debug!("Created update file `{}`", UPDATE_FILE_NAME);
-                        };
-                        updated = true;
-                    };
-                } else {
-                    warn!("Malformed JSON response");
-                    continue;
-                };
+    info!(
+        "Finished retrying {} in {}: {}",
+        color_stdout(&path.display()),
+        format_duration(start.elapsed()),
+        summary
+    );
 
-                let url = if let Some(url) = i["url"].as_str() {
-                    match url.parse::<Uri>() {
-                        Ok(value) => value,
-                        Err(e) => {
-                            warn!("Invalid URL {}: {}", color_stderr(&url), e);
-                            continue;
-                        }
-                    }
-                } else {
-                    warn!("Malformed JSON response");
-                    continue;
-                };
-                let is_self = if let Some(value) = i["is_self"].as_bool() {
-                    value
-                } else {
-                    warn!("Malformed JSON response");
-                    continue;
-                };
-                let extension = file_extension(&url, parameters.gfycat_type, is_self).unwrap_or("");
+    if storage_full {
+        return Ok(stopped_storage_full());
+    };
 
-                let mut title = parameters
-                    .title
-                    .format(&mut i, parameters.max_file_name_length - extension.len());
-                title.push_str(extension);
+    Ok(Finished::Failed(summary.failed))
+}
 
-                let post: pushshift::Post = match serde_json::from_value(i) {
-                    Ok(value) => value,
-                    Err(e) => {
-                        warn!("Malformed JSON response: {}", e);
-                        continue;
-                    }
-                };
+/// Stops queueing jobs of `--reconcile` and `--retry-failed` once a download
+/// failed because the disk is full, like the listings of a run.
+fn check_storage_full(result: &Result<DownloadStats>, storage_full: &mut bool) {
+    if !*storage_full && matches!(result, Err(ref e) if is_storage_full(e)) {
+        error!("The disk is full, stopping the downloads");
+        *storage_full = true;
+    };
+}
 
-                if queue.len() == parameters.queue_size {
-                    // Run one job to completion
-                    if let Some(output) = queue.next().await {
-                        evaluate_job(output);
-                    };
-                };
+/// Reports a run stopped by a full disk, see [`Finished::StorageFull`].
+///
+/// [`Finished::StorageFull`]: enum.Finished.html#variant.StorageFull
+fn stopped_storage_full() -> Finished {
+    error!("Stopped the run because the disk is full, free some space and run it again");
+    Finished::StorageFull
+}
 
-                queue.push(fetch(FetchJob {
-                    client: &client,
-                    parameters: &parameters,
-                    is_selfpost: is_self,
-                    domain: post.domain,
-                    url,
-                    output: output.with_file_name(title),
-                    temp_dir: &temp_dir,
-                    text: post.selftext,
-                    gallery: post.media_metadata,
-                    media: post.secure_media,
-                }));
-            }
+/// Returns whether a post can only be downloaded with the data of the listing,
+/// which is not recorded in the manifest, see `--retry-failed`.
+///
+/// Reddit videos need the stream details and galleries need their items.
+/// Links to other reddit posts look the post up themselves.
+fn needs_listing(domain: &str, url: &Uri) -> bool {
+    match sites::handler(sites::normalize_domain(domain)) {
+        Some(Handler::RedditVideo) => true,
+        Some(Handler::Reddit) => reddit::permalink_id(url).is_none(),
+        _ => false,
+    }
+}
+
+/// Returns the path an existing file is downloaded to for the comparison.
+/// It is located next to the file and keeps the file extension.
+fn reconcile_path(entry: &Entry) -> PathBuf {
+    let name = match entry.output.extension() {
+        Some(extension) => format!(".redditrip-{}.{}", entry.id, extension.to_string_lossy()),
+        None => format!(".redditrip-{}", entry.id),
+    };
+
+    entry.output.with_file_name(name)
+}
+
+/// Handles the output of a job started by `reconcile()`.
+fn evaluate_reconcile(
+    output: (Entry, (FetchJob<'_>, Result<DownloadStats>)),
+    summary: &mut Summary,
+    manifest: &mut Option<Manifest>,
+    no_overwrite: bool,
+) {
+    let (mut entry, (job, result)) = output;
+    let name = Path::new(entry.output.file_name().unwrap()).display();
+    let restored = job.output == entry.output;
+
+    let stats = match result {
+        Ok(stats) => stats,
+        Err(e) => {
+            if restored {
+                warn!("Failed to restore {}:\n    {}", color_stderr(&job.url), e);
+            } else {
+                warn!(
+                    "Failed to retrieve {}, keeping the local file:\n    {}",
+                    color_stderr(&job.url),
+                    e
+                );
+                let _ = fs::remove_file(&job.output);
+            };
+            summary.failed += 1;
+            return;
         }
+    };
 
-        // Run the remaining jobs
-        while let Some(i) = queue.next().await {
-            evaluate_job(i);
+    let hash = match manifest::hash(&job.output) {
+        Ok(value) => value,
+        Err(e) => {
+            warn!("Failed to hash {}: {}", color_stderr(&name), e);
+            summary.failed += 1;
+            return;
         }
+    };
+
+    if restored {
+        info!("Restored {} ({})", color_stdout(&name), stats);
+        summary.saved += 1;
+        summary.stats += stats;
+    } else if hash == entry.hash && manifest::hash(&entry.output).ok().as_ref() == Some(&hash) {
+        debug!("{} is unchanged", name);
+        let _ = fs::remove_file(&job.output);
+        summary.skipped += 1;
+        entry.outcome = Outcome::Skipped;
+        record(manifest, &entry);
+        return;
+    } else if no_overwrite {
+        info!("Not replacing changed {}", color_stdout(&name));
+        let _ = fs::remove_file(&job.output);
+        summary.skipped += 1;
+        return;
+    } else {
+        if let Err(e) = fs::rename(&job.output, &entry.output) {
+            warn!("Failed to replace {}: {}", color_stderr(&name), e);
+            let _ = fs::remove_file(&job.output);
+            summary.failed += 1;
+            return;
+        };
+        info!("Replaced changed {} ({})", color_stdout(&name), stats);
+        summary.saved += 1;
+        summary.stats += stats;
+    };
+
+    entry.outcome = Outcome::Saved;
+    entry.hash = hash;
+    record(manifest, &entry);
+}
+
+/// Formats a duration with one decimal place.
+fn format_duration(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
+
+/// Lists the unsupported domains with their number of posts, the most frequent first.
+fn format_unsupported(domains: &BTreeMap<String, usize>) -> String {
+    let mut domains: Vec<_> = domains.iter().collect();
+    // Sorting is stable, so domains with the same count stay in alphabetical order
+    domains.sort_by(|a, b| b.1.cmp(a.1));
+
+    domains
+        .iter()
+        .map(|(domain, count)| format!("    {}: {}", domain, count))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Returns the URL with a lowercase host and without tracking parameters,
+/// so reposts of the same link are recognized, see `--skip-duplicate-urls`.
+fn normalize_url(url: &Uri) -> String {
+    let mut result = format!(
+        "{}://{}",
+        url.scheme_str().unwrap_or("https"),
+        url.host().unwrap_or_default().to_lowercase()
+    );
+    if let Some(port) = url.port_u16() {
+        result.push_str(&format!(":{}", port));
+    };
+    result.push_str(url.path());
+
+    let query: Vec<&str> = url
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or_default();
+            !key.is_empty() && !key.starts_with("utm_") && !TRACKING_PARAMETERS.contains(&key)
+        })
+        .collect();
+    if !query.is_empty() {
+        result.push('?');
+        result.push_str(&query.join("&"));
+    };
+
+    result
+}
+
+/// Returns whether posts from the domain should be downloaded.
+///
+/// A domain passes if it is in `--allow` (or `--allow` is not given)
+/// and not in `--exclude`, so `--exclude` wins if a domain is in both.
+/// Pushshift already applies one of the filters on the server side,
+/// but checking both here is cheap and covers every listing.
+fn domain_allowed(parameters: &Parameters, domain: &str) -> bool {
+    let allowed = match parameters.allow {
+        Some(ref allowed_domains) => allowed_domains.iter().any(|i| i == domain),
+        None => true,
+    };
+    let excluded = match parameters.exclude {
+        Some(ref excluded_domains) => excluded_domains.iter().any(|i| i == domain),
+        None => false,
+    };
+
+    allowed && !excluded
+}
+
+/// Returns whether a post with the flair passes `--flair` and `--require-flair`.
+///
+/// Posts without a flair never match, so they are
+/// only downloaded if the flairs are excluded.
+fn flair_allowed(parameters: &Parameters, flair: Option<&str>) -> bool {
+    let flair = flair.map(str::trim).filter(|flair| !flair.is_empty());
+    if parameters.require_flair && flair.is_none() {
+        return false;
+    };
+    if parameters.flair.is_empty() {
+        return true;
+    };
+
+    let matches = match flair {
+        Some(flair) => parameters.flair.iter().any(|i| {
+            if parameters.flair_case_sensitive {
+                i == flair
+            } else {
+                i.to_lowercase() == flair.to_lowercase()
+            }
+        }),
+        None => false,
+    };
+
+    match parameters.flair_filter_mode {
+        FlairFilterMode::Include => matches,
+        FlairFilterMode::Exclude => !matches,
     }
+}
 
-    Ok(())
+/// Returns the output with the file extension of the received media if it
+/// differs from the guessed one, see `--rename-on-extension-mismatch`.
+/// If the output has no media file extension, the extension is appended.
+///
+/// Audio in an MP4 container is saved as `.m4a`, but usually not marked
+/// as such, so `.mp4` media is accepted for `.m4a` files.
+fn corrected_output(output: &Path, extension: &str) -> Option<PathBuf> {
+    let current = output
+        .extension()
+        .and_then(|i| i.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let expected = extension.trim_start_matches('.');
+    if current == expected
+        || (current == "jpeg" && expected == "jpg")
+        || (current == "m4a" && expected == "mp4")
+    {
+        return None;
+    };
+
+    if is_media_file(output) {
+        Some(output.with_extension(expected))
+    } else {
+        let mut output = output.as_os_str().to_owned();
+        output.push(extension);
+        Some(PathBuf::from(output))
+    }
 }
 
-/// Handles the job output.
-fn evaluate_job(output: (FetchJob<'_>, Result<()>)) {
-    let (job, result) = output;
-    match result {
-        Ok(()) => info!(
-            "Saved {}",
-            color_stdout(&Path::new(job.output.file_name().unwrap()).display())
-        ),
-        Err(e) => warn!("Failed to retrieve {}:\n    {}", color_stderr(&job.url), e),
+/// Returns the directory name of a subreddit, which is its `--alias` if one matches.
+/// Subreddit names are compared case-insensitively, like on reddit.
+fn directory_name(aliases: &[(String, String)], subreddit: &Subreddit) -> String {
+    let path = subreddit.to_path();
+
+    aliases
+        .iter()
+        .find(|(input, _)| input.eq_ignore_ascii_case(&path))
+        .map(|(_, name)| name.to_owned())
+        .unwrap_or(path)
+}
+
+/// Returns whether the output of a post already exists.
+/// Directories, as created for galleries, only count if they are not empty.
+fn exists(path: &Path) -> bool {
+    match fs::read_dir(path) {
+        Ok(mut entries) => entries.next().is_some(),
+        Err(_) => path.exists(),
+    }
+}
+
+/// Returns whether the output exists with any extension resolved
+/// while downloading, see [`RESOLVED_EXTENSIONS`].
+///
+/// [`RESOLVED_EXTENSIONS`]: ../sites/constant.RESOLVED_EXTENSIONS.html
+fn resolved_exists(output: &Path) -> bool {
+    RESOLVED_EXTENSIONS.iter().any(|extension| {
+        let mut path = output.as_os_str().to_owned();
+        path.push(extension);
+        Path::new(&path).exists()
+    })
+}
+
+/// Returns whether the items of a flattened gallery exist, see `--flatten-galleries`.
+///
+/// The items of reddit galleries are known from the listing, so all of them
+/// must exist. For albums of other sites, the first item is looked for
+/// with any extension resolved while downloading.
+fn flattened_exists(output: &Path, id: &str, gallery: Option<&pushshift::Gallery>) -> bool {
+    let output = GalleryOutput {
+        path: output,
+        id,
+        flatten: true,
+        archive: None,
     };
+
+    match gallery {
+        Some(gallery) => {
+            let mut items = gallery
+                .values()
+                .filter_map(reddit::gallery_item_name)
+                .peekable();
+            items.peek().is_some() && items.all(|name| output.item(&name).exists())
+        }
+        None => resolved_exists(&output.item("0")),
+    }
 }
 
-/// Returns the most recent post ID from a marker file in the directory.
-fn read_update_file(directory: &Path) -> io::Result<String> {
+/// The content of the update file.
+///
+/// The first line is the ID of the newest post, which is all that older
+/// versions wrote. It is followed by `key=value` lines and comments.
+#[derive(Debug, Default, PartialEq)]
+struct UpdateFile {
+    /// The ID of the newest downloaded post.
+    id: String,
+
+    /// The position to resume an interrupted download from,
+    /// which is the `before` timestamp of the Pushshift listing.
+    cursor: Option<u64>,
+
+    /// The months whose posts were all seen, see `--date-index`.
+    complete: BTreeSet<Month>,
+}
+
+/// A month as year and number of the month, starting at 1.
+type Month = (i32, i32);
+
+/// Reads the update file of the directory.
+fn read_update_file(directory: &Path) -> io::Result<UpdateFile> {
     let file = directory.with_file_name(UPDATE_FILE_NAME);
-    let mut data = fs::read_to_string(&file)?;
-    let line = if let Some(index) = data.find('\n') {
-        data.truncate(index);
-        data
-    } else {
-        data
+    let data = fs::read_to_string(&file)?;
+
+    Ok(parse_update_file(&data))
+}
+
+/// Parses the content of an update file.
+/// Unknown and malformed lines are ignored.
+fn parse_update_file(data: &str) -> UpdateFile {
+    let mut lines = data.lines();
+    let mut update = UpdateFile {
+        id: lines.next().unwrap_or_default().to_string(),
+        ..UpdateFile::default()
     };
 
-    Ok(line)
+    for line in lines {
+        if let Some(value) = line.strip_prefix(RESUME_PREFIX) {
+            update.cursor = value.trim().parse().ok();
+        } else if let Some(value) = line.strip_prefix(COMPLETE_PREFIX) {
+            if let Some(month) = parse_month(value.trim()) {
+                update.complete.insert(month);
+            };
+        };
+    }
+
+    update
 }
 
-/// Creates a new update containing the content.
-async fn create_update_file(directory: &Path, content: &str) -> io::Result<()> {
+/// Writes the update file of the directory.
+async fn create_update_file(directory: &Path, update: &UpdateFile) -> io::Result<()> {
     let file = directory.with_file_name(UPDATE_FILE_NAME);
-    let mut content = content.as_bytes().to_vec();
-    content.extend_from_slice(b"\n# This is a file generated by redditrip to keep track of the already downloaded files.\n# Modify at your own risk!");
+    let mut content = update.id.clone();
+    if let Some(cursor) = update.cursor {
+        content.push_str(&format!("\n{}{}", RESUME_PREFIX, cursor));
+    };
+    for &month in update.complete.iter().rev() {
+        content.push_str(&format!("\n{}{}", COMPLETE_PREFIX, format_month(month)));
+    }
+    content.push_str("\n# This is a file generated by redditrip to keep track of the already downloaded files.\n# Modify at your own risk!");
     tokio::fs::write(&file, content).await
 }
 
+/// Returns the current UNIX timestamp.
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+/// Returns the month of a UNIX timestamp, in UTC.
+fn month(timestamp: u64) -> Month {
+    let time = ::time::at_utc(::time::Timespec::new(timestamp as i64, 0));
+
+    (time.tm_year + 1900, time.tm_mon + 1)
+}
+
+/// Returns the UNIX timestamp at which a month starts.
+fn month_start((year, month): Month) -> u64 {
+    let time = ::time::Tm {
+        tm_year: year - 1900,
+        tm_mon: month - 1,
+        tm_mday: 1,
+        ..::time::empty_tm()
+    };
+
+    time.to_timespec().sec.max(0) as u64
+}
+
+/// Returns the month after a month.
+fn next_month((year, month): Month) -> Month {
+    if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    }
+}
+
+/// Formats a month like `2020-01`.
+fn format_month((year, month): Month) -> String {
+    format!("{:04}-{:02}", year, month)
+}
+
+/// Parses a month formatted by [`format_month()`].
+///
+/// [`format_month()`]: fn.format_month.html
+fn parse_month(input: &str) -> Option<Month> {
+    let mut parts = input.splitn(2, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+
+    if (1..=12).contains(&month) {
+        Some((year, month))
+    } else {
+        None
+    }
+}
+
+/// Returns the months which lie entirely between two timestamps,
+/// excluding the month of `from`.
+fn complete_months(from: u64, to: u64) -> Vec<Month> {
+    let mut months = Vec::new();
+    let mut current = next_month(month(from));
+
+    while month_start(next_month(current)) <= to {
+        months.push(current);
+        current = next_month(current);
+    }
+
+    months
+}
+
+/// Moves the `before` cursor of a listing past the complete months directly below it.
+fn skip_complete_months(complete: &BTreeSet<Month>, mut before: u64) -> u64 {
+    while before > 0 && complete.contains(&month(before - 1)) {
+        before = month_start(month(before - 1));
+    }
+
+    before
+}
+
 #[tokio::test]
 #[allow(unused_must_use)]
 async fn update_file() {
     let mut directory = env::temp_dir();
     directory.push("index");
-    {
-        create_update_file(&directory, "Lorem").await.unwrap();
-        create_update_file(&directory, "ipsum").await.unwrap();
-        create_update_file(&directory, "dolor").await.unwrap();
-    };
-    assert_eq!("dolor", read_update_file(&directory).unwrap());
+    let mut update = UpdateFile::default();
+    for i in &["Lorem", "ipsum", "dolor"] {
+        update.id = i.to_string();
+        create_update_file(&directory, &update).await.unwrap();
+    }
+    assert_eq!(update, read_update_file(&directory).unwrap());
+
+    update.cursor = Some(1577836800);
+    update.complete.extend(vec![(2019, 11), (2019, 12)]);
+    create_update_file(&directory, &update).await.unwrap();
+    assert_eq!(update, read_update_file(&directory).unwrap());
 
     fs::remove_file(directory.with_file_name(UPDATE_FILE_NAME));
+
+    // Files of older versions only contain the ID
+    assert_eq!(
+        UpdateFile {
+            id: String::from("dolor"),
+            ..UpdateFile::default()
+        },
+        parse_update_file("dolor\n# This is a file generated by redditrip")
+    );
+}
+
+#[test]
+fn date_index() {
+    assert_eq!((2020, 1), month(1577836800));
+    assert_eq!((2019, 12), month(1577836799));
+    assert_eq!(1577836800, month_start((2020, 1)));
+    assert_eq!((2020, 1), next_month((2019, 12)));
+    assert_eq!(Some((2020, 1)), parse_month("2020-01"));
+    assert_eq!(None, parse_month("2020-13"));
+    assert_eq!("2020-01", format_month((2020, 1)));
+
+    // From 2019-10-15 to 2020-01-15
+    assert_eq!(
+        vec![(2019, 11), (2019, 12)],
+        complete_months(1571097600, 1579046400)
+    );
+    assert!(complete_months(1577836800, 1579046400).is_empty());
+
+    let complete = vec![(2019, 11), (2019, 12)].into_iter().collect();
+    assert_eq!(1572566400, skip_complete_months(&complete, 1577836800));
+    assert_eq!(1572566400, skip_complete_months(&complete, 1575000000));
+    assert_eq!(1579046400, skip_complete_months(&complete, 1579046400));
+}
+
+#[test]
+fn summary() {
+    let mut summary = Summary {
+        saved: 2,
+        skipped: 1,
+        failed: 1,
+        stats: DownloadStats {
+            bytes: 2048,
+            ..DownloadStats::default()
+        },
+        ..Summary::default()
+    };
+    summary += Summary {
+        saved: 1,
+        filtered: 3,
+        unsupported: 1,
+        ..Summary::default()
+    };
+
+    assert_eq!(
+        "3 saved (2.00 KiB), 1 skipped, 3 filtered, 1 failed, 1 unsupported",
+        summary.to_string()
+    );
+
+    summary.recorded = 2;
+    assert_eq!(
+        "3 saved (2.00 KiB), 1 skipped, 3 filtered, 1 failed, 1 unsupported, 2 recorded",
+        summary.to_string()
+    );
+}
+
+#[test]
+fn unsupported_domains() {
+    let mut domains = BTreeMap::new();
+    domains.insert("b.com".to_string(), 1);
+    domains.insert("c.com".to_string(), 4);
+    domains.insert("a.com".to_string(), 1);
+
+    assert_eq!(
+        "    c.com: 4\n    a.com: 1\n    b.com: 1",
+        format_unsupported(&domains)
+    );
+}
+
+#[test]
+fn domain_filter() {
+    use structopt::StructOpt;
+
+    let parameters = Parameters::from_iter(&["test"]);
+    assert!(domain_allowed(&parameters, "i.redd.it"));
+
+    let parameters = Parameters::from_iter(&["test", "--allow", "i.redd.it"]);
+    assert!(domain_allowed(&parameters, "i.redd.it"));
+    assert!(!domain_allowed(&parameters, "imgur.com"));
+
+    let parameters = Parameters::from_iter(&["test", "--exclude", "i.redd.it"]);
+    assert!(!domain_allowed(&parameters, "i.redd.it"));
+    assert!(domain_allowed(&parameters, "imgur.com"));
+
+    let parameters = Parameters::from_iter(&[
+        "test",
+        "--allow",
+        "i.redd.it",
+        "--allow",
+        "imgur.com",
+        "--exclude",
+        "imgur.com",
+    ]);
+    assert!(domain_allowed(&parameters, "i.redd.it"));
+    assert!(!domain_allowed(&parameters, "imgur.com"));
+    assert!(!domain_allowed(&parameters, "v.redd.it"));
+}
+
+#[test]
+fn flair_filter() {
+    use structopt::StructOpt;
+
+    let parameters = Parameters::from_iter(&["test"]);
+    assert!(flair_allowed(&parameters, None));
+
+    let parameters = Parameters::from_iter(&["test", "--flair", "OC", "--flair", "Art"]);
+    assert!(flair_allowed(&parameters, Some("OC")));
+    assert!(flair_allowed(&parameters, Some("art")));
+    assert!(!flair_allowed(&parameters, Some("Meta")));
+    assert!(!flair_allowed(&parameters, Some("")));
+    assert!(!flair_allowed(&parameters, None));
+
+    let parameters = Parameters::from_iter(&["test", "--flair", "OC", "--flair-case-sensitive"]);
+    assert!(flair_allowed(&parameters, Some("OC")));
+    assert!(!flair_allowed(&parameters, Some("oc")));
+
+    let parameters =
+        Parameters::from_iter(&["test", "--flair", "Meta", "--flair-filter-mode", "exclude"]);
+    assert!(!flair_allowed(&parameters, Some("meta")));
+    assert!(flair_allowed(&parameters, Some("OC")));
+    assert!(flair_allowed(&parameters, None));
+
+    let parameters = Parameters::from_iter(&["test", "--require-flair"]);
+    assert!(flair_allowed(&parameters, Some("OC")));
+    assert!(!flair_allowed(&parameters, Some(" ")));
+    assert!(!flair_allowed(&parameters, None));
+
+    let parameters = Parameters::from_iter(&[
+        "test",
+        "--flair",
+        "Meta",
+        "--flair-filter-mode",
+        "exclude",
+        "--require-flair",
+    ]);
+    assert!(flair_allowed(&parameters, Some("OC")));
+    assert!(!flair_allowed(&parameters, None));
+}
+
+#[test]
+fn extension_mismatch() {
+    // A JPEG without extension
+    assert_eq!(
+        Some(PathBuf::from("pics/abc-Lorem ipsum.jpg")),
+        corrected_output(Path::new("pics/abc-Lorem ipsum"), ".jpg")
+    );
+    // A video labeled as GIF
+    assert_eq!(
+        Some(PathBuf::from("pics/abc-Lorem.mp4")),
+        corrected_output(Path::new("pics/abc-Lorem.gif"), ".mp4")
+    );
+    assert_eq!(
+        Some(PathBuf::from("pics/abc-v1.2.png")),
+        corrected_output(Path::new("pics/abc-v1.2"), ".png")
+    );
+    assert_eq!(None, corrected_output(Path::new("abc.JPEG"), ".jpg"));
+    assert_eq!(None, corrected_output(Path::new("abc.mp4"), ".mp4"));
+    // The DASH audio of `--vreddit-mode audio-only` has no audio brand
+    let extension = sniff_extension(b"\0\0\0\x18ftypdash").unwrap();
+    assert_eq!(None, corrected_output(Path::new("abc.m4a"), extension));
+    assert_eq!(None, corrected_output(Path::new("abc.m4a"), ".m4a"));
+    assert_eq!(
+        Some(PathBuf::from("abc.jpg")),
+        corrected_output(Path::new("abc.m4a"), ".jpg")
+    );
+}
+
+#[test]
+fn subreddit_alias() {
+    let aliases = [(String::from("u_someuser"), String::from("someone"))];
+
+    assert_eq!(
+        "someone",
+        directory_name(&aliases, &Subreddit::Profile(String::from("SomeUser")))
+    );
+    assert_eq!(
+        "someone",
+        directory_name(&aliases, &Subreddit::Subreddit(String::from("u_someuser")))
+    );
+    assert_eq!(
+        "pics",
+        directory_name(&aliases, &Subreddit::Subreddit(String::from("pics")))
+    );
+}
+
+#[test]
+#[allow(unused_must_use)]
+fn existing_output() {
+    let mut directory = env::temp_dir();
+    directory.push("redditrip_existing_output");
+    fs::remove_dir_all(&directory);
+
+    assert!(!exists(&directory));
+    fs::create_dir(&directory).unwrap();
+    assert!(!exists(&directory));
+
+    let file = directory.join("file");
+    fs::write(&file, "Lorem").unwrap();
+    assert!(exists(&file));
+    assert!(exists(&directory));
+
+    // Like `imgur.com/<hash>`, resolved to `<title>.png`
+    let title = directory.join("abc-Lorem ipsum");
+    assert!(!resolved_exists(&title));
+    fs::write(directory.join("abc-Lorem ipsum.png"), "Lorem").unwrap();
+    assert!(resolved_exists(&title));
+
+    // Flattened galleries have no directory
+    let gallery = directory.join("def-Dolor");
+    assert!(!flattened_exists(&gallery, "def", None));
+    fs::write(directory.join("def-Dolor-0.jpg"), "Lorem").unwrap();
+    assert!(flattened_exists(&gallery, "def", None));
+
+    let item = |id: &str, status: &str| pushshift::GalleryItem {
+        e: Some(String::from("Image")),
+        id: Some(String::from(id)),
+        m: Some(String::from("image/png")),
+        status: String::from(status),
+    };
+    let mut items = pushshift::Gallery::new();
+    items.insert(String::from("ghi"), item("ghi", "valid"));
+    items.insert(String::from("jkl"), item("jkl", "valid"));
+    items.insert(String::from("mno"), item("mno", "failed"));
+    let gallery = directory.join("pqr-Sit");
+    fs::write(directory.join("pqr-Sit-ghi.png"), "Lorem").unwrap();
+    assert!(!flattened_exists(&gallery, "pqr", Some(&items)));
+    fs::write(directory.join("pqr-Sit-jkl.png"), "Lorem").unwrap();
+    assert!(flattened_exists(&gallery, "pqr", Some(&items)));
+
+    fs::remove_dir_all(&directory);
+}
+
+#[test]
+fn retry_needs_listing() {
+    let needs = |domain: &str, url: &str| needs_listing(domain, &url.parse().unwrap());
+
+    assert!(needs("v.redd.it", "https://v.redd.it/abc"));
+    assert!(needs("reddit.com", "https://www.reddit.com/gallery/abc"));
+    assert!(!needs(
+        "reddit.com",
+        "https://www.reddit.com/r/pics/comments/abc/lorem/"
+    ));
+    assert!(!needs("i.redd.it", "https://i.redd.it/abc.jpg"));
+    assert!(!needs("imgur.com", "https://imgur.com/abc"));
+}
+
+#[test]
+fn normalize_urls() {
+    let normalize = |url: &str| normalize_url(&url.parse().unwrap());
+
+    assert_eq!(
+        "https://gfycat.com/abc",
+        normalize("https://GfyCat.com/abc?utm_source=reddit&utm_medium=share")
+    );
+    assert_eq!(
+        "https://imgur.com/a/abc?s=1",
+        normalize("https://imgur.com/a/abc?fbclid=xyz&s=1&ref=share")
+    );
+    assert_eq!(
+        "http://example.com:8080/Abc.jpg",
+        normalize("http://EXAMPLE.com:8080/Abc.jpg?")
+    );
+    assert_ne!(
+        normalize("https://youtube.com/watch?v=abc"),
+        normalize("https://youtube.com/watch?v=def")
+    );
+}
+
+#[test]
+fn adaptive_limit() {
+    let adaptive = AdaptiveLimit::new(4);
+
+    // Windows of four downloads
+    assert_eq!(4, adaptive.finish(false));
+    assert_eq!(4, adaptive.finish(false));
+    assert_eq!(4, adaptive.finish(true));
+    assert_eq!(2, adaptive.finish(false));
+
+    // Half of a window failing keeps the limit
+    assert_eq!(2, adaptive.finish(false));
+    assert_eq!(2, adaptive.finish(true));
+    assert_eq!(2, adaptive.finish(false));
+    assert_eq!(1, adaptive.finish(false));
+    assert_eq!(1, adaptive.finish(false));
+
+    // Recovering one step per window without failures
+    assert_eq!(2, adaptive.finish(true));
+    assert_eq!(2, adaptive.finish(true));
+    assert_eq!(3, adaptive.finish(true));
+    assert_eq!(3, adaptive.finish(true));
+    assert_eq!(3, adaptive.finish(true));
+    assert_eq!(4, adaptive.finish(true));
+    for _ in 0..4 {
+        assert_eq!(4, adaptive.finish(true));
+    }
 }
@@ -18,10 +18,14 @@
 Utilities for title formatting.
 */
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use aho_corasick::AhoCorasick;
+use serde::Serialize;
 #[cfg(test)]
 use serde_json::json;
 use serde_json::Value;
+use unicode_normalization::UnicodeNormalization;
 
 /// The available fields.
 pub static FIELDS: &'static [&'static str] = &[
@@ -80,6 +84,35 @@ pub static FIELDS: &'static [&'static str] = &[
     "wls",
 ];
 
+/// Fields which are not part of the post data, but generated while running.
+///
+/// Their values are inserted into the post JSON by the caller
+/// before formatting, and they are never requested from the API.
+pub static SYNTHETIC_FIELDS: &[&str] = &["index", "ext", "host"];
+
+/// Fields which are set for every post, because they are always requested
+/// from the API, in addition to the synthetic fields.
+pub static ALWAYS_PRESENT_FIELDS: &[&str] = &["created_utc", "domain", "id", "is_self", "url"];
+
+/// The escapes of literal braces in formatting strings and their replacements.
+static ESCAPES: [(&str, &str); 2] = [("{{", "{"), ("}}", "}")];
+
+/// The width the `{index}` placeholder is zero-padded to
+/// if the number of posts is unknown, see [`index_width()`].
+///
+/// [`index_width()`]: fn.index_width.html
+pub const INDEX_WIDTH: usize = 3;
+
+/// Returns the width the `{index}` placeholder is zero-padded to.
+///
+/// This is the number of digits of the number of posts, so the
+/// file names sort in post order, but at least the width of `--zero-pad`.
+pub fn index_width(count: Option<u64>, zero_pad: Option<usize>) -> usize {
+    let width = count.map_or(INDEX_WIDTH, |count| count.max(1).to_string().len());
+
+    width.max(zero_pad.unwrap_or(0))
+}
+
 /// A title formatter.
 #[derive(Debug)]
 pub struct Title {
@@ -91,40 +124,130 @@ pub struct Title {
 
     /// An iterator over the placeholders.
     formatter: AhoCorasick,
+
+    /// The width numbers are zero-padded to, see `--zero-pad`.
+    zero_pad: usize,
+
+    /// Whether titles are converted to the Unicode normalization form C.
+    normalize_unicode: bool,
+
+    /// Whether long titles are shortened at a word boundary, see `--truncate-at-word`.
+    truncate_at_word: bool,
 }
 
 impl Title {
+    /// Checks that every placeholder of a formatting string is a known field,
+    /// so a typo is not silently kept as text.
+    /// Literal braces are written as `{{` and `}}`.
+    pub fn validate(haystack: &str) -> Result<(), String> {
+        let mut rest = haystack;
+
+        while let Some(i) = rest.find(&['{', '}'][..]) {
+            let (brace, after) = rest[i..].split_at(1);
+            if after.starts_with(brace) {
+                rest = &after[1..];
+                continue;
+            };
+            if brace == "}" {
+                return Err(String::from(
+                    "Unmatched '}' in the title, write '}}' for a literal brace",
+                ));
+            };
+
+            let end = after.find('}').ok_or_else(|| {
+                String::from("Unmatched '{' in the title, write '{{' for a literal brace")
+            })?;
+            let name = &after[..end];
+            if !FIELDS.contains(&name) && !SYNTHETIC_FIELDS.contains(&name) {
+                return Err(format!(
+                    "Unknown placeholder '{{{}}}' in the title, see '--formatting-fields'",
+                    name
+                ));
+            };
+            rest = &after[end + 1..];
+        }
+
+        Ok(())
+    }
+
+    /// Validates a formatting string and generates a formatter from it.
+    pub fn parse(haystack: &str) -> Result<Title, String> {
+        Title::validate(haystack)?;
+
+        Ok(Title::new(haystack))
+    }
+
     /// Generates a formatter from a formatting string.
+    /// The escapes `{{` and `}}` are formatted as single braces,
+    /// while unknown placeholders are kept as text, see [`validate()`].
+    ///
+    /// [`validate()`]: #method.validate
     pub fn new(haystack: &str) -> Title {
         let haystack = clean(haystack);
         let mut fields = Vec::new();
         let mut fields_placeholders = Vec::new();
-        let patterns = FIELDS.iter().map(|field| format!("{{{}}}", field));
 
-        for (i, pattern) in patterns.enumerate() {
+        for field in FIELDS.iter().chain(SYNTHETIC_FIELDS) {
+            let pattern = format!("{{{}}}", field);
+
             // Using the normal string searcher because constructing
             // an Aho-Corasick for only one search is too expensive
             if haystack.contains(&pattern) {
-                fields.push(FIELDS[i]);
+                fields.push(*field);
                 fields_placeholders.push(pattern);
             };
         }
+        // The escapes come after the fields, see `format()`
+        for (escape, _) in ESCAPES.iter() {
+            fields_placeholders.push(escape.to_string());
+        }
 
         Title {
             haystack,
             fields,
             formatter: AhoCorasick::new_auto_configured(&fields_placeholders),
+            zero_pad: 0,
+            normalize_unicode: true,
+            truncate_at_word: false,
         }
     }
 
+    /// Sets whether titles are converted to the Unicode normalization form C,
+    /// which composes accented characters, see `--no-normalize-unicode`.
+    /// Titles are normalized by default.
+    pub fn set_normalize_unicode(&mut self, normalize: bool) {
+        self.normalize_unicode = normalize;
+    }
+
+    /// Sets whether long titles are shortened at the last whitespace before
+    /// the maximum length instead of in the middle of a word.
+    pub fn set_truncate_at_word(&mut self, truncate_at_word: bool) {
+        self.truncate_at_word = truncate_at_word;
+    }
+
+    /// Zero-pads the values of numeric placeholders to the width,
+    /// so the file names sort in numeric order.
+    pub fn set_zero_pad(&mut self, width: usize) {
+        self.zero_pad = width;
+    }
+
     /// Returns whether the `{id}` placeholder is in the haystack.
     pub fn utilizes_id(&self) -> bool {
         self.fields.contains(&"id")
     }
 
-    /// Returns an iterator over the fields.
+    /// Returns whether the placeholder of the field is in the haystack.
+    pub fn utilizes(&self, field: &str) -> bool {
+        self.fields.contains(&field)
+    }
+
+    /// Returns an iterator over the fields that are part of the post data.
+    /// Synthetic fields are left out.
     pub fn iter(&self) -> impl Iterator<Item = &str> {
-        self.fields.iter().map(|item| *item)
+        self.fields
+            .iter()
+            .filter(|item| !SYNTHETIC_FIELDS.contains(item))
+            .map(|item| *item)
     }
 
     /// Formats a title.
@@ -135,11 +258,20 @@ impl Title {
 
         self.formatter
             .replace_all_with(&self.haystack, &mut buf, |i, _, buf| {
-                let value = &json[self.fields[i.pattern()]];
+                let field = match self.fields.get(i.pattern()) {
+                    Some(field) => field,
+                    None => {
+                        buf.push_str(ESCAPES[i.pattern() - self.fields.len()].1);
+                        return true;
+                    }
+                };
+                let value = &json[*field];
                 let text = if value.is_null() {
                     return true;
                 } else if let Some(value) = value.as_str() {
                     clean(value)
+                } else if let Some(number) = integer(value) {
+                    format!("{:01$}", number, self.zero_pad)
                 } else {
                     clean(&value.to_string())
                 };
@@ -148,16 +280,110 @@ impl Title {
                 true
             });
 
-        // Todo: Deal with character boundaries
-        buf.truncate(length);
+        // Composed and decomposed characters look the same, but are different file names.
+        // Normalizing can change the length, so this happens before truncating.
+        if self.normalize_unicode {
+            buf = buf.nfc().collect();
+        };
+
+        if self.truncate_at_word {
+            truncate_at_word(&mut buf, length);
+        } else {
+            truncate(&mut buf, length);
+        };
+
+        // A title made only of separators is a hidden or confusing file name
+        if buf.chars().all(is_separator) {
+            if let Some(id) = json["id"].as_str() {
+                if !WARNED_EMPTY.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        "The title of post {} is empty, using the post ID as file name instead",
+                        id
+                    );
+                };
+                buf = clean(id);
+                truncate(&mut buf, length);
+            };
+        };
 
         buf
     }
 }
 
+/// Returns the value of a non-negative integer,
+/// which may also be given as a float without fractional part.
+fn integer(value: &Value) -> Option<u64> {
+    value.as_u64().or_else(|| {
+        value
+            .as_f64()
+            .filter(|number| *number >= 0.0 && number.fract() == 0.0)
+            .map(|number| number as u64)
+    })
+}
+
+/// Shortens a string to at most `length` bytes without splitting a character.
+fn truncate(text: &mut String, length: usize) {
+    let mut length = length.min(text.len());
+    while !text.is_char_boundary(length) {
+        length -= 1;
+    }
+    text.truncate(length);
+}
+
+/// Shortens the texts of `--filename-prefix` and `--filename-suffix`,
+/// if they leave less than `reserved` of `length` bytes for the title.
+///
+/// Without this, long affixes would leave no room for the title at all,
+/// and the file name would exceed `length` nevertheless.
+/// The suffix is shortened first, then the prefix.
+pub fn truncate_affixes(prefix: &mut String, suffix: &mut String, length: usize, reserved: usize) {
+    let available = length.saturating_sub(reserved.min(length));
+    if prefix.len() + suffix.len() <= available {
+        return;
+    };
+
+    truncate(suffix, available.saturating_sub(prefix.len()));
+    truncate(prefix, available);
+}
+
+/// Shortens a string to at most `length` bytes at the last whitespace,
+/// falling back to [`truncate()`] if the first word is already too long.
+///
+/// [`truncate()`]: fn.truncate.html
+fn truncate_at_word(text: &mut String, length: usize) {
+    if text.len() <= length {
+        return;
+    };
+    let mut end = length;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    // A word may end exactly at the limit
+    let cut = if text[end..].starts_with(char::is_whitespace) {
+        Some(end)
+    } else {
+        text[..end].rfind(char::is_whitespace)
+    };
+    let cut = cut
+        .map(|cut| text[..cut].trim_end().len())
+        .filter(|cut| *cut > 0)
+        .unwrap_or(end);
+    text.truncate(cut);
+}
+
+/// Whether a title was replaced by the post ID, which is only warned about once.
+static WARNED_EMPTY: AtomicBool = AtomicBool::new(false);
+
+/// Returns whether a character is a separator,
+/// or the replacement of an illegal character.
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || c == '_' || c == '-' || c == '.'
+}
+
 /// Replaces illegal characters in file names with `_`.
 /// This method always writes exactly `title.len()` bytes.
-fn clean(title: &str) -> String {
+pub fn clean(title: &str) -> String {
     let mut result = String::with_capacity(title.len());
 
     for i in title.chars() {
@@ -170,6 +396,104 @@ fn clean(title: &str) -> String {
     result
 }
 
+/// Normalizes a file name, see `--lowercase-filenames` and `--ascii-filenames`.
+///
+/// With `ascii`, accented Latin letters and common punctuation are transliterated
+/// and all other non-ASCII characters are removed. The result is truncated to
+/// `length` bytes, since lowercasing can make a name longer.
+pub fn normalize(name: &str, lowercase: bool, ascii: bool, length: usize) -> String {
+    let mut result = String::with_capacity(name.len());
+
+    for i in name.chars() {
+        if !ascii || i.is_ascii() {
+            result.push(i);
+        } else if i.is_whitespace() {
+            result.push(' ');
+        } else if let Some(text) = transliterate(i) {
+            result.push_str(text);
+        };
+    }
+
+    if lowercase {
+        result = result.to_lowercase();
+    };
+
+    let mut length = length.min(result.len());
+    while !result.is_char_boundary(length) {
+        length -= 1;
+    }
+    result.truncate(length);
+
+    result
+}
+
+/// Appends a number in parentheses to a file name, like `name (1)`.
+/// The name is shortened if the result would be longer than `length` bytes.
+pub fn numbered(name: &str, number: usize, length: usize) -> String {
+    let number = format!(" ({})", number);
+    let mut end = length.saturating_sub(number.len()).min(name.len());
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!("{}{}", &name[..end], number)
+}
+
+/// Returns the ASCII replacement of a character, if there is one.
+fn transliterate(c: char) -> Option<&'static str> {
+    Some(match c {
+        'À'..='Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+        'à'..='å' | 'ā' | 'ă' | 'ą' => "a",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => "C",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'Ð' | 'Ď' | 'Đ' => "D",
+        'ð' | 'ď' | 'đ' => "d",
+        'È'..='Ë' | 'Ē' | 'Ĕ' | 'Ė' | 'Ę' | 'Ě' => "E",
+        'è'..='ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'Ĝ' | 'Ğ' | 'Ġ' | 'Ģ' => "G",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'Ĥ' | 'Ħ' => "H",
+        'ĥ' | 'ħ' => "h",
+        'Ì'..='Ï' | 'Ĩ' | 'Ī' | 'Ĭ' | 'Į' | 'İ' => "I",
+        'ì'..='ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'Ĵ' => "J",
+        'ĵ' => "j",
+        'Ķ' => "K",
+        'ķ' => "k",
+        'Ĺ' | 'Ļ' | 'Ľ' | 'Ŀ' | 'Ł' => "L",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'Ñ' | 'Ń' | 'Ņ' | 'Ň' => "N",
+        'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+        'Ò'..='Ö' | 'Ø' | 'Ō' | 'Ŏ' | 'Ő' => "O",
+        'ò'..='ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'Œ' => "OE",
+        'œ' => "oe",
+        'Ŕ' | 'Ŗ' | 'Ř' => "R",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'Ś' | 'Ŝ' | 'Ş' | 'Š' => "S",
+        'ś' | 'ŝ' | 'ş' | 'š' => "s",
+        'ß' => "ss",
+        'Ţ' | 'Ť' | 'Ŧ' => "T",
+        'ţ' | 'ť' | 'ŧ' => "t",
+        'Þ' => "TH",
+        'þ' => "th",
+        'Ù'..='Ü' | 'Ũ' | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => "U",
+        'ù'..='ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'Ŵ' => "W",
+        'ŵ' => "w",
+        'Ý' | 'Ŷ' | 'Ÿ' => "Y",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        'ź' | 'ż' | 'ž' => "z",
+        '‘' | '’' => "'",
+        '‐'..='—' => "-",
+        '…' => "...",
+        _ => return None,
+    })
+}
+
 /// Returns a list of supported fields and their respective type.
 pub fn formatting_help() -> &'static str {
     "\
@@ -184,8 +508,11 @@ contest_mode: bool
 created_utc: integer
 crosspost_parent: string
 domain: string
+ext: string (the file extension without the dot, which is still appended)
 full_link: string
+host: string (the host of the downloaded URL, unlike 'domain' also for thumbnails)
 id: string
+index: string (the position of the post within the run, zero-padded to the digits of the number of posts)
 is_crosspostable: bool
 is_meta: bool
 is_original_content: bool
@@ -228,6 +555,57 @@ wls: integer
 "
 }
 
+/// A formatting field, as output by `--formatting-fields-json`.
+#[derive(Serialize, Debug, PartialEq)]
+struct FieldInfo<'a> {
+    name: &'a str,
+
+    #[serde(rename = "type")]
+    kind: &'a str,
+
+    /// Whether the field is set for every post.
+    always_present: bool,
+}
+
+/// Returns the available formatting fields as a JSON array.
+///
+/// The fields and types are taken from [`formatting_help()`].
+///
+/// [`formatting_help()`]: fn.formatting_help.html
+pub fn formatting_fields_json() -> String {
+    let fields: Vec<FieldInfo> = formatting_help()
+        .lines()
+        .filter_map(|line| {
+            let (name, description) = line.split_at(line.find(": ")?);
+            let kind = description[2..].split(' ').next()?;
+
+            Some(FieldInfo {
+                name,
+                kind,
+                always_present: ALWAYS_PRESENT_FIELDS.contains(&name)
+                    || SYNTHETIC_FIELDS.contains(&name),
+            })
+        })
+        .collect();
+
+    serde_json::to_string(&fields).unwrap() // Serializing plain structs cannot fail
+}
+
+/// Formats fields of a post as a row of tab-separated values, see `--print-fields`.
+///
+/// Missing fields are empty, and tabs and line breaks within values are replaced by spaces.
+pub fn tsv_row(fields: &[String], post: &Value) -> String {
+    fields
+        .iter()
+        .map(|field| match &post[field] {
+            Value::Null => String::new(),
+            Value::String(value) => value.replace(&['\t', '\n', '\r'][..], " "),
+            value => value.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
 #[test]
 fn format_no_fields() {
     let data = "Lorem ipsum";
@@ -260,6 +638,24 @@ fn format_null_field() {
     assert_eq!("Loremipsum", fmt.format(&mut Value::Null, 0xf));
 }
 
+#[test]
+fn format_empty() {
+    let fmt = Title::new("{title}");
+
+    assert_eq!(
+        "abc123",
+        fmt.format(&mut json!({ "id": "abc123", "title": "???" }), 0xf)
+    );
+    assert_eq!(
+        "abc123",
+        fmt.format(&mut json!({ "id": "abc123", "title": " " }), 0xf)
+    );
+    assert_eq!(
+        "_a_",
+        fmt.format(&mut json!({ "id": "abc123", "title": "?a?" }), 0xf)
+    );
+}
+
 #[test]
 #[rustfmt::skip]
 fn format_replace() {
@@ -304,3 +700,211 @@ fn format_clean() {
         "test": "/\\|?<>"
     }}, 0xf));
 }
+
+#[test]
+#[rustfmt::skip]
+fn format_synthetic() {
+    let data = "{index}-{id}";
+    let fmt = Title::new(data);
+
+    assert!(fmt.utilizes("index"));
+    assert_eq!(vec!["id"], fmt.iter().collect::<Vec<_>>());
+    assert_eq!("001-Lorem", fmt.format(&mut json! {{
+        "id": "Lorem",
+        "index": format!("{:01$}", 1, INDEX_WIDTH)
+    }}, 0xf));
+
+    let data = "{host}-{id}-{ext}";
+    let fmt = Title::new(data);
+
+    assert!(fmt.utilizes("host"));
+    assert!(fmt.utilizes("ext"));
+    assert_eq!(vec!["id"], fmt.iter().collect::<Vec<_>>());
+    assert_eq!("i.imgur.com-Lorem-mp4", fmt.format(&mut json! {{
+        "id": "Lorem",
+        "host": "i.imgur.com",
+        "ext": "mp4"
+    }}, 0xff));
+}
+
+#[test]
+fn format_affixes() {
+    let truncated = |prefix: &str, suffix: &str, length, reserved| {
+        let mut prefix = String::from(prefix);
+        let mut suffix = String::from(suffix);
+        truncate_affixes(&mut prefix, &mut suffix, length, reserved);
+        (prefix, suffix)
+    };
+    let affixes = |prefix: &str, suffix: &str| (String::from(prefix), String::from(suffix));
+
+    assert_eq!(affixes("abc-", "-def"), truncated("abc-", "-def", 20, 6));
+    assert_eq!(affixes("abc-", "-d"), truncated("abc-", "-def", 12, 6));
+    assert_eq!(affixes("abc-", ""), truncated("abc-", "-def", 10, 6));
+    assert_eq!(affixes("ab", ""), truncated("abc-", "-def", 8, 6));
+    // The title takes up the whole length
+    assert_eq!(affixes("", ""), truncated("abc-", "-def", 4, 6));
+    assert_eq!(affixes("", ""), truncated("abc-", "-def", 0, 0));
+    // Characters are not split
+    assert_eq!(affixes("Caf", ""), truncated("Caf\u{e9}-", "", 10, 6));
+}
+
+#[test]
+fn format_index_width() {
+    assert_eq!(INDEX_WIDTH, index_width(None, None));
+    assert_eq!(1, index_width(Some(0), None));
+    assert_eq!(1, index_width(Some(9), None));
+    assert_eq!(2, index_width(Some(10), None));
+    assert_eq!(5, index_width(Some(12345), Some(3)));
+    assert_eq!(6, index_width(Some(12345), Some(6)));
+    assert_eq!(4, index_width(None, Some(4)));
+}
+
+#[test]
+#[rustfmt::skip]
+fn format_zero_pad() {
+    let mut fmt = Title::new("{score}-{created_utc}-{id}");
+
+    assert_eq!("7-1577836800-Lorem", fmt.format(&mut json! {{
+        "id": "Lorem",
+        "score": 7,
+        "created_utc": 1577836800.0
+    }}, 0xff));
+
+    fmt.set_zero_pad(4);
+    assert_eq!("0007-1577836800-Lorem", fmt.format(&mut json! {{
+        "id": "Lorem",
+        "score": 7,
+        "created_utc": 1577836800.0
+    }}, 0xff));
+    assert_eq!("-1-1.5-Lorem", fmt.format(&mut json! {{
+        "id": "Lorem",
+        "score": -1,
+        "created_utc": 1.5
+    }}, 0xff));
+}
+
+#[test]
+fn format_normalize_unicode() {
+    let mut fmt = Title::new("{title}");
+    let mut data = json!({ "title": "Cafe\u{301}" });
+
+    assert_eq!("Caf\u{e9}", fmt.format(&mut data, 0xff));
+    assert_eq!("Caf", fmt.format(&mut data, 4)); // `\u{e9}` takes two bytes
+
+    fmt.set_normalize_unicode(false);
+    assert_eq!("Cafe\u{301}", fmt.format(&mut data, 0xff));
+}
+
+#[test]
+fn format_truncate_at_word() {
+    let mut fmt = Title::new("{id}-{title}");
+    let mut data = json!({ "id": "abc", "title": "Lorem ipsum dolor" });
+
+    assert_eq!("abc-Lorem ips", fmt.format(&mut data, 13));
+    fmt.set_truncate_at_word(true);
+    assert_eq!("abc-Lorem", fmt.format(&mut data, 13));
+    assert_eq!("abc-Lorem ipsum", fmt.format(&mut data, 15));
+    assert_eq!("abc-Lorem ipsum", fmt.format(&mut data, 16));
+    assert_eq!("abc-Lorem ipsum dolor", fmt.format(&mut data, 0xff));
+
+    // A single word longer than the limit is cut anyway
+    assert_eq!("abc-Lo", fmt.format(&mut data, 6));
+    let mut data = json!({ "id": "abc", "title": "Caf\u{e9} au lait" });
+    assert_eq!("abc-Caf", fmt.format(&mut data, 8));
+    assert_eq!("abc-Caf\u{e9} au", fmt.format(&mut data, 14));
+}
+
+#[test]
+fn format_validate() {
+    assert_eq!(Ok(()), Title::validate("{id}-{author}_{title}"));
+    assert_eq!(Ok(()), Title::validate("{index}-{ext}-{host}"));
+    assert_eq!(Ok(()), Title::validate("{{literal}} {id}"));
+    assert_eq!(
+        Err(String::from(
+            "Unknown placeholder '{athor}' in the title, see '--formatting-fields'"
+        )),
+        Title::validate("{id}-{athor}")
+    );
+    assert!(Title::validate("{id").is_err());
+    assert!(Title::validate("id}").is_err());
+    assert!(Title::parse("{athor}").is_err());
+
+    let fmt = Title::new("{{id}}-{id} }}");
+    let mut data = json!({ "id": "abc" });
+    assert_eq!("{id}-abc }", fmt.format(&mut data, 0xff));
+    assert_eq!(vec!["id"], fmt.iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn format_escapes() {
+    let mut data = json!({ "id": "abc", "title": "Lorem" });
+    let format = |haystack| {
+        assert_eq!(Ok(()), Title::validate(haystack));
+        Title::new(haystack).format(&mut data.clone(), 0xff)
+    };
+
+    assert_eq!("a{b}c", format("a{{b}}c"));
+    assert_eq!("{abc}", format("{{{id}}}"));
+    assert_eq!("abc{Lorem", format("{id}{{{title}"));
+    assert_eq!("{{", format("{{{{"));
+    assert_eq!("abc}}Lorem", format("{id}}}}}{title}"));
+
+    data["test"] = json!("{id}");
+    assert_eq!("{id}", Title::new("{test}").format(&mut data, 0xff));
+}
+
+#[test]
+fn fields_json() {
+    let fields: Vec<Value> = serde_json::from_str(&formatting_fields_json()).unwrap();
+
+    assert_eq!(FIELDS.len() - 1 + SYNTHETIC_FIELDS.len(), fields.len()); // Without `test`
+    assert_eq!(
+        json!({ "name": "allow_live_comments", "type": "bool", "always_present": false }),
+        fields[0]
+    );
+    assert!(fields.contains(&json!({ "name": "id", "type": "string", "always_present": true })));
+    assert!(fields.contains(&json!({ "name": "index", "type": "string", "always_present": true })));
+}
+
+#[test]
+fn normalize_names() {
+    assert_eq!(
+        "Cafe creme-brulee",
+        normalize("Café crème–brûlée", false, true, 0xff)
+    );
+    assert_eq!(
+        "strasse aerodynamik",
+        normalize("Straße Ærodynamik", true, true, 0xff)
+    );
+    assert_eq!("abc-", normalize("abc-日本語のタイトル", false, true, 0xff));
+    assert_eq!("", normalize("日本語", false, true, 0xff));
+    assert_eq!("abc-日本語", normalize("ABC-日本語", true, false, 0xff));
+    assert_eq!("émil", normalize("ÉMIL", true, false, 0xff));
+
+    // Never split a character
+    assert_eq!("é", normalize("ÉÉ", true, false, 3));
+    assert_eq!("ee", normalize("ÉÉ", true, true, 3));
+}
+
+#[test]
+fn numbered_names() {
+    assert_eq!("Lorem (1)", numbered("Lorem", 1, 0xff));
+    assert_eq!("Lor (12)", numbered("Lorem", 12, 8));
+    assert_eq!("é (2)", numbered("éé", 2, 7));
+}
+
+#[test]
+fn tsv_rows() {
+    let fields = ["id", "score", "author", "title"]
+        .iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        "abc\t42\t\tLorem ipsum",
+        tsv_row(
+            &fields,
+            &json!({ "id": "abc", "score": 42, "title": "Lorem\tipsum" })
+        )
+    );
+}